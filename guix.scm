@@ -584,15 +584,21 @@ newspace."
          ("rust-linked-hash-set"
           ,rust-linked-hash-set-0.1)
          ("rust-log" ,rust-log-0.4)
+         ("rust-native-tls" ,rust-native-tls-0.2)
          ("rust-rand" ,rust-rand-0.8)
          ("rust-rpassword" ,rust-rpassword-3)
          ("rust-rust-crypto" ,rust-rust-crypto-0.2)
+         ("rust-sasl" ,rust-sasl-0.5)
          ("rust-serde" ,rust-serde-1)
          ("rust-termion" ,rust-termion-1)
          ("rust-textwrap" ,rust-textwrap-0.12)
          ("rust-tokio" ,rust-tokio-1)
+         ("rust-tokio-native-tls"
+          ,rust-tokio-native-tls-0.3)
          ("rust-tokio-xmpp" ,rust-tokio-xmpp-3)
          ("rust-toml" ,rust-toml-0.5)
+         ("rust-trust-dns-resolver"
+          ,rust-trust-dns-resolver-0.20)
          ("rust-unicode-segmentation"
           ,rust-unicode-segmentation-1)
          ("rust-uuid" ,rust-uuid-0.7)
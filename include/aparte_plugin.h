@@ -0,0 +1,39 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+#ifndef APARTE_PLUGIN_H
+#define APARTE_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/* Plugins must check api->version before using the API */
+#define APARTE_PLUGIN_API_VERSION 1
+
+struct aparte_plugin_api;
+
+typedef void (*aparte_command_callback)(const struct aparte_plugin_api *api, size_t argc,
+                                        const char *const *argv);
+typedef void (*aparte_message_callback)(const struct aparte_plugin_api *api, const char *window,
+                                        const char *from, const char *body);
+typedef void (*aparte_connected_callback)(const struct aparte_plugin_api *api,
+                                          const char *account);
+
+/* Strings are NUL terminated UTF-8, strings given to plugins are only valid during the call */
+struct aparte_plugin_api {
+    uint32_t version;
+    void *host;
+    void (*log)(const struct aparte_plugin_api *api, const char *text);
+    void (*command)(const struct aparte_plugin_api *api, const char *line);
+    void (*send)(const struct aparte_plugin_api *api, const char *window, const char *text);
+    /* Only allowed in aparte_plugin_init, returns 0 on success */
+    int (*register_command)(const struct aparte_plugin_api *api, const char *name,
+                            const char *help, aparte_command_callback callback);
+    void (*on_message)(const struct aparte_plugin_api *api, aparte_message_callback callback);
+    void (*on_connected)(const struct aparte_plugin_api *api, aparte_connected_callback callback);
+};
+
+/* Exported by every plugin, returns 0 on success */
+int aparte_plugin_init(const struct aparte_plugin_api *api);
+
+#endif
@@ -2,15 +2,95 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use serde::Deserialize;
+use std::process::Command;
 use xmpp_parsers::FullJid;
 
 /// Uniquely identify an account inside Aparté
 pub type Account = FullJid;
 
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Use TCP, falling back to WebSocket and then BOSH when the server can't be reached
+    Auto,
+    Tcp,
+    WebSocket,
+    Bosh,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Transport::Auto
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ConnectionInfo {
     pub jid: String,
     pub server: Option<String>,
     pub port: Option<u16>,
     pub autoconnect: bool,
+    #[serde(default)]
+    pub transport: Transport,
+    /// WebSocket endpoint, discovered through host-meta when unset
+    pub websocket_url: Option<String>,
+    /// BOSH endpoint, discovered through host-meta when unset
+    pub bosh_url: Option<String>,
+    /// Log in with SASL ANONYMOUS, the JID only holds the server domain
+    #[serde(default)]
+    pub anonymous: bool,
+    /// Only connect using direct TLS (XEP-0368) instead of STARTTLS
+    #[serde(default)]
+    pub direct_tls: bool,
+    /// SHA-256 fingerprint of the only server certificate accepted for this account
+    pub certificate_fingerprint: Option<String>,
+    /// Shell command whose first output line is used as password
+    pub password_command: Option<String>,
+    /// Nick used in channels joined without one, the local part of the JID by default
+    pub nick: Option<String>,
+    /// Commands run each time the account is connected
+    #[serde(default)]
+    pub autoexec: Vec<String>,
+}
+
+/// Run a password command, returning the first line of its standard output
+pub fn run_password_command(password_command: &str) -> Result<String, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(password_command)
+        .output()
+        .map_err(|err| format!("Cannot run password command: {}", err))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "Password command failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| "Password command output isn't valid UTF-8".to_string())?;
+    match stdout.lines().next() {
+        Some(password) if !password.is_empty() => Ok(password.to_string()),
+        _ => Err("Password command returned an empty password".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_command() {
+        assert_eq!(
+            run_password_command("printf 'secret\\nmetadata\\n'"),
+            Ok("secret".to_string())
+        );
+    }
+
+    #[test]
+    fn test_failing_password_command() {
+        assert!(run_password_command("false").is_err());
+        assert!(run_password_command("true").is_err());
+    }
 }
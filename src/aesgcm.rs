@@ -0,0 +1,192 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use crypto::aead::AeadDecryptor;
+use crypto::aes::KeySize;
+use crypto::aes_gcm::AesGcm;
+use crypto::aessafe::AesSafe256Encryptor;
+use crypto::ghash::Ghash;
+use crypto::symmetriccipher::BlockEncryptor;
+use crypto::util::fixed_time_eq;
+
+const SCHEME: &str = "aesgcm://";
+const IV_LEN: usize = 12;
+/// IV length of the files shared by older clients
+const LONG_IV_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+/// File encrypted before being uploaded, shared as an aesgcm:// URL whose fragment holds the
+/// IV and key in hexadecimal, like Conversations does
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptedUrl {
+    /// HTTPS URL of the encrypted file
+    pub url: String,
+    iv: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl EncryptedUrl {
+    pub fn is_encrypted(url: &str) -> bool {
+        url.starts_with(SCHEME)
+    }
+
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("Invalid encrypted URL {}: {}", url, reason);
+        let location = url
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| invalid("not an aesgcm:// URL"))?;
+        let (location, fragment) = match location.rfind('#') {
+            Some(index) => (&location[..index], &location[index + 1..]),
+            None => return Err(invalid("no key")),
+        };
+        let secret = decode_hex(fragment).ok_or_else(|| invalid("the key isn't hexadecimal"))?;
+        let iv_len = secret.len().saturating_sub(KEY_LEN);
+        if iv_len != IV_LEN && iv_len != LONG_IV_LEN {
+            return Err(invalid(
+                "only 12 or 16 bytes IVs and 32 bytes keys are supported",
+            ));
+        }
+
+        Ok(Self {
+            url: format!("https://{}", location),
+            iv: secret[..iv_len].to_vec(),
+            key: secret[iv_len..].to_vec(),
+        })
+    }
+
+    /// Decrypt the downloaded file, made of the ciphertext followed by the authentication tag
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        if data.len() < TAG_LEN {
+            return Err(format!("Cannot decrypt {}: file too short", self.url));
+        }
+        let (ciphertext, tag) = data.split_at(data.len() - TAG_LEN);
+        let plaintext = match self.iv.len() {
+            IV_LEN => {
+                let mut plaintext = vec![0u8; ciphertext.len()];
+                let mut cipher = AesGcm::new(KeySize::KeySize256, &self.key, &self.iv, &[]);
+                match cipher.decrypt(ciphertext, &mut plaintext, tag) {
+                    true => Some(plaintext),
+                    false => None,
+                }
+            }
+            _ => decrypt_long_iv(&self.key, &self.iv, ciphertext, tag),
+        };
+        plaintext.ok_or_else(|| format!("Cannot decrypt {}: wrong key or corrupted file", self.url))
+    }
+}
+
+/// AES-256-GCM decryption with an IV AesGcm doesn't handle, other than 12 bytes: the initial
+/// counter block is then the GHASH of the IV
+fn decrypt_long_iv(key: &[u8], iv: &[u8], ciphertext: &[u8], tag: &[u8]) -> Option<Vec<u8>> {
+    let aes = AesSafe256Encryptor::new(key);
+    let encrypt = |block: &[u8; 16]| {
+        let mut output = [0u8; 16];
+        aes.encrypt_block(block, &mut output);
+        output
+    };
+    let hash_key = encrypt(&[0u8; 16]);
+    let mut counter = Ghash::new(&hash_key).input_c(iv).result();
+
+    let mut expected = Ghash::new(&hash_key).input_c(ciphertext).result();
+    for (byte, mask) in expected.iter_mut().zip(encrypt(&counter).iter()) {
+        *byte ^= mask;
+    }
+    if !fixed_time_eq(&expected, tag) {
+        return None;
+    }
+
+    let mut plaintext = Vec::with_capacity(ciphertext.len());
+    for chunk in ciphertext.chunks(16) {
+        increment(&mut counter);
+        let keystream = encrypt(&counter);
+        plaintext.extend(
+            chunk
+                .iter()
+                .zip(keystream.iter())
+                .map(|(byte, mask)| byte ^ mask),
+        );
+    }
+    Some(plaintext)
+}
+
+/// Next counter block, only its last 32 bits are incremented
+fn increment(counter: &mut [u8; 16]) {
+    let low = u32::from_be_bytes([counter[12], counter[13], counter[14], counter[15]]);
+    counter[12..].copy_from_slice(&low.wrapping_add(1).to_be_bytes());
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::aead::AeadEncryptor;
+
+    #[test]
+    fn test_decrypt() {
+        // Given
+        let iv = [7u8; IV_LEN];
+        let key = [42u8; KEY_LEN];
+        let mut ciphertext = vec![0u8; 5];
+        let mut tag = [0u8; TAG_LEN];
+        AesGcm::new(KeySize::KeySize256, &key, &iv, &[]).encrypt(
+            b"Hello",
+            &mut ciphertext,
+            &mut tag,
+        );
+        ciphertext.extend_from_slice(&tag);
+        let url = format!(
+            "aesgcm://upload.capulet.lit/abc/balcony.jpg#{}{}",
+            "07".repeat(IV_LEN),
+            "2a".repeat(KEY_LEN)
+        );
+
+        // When
+        let encrypted = EncryptedUrl::parse(&url).unwrap();
+        let plaintext = encrypted.decrypt(&ciphertext).unwrap();
+
+        // Then
+        assert_eq!(encrypted.url, "https://upload.capulet.lit/abc/balcony.jpg");
+        assert_eq!(plaintext, b"Hello");
+        ciphertext[0] ^= 1;
+        assert!(encrypted.decrypt(&ciphertext).is_err());
+        assert!(EncryptedUrl::parse("aesgcm://upload.capulet.lit/balcony.jpg").is_err());
+        assert!(EncryptedUrl::parse("aesgcm://upload.capulet.lit/balcony.jpg#zz").is_err());
+        assert!(!EncryptedUrl::is_encrypted(
+            "https://upload.capulet.lit/balcony.jpg"
+        ));
+    }
+
+    #[test]
+    fn test_decrypt_long_iv() {
+        // Given
+        let url = format!(
+            "aesgcm://upload.capulet.lit/abc/letter.txt#{}{}",
+            "07".repeat(LONG_IV_LEN),
+            "2a".repeat(KEY_LEN)
+        );
+        let mut data = decode_hex("4900f3e01ad099a6c32034752660ecf84f1c351b22f84584df3a3ab0547fbccd16e519ed3052bbe41b0eb00a6fcedabd6d614e12684a79bdc89324376c").unwrap();
+
+        // When
+        let encrypted = EncryptedUrl::parse(&url).unwrap();
+        let plaintext = encrypted.decrypt(&data).unwrap();
+
+        // Then
+        assert_eq!(plaintext, b"Hello, Verona! ".repeat(3));
+        data[0] ^= 1;
+        assert!(encrypted.decrypt(&data).is_err());
+        let mut counter = [0xffu8; 16];
+        increment(&mut counter);
+        assert_eq!(counter[..12], [0xffu8; 12]);
+        assert_eq!(counter[12..], [0u8; 4]);
+    }
+}
@@ -0,0 +1,962 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use futures::sink::SinkExt;
+use futures::stream::{FuturesOrdered, StreamExt};
+use native_tls::TlsConnector as NativeTlsConnector;
+use sasl::client::mechanisms::{Anonymous, Plain, Scram};
+use sasl::client::Mechanism;
+use sasl::common::scram::{Sha1 as ScramSha1, Sha256 as ScramSha256};
+use sasl::common::{ChannelBinding, Credentials};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::future::Future;
+use std::io::{Error as IoError, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task;
+use tokio_native_tls::{TlsConnector, TlsStream};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tokio_xmpp::xmpp_stream::XMPPStream;
+use tokio_xmpp::{Error as XmppError, Packet};
+use trust_dns_resolver::TokioAsyncResolver;
+use uuid::Uuid;
+use xmpp_parsers::bind::{BindQuery, BindResponse};
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::sasl::{Auth, Challenge, Failure, Mechanism as SaslMechanism, Response, Success};
+use xmpp_parsers::{Element, Jid};
+
+use crate::account::{Account, ConnectionInfo, Transport};
+
+const NS_JABBER_CLIENT: &str = "jabber:client";
+const NS_XMPP_TLS: &str = "urn:ietf:params:xml:ns:xmpp-tls";
+const NS_XMPP_SASL: &str = "urn:ietf:params:xml:ns:xmpp-sasl";
+const NS_XMPP_FRAMING: &str = "urn:ietf:params:xml:ns:xmpp-framing";
+const NS_STREAM: &str = "http://etherx.jabber.org/streams";
+const NS_XRD: &str = "http://docs.oasis-open.org/ns/xri/xrd-1.0";
+const REL_WEBSOCKET: &str = "urn:xmpp:alt-connections:websocket";
+const REL_BOSH: &str = "urn:xmpp:alt-connections:xbosh";
+const NS_HTTPBIND: &str = "http://jabber.org/protocol/httpbind";
+const NS_XBOSH: &str = "urn:xmpp:xbosh";
+/// Longest time, in seconds, the BOSH connection manager may hold a request
+const BOSH_WAIT: u64 = 60;
+/// Maximum simultaneous BOSH requests
+const BOSH_REQUESTS: usize = 2;
+
+pub type XmppStream = XMPPStream<TlsStream<TcpStream>>;
+
+/// A server address along with the way TLS is negotiated on it
+#[derive(Debug, Clone)]
+struct Endpoint {
+    host: String,
+    port: u16,
+    direct_tls: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(IoError),
+    Tls(String),
+    UntrustedCertificate { fingerprint: String, reason: String },
+    Transport(String),
+    Protocol(String),
+    Auth(String),
+    Disconnected,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Tls(err) => write!(f, "TLS error: {}", err),
+            Error::UntrustedCertificate {
+                fingerprint,
+                reason,
+            } => write!(f, "untrusted certificate {}: {}", fingerprint, reason),
+            Error::Transport(err) => write!(f, "transport error: {}", err),
+            Error::Protocol(err) => write!(f, "protocol error: {}", err),
+            Error::Auth(err) => write!(f, "authentication failed: {}", err),
+            Error::Disconnected => write!(f, "disconnected"),
+        }
+    }
+}
+
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<XmppError> for Error {
+    fn from(err: XmppError) -> Self {
+        Error::Protocol(err.to_string())
+    }
+}
+
+/// Certificates explicitly trusted by the user, stored one `<domain> <fingerprint>` per line
+#[derive(Clone)]
+pub struct TrustStore {
+    path: PathBuf,
+}
+
+impl TrustStore {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            path: data_dir.join("certificates"),
+        }
+    }
+
+    pub fn is_trusted(&self, domain: &str, fingerprint: &str) -> bool {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => content.lines().any(|line| {
+                let mut fields = line.split_whitespace();
+                fields.next() == Some(domain)
+                    && fields
+                        .next()
+                        .map_or(false, |trusted| same_fingerprint(trusted, fingerprint))
+            }),
+            Err(_) => false,
+        }
+    }
+
+    pub fn trust(&self, domain: &str, fingerprint: &str) -> Result<(), IoError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{} {}", domain, fingerprint)
+    }
+}
+
+/// Compare two fingerprints ignoring case and separators
+pub fn same_fingerprint(a: &str, b: &str) -> bool {
+    let normalize = |fingerprint: &str| {
+        fingerprint
+            .chars()
+            .filter(|c| c.is_ascii_hexdigit())
+            .map(|c| c.to_ascii_uppercase())
+            .collect::<String>()
+    };
+
+    normalize(a) == normalize(b)
+}
+
+/// SHA-256 fingerprint of the certificate presented by the server
+fn fingerprint(stream: &TlsStream<TcpStream>) -> Result<String, Error> {
+    let certificate = stream
+        .get_ref()
+        .peer_certificate()
+        .map_err(|err| Error::Tls(err.to_string()))?
+        .ok_or(Error::Tls(
+            "server didn't present any certificate".to_string(),
+        ))?;
+    let der = certificate
+        .to_der()
+        .map_err(|err| Error::Tls(err.to_string()))?;
+
+    let mut hasher = Sha256::new();
+    hasher.input(&der);
+    let mut digest = [0u8; 32];
+    hasher.result(&mut digest);
+
+    Ok(digest
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(":"))
+}
+
+/// WebSocket connection framed as described in RFC 7395
+pub struct WebSocket {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    features: Element,
+}
+
+impl WebSocket {
+    async fn send(&mut self, element: Element) -> Result<(), Error> {
+        self.stream
+            .send(WsMessage::Text(String::from(&element)))
+            .await
+            .map_err(|err| Error::Transport(err.to_string()))
+    }
+
+    async fn recv(&mut self) -> Option<Result<Element, Error>> {
+        loop {
+            match self.stream.next().await {
+                Some(Ok(WsMessage::Text(text))) => {
+                    return match text.parse::<Element>() {
+                        Ok(element) if element.is("close", NS_XMPP_FRAMING) => None,
+                        Ok(element) => Some(Ok(element)),
+                        Err(err) => Some(Err(Error::Protocol(err.to_string()))),
+                    }
+                }
+                Some(Ok(WsMessage::Close(_))) | None => return None,
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Some(Err(Error::Transport(err.to_string()))),
+            }
+        }
+    }
+
+    /// Open a new XMPP stream and wait for its features
+    async fn open(&mut self, domain: &str) -> Result<(), Error> {
+        let open = Element::builder("open", NS_XMPP_FRAMING)
+            .attr("to", domain)
+            .attr("version", "1.0")
+            .build();
+        self.send(open).await?;
+
+        loop {
+            match self.recv().await {
+                Some(Ok(element)) if element.is("features", NS_STREAM) => {
+                    self.features = element;
+                    return Ok(());
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err),
+                None => return Err(Error::Disconnected),
+            }
+        }
+    }
+}
+
+enum BoshRequest {
+    Stanza(Element),
+    Restart,
+}
+
+/// BOSH session (XEP-0124 and XEP-0206)
+///
+/// HTTP requests are handled by a background task so that a long polling request is always
+/// pending, dropping the session terminates it.
+pub struct Bosh {
+    requests: mpsc::UnboundedSender<BoshRequest>,
+    responses: mpsc::UnboundedReceiver<Result<Element, Error>>,
+    features: Element,
+}
+
+impl Bosh {
+    fn send(&mut self, element: Element) -> Result<(), Error> {
+        self.requests
+            .send(BoshRequest::Stanza(element))
+            .map_err(|_| Error::Disconnected)
+    }
+
+    async fn recv(&mut self) -> Option<Result<Element, Error>> {
+        self.responses.recv().await
+    }
+
+    async fn restart(&mut self) -> Result<(), Error> {
+        self.requests
+            .send(BoshRequest::Restart)
+            .map_err(|_| Error::Disconnected)?;
+        self.wait_features().await
+    }
+
+    async fn wait_features(&mut self) -> Result<(), Error> {
+        loop {
+            match self.recv().await {
+                Some(Ok(element)) if element.is("features", NS_STREAM) => {
+                    self.features = element;
+                    return Ok(());
+                }
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err),
+                None => return Err(Error::Disconnected),
+            }
+        }
+    }
+}
+
+fn bosh_body<I: IntoIterator<Item = Element>>(
+    sid: &str,
+    rid: u64,
+    attributes: &str,
+    payload: I,
+) -> String {
+    let payload: String = payload
+        .into_iter()
+        .map(|element| String::from(&element))
+        .collect();
+    format!(
+        "<body xmlns='{}' xmlns:xmpp='{}' sid='{}' rid='{}'{}>{}</body>",
+        NS_HTTPBIND, NS_XBOSH, sid, rid, attributes, payload
+    )
+}
+
+async fn bosh_post(client: reqwest::Client, url: String, body: String) -> Result<Element, Error> {
+    let response = client
+        .post(url.as_str())
+        .header("Content-Type", "text/xml; charset=utf-8")
+        .body(body)
+        .send()
+        .await
+        .map_err(|err| Error::Transport(err.to_string()))?;
+    if !response.status().is_success() {
+        return Err(Error::Transport(format!("HTTP {}", response.status())));
+    }
+
+    response
+        .text()
+        .await
+        .map_err(|err| Error::Transport(err.to_string()))?
+        .parse::<Element>()
+        .map_err(|err| Error::Protocol(err.to_string()))
+}
+
+async fn bosh_poll(
+    client: reqwest::Client,
+    url: String,
+    domain: String,
+    sid: String,
+    mut rid: u64,
+    mut requests: mpsc::UnboundedReceiver<BoshRequest>,
+    responses: mpsc::UnboundedSender<Result<Element, Error>>,
+) {
+    // Responses are handled in rid order, as the server expects
+    let mut pending = FuturesOrdered::new();
+    let mut queue = Vec::new();
+    let mut restart = false;
+
+    loop {
+        // Always keep a request pending so that the server can push data
+        if pending.len() < BOSH_REQUESTS && (restart || !queue.is_empty() || pending.is_empty()) {
+            // Stanzas queued meanwhile are sent in the next request
+            let body = match restart {
+                true => {
+                    let attributes = format!(" to='{}' xml:lang='en' xmpp:restart='true'", domain);
+                    bosh_body(&sid, rid, &attributes, Vec::new())
+                }
+                false => bosh_body(&sid, rid, "", queue.drain(..)),
+            };
+            pending.push_back(bosh_post(client.clone(), url.clone(), body));
+            restart = false;
+            rid += 1;
+        }
+
+        tokio::select! {
+            response = pending.next() => match response {
+                Some(Ok(body)) => {
+                    for child in body.children() {
+                        if responses.send(Ok(child.clone())).is_err() {
+                            return;
+                        }
+                    }
+                    if body.attr("type") == Some("terminate") {
+                        let condition = body.attr("condition").unwrap_or("unknown");
+                        let _ = responses.send(Err(Error::Transport(format!(
+                            "BOSH session terminated: {}",
+                            condition
+                        ))));
+                        return;
+                    }
+                }
+                Some(Err(err)) => {
+                    let _ = responses.send(Err(err));
+                    return;
+                }
+                None => {}
+            },
+            request = requests.recv() => match request {
+                Some(BoshRequest::Stanza(stanza)) => queue.push(stanza),
+                Some(BoshRequest::Restart) => restart = true,
+                None => {
+                    let body = bosh_body(&sid, rid, " type='terminate'", Vec::new());
+                    let _ = bosh_post(client.clone(), url.clone(), body).await;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// An XMPP stream over any supported transport
+pub enum Session {
+    Tcp(XmppStream),
+    WebSocket(WebSocket),
+    Bosh(Bosh),
+}
+
+impl Session {
+    pub async fn send(&mut self, element: Element) -> Result<(), Error> {
+        match self {
+            Session::Tcp(stream) => Ok(stream.send(Packet::Stanza(element)).await?),
+            Session::WebSocket(websocket) => websocket.send(element).await,
+            Session::Bosh(bosh) => bosh.send(element),
+        }
+    }
+
+    /// Receive next top level element, None meaning the stream has been closed
+    pub async fn recv(&mut self) -> Option<Result<Element, Error>> {
+        match self {
+            Session::Tcp(stream) => loop {
+                match stream.next().await {
+                    Some(Ok(Packet::Stanza(stanza))) => return Some(Ok(stanza)),
+                    Some(Ok(Packet::StreamEnd)) | None => return None,
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => return Some(Err(Error::Protocol(err.to_string()))),
+                }
+            },
+            Session::WebSocket(websocket) => websocket.recv().await,
+            Session::Bosh(bosh) => bosh.recv().await,
+        }
+    }
+
+    fn sasl_mechanisms(&self) -> Result<HashSet<String>, Error> {
+        let features = match self {
+            Session::Tcp(stream) => {
+                return Ok(stream
+                    .stream_features
+                    .sasl_mechanisms()
+                    .map_err(|err| Error::Auth(err.to_string()))?
+                    .collect())
+            }
+            Session::WebSocket(websocket) => &websocket.features,
+            Session::Bosh(bosh) => &bosh.features,
+        };
+
+        Ok(features
+            .get_child("mechanisms", NS_XMPP_SASL)
+            .ok_or(Error::Auth("no supported mechanism".to_string()))?
+            .children()
+            .filter(|child| child.is("mechanism", NS_XMPP_SASL))
+            .map(|mechanism| mechanism.text())
+            .collect())
+    }
+
+    /// Restart the stream after authentication
+    async fn restart(self, account: &Account) -> Result<Self, Error> {
+        match self {
+            Session::Tcp(stream) => Ok(Session::Tcp(
+                XMPPStream::start(
+                    stream.into_inner(),
+                    Jid::Full(account.clone()),
+                    NS_JABBER_CLIENT.to_string(),
+                )
+                .await?,
+            )),
+            Session::WebSocket(mut websocket) => {
+                websocket.open(&account.domain).await?;
+                Ok(Session::WebSocket(websocket))
+            }
+            Session::Bosh(mut bosh) => {
+                bosh.restart().await?;
+                Ok(Session::Bosh(bosh))
+            }
+        }
+    }
+}
+
+pub struct Client {
+    account: Account,
+    connection_info: ConnectionInfo,
+    password: String,
+    trust_store: TrustStore,
+}
+
+impl Client {
+    pub fn new(
+        account: Account,
+        connection_info: ConnectionInfo,
+        password: String,
+        trust_store: TrustStore,
+    ) -> Self {
+        Self {
+            account,
+            connection_info,
+            password,
+            trust_store,
+        }
+    }
+
+    /// Open an authenticated and bound session, returning it along with the bound JID
+    pub async fn connect(&self) -> Result<(Session, Jid), Error> {
+        let session = match self.connection_info.transport {
+            Transport::Tcp => self.connect_tcp().await?,
+            Transport::WebSocket => self.connect_websocket().await?,
+            Transport::Bosh => self.connect_bosh().await?,
+            Transport::Auto => match self.connect_tcp().await {
+                // The pinned certificate is the one of the XMPP server, other transports would
+                // silently bypass it
+                Err(err @ Error::Io(_)) | Err(err @ Error::Disconnected)
+                    if self.connection_info.certificate_fingerprint.is_some() =>
+                {
+                    return Err(err)
+                }
+                Err(Error::Io(_)) | Err(Error::Disconnected) => {
+                    info!(
+                        "Cannot reach {} over TCP, trying WebSocket",
+                        self.account.domain
+                    );
+                    match self.connect_websocket().await {
+                        Err(Error::Transport(_)) | Err(Error::Disconnected) => {
+                            info!(
+                                "Cannot reach {} over WebSocket, trying BOSH",
+                                self.account.domain
+                            );
+                            self.connect_bosh().await?
+                        }
+                        result => result?,
+                    }
+                }
+                result => result?,
+            },
+        };
+
+        let session = self.auth(session).await?;
+        let session = session.restart(&self.account).await?;
+        self.bind(session).await
+    }
+
+    async fn connect_tcp(&self) -> Result<Session, Error> {
+        let tls_stream = self.connect_tls().await?;
+        let stream =
+            XMPPStream::start(tls_stream, self.jid(), NS_JABBER_CLIENT.to_string()).await?;
+        Ok(Session::Tcp(stream))
+    }
+
+    async fn connect_websocket(&self) -> Result<Session, Error> {
+        let url = match &self.connection_info.websocket_url {
+            Some(url) => url.clone(),
+            None => discover(&self.account.domain, REL_WEBSOCKET, "wss://").await?,
+        };
+        debug!("Trying {}", url);
+
+        let mut request = url
+            .as_str()
+            .into_client_request()
+            .map_err(|err| Error::Transport(err.to_string()))?;
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", HeaderValue::from_static("xmpp"));
+
+        // TLS is established here to go through the certificate policy of the account
+        let invalid_url = || Error::Transport(format!("invalid WebSocket URL {}", url));
+        let host = request.uri().host().ok_or_else(invalid_url)?.to_string();
+        match request.uri().scheme_str() {
+            Some("wss") => {}
+            // Credentials would be sent in clear
+            Some("ws") => return Err(unencrypted(&url)),
+            _ => return Err(invalid_url()),
+        }
+        let port = request.uri().port_u16().unwrap_or(443);
+        let host = host.as_str();
+        let stream = MaybeTlsStream::NativeTls(
+            self.check_certificate(move |verify| connect_direct_tls(host, port, verify))
+                .await?,
+        );
+        let (stream, _) = tokio_tungstenite::client_async(request, stream)
+            .await
+            .map_err(|err| Error::Transport(err.to_string()))?;
+
+        let mut websocket = WebSocket {
+            stream,
+            features: Element::builder("features", NS_STREAM).build(),
+        };
+        websocket.open(&self.account.domain).await?;
+        Ok(Session::WebSocket(websocket))
+    }
+
+    async fn connect_bosh(&self) -> Result<Session, Error> {
+        // The HTTP client doesn't expose the certificate of the server to check it
+        if self.connection_info.certificate_fingerprint.is_some() {
+            return Err(Error::Transport(
+                "BOSH cannot be used with a pinned certificate".to_string(),
+            ));
+        }
+
+        let url = match &self.connection_info.bosh_url {
+            Some(url) => url.clone(),
+            None => discover(&self.account.domain, REL_BOSH, "https://").await?,
+        };
+        // Credentials would be sent in clear
+        match reqwest::Url::parse(&url) {
+            Ok(parsed) if parsed.scheme() == "https" => {}
+            Ok(parsed) if parsed.scheme() == "http" => return Err(unencrypted(&url)),
+            _ => return Err(Error::Transport(format!("invalid BOSH URL {}", url))),
+        }
+        debug!("Trying {}", url);
+
+        let client = reqwest::Client::new();
+        let rid = u64::from(rand::random::<u32>());
+        let body = format!(
+            "<body xmlns='{}' xmlns:xmpp='{}' content='text/xml; charset=utf-8' hold='1' rid='{}' to='{}' ver='1.6' wait='{}' xml:lang='en' xmpp:version='1.0'/>",
+            NS_HTTPBIND, NS_XBOSH, rid, self.account.domain, BOSH_WAIT
+        );
+        let response = bosh_post(client.clone(), url.clone(), body).await?;
+        let sid = response
+            .attr("sid")
+            .ok_or(Error::Transport("BOSH session creation failed".to_string()))?
+            .to_string();
+
+        let (requests, requests_rx) = mpsc::unbounded_channel();
+        let (responses_tx, responses) = mpsc::unbounded_channel();
+        for child in response.children() {
+            let _ = responses_tx.send(Ok(child.clone()));
+        }
+        task::spawn_local(bosh_poll(
+            client,
+            url,
+            self.account.domain.clone(),
+            sid,
+            rid + 1,
+            requests_rx,
+            responses_tx,
+        ));
+
+        let mut bosh = Bosh {
+            requests,
+            responses,
+            features: Element::builder("features", NS_STREAM).build(),
+        };
+        bosh.wait_features().await?;
+        Ok(Session::Bosh(bosh))
+    }
+
+    fn jid(&self) -> Jid {
+        Jid::Full(self.account.clone())
+    }
+
+    async fn connect_tls(&self) -> Result<TlsStream<TcpStream>, Error> {
+        let domain = self.account.domain.as_str();
+        self.check_certificate(move |verify| self.open_tls(domain, verify))
+            .await
+    }
+
+    /// Establish TLS according to the account certificate policy, `open` negotiating it with or
+    /// without verifying the certificate
+    ///
+    /// A pinned fingerprint replaces the usual chain verification. Otherwise the certificate must
+    /// be valid or have been explicitly trusted by the user.
+    async fn check_certificate<F, T>(&self, open: F) -> Result<TlsStream<TcpStream>, Error>
+    where
+        F: Fn(bool) -> T,
+        T: Future<Output = Result<TlsStream<TcpStream>, Error>>,
+    {
+        let domain = &self.account.domain;
+
+        if let Some(pinned) = &self.connection_info.certificate_fingerprint {
+            let stream = open(false).await?;
+            let fingerprint = fingerprint(&stream)?;
+            if !same_fingerprint(&fingerprint, pinned) {
+                return Err(Error::UntrustedCertificate {
+                    fingerprint,
+                    reason: "certificate doesn't match pinned fingerprint".to_string(),
+                });
+            }
+            return Ok(stream);
+        }
+
+        match open(true).await {
+            Err(Error::Tls(reason)) => {
+                let stream = open(false).await?;
+                let fingerprint = fingerprint(&stream)?;
+                if self.trust_store.is_trusted(domain, &fingerprint) {
+                    Ok(stream)
+                } else {
+                    Err(Error::UntrustedCertificate {
+                        fingerprint,
+                        reason,
+                    })
+                }
+            }
+            result => result,
+        }
+    }
+
+    async fn endpoints(&self, domain: &str) -> Vec<Endpoint> {
+        let direct_tls = self.connection_info.direct_tls;
+
+        // An explicit host or port bypasses SRV resolution
+        if self.connection_info.server.is_some() || self.connection_info.port.is_some() {
+            return vec![Endpoint {
+                host: self
+                    .connection_info
+                    .server
+                    .clone()
+                    .unwrap_or(domain.to_string()),
+                port: self
+                    .connection_info
+                    .port
+                    .unwrap_or(if direct_tls { 5223 } else { 5222 }),
+                direct_tls,
+            }];
+        }
+
+        let mut endpoints = resolve(domain).await;
+        if direct_tls {
+            endpoints.push(Endpoint {
+                host: domain.to_string(),
+                port: 5223,
+                direct_tls: true,
+            });
+            endpoints.retain(|endpoint| endpoint.direct_tls);
+        } else {
+            endpoints.push(Endpoint {
+                host: domain.to_string(),
+                port: 5222,
+                direct_tls: false,
+            });
+        }
+
+        endpoints
+    }
+
+    /// Connect to the first reachable endpoint and negotiate TLS on it
+    async fn open_tls(&self, domain: &str, verify: bool) -> Result<TlsStream<TcpStream>, Error> {
+        let mut last_error = Error::Disconnected;
+        for endpoint in self.endpoints(domain).await {
+            debug!("Trying {:?}", endpoint);
+            let tcp_stream = match TcpStream::connect((endpoint.host.as_str(), endpoint.port)).await
+            {
+                Ok(tcp_stream) => tcp_stream,
+                Err(err) => {
+                    last_error = Error::Io(err);
+                    continue;
+                }
+            };
+
+            if endpoint.direct_tls {
+                return handshake(domain, tcp_stream, verify).await;
+            } else {
+                return self.starttls(domain, tcp_stream, verify).await;
+            }
+        }
+
+        Err(last_error)
+    }
+
+    async fn starttls(
+        &self,
+        domain: &str,
+        tcp_stream: TcpStream,
+        verify: bool,
+    ) -> Result<TlsStream<TcpStream>, Error> {
+        let mut stream =
+            XMPPStream::start(tcp_stream, self.jid(), NS_JABBER_CLIENT.to_string()).await?;
+        if !stream.stream_features.can_starttls() {
+            return Err(Error::Protocol("server doesn't offer STARTTLS".to_string()));
+        }
+
+        let starttls = Element::builder("starttls", NS_XMPP_TLS).build();
+        stream.send(Packet::Stanza(starttls)).await?;
+        loop {
+            match stream.next().await {
+                Some(Ok(Packet::Stanza(ref stanza))) if stanza.name() == "proceed" => break,
+                Some(Ok(Packet::Text(_))) => {}
+                Some(Err(err)) => return Err(Error::Protocol(err.to_string())),
+                Some(Ok(_)) => return Err(Error::Protocol("STARTTLS failed".to_string())),
+                None => return Err(Error::Disconnected),
+            }
+        }
+
+        handshake(domain, stream.into_inner(), verify).await
+    }
+
+    async fn auth(&self, mut session: Session) -> Result<Session, Error> {
+        let remote_mechanisms = session.sasl_mechanisms()?;
+
+        let credentials = Credentials::default()
+            .with_username(self.account.node.clone().unwrap_or_default())
+            .with_password(self.password.clone())
+            .with_channel_binding(ChannelBinding::None);
+
+        let mut mechanism: Box<dyn Mechanism> = if self.connection_info.anonymous {
+            if !remote_mechanisms.contains("ANONYMOUS") {
+                return Err(Error::Auth(
+                    "server doesn't allow anonymous login".to_string(),
+                ));
+            }
+            Box::new(Anonymous::new())
+        } else if remote_mechanisms.contains("SCRAM-SHA-256") {
+            Box::new(
+                Scram::<ScramSha256>::from_credentials(credentials)
+                    .map_err(|err| Error::Auth(err.to_string()))?,
+            )
+        } else if remote_mechanisms.contains("SCRAM-SHA-1") {
+            Box::new(
+                Scram::<ScramSha1>::from_credentials(credentials)
+                    .map_err(|err| Error::Auth(err.to_string()))?,
+            )
+        } else if remote_mechanisms.contains("PLAIN") {
+            Box::new(
+                Plain::from_credentials(credentials).map_err(|err| Error::Auth(err.to_string()))?,
+            )
+        } else {
+            return Err(Error::Auth("no supported mechanism".to_string()));
+        };
+
+        let auth = Auth {
+            mechanism: SaslMechanism::from_str(mechanism.name())
+                .map_err(|err| Error::Auth(err.to_string()))?,
+            data: mechanism.initial(),
+        };
+        session.send(auth.into()).await?;
+
+        loop {
+            match session.recv().await {
+                Some(Ok(stanza)) => {
+                    if let Ok(challenge) = Challenge::try_from(stanza.clone()) {
+                        let data = mechanism
+                            .response(&challenge.data)
+                            .map_err(|err| Error::Auth(err.to_string()))?;
+                        session.send(Response { data }.into()).await?;
+                    } else if let Ok(_) = Success::try_from(stanza.clone()) {
+                        return Ok(session);
+                    } else if let Ok(failure) = Failure::try_from(stanza) {
+                        return Err(Error::Auth(format!("{:?}", failure.defined_condition)));
+                    }
+                }
+                Some(Err(err)) => return Err(err),
+                None => return Err(Error::Disconnected),
+            }
+        }
+    }
+
+    async fn bind(&self, mut session: Session) -> Result<(Session, Jid), Error> {
+        let id = Uuid::new_v4().to_string();
+        let iq = Iq::from_set(
+            id.clone(),
+            BindQuery::new(Some(self.account.resource.clone())),
+        );
+        session.send(iq.into()).await?;
+
+        loop {
+            match session.recv().await {
+                Some(Ok(stanza)) => match Iq::try_from(stanza) {
+                    Ok(iq) if iq.id == id => {
+                        return match iq.payload {
+                            IqType::Result(Some(payload)) => {
+                                match BindResponse::try_from(payload) {
+                                    Ok(bind) => Ok((session, Jid::from(bind))),
+                                    Err(err) => Err(Error::Protocol(err.to_string())),
+                                }
+                            }
+                            IqType::Result(None) => Ok((session, self.jid())),
+                            _ => Err(Error::Protocol("resource binding failed".to_string())),
+                        };
+                    }
+                    _ => {}
+                },
+                Some(Err(err)) => return Err(err),
+                None => return Err(Error::Disconnected),
+            }
+        }
+    }
+}
+
+async fn handshake(
+    domain: &str,
+    stream: TcpStream,
+    verify: bool,
+) -> Result<TlsStream<TcpStream>, Error> {
+    let connector = NativeTlsConnector::builder()
+        .danger_accept_invalid_certs(!verify)
+        .danger_accept_invalid_hostnames(!verify)
+        .build()
+        .map_err(|err| Error::Tls(err.to_string()))?;
+
+    TlsConnector::from(connector)
+        .connect(domain, stream)
+        .await
+        .map_err(|err| Error::Tls(err.to_string()))
+}
+
+/// Connect to a host and negotiate TLS right away, like HTTPS does
+async fn connect_direct_tls(
+    host: &str,
+    port: u16,
+    verify: bool,
+) -> Result<TlsStream<TcpStream>, Error> {
+    let stream = TcpStream::connect((host, port))
+        .await
+        .map_err(|err| Error::Transport(err.to_string()))?;
+    handshake(host, stream, verify).await
+}
+
+/// Error for an endpoint without TLS, credentials cannot be sent to it
+fn unencrypted(url: &str) -> Error {
+    Error::Transport(format!("{} isn't encrypted, refusing to connect", url))
+}
+
+/// Discover an alternative connection endpoint through host-meta (XEP-0156)
+async fn discover(domain: &str, rel: &str, scheme: &str) -> Result<String, Error> {
+    let url = format!("https://{}/.well-known/host-meta", domain);
+    let host_meta = reqwest::get(&url)
+        .await
+        .map_err(|err| Error::Transport(err.to_string()))?
+        .text()
+        .await
+        .map_err(|err| Error::Transport(err.to_string()))?;
+    let xrd = host_meta
+        .parse::<Element>()
+        .map_err(|err| Error::Transport(format!("invalid host-meta: {}", err)))?;
+
+    xrd.children()
+        .filter(|link| link.is("Link", NS_XRD) && link.attr("rel") == Some(rel))
+        .filter_map(|link| link.attr("href"))
+        .find(|href| href.starts_with(scheme))
+        .map(|href| href.to_string())
+        .ok_or(Error::Transport(format!(
+            "no {} endpoint advertised for {}",
+            rel, domain
+        )))
+}
+
+/// Resolve both STARTTLS and direct TLS (XEP-0368) endpoints ordered by SRV priority
+async fn resolve(domain: &str) -> Vec<Endpoint> {
+    let mut records = Vec::new();
+
+    match TokioAsyncResolver::tokio_from_system_conf() {
+        Ok(resolver) => {
+            for (service, direct_tls) in
+                &[("_xmpps-client._tcp", true), ("_xmpp-client._tcp", false)]
+            {
+                match resolver
+                    .srv_lookup(format!("{}.{}.", service, domain))
+                    .await
+                {
+                    Ok(lookup) => records.extend(lookup.iter().filter_map(|srv| {
+                        match srv.target().to_string().trim_end_matches('.') {
+                            "" => None,
+                            host => Some((
+                                srv.priority(),
+                                Endpoint {
+                                    host: host.to_string(),
+                                    port: srv.port(),
+                                    direct_tls: *direct_tls,
+                                },
+                            )),
+                        }
+                    })),
+                    Err(err) => debug!("No {} record for {}: {}", service, domain, err),
+                }
+            }
+        }
+        Err(err) => warn!("Cannot create DNS resolver: {}", err),
+    }
+
+    // Direct TLS is preferred over STARTTLS for a same priority
+    records.sort_by_key(|(priority, endpoint)| (*priority, !endpoint.direct_tls));
+    records.into_iter().map(|(_, endpoint)| endpoint).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_fingerprint() {
+        assert!(same_fingerprint("AB:CD:01", "ab:cd:01"));
+        assert!(same_fingerprint("ABCD01", "AB:CD:01"));
+        assert!(!same_fingerprint("AB:CD:01", "AB:CD:02"));
+    }
+}
@@ -4,15 +4,22 @@ use hsluv::hsluv_to_rgb;
 use std::convert::TryInto;
 use termion::color;
 
-pub fn id_to_rgb(identifier: &str) -> (u8, u8, u8) {
-    // Follow xep 0392 for color generation
+use crate::theme::Color;
+
+/// Hue angle of the given identifier as defined by XEP-0392 (Consistent Color Generation)
+pub fn id_to_hue(identifier: &str) -> f64 {
     let mut hasher = Sha1::new();
     hasher.input_str(identifier);
     let mut hash = [0; 20];
     hasher.result(&mut hash);
 
     let a = u16::from_le_bytes(hash[..2].try_into().unwrap());
-    let hue_angle = f64::from(a) / 65536f64 * 360f64;
+    f64::from(a) / 65536f64 * 360f64
+}
+
+pub fn id_to_rgb(identifier: &str) -> (u8, u8, u8) {
+    // Follow xep 0392 for color generation
+    let hue_angle = id_to_hue(identifier);
     let hue = (hue_angle, 100.0, 75.0);
     let (r, g, b) = hsluv_to_rgb(hue);
     let (r, g, b) = (r * 255.0, g * 255.0, b * 255.0);
@@ -62,7 +69,7 @@ pub fn rainbow(input: &str) -> String {
             '\n' => rainbow.new_line(),
             _ => {
                 let (r, g, b) = rainbow.get_color();
-                output.push_str(&format!("{}", color::Fg(color::Rgb(r, g, b))));
+                output.push_str(&format!("{}", color::Fg(Color::Rgb(r, g, b))));
             }
         }
         output.push(c);
@@ -70,3 +77,23 @@ pub fn rainbow(input: &str) -> String {
 
     output
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_to_hue() {
+        // Test vectors from XEP-0392
+        assert!((id_to_hue("Romeo") - 327.255249).abs() < 1e-6);
+        assert!((id_to_hue("juliet@capulet.lit") - 209.410400).abs() < 1e-6);
+        assert!((id_to_hue("😺") - 331.199341).abs() < 1e-6);
+        assert!((id_to_hue("council") - 359.994507).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_id_to_rgb_is_stable() {
+        assert_eq!(id_to_rgb("Romeo"), id_to_rgb("Romeo"));
+        assert_ne!(id_to_rgb("Romeo"), id_to_rgb("juliet@capulet.lit"));
+    }
+}
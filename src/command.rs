@@ -258,6 +258,98 @@ pub struct CommandParser {
     pub parse: fn(&Option<Account>, &str, &str) -> Result<Command, String>,
     pub exec: fn(&mut Aparte, Command) -> Result<(), String>,
     pub autocompletions: Vec<Option<Box<dyn Fn(&mut Aparte, Command) -> Vec<String>>>>,
+    /// Names of the arguments given as `--name value`, taking no positional slot
+    pub named: Vec<&'static str>,
+    /// Names of the arguments given as `--name`, taking no positional slot
+    pub flags: Vec<&'static str>,
+    pub subcommands: Vec<(&'static str, fn() -> CommandParser)>,
+}
+
+/// What the argument under the cursor is, for completion
+#[derive(Debug, PartialEq)]
+enum CompletionTarget {
+    Nothing,
+    Options,
+    Positional(usize),
+    /// Index of the subcommand name in the arguments, and that name
+    Subcommand(usize, &'static str),
+}
+
+impl CommandParser {
+    /// One line description of the command, taken from its help
+    pub fn summary(&self) -> &str {
+        let mut lines = self.help.lines().map(|line| line.trim());
+        let usage = lines.next().unwrap_or("");
+        match lines.find(|line| *line == "Description:") {
+            Some(_) => lines.find(|line| !line.is_empty()).unwrap_or(usage),
+            None => usage,
+        }
+    }
+
+    /// Completions for the argument under the cursor, delegated to the provider of its position
+    pub fn complete(&self, aparte: &mut Aparte, command: Command) -> Vec<String> {
+        match self.completion_target(&command.args, command.cursor) {
+            CompletionTarget::Nothing => Vec::new(),
+            CompletionTarget::Options => self
+                .named
+                .iter()
+                .chain(self.flags.iter())
+                .map(|name| format!("--{}", name))
+                .collect(),
+            CompletionTarget::Positional(position) => match self.autocompletions.get(position) {
+                Some(Some(completion)) => completion(aparte, command),
+                _ => Vec::new(),
+            },
+            CompletionTarget::Subcommand(index, name) => {
+                match self.subcommands.iter().find(|(sub, _)| *sub == name) {
+                    Some((_, new)) => new().complete(
+                        aparte,
+                        Command {
+                            args: command.args[index..].to_vec(),
+                            cursor: command.cursor - index,
+                            ..command
+                        },
+                    ),
+                    None => Vec::new(),
+                }
+            }
+        }
+    }
+
+    fn completion_target(&self, args: &[String], cursor: usize) -> CompletionTarget {
+        if args.get(cursor).map_or(false, |arg| arg.starts_with("--")) {
+            return CompletionTarget::Options;
+        }
+
+        let mut position = 0;
+        let mut i = 1;
+        while i < cursor {
+            let arg = args[i].as_str();
+            let option = arg.strip_prefix("--").unwrap_or(arg);
+            if arg.starts_with("--") && self.named.iter().any(|name| *name == option) {
+                if i + 1 == cursor {
+                    // Values of named arguments have no completion
+                    return CompletionTarget::Nothing;
+                }
+                i += 2;
+            } else if arg.starts_with("--") && self.flags.iter().any(|name| *name == option) {
+                i += 1;
+            } else if self.named.iter().any(|name| {
+                option
+                    .strip_prefix(name)
+                    .map_or(false, |value| value.starts_with('='))
+            }) {
+                i += 1;
+            } else if let Some((sub, _)) = self.subcommands.iter().find(|(sub, _)| *sub == arg) {
+                return CompletionTarget::Subcommand(i, sub);
+            } else {
+                position += 1;
+                i += 1;
+            }
+        }
+
+        CompletionTarget::Positional(position)
+    }
 }
 
 #[macro_export]
@@ -313,20 +405,29 @@ macro_rules! parse_command_args(
     );
     ($aparte:ident, $command:ident, $index:ident, { $arg:ident: Named<$type:ty> $(= $attr:tt)? $(, $($tail:tt)*)? }) => (
         let $arg: Option<$type> = {
-            // Could use let matching = $command.args.drain_filter(|a| a.starts_with(stringify!($arg))).collect::<Vec<String>>();
+            // Accept name=value, --name=value and --name value
             let mut matching = Vec::new();
             let mut i = 0;
             while i != $command.args.len() {
-                if $command.args[i].starts_with(stringify!($arg)) {
+                if $command.args[i] == concat!("--", stringify!($arg)) {
+                    $command.args.remove(i);
+                    if i == $command.args.len() {
+                        return Err(format!("Missing value for {} argument", stringify!($arg)));
+                    }
                     matching.push($command.args.remove(i));
+                } else if let Some(value) = $command.args[i]
+                    .strip_prefix(concat!("--", stringify!($arg), "="))
+                    .or($command.args[i].strip_prefix(concat!(stringify!($arg), "=")))
+                {
+                    matching.push(value.to_string());
+                    $command.args.remove(i);
                 } else {
                     i += 1;
                 }
             }
             match matching.as_slice() {
                 [] => None,
-                [named] => {
-                    let arg = named.splitn(2, "=").collect::<Vec<&str>>()[1];
+                [arg] => {
                     match <$type>::from_str(&arg) {
                         Ok(arg) => Some(arg),
                         Err(e) => return Err(format!("Invalid format for {} argument: {}", stringify!($arg), e)),
@@ -338,6 +439,15 @@ macro_rules! parse_command_args(
 
         parse_command_args!($aparte, $command, $index, { $($($tail)*)? });
     );
+    ($aparte:ident, $command:ident, $index:ident, { $arg:ident: Flag $(, $($tail:tt)*)? }) => (
+        let $arg: bool = {
+            let count = $command.args.len();
+            $command.args.retain(|arg| arg != concat!("--", stringify!($arg)));
+            count != $command.args.len()
+        };
+
+        parse_command_args!($aparte, $command, $index, { $($($tail)*)? });
+    );
     ($aparte:ident, $command:ident, $index:ident, { $arg:ident: Command = $attr:tt $(, $($tail:tt)*)? }) => (
         if $command.args.len() <= $index {
             return Err(format!("Missing {} argument", stringify!($arg)))
@@ -376,6 +486,13 @@ macro_rules! parse_command_args(
 #[macro_export]
 macro_rules! generate_command_autocompletions(
     ($autocompletions:ident, {}) => ();
+    // Named arguments and flags don't take a positional slot
+    ($autocompletions:ident, { $argname:ident: Named<$type:ty> $(= $attrs:tt)? $(, $($tail:tt)*)? }) => (
+        generate_command_autocompletions!($autocompletions, { $($($tail)*)? });
+    );
+    ($autocompletions:ident, { $argname:ident: Flag $(, $($tail:tt)*)? }) => (
+        generate_command_autocompletions!($autocompletions, { $($($tail)*)? });
+    );
     ($autocompletions:ident, { $argname:ident: $type:ty = $attrs:tt $(, $($tail:tt)*)? }) => (
         let count = $autocompletions.len();
         generate_arg_autocompletion!($autocompletions, $type, $attrs);
@@ -391,6 +508,43 @@ macro_rules! generate_command_autocompletions(
     );
 );
 
+#[macro_export]
+macro_rules! generate_command_options(
+    ($named:ident, $flags:ident, {}) => ();
+    ($named:ident, $flags:ident, { $argname:ident: Named<$type:ty> $(= $attrs:tt)? $(, $($tail:tt)*)? }) => (
+        $named.push(stringify!($argname));
+        generate_command_options!($named, $flags, { $($($tail)*)? });
+    );
+    ($named:ident, $flags:ident, { $argname:ident: Flag $(, $($tail:tt)*)? }) => (
+        $flags.push(stringify!($argname));
+        generate_command_options!($named, $flags, { $($($tail)*)? });
+    );
+    ($named:ident, $flags:ident, { $argname:ident: $type:ty $(= $attrs:tt)? $(, $($tail:tt)*)? }) => (
+        generate_command_options!($named, $flags, { $($($tail)*)? });
+    );
+);
+
+#[macro_export]
+macro_rules! generate_command_subcommands(
+    ($subcommands:ident, {}) => ();
+    ($subcommands:ident, { $argname:ident: Command = { children: $subs:tt $(, $($attrs:tt)*)? } $(, $($tail:tt)*)? }) => (
+        generate_subcommand_list!($subcommands, $subs);
+        generate_command_subcommands!($subcommands, { $($($tail)*)? });
+    );
+    ($subcommands:ident, { $argname:ident: $type:ty $(= $attrs:tt)? $(, $($tail:tt)*)? }) => (
+        generate_command_subcommands!($subcommands, { $($($tail)*)? });
+    );
+);
+
+#[macro_export]
+macro_rules! generate_subcommand_list(
+    ($subcommands:ident, {}) => ();
+    ($subcommands:ident, { $subname:tt: $sub:ident $(, $($tail:tt)*)? }) => (
+        $subcommands.push(($subname, $sub::new as fn() -> CommandParser));
+        generate_subcommand_list!($subcommands, { $($($tail)*)? });
+    );
+);
+
 #[macro_export]
 macro_rules! generate_sub_autocompletion(
     ($completion:ident, {}) => ();
@@ -469,8 +623,18 @@ macro_rules! command_def (
             }
 
             pub fn new() -> CommandParser {
+                #[allow(unused_mut)]
                 let mut autocompletions = Vec::<Option<Box<dyn Fn(&mut Aparte, Command) -> Vec<String>>>>::new();
+                #[allow(unused_mut)]
+                let mut named = Vec::new();
+                #[allow(unused_mut)]
+                let mut flags = Vec::new();
+                #[allow(unused_mut)]
+                let mut subcommands = Vec::<(&'static str, fn() -> CommandParser)>::new();
+
                 generate_command_autocompletions!(autocompletions, $args);
+                generate_command_options!(named, flags, $args);
+                generate_command_subcommands!(subcommands, $args);
 
                 CommandParser {
                     name: stringify!($name),
@@ -478,6 +642,9 @@ macro_rules! command_def (
                     parse,
                     exec,
                     autocompletions: autocompletions,
+                    named,
+                    flags,
+                    subcommands,
                 }
             }
         }
@@ -511,8 +678,16 @@ macro_rules! command_def (
             pub fn new() -> CommandParser {
                 #[allow(unused_mut)]
                 let mut autocompletions = Vec::<Option<Box<dyn Fn(&mut Aparte, Command) -> Vec<String>>>>::new();
+                #[allow(unused_mut)]
+                let mut named = Vec::new();
+                #[allow(unused_mut)]
+                let mut flags = Vec::new();
+                #[allow(unused_mut)]
+                let mut subcommands = Vec::<(&'static str, fn() -> CommandParser)>::new();
 
                 generate_command_autocompletions!(autocompletions, $args);
+                generate_command_options!(named, flags, $args);
+                generate_command_subcommands!(subcommands, $args);
 
                 CommandParser {
                     name: stringify!($name),
@@ -520,6 +695,9 @@ macro_rules! command_def (
                     parse,
                     exec,
                     autocompletions: autocompletions,
+                    named,
+                    flags,
+                    subcommands,
                 }
             }
         }
@@ -529,6 +707,7 @@ macro_rules! command_def (
 #[cfg(test)]
 mod tests_command_macro {
     use super::*;
+    use std::collections::HashMap;
     use std::str::FromStr;
 
     command_def!(no_args, "help", {}, |_aparte, _command| { Ok(()) });
@@ -584,6 +763,20 @@ mod tests_command_macro {
         assert_eq!(cmd.autocompletions.len(), 2);
     }
 
+    command_def!(named_args, "help", {
+        _flag: Flag,
+        _named: Named<String>,
+        _first_arg: String
+    }, |_aparte, _command| { Ok(()) });
+
+    #[test]
+    fn test_command_with_named_args() {
+        let cmd = named_args::new();
+
+        assert_eq!(cmd.name, "named_args");
+        assert_eq!(cmd.autocompletions.len(), 1);
+    }
+
     command_def!(two_args_completion, "help", {
         _first_arg: String = {
             completion: (|_aparte, _command| {
@@ -601,6 +794,83 @@ mod tests_command_macro {
         assert_eq!(cmd.help, "help");
         assert_eq!(cmd.autocompletions.len(), 2);
     }
+
+    command_def!(
+        described,
+        r#"/described arg
+
+    arg     Some argument
+
+Description:
+    Do something.
+    With details.
+
+Examples:
+    /described foo"#,
+        { _arg: String },
+        |_aparte, _command| { Ok(()) }
+    );
+
+    command_def!(with_options, "help", {
+        _password: Named<String>,
+        _first_arg: String,
+        _flag: Flag,
+        _second_arg: Option<String>
+    }, |_aparte, _command| { Ok(()) });
+
+    command_def!(with_children, "help", {
+        _action: Command = {
+            children: {
+                "options": with_options,
+                "none": no_args,
+            }
+        },
+    });
+
+    #[test]
+    fn test_completion_target() {
+        let args = |line: &str| -> Vec<String> { line.split(' ').map(String::from).collect() };
+        let cmd = with_options::new();
+
+        assert_eq!(cmd.named, vec!["_password"]);
+        assert_eq!(cmd.flags, vec!["_flag"]);
+        assert_eq!(
+            cmd.completion_target(&args("with_options "), 1),
+            CompletionTarget::Positional(0)
+        );
+        assert_eq!(
+            cmd.completion_target(&args("with_options --_password secret foo "), 4),
+            CompletionTarget::Positional(1)
+        );
+        assert_eq!(
+            cmd.completion_target(&args("with_options _password=secret --_flag foo "), 4),
+            CompletionTarget::Positional(1)
+        );
+        assert_eq!(
+            cmd.completion_target(&args("with_options --_password "), 2),
+            CompletionTarget::Nothing
+        );
+        assert_eq!(
+            cmd.completion_target(&args("with_options foo --_p"), 2),
+            CompletionTarget::Options
+        );
+
+        let cmd = with_children::new();
+        assert_eq!(
+            cmd.completion_target(&args("with_children opt"), 1),
+            CompletionTarget::Positional(0)
+        );
+        assert_eq!(
+            cmd.completion_target(&args("with_children options foo "), 3),
+            CompletionTarget::Subcommand(1, "options")
+        );
+    }
+
+    #[test]
+    fn test_command_summary() {
+        assert_eq!(described::new().summary(), "Do something.");
+        assert_eq!(no_args::new().summary(), "help");
+    }
 }
 
 #[cfg(test)]
@@ -1,12 +1,601 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use regex::Regex;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
 use crate::account::ConnectionInfo;
+use crate::theme::ThemeConfig;
+
+/// How the roster displays contacts that are offline
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OfflineContacts {
+    Show,
+    Hide,
+    Group,
+}
+
+impl Default for OfflineContacts {
+    fn default() -> Self {
+        OfflineContacts::Show
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RosterConfig {
+    #[serde(default)]
+    pub offline: OfflineContacts,
+    /// Width of the side list, see `SideWidth`
+    pub width: Option<String>,
+}
+
+/// Width of the side list of windows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SideWidth {
+    /// Number of columns
+    Absolute(u16),
+    /// Percentage of the terminal width
+    Relative(u16),
+}
+
+impl SideWidth {
+    /// Number of columns given the terminal width
+    pub fn columns(&self, width: u16) -> u16 {
+        match self {
+            SideWidth::Absolute(columns) => *columns,
+            SideWidth::Relative(percent) => (u32::from(width) * u32::from(*percent) / 100) as u16,
+        }
+    }
+}
+
+impl FromStr for SideWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid roster width {}", s);
+        match s.trim().strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<u16>() {
+                Ok(percent) if percent <= 100 => Ok(SideWidth::Relative(percent)),
+                _ => Err(invalid()),
+            },
+            None => s
+                .trim()
+                .parse()
+                .map(SideWidth::Absolute)
+                .map_err(|_| invalid()),
+        }
+    }
+}
+
+/// What new messages of a window trigger
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyLevel {
+    /// Nothing
+    None,
+    /// The window is marked as unread
+    Activity,
+    /// The window is marked as unread, messages addressed to us ring the bell
+    Highlight,
+    /// The window is marked as unread and every message rings the bell
+    All,
+}
+
+impl Default for NotifyLevel {
+    fn default() -> Self {
+        NotifyLevel::All
+    }
+}
+
+impl FromStr for NotifyLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(NotifyLevel::None),
+            "activity" => Ok(NotifyLevel::Activity),
+            "highlight" => Ok(NotifyLevel::Highlight),
+            "all" => Ok(NotifyLevel::All),
+            _ => Err(format!("Unknown notification level {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for NotifyLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotifyLevel::None => write!(f, "none"),
+            NotifyLevel::Activity => write!(f, "activity"),
+            NotifyLevel::Highlight => write!(f, "highlight"),
+            NotifyLevel::All => write!(f, "all"),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Deserialize)]
+pub struct NotifyConfig {
+    #[serde(default)]
+    pub default: NotifyLevel,
+    /// Level of each window, by window name
+    #[serde(default)]
+    pub windows: HashMap<String, NotifyLevel>,
+    /// Show desktop notifications for messages addressed to us
+    #[serde(default)]
+    pub desktop: bool,
+    /// Include the message in desktop notifications
+    #[serde(default = "NotifyConfig::default_preview")]
+    pub preview: bool,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            default: NotifyLevel::default(),
+            windows: HashMap::new(),
+            desktop: false,
+            preview: Self::default_preview(),
+        }
+    }
+}
+
+impl NotifyConfig {
+    fn default_preview() -> bool {
+        true
+    }
+
+    pub fn get(&self, window: &str) -> NotifyLevel {
+        self.windows.get(window).copied().unwrap_or(self.default)
+    }
+}
+
+/// Words and regular expressions highlighting channel messages, besides our nick
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HighlightRules {
+    /// Matched as whole words, ignoring case
+    #[serde(default)]
+    pub words: Vec<String>,
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+impl HighlightRules {
+    fn compile(&self) -> Result<Vec<Regex>, String> {
+        let words = self
+            .words
+            .iter()
+            .map(|word| format!(r"(?i)(^|\W){}($|\W)", regex::escape(word)));
+        words
+            .chain(self.patterns.iter().cloned())
+            .map(|pattern| {
+                Regex::new(&pattern)
+                    .map_err(|err| format!("Invalid highlight pattern {}: {}", pattern, err))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HighlightConfig {
+    #[serde(flatten)]
+    pub default: HighlightRules,
+    /// Rules of each window, by window name, replacing the default ones
+    #[serde(default)]
+    pub windows: HashMap<String, HighlightRules>,
+}
+
+impl HighlightConfig {
+    pub fn compile(&self) -> Result<Highlights, String> {
+        let mut windows = HashMap::new();
+        for (window, rules) in &self.windows {
+            windows.insert(window.clone(), rules.compile()?);
+        }
+        Ok(Highlights {
+            default: self.default.compile()?,
+            windows,
+        })
+    }
+}
+
+/// Compiled `HighlightConfig`
+#[derive(Debug, Clone, Default)]
+pub struct Highlights {
+    default: Vec<Regex>,
+    windows: HashMap<String, Vec<Regex>>,
+}
+
+impl Highlights {
+    /// Whether a message of a window matches one of its highlight rules
+    pub fn matches(&self, window: &str, body: &str) -> bool {
+        self.windows
+            .get(window)
+            .unwrap_or(&self.default)
+            .iter()
+            .any(|regex| regex.is_match(body))
+    }
+}
+
+/// Maximum size of a window history, the oldest messages are dropped beyond it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct ScrollbackLimit {
+    /// Maximum number of messages
+    pub messages: Option<usize>,
+    /// Maximum size of the messages, in bytes
+    pub bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScrollbackConfig {
+    #[serde(default = "ScrollbackConfig::default_messages")]
+    pub messages: Option<usize>,
+    #[serde(default)]
+    pub bytes: Option<usize>,
+    /// Limits of each window, by window name
+    #[serde(default)]
+    pub windows: HashMap<String, ScrollbackLimit>,
+}
+
+impl Default for ScrollbackConfig {
+    fn default() -> Self {
+        Self {
+            messages: Self::default_messages(),
+            bytes: None,
+            windows: HashMap::new(),
+        }
+    }
+}
+
+impl ScrollbackConfig {
+    fn default_messages() -> Option<usize> {
+        Some(10000)
+    }
+
+    pub fn get(&self, window: &str) -> ScrollbackLimit {
+        match self.windows.get(window) {
+            Some(limit) => *limit,
+            None => ScrollbackLimit {
+                messages: self.messages,
+                bytes: self.bytes,
+            },
+        }
+    }
+}
+
+/// Inline preview of image attachments
+#[derive(Debug, Clone, Deserialize)]
+pub struct PreviewConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Maximum size of a downloaded image, in bytes
+    #[serde(default = "PreviewConfig::default_max_size")]
+    pub max_size: u64,
+    /// Height of the preview, in lines
+    #[serde(default = "PreviewConfig::default_rows")]
+    pub rows: u16,
+    /// Graphics protocol of the terminal (kitty, iterm or sixel), detected when unset
+    pub protocol: Option<String>,
+}
+
+impl PreviewConfig {
+    fn default_max_size() -> u64 {
+        2 * 1024 * 1024
+    }
+
+    fn default_rows() -> u16 {
+        8
+    }
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_size: Self::default_max_size(),
+            rows: Self::default_rows(),
+            protocol: None,
+        }
+    }
+}
+
+/// Input prompt formats per window type, with `{window}`, `{account}` and `{nick}` components
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PromptConfig {
+    pub console: Option<String>,
+    pub chat: Option<String>,
+    pub channel: Option<String>,
+}
+
+/// Rule run on incoming messages matching a pattern, see `TriggerAction`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TriggerConfig {
+    pub pattern: String,
+    pub action: String,
+    pub window: Option<String>,
+}
+
+/// Limit of outgoing messages, to not trip flood protections of servers
+#[derive(Debug, Clone, Deserialize)]
+pub struct SendConfig {
+    /// Messages sent per second once the burst is exhausted, 0 for no limit
+    #[serde(default = "SendConfig::default_rate")]
+    pub rate: f64,
+    /// Messages sent at once
+    #[serde(default = "SendConfig::default_burst")]
+    pub burst: u32,
+}
+
+impl SendConfig {
+    fn default_rate() -> f64 {
+        2.0
+    }
+
+    fn default_burst() -> u32 {
+        5
+    }
+}
+
+impl Default for SendConfig {
+    fn default() -> Self {
+        Self {
+            rate: Self::default_rate(),
+            burst: Self::default_burst(),
+        }
+    }
+}
+
+/// Where incoming events are written as JSON, one per line
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventOutput {
+    /// Standard output, only when headless
+    Stdout,
+    /// Unix socket any number of programs can read from
+    Socket,
+}
+
+/// Interfaces to control the client from other programs
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RemoteConfig {
+    /// Expose the client on the session bus
+    #[serde(default)]
+    pub dbus: bool,
+    /// Run commands written to a Unix socket
+    #[serde(default)]
+    pub socket: bool,
+    /// Write incoming messages, presences and invitations as JSON
+    pub events: Option<EventOutput>,
+}
+
+/// What the client advertises to other entities asking what it is (XEP-0030)
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscoConfig {
+    /// Name of the client identity
+    #[serde(default = "DiscoConfig::default_name")]
+    pub name: String,
+    /// Type of the client identity, like console, pc or phone
+    #[serde(default = "DiscoConfig::default_type", rename = "type")]
+    pub type_: String,
+    /// Language of the identity name
+    pub lang: Option<String>,
+    /// Advertise the client version and operating system (XEP-0232)
+    #[serde(default = "DiscoConfig::default_version")]
+    pub version: bool,
+    /// Features not advertised even though the plugin supporting them is enabled
+    #[serde(default)]
+    pub hidden_features: Vec<String>,
+}
+
+impl DiscoConfig {
+    fn default_name() -> String {
+        String::from("Aparté")
+    }
+
+    fn default_type() -> String {
+        String::from("console")
+    }
+
+    fn default_version() -> bool {
+        true
+    }
+}
+
+impl Default for DiscoConfig {
+    fn default() -> Self {
+        Self {
+            name: Self::default_name(),
+            type_: Self::default_type(),
+            lang: None,
+            version: Self::default_version(),
+            hidden_features: Vec::new(),
+        }
+    }
+}
+
+/// Invitations to channels
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct InvitationsConfig {
+    /// Addresses and domains whose invitations are accepted without asking, those of the
+    /// channels themselves for invitations sent through them
+    #[serde(default)]
+    pub auto_accept: Vec<String>,
+}
+
+/// Debug log file
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LogConfig {
+    /// Default level followed by per module levels, like `info,aparte::mods::mam=debug`
+    pub level: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct Config {
+    #[serde(default)]
     pub accounts: HashMap<String, ConnectionInfo>,
+    #[serde(default)]
+    pub theme: ThemeConfig,
+    pub time_format: Option<String>,
+    pub status_format: Option<String>,
+    #[serde(default)]
+    pub roster: RosterConfig,
+    #[serde(default)]
+    pub preview: PreviewConfig,
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+    #[serde(default)]
+    pub scrollback: ScrollbackConfig,
+    #[serde(default)]
+    pub prompt: PromptConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    /// Commands run on startup, before those of the rc file
+    #[serde(default)]
+    pub autoexec: Vec<String>,
+    #[serde(default)]
+    pub triggers: Vec<TriggerConfig>,
+    #[serde(default)]
+    pub remote: RemoteConfig,
+    #[serde(default)]
+    pub send: SendConfig,
+    #[serde(default)]
+    pub disco: DiscoConfig,
+    #[serde(default)]
+    pub invitations: InvitationsConfig,
+}
+
+/// Commands of an rc file, one per line, empty lines and lines starting with # are ignored
+pub fn parse_rc(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roster_config() {
+        let config: Config = toml::from_str("[roster]\noffline = \"group\"").unwrap();
+        assert_eq!(config.roster.offline, OfflineContacts::Group);
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.roster.offline, OfflineContacts::Show);
+        assert_eq!(config.roster.width, None);
+    }
+
+    #[test]
+    fn test_parse_rc() {
+        let rc = "# Startup\n/connect me@example.org\n\n  /join room@conference.example.org  \n";
+        assert_eq!(
+            parse_rc(rc),
+            vec![
+                "/connect me@example.org",
+                "/join room@conference.example.org"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_side_width() {
+        assert_eq!("25".parse(), Ok(SideWidth::Absolute(25)));
+        assert_eq!("30%".parse(), Ok(SideWidth::Relative(30)));
+        assert!("150%".parse::<SideWidth>().is_err());
+        assert!("wide".parse::<SideWidth>().is_err());
+
+        assert_eq!(SideWidth::Absolute(25).columns(80), 25);
+        assert_eq!(SideWidth::Relative(30).columns(80), 24);
+    }
+
+    #[test]
+    fn test_preview_config() {
+        let config: Config = toml::from_str("[preview]\nenabled = true").unwrap();
+        assert!(config.preview.enabled);
+        assert_eq!(config.preview.rows, 8);
+        assert_eq!(config.preview.max_size, 2 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_notify_config() {
+        let config: Config = toml::from_str(
+            "[notify]\ndefault = \"highlight\"\n[notify.windows]\n\"room@conference.example.org\" = \"none\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.notify.get("room@conference.example.org"),
+            NotifyLevel::None
+        );
+        assert_eq!(
+            config.notify.get("juliet@example.org"),
+            NotifyLevel::Highlight
+        );
+        assert!(!config.notify.desktop);
+        assert!(config.notify.preview);
+    }
+
+    #[test]
+    fn test_highlight_config() {
+        let config: Config = toml::from_str(
+            "[highlight]\nwords = [\"aparte\", \"c++\"]\npatterns = [\"^ping\"]\n[highlight.windows.\"room@conference.example.org\"]\nwords = [\"release\"]",
+        )
+        .unwrap();
+        let highlights = config.highlight.compile().unwrap();
+        assert!(highlights.matches("juliet@example.org", "Trying Aparte today"));
+        assert!(highlights.matches("juliet@example.org", "learning C++"));
+        assert!(highlights.matches("juliet@example.org", "ping?"));
+        assert!(!highlights.matches("juliet@example.org", "apartement"));
+        assert!(highlights.matches("room@conference.example.org", "Release is out"));
+        assert!(!highlights.matches("room@conference.example.org", "aparte"));
+
+        let config: Config = toml::from_str("[highlight]\npatterns = [\"(\"]").unwrap();
+        assert!(config.highlight.compile().is_err());
+    }
+
+    #[test]
+    fn test_scrollback_config() {
+        let config: Config = toml::from_str(
+            "[scrollback]\nbytes = 1000000\n[scrollback.windows.console]\nmessages = 100",
+        )
+        .unwrap();
+        assert_eq!(
+            config.scrollback.get("console"),
+            ScrollbackLimit {
+                messages: Some(100),
+                bytes: None,
+            }
+        );
+        assert_eq!(
+            config.scrollback.get("juliet@example.org"),
+            ScrollbackLimit {
+                messages: Some(10000),
+                bytes: Some(1000000),
+            }
+        );
+    }
+
+    #[test]
+    fn test_disco_config() {
+        let config: Config = toml::from_str(
+            "[disco]\ntype = \"pc\"\nversion = false\nhidden_features = [\"http://jabber.org/protocol/chatstates\"]",
+        )
+        .unwrap();
+        assert_eq!(config.disco.name, "Aparté");
+        assert_eq!(config.disco.type_, "pc");
+        assert!(!config.disco.version);
+        assert_eq!(
+            config.disco.hidden_features,
+            vec!["http://jabber.org/protocol/chatstates"]
+        );
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.disco.type_, "console");
+        assert!(config.disco.version);
+    }
 }
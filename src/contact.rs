@@ -2,7 +2,9 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use std::cmp;
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use xmpp_parsers::presence;
 use xmpp_parsers::roster::Subscription;
 use xmpp_parsers::{BareJid, Element};
 
@@ -16,6 +18,43 @@ pub enum Presence {
     Xa,
 }
 
+impl From<&presence::Presence> for Presence {
+    fn from(presence: &presence::Presence) -> Self {
+        match (&presence.type_, &presence.show) {
+            (presence::Type::Unavailable, _) => Presence::Unavailable,
+            (_, Some(presence::Show::Away)) => Presence::Away,
+            (_, Some(presence::Show::Chat)) => Presence::Chat,
+            (_, Some(presence::Show::Dnd)) => Presence::Dnd,
+            (_, Some(presence::Show::Xa)) => Presence::Xa,
+            (_, None) => Presence::Available,
+        }
+    }
+}
+
+impl Presence {
+    /// Order in which presences are prefered, most available first
+    pub fn rank(&self) -> u8 {
+        match self {
+            Presence::Chat => 0,
+            Presence::Available => 1,
+            Presence::Away => 2,
+            Presence::Xa => 3,
+            Presence::Dnd => 4,
+            Presence::Unavailable => 5,
+        }
+    }
+}
+
+/// A connected client of a contact
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resource {
+    pub presence: Presence,
+    pub priority: i8,
+    pub status: Option<String>,
+    /// Client software, as advertised by its entity capabilities node
+    pub client: Option<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Group(pub String);
 
@@ -38,8 +77,44 @@ pub struct Contact {
     pub jid: BareJid,
     pub name: Option<String>,
     pub subscription: Subscription,
+    /// Best presence among the resources
     pub presence: Presence,
     pub groups: Vec<Group>,
+    /// Connected resources, by name
+    pub resources: HashMap<String, Resource>,
+    /// Hash of the avatar advertised in presences (XEP-0153)
+    pub avatar: Option<String>,
+}
+
+impl Contact {
+    /// Track the presence of a resource, unavailable resources being forgotten
+    pub fn update_resource(&mut self, name: &str, resource: Resource) {
+        match resource.presence {
+            Presence::Unavailable => self.resources.remove(name),
+            _ => self.resources.insert(name.to_string(), resource),
+        };
+        self.presence = self
+            .best_resource()
+            .map_or(Presence::Unavailable, |(_, resource)| {
+                resource.presence.clone()
+            });
+    }
+
+    /// Forget all resources, when the contact itself becomes unavailable
+    pub fn clear_resources(&mut self) {
+        self.resources.clear();
+        self.presence = Presence::Unavailable;
+    }
+
+    /// Resource with the highest priority, the most available one between equals
+    pub fn best_resource(&self) -> Option<(&String, &Resource)> {
+        self.resources.iter().min_by(|(a_name, a), (b_name, b)| {
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| a.presence.rank().cmp(&b.presence.rank()))
+                .then_with(|| a_name.cmp(b_name))
+        })
+    }
 }
 
 impl Hash for Contact {
@@ -106,3 +181,48 @@ impl PartialOrd for Bookmark {
         Some(self.cmp(other))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn resource(presence: Presence, priority: i8) -> Resource {
+        Resource {
+            presence,
+            priority,
+            status: None,
+            client: None,
+        }
+    }
+
+    #[test]
+    fn test_best_resource() {
+        // Given
+        let mut contact = Contact {
+            jid: BareJid::from_str("juliet@capulet.lit").unwrap(),
+            name: None,
+            subscription: Subscription::Both,
+            presence: Presence::Unavailable,
+            groups: Vec::new(),
+            resources: HashMap::new(),
+            avatar: None,
+        };
+
+        // When
+        contact.update_resource("balcony", resource(Presence::Away, 5));
+        contact.update_resource("chamber", resource(Presence::Dnd, 5));
+        contact.update_resource("phone", resource(Presence::Chat, 0));
+
+        // Then
+        assert_eq!(contact.presence, Presence::Away);
+        assert_eq!(contact.best_resource().unwrap().0, "balcony");
+
+        contact.update_resource("balcony", resource(Presence::Unavailable, 0));
+        assert_eq!(contact.presence, Presence::Dnd);
+        assert_eq!(contact.resources.len(), 2);
+
+        contact.clear_resources();
+        assert_eq!(contact.presence, Presence::Unavailable);
+    }
+}
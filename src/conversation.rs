@@ -7,6 +7,7 @@ use std::hash::{Hash, Hasher};
 use xmpp_parsers::BareJid;
 
 use crate::account::Account;
+use crate::contact::Presence;
 
 #[derive(Hash, Eq, PartialEq, Clone, Debug, Copy)]
 pub enum Affiliation {
@@ -31,6 +32,8 @@ pub struct Occupant {
     pub jid: Option<BareJid>,
     pub affiliation: Affiliation,
     pub role: Role,
+    pub presence: Presence,
+    pub status: Option<String>,
 }
 
 impl Ord for Occupant {
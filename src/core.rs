@@ -3,45 +3,51 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use chrono::{DateTime, FixedOffset, Local as LocalTz};
 use core::fmt::Debug;
-use futures::sink::SinkExt;
+use flexi_logger::{LogSpecification, ReconfigurationHandle};
 use futures::stream::StreamExt;
 use rand::{self, Rng};
 use std::any::TypeId;
 use std::cell::{Ref, RefCell, RefMut};
 use std::collections::{HashMap, VecDeque};
 use std::convert::TryFrom;
+use std::env;
 use std::fmt;
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
+use std::future::Future;
 use std::io::Read;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::str::FromStr;
-use termion::event::Key;
+use std::time::{Duration, Instant};
+use termion::event::{Key, MouseEvent};
 use tokio::runtime::Runtime as TokioRuntime;
 use tokio::signal::unix;
 use tokio::sync::mpsc;
 use tokio::task;
-use tokio_xmpp::{
-    AsyncClient as TokioXmppClient, Error as XmppError, Event as XmppEvent, Packet as XmppPacket,
-};
 use uuid::Uuid;
 use xmpp_parsers;
+use xmpp_parsers::chatstates::ChatState;
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::iq::{Iq, IqType};
 use xmpp_parsers::message::Message as XmppParsersMessage;
 use xmpp_parsers::muc::Muc;
 use xmpp_parsers::presence::{Presence, Show as PresenceShow, Type as PresenceType};
 use xmpp_parsers::pubsub::event::PubSubEvent;
-use xmpp_parsers::{iq, presence, BareJid, Element, FullJid, Jid};
+use xmpp_parsers::{iq, BareJid, Element, FullJid, Jid};
 
-use crate::account::{Account, ConnectionInfo};
+use crate::account::{self, Account, ConnectionInfo};
+use crate::client::{Client, Error as ClientError, TrustStore};
 use crate::color;
 use crate::command::{Command, CommandParser};
-use crate::config::Config;
+use crate::config::{self, Config, NotifyLevel};
 use crate::conversation::{Channel, Conversation};
 use crate::cursor::Cursor;
-use crate::message::Message;
+use crate::error::Error;
+use crate::message::{Message, XmppMessageType};
 use crate::mods;
+use crate::ratelimit::RateLimiter;
+use crate::settings::{self, Settings};
+use crate::terminus::{Graphic, Orientation};
 use crate::{
     command_def, generate_arg_autocompletion, generate_command_autocompletions, generate_help,
     parse_command_args,
@@ -55,6 +61,11 @@ const WELCOME: &str = r#"
 ▘ ▘▝▀▘ ▘▝▀ ▝▀ ▘▝ ▘▝▀▘  ▀ ▝▀  ▘ ▘▌  ▝▀▘▘   ▀ ▝▀▘
 "#;
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+/// Values of the show argument of /presence
+const PRESENCE_SHOWS: [&str; 6] = ["available", "chat", "away", "xa", "dnd", "unavailable"];
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Time given to connections to send the last stanzas before quitting
+const QUIT_DELAY: Duration = Duration::from_millis(500);
 
 #[derive(Debug, Clone)]
 pub enum Event {
@@ -63,7 +74,16 @@ pub enum Event {
     Connected(Account, Jid),
     Disconnected(Account, String),
     AuthError(Account, String),
+    UntrustedCertificate {
+        account: Account,
+        connection_info: ConnectionInfo,
+        password: Password<String>,
+        fingerprint: String,
+        reason: String,
+    },
     Stanza(Account, Element),
+    /// Stanza sent to the server, only while they are echoed, see `Aparte::echo_stanzas`
+    SentStanza(Account, Element),
     RawMessage(Account, XmppParsersMessage, Option<Delay>),
     RawCommand(Option<Account>, String, String),
     Command(Command),
@@ -76,6 +96,7 @@ pub enum Event {
     Join {
         account: FullJid,
         channel: Jid,
+        password: Option<String>,
         user_request: bool,
     },
     Joined {
@@ -83,18 +104,46 @@ pub enum Event {
         channel: FullJid,
         user_request: bool,
     },
-    Leave(Channel),
+    /// Leave a channel, with a message for its occupants
+    Leave(Channel, Option<String>),
     Iq(Account, iq::Iq),
     Disco(Account),
     PubSub(Account, PubSubEvent),
-    Presence(Account, presence::Presence),
+    Presence(Account, Presence),
     ReadPassword(Command),
     Win(String),
     Close(String),
+    Search(Option<String>),
+    Split(Orientation),
+    Unsplit,
+    ToggleRoster,
+    RosterFilter(Option<String>),
+    OpenUrl(Option<usize>),
+    CopyUrl(Option<usize>),
+    /// Load a Rhai script from a file
+    LoadScript(PathBuf),
+    /// Inline preview of the attachment of a message
+    Preview {
+        id: String,
+        graphic: Graphic,
+    },
+    /// A message written while disconnected waits to be sent, or has been sent
+    Pending {
+        id: String,
+        pending: bool,
+    },
+    /// A sent message bounced with the given error, or is sent again (None)
+    MessageError {
+        account: Account,
+        id: String,
+        error: Option<String>,
+    },
     Contact(Account, contact::Contact),
     ContactUpdate(Account, contact::Contact),
     Bookmark(contact::Bookmark),
     DeletedBookmark(BareJid),
+    /// Bookmarks of an account retrieved, or known to be unavailable
+    Bookmarks(Account),
     Occupant {
         account: Account,
         conversation: BareJid,
@@ -113,17 +162,70 @@ pub enum Event {
     },
     Quit,
     Key(Key),
+    /// Text pasted in the terminal
+    Paste(String),
+    Mouse(MouseEvent),
     AutoComplete {
         account: Option<Account>,
         context: String,
         raw_buf: String,
         cursor: Cursor,
+        /// Cycle through candidates in reverse order
+        backward: bool,
     },
     ResetCompletion,
     Completed(String, Cursor),
+    /// Completion candidates with the index of the one inserted in the input
+    CompletionCandidates(Vec<String>, usize),
     ChangeWindow(String),
     Notification(String),
     Subject(Account, Jid, HashMap<String, String>),
+    ChatState {
+        account: Account,
+        from: Jid,
+        type_: XmppMessageType,
+        state: ChatState,
+    },
+    /// Sent every minute to refresh displayed clocks
+    Tick,
+    /// Move the current window to the given position, starting at 1
+    MoveWindow(usize),
+    SwapWindows(usize, usize),
+    /// Name displayed for a window, None displays the window name again
+    WindowAlias(String, Option<String>),
+    NotifyLevel(String, NotifyLevel),
+    /// Enable, disable or toggle (None) do not disturb mode
+    DoNotDisturb(Option<bool>),
+    /// Silence a window until the given date, or until unmuted
+    Mute(String, Option<DateTime<FixedOffset>>),
+    Unmute(String),
+    /// Empty the history displayed in a window
+    ClearBuffer(String),
+    /// Message shown in the given window, like the answer to a command run from it
+    Info(String, Message),
+    /// Messages listed in a temporary window, created if needed, replacing its previous content
+    Results(String, Vec<Message>),
+    /// Rooms of a conference service are about to be listed
+    RoomDirectory {
+        account: Account,
+        service: BareJid,
+    },
+    /// Room listed by /rooms, or completed with its details
+    DirectoryRoom(Account, mods::rooms::Room),
+    /// Latency of the connection of an account, measured by pinging its server
+    Latency(Account, mods::ping::Latency),
+    /// Whether an account now appears offline to its contacts
+    Invisible(Account, bool),
+    /// Invitation to join a channel, received while connected
+    Invitation(Account, mods::invitations::Invitation),
+    /// What was left unread in a conversation when quitting, once its window is open again
+    ReadState(mods::archive::ReadState),
+    /// A setting has been changed with /set
+    Setting(String, toml::Value),
+    /// Send text to the conversation of a window, as if typed in its input
+    SendText(String, String),
+    /// Change the presence of every connected account
+    SetPresence(Option<PresenceShow>, Option<String>),
 }
 
 pub enum Mod {
@@ -137,6 +239,26 @@ pub enum Mod {
     UI(mods::ui::UIMod),
     Mam(mods::mam::MamMod),
     Correction(mods::correction::CorrectionMod),
+    ChatStates(mods::chatstates::ChatStatesMod),
+    Preview(mods::preview::PreviewMod),
+    Scripting(mods::scripting::ScriptingMod),
+    Plugins(mods::plugins::PluginsMod),
+    Archive(mods::archive::ArchiveMod),
+    Triggers(mods::triggers::TriggersMod),
+    DBus(mods::dbus::DBusMod),
+    Socket(mods::socket::SocketMod),
+    Outbox(mods::outbox::OutboxMod),
+    Whois(mods::whois::WhoisMod),
+    Affiliations(mods::affiliations::AffiliationsMod),
+    Ignore(mods::ignore::IgnoreMod),
+    Rooms(mods::rooms::RoomsMod),
+    Captcha(mods::captcha::CaptchaMod),
+    Ping(mods::ping::PingMod),
+    XmlConsole(mods::xml_console::XmlConsoleMod),
+    Invisible(mods::invisible::InvisibleMod),
+    Invitations(mods::invitations::InvitationsMod),
+    Session(mods::session::SessionMod),
+    Events(mods::events::EventsMod),
 }
 
 macro_rules! from_mod {
@@ -171,10 +293,37 @@ from_mod!(UI, mods::ui::UIMod);
 from_mod!(Mam, mods::mam::MamMod);
 from_mod!(Messages, mods::messages::MessagesMod);
 from_mod!(Correction, mods::correction::CorrectionMod);
+from_mod!(ChatStates, mods::chatstates::ChatStatesMod);
+from_mod!(Preview, mods::preview::PreviewMod);
+from_mod!(Scripting, mods::scripting::ScriptingMod);
+from_mod!(Plugins, mods::plugins::PluginsMod);
+from_mod!(Archive, mods::archive::ArchiveMod);
+from_mod!(Triggers, mods::triggers::TriggersMod);
+from_mod!(DBus, mods::dbus::DBusMod);
+from_mod!(Socket, mods::socket::SocketMod);
+from_mod!(Outbox, mods::outbox::OutboxMod);
+from_mod!(Whois, mods::whois::WhoisMod);
+from_mod!(Affiliations, mods::affiliations::AffiliationsMod);
+from_mod!(Ignore, mods::ignore::IgnoreMod);
+from_mod!(Rooms, mods::rooms::RoomsMod);
+from_mod!(Captcha, mods::captcha::CaptchaMod);
+from_mod!(Ping, mods::ping::PingMod);
+from_mod!(XmlConsole, mods::xml_console::XmlConsoleMod);
+from_mod!(Invisible, mods::invisible::InvisibleMod);
+from_mod!(Invitations, mods::invitations::InvitationsMod);
+from_mod!(Session, mods::session::SessionMod);
+from_mod!(Events, mods::events::EventsMod);
 
 pub trait ModTrait: fmt::Display {
     fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()>;
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event);
+    /// Order in which mods receive events, higher first
+    ///
+    /// A mod can stop an event from reaching the following ones with `Aparte::consume_event`.
+    fn priority(&self) -> i32 {
+        0
+    }
+
     /// Return weither this message can be handled
     /// 0 means no, 1 mean definitely yes
     fn can_handle_xmpp_message(
@@ -211,6 +360,26 @@ impl ModTrait for Mod {
             Mod::Mam(r#mod) => r#mod.init(aparte),
             Mod::Messages(r#mod) => r#mod.init(aparte),
             Mod::Correction(r#mod) => r#mod.init(aparte),
+            Mod::ChatStates(r#mod) => r#mod.init(aparte),
+            Mod::Preview(r#mod) => r#mod.init(aparte),
+            Mod::Scripting(r#mod) => r#mod.init(aparte),
+            Mod::Plugins(r#mod) => r#mod.init(aparte),
+            Mod::Archive(r#mod) => r#mod.init(aparte),
+            Mod::Triggers(r#mod) => r#mod.init(aparte),
+            Mod::DBus(r#mod) => r#mod.init(aparte),
+            Mod::Socket(r#mod) => r#mod.init(aparte),
+            Mod::Outbox(r#mod) => r#mod.init(aparte),
+            Mod::Whois(r#mod) => r#mod.init(aparte),
+            Mod::Affiliations(r#mod) => r#mod.init(aparte),
+            Mod::Ignore(r#mod) => r#mod.init(aparte),
+            Mod::Rooms(r#mod) => r#mod.init(aparte),
+            Mod::Captcha(r#mod) => r#mod.init(aparte),
+            Mod::Ping(r#mod) => r#mod.init(aparte),
+            Mod::XmlConsole(r#mod) => r#mod.init(aparte),
+            Mod::Invisible(r#mod) => r#mod.init(aparte),
+            Mod::Invitations(r#mod) => r#mod.init(aparte),
+            Mod::Session(r#mod) => r#mod.init(aparte),
+            Mod::Events(r#mod) => r#mod.init(aparte),
         }
     }
 
@@ -226,6 +395,61 @@ impl ModTrait for Mod {
             Mod::Mam(r#mod) => r#mod.on_event(aparte, event),
             Mod::Messages(r#mod) => r#mod.on_event(aparte, event),
             Mod::Correction(r#mod) => r#mod.on_event(aparte, event),
+            Mod::ChatStates(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Preview(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Scripting(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Plugins(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Archive(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Triggers(r#mod) => r#mod.on_event(aparte, event),
+            Mod::DBus(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Socket(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Outbox(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Whois(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Affiliations(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Ignore(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Rooms(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Captcha(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Ping(r#mod) => r#mod.on_event(aparte, event),
+            Mod::XmlConsole(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Invisible(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Invitations(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Session(r#mod) => r#mod.on_event(aparte, event),
+            Mod::Events(r#mod) => r#mod.on_event(aparte, event),
+        }
+    }
+
+    fn priority(&self) -> i32 {
+        match self {
+            Mod::Completion(r#mod) => r#mod.priority(),
+            Mod::Carbons(r#mod) => r#mod.priority(),
+            Mod::Contact(r#mod) => r#mod.priority(),
+            Mod::Conversation(r#mod) => r#mod.priority(),
+            Mod::Disco(r#mod) => r#mod.priority(),
+            Mod::Bookmarks(r#mod) => r#mod.priority(),
+            Mod::UI(r#mod) => r#mod.priority(),
+            Mod::Mam(r#mod) => r#mod.priority(),
+            Mod::Messages(r#mod) => r#mod.priority(),
+            Mod::Correction(r#mod) => r#mod.priority(),
+            Mod::ChatStates(r#mod) => r#mod.priority(),
+            Mod::Preview(r#mod) => r#mod.priority(),
+            Mod::Scripting(r#mod) => r#mod.priority(),
+            Mod::Plugins(r#mod) => r#mod.priority(),
+            Mod::Archive(r#mod) => r#mod.priority(),
+            Mod::Triggers(r#mod) => r#mod.priority(),
+            Mod::DBus(r#mod) => r#mod.priority(),
+            Mod::Socket(r#mod) => r#mod.priority(),
+            Mod::Outbox(r#mod) => r#mod.priority(),
+            Mod::Whois(r#mod) => r#mod.priority(),
+            Mod::Affiliations(r#mod) => r#mod.priority(),
+            Mod::Ignore(r#mod) => r#mod.priority(),
+            Mod::Rooms(r#mod) => r#mod.priority(),
+            Mod::Captcha(r#mod) => r#mod.priority(),
+            Mod::Ping(r#mod) => r#mod.priority(),
+            Mod::XmlConsole(r#mod) => r#mod.priority(),
+            Mod::Invisible(r#mod) => r#mod.priority(),
+            Mod::Invitations(r#mod) => r#mod.priority(),
+            Mod::Session(r#mod) => r#mod.priority(),
+            Mod::Events(r#mod) => r#mod.priority(),
         }
     }
 
@@ -253,6 +477,34 @@ impl ModTrait for Mod {
             Mod::Correction(r#mod) => {
                 r#mod.can_handle_xmpp_message(aparte, account, message, delay)
             }
+            Mod::ChatStates(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
+            Mod::Preview(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Scripting(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Plugins(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Archive(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Triggers(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::DBus(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Socket(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Outbox(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Whois(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Affiliations(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
+            Mod::Ignore(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Rooms(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Captcha(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Ping(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::XmlConsole(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
+            Mod::Invisible(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Invitations(r#mod) => {
+                r#mod.can_handle_xmpp_message(aparte, account, message, delay)
+            }
+            Mod::Session(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
+            Mod::Events(r#mod) => r#mod.can_handle_xmpp_message(aparte, account, message, delay),
         }
     }
 
@@ -274,6 +526,26 @@ impl ModTrait for Mod {
             Mod::Mam(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
             Mod::Messages(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
             Mod::Correction(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::ChatStates(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Preview(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Scripting(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Plugins(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Archive(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Triggers(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::DBus(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Socket(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Outbox(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Whois(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Affiliations(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Ignore(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Rooms(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Captcha(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Ping(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::XmlConsole(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Invisible(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Invitations(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Session(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
+            Mod::Events(r#mod) => r#mod.handle_xmpp_message(aparte, account, message, delay),
         }
     }
 }
@@ -291,6 +563,26 @@ impl fmt::Debug for Mod {
             Mod::Mam(_) => f.write_str("Mod::Mam"),
             Mod::Messages(_) => f.write_str("Mod::Messages"),
             Mod::Correction(_) => f.write_str("Mod::Correction"),
+            Mod::ChatStates(_) => f.write_str("Mod::ChatStates"),
+            Mod::Preview(_) => f.write_str("Mod::Preview"),
+            Mod::Scripting(_) => f.write_str("Mod::Scripting"),
+            Mod::Plugins(_) => f.write_str("Mod::Plugins"),
+            Mod::Archive(_) => f.write_str("Mod::Archive"),
+            Mod::Triggers(_) => f.write_str("Mod::Triggers"),
+            Mod::DBus(_) => f.write_str("Mod::DBus"),
+            Mod::Socket(_) => f.write_str("Mod::Socket"),
+            Mod::Outbox(_) => f.write_str("Mod::Outbox"),
+            Mod::Whois(_) => f.write_str("Mod::Whois"),
+            Mod::Affiliations(_) => f.write_str("Mod::Affiliations"),
+            Mod::Ignore(_) => f.write_str("Mod::Ignore"),
+            Mod::Rooms(_) => f.write_str("Mod::Rooms"),
+            Mod::Captcha(_) => f.write_str("Mod::Captcha"),
+            Mod::Ping(_) => f.write_str("Mod::Ping"),
+            Mod::XmlConsole(_) => f.write_str("Mod::XmlConsole"),
+            Mod::Invisible(_) => f.write_str("Mod::Invisible"),
+            Mod::Invitations(_) => f.write_str("Mod::Invitations"),
+            Mod::Session(_) => f.write_str("Mod::Session"),
+            Mod::Events(_) => f.write_str("Mod::Events"),
         }
     }
 }
@@ -308,6 +600,26 @@ impl fmt::Display for Mod {
             Mod::Mam(r#mod) => r#mod.fmt(f),
             Mod::Messages(r#mod) => r#mod.fmt(f),
             Mod::Correction(r#mod) => r#mod.fmt(f),
+            Mod::ChatStates(r#mod) => r#mod.fmt(f),
+            Mod::Preview(r#mod) => r#mod.fmt(f),
+            Mod::Scripting(r#mod) => r#mod.fmt(f),
+            Mod::Plugins(r#mod) => r#mod.fmt(f),
+            Mod::Archive(r#mod) => r#mod.fmt(f),
+            Mod::Triggers(r#mod) => r#mod.fmt(f),
+            Mod::DBus(r#mod) => r#mod.fmt(f),
+            Mod::Socket(r#mod) => r#mod.fmt(f),
+            Mod::Outbox(r#mod) => r#mod.fmt(f),
+            Mod::Whois(r#mod) => r#mod.fmt(f),
+            Mod::Affiliations(r#mod) => r#mod.fmt(f),
+            Mod::Ignore(r#mod) => r#mod.fmt(f),
+            Mod::Rooms(r#mod) => r#mod.fmt(f),
+            Mod::Captcha(r#mod) => r#mod.fmt(f),
+            Mod::Ping(r#mod) => r#mod.fmt(f),
+            Mod::XmlConsole(r#mod) => r#mod.fmt(f),
+            Mod::Invisible(r#mod) => r#mod.fmt(f),
+            Mod::Invitations(r#mod) => r#mod.fmt(f),
+            Mod::Session(r#mod) => r#mod.fmt(f),
+            Mod::Events(r#mod) => r#mod.fmt(f),
         }
     }
 }
@@ -327,26 +639,56 @@ impl<T: FromStr> FromStr for Password<T> {
 }
 
 pub struct Connection {
-    pub sink: mpsc::Sender<Element>,
+    /// Unbounded so that stanzas sent while reconnecting wait instead of blocking
+    pub sink: mpsc::UnboundedSender<Element>,
     pub account: FullJid,
+    /// Connected at least once, later connections are reconnections
+    pub connected: bool,
 }
 
 pub struct Aparte {
     pub command_parsers: Rc<HashMap<String, CommandParser>>,
     mods: Rc<HashMap<TypeId, RefCell<Mod>>>,
+    /// Mods sorted by decreasing priority, in which order they receive events
+    mods_order: Vec<TypeId>,
+    /// Whether a mod consumed the event being dispatched
+    event_consumed: bool,
     connections: HashMap<Account, Connection>,
     current_connection: Option<Account>,
     event_queue: Vec<Event>,
     send_queue: VecDeque<(Account, Element)>,
     event_channel: Option<mpsc::Sender<Event>>,
+    /// Last certificate rejected during connection, waiting for user approval
+    untrusted_certificate: Option<(Account, ConnectionInfo, Password<String>, String)>,
     /// Aparté main configuration
     pub config: Config,
+    config_path: PathBuf,
+    /// Aparté data directory, for logs, archives and saved states
+    pub data_dir: PathBuf,
+    /// Aparté configuration directory, for scripts and plugins
+    pub config_dir: PathBuf,
+    /// Settings registered by mods, changed with /set
+    pub settings: Settings,
+    /// Handle to change the log levels at runtime
+    logger: Option<ReconfigurationHandle>,
+    log_level: String,
+    /// Account given on the command line, connected instead of those with autoconnect
+    startup_account: Option<String>,
+    /// Commands given on the command line
+    startup_commands: Vec<String>,
+    /// /quit has been refused once because messages are pending
+    quit_requested: bool,
+    /// Sent stanzas are echoed as events
+    echo_stanzas: bool,
 }
 
 command_def!(connect,
-r#"/connect <account>
+r#"/connect [--anonymous] <account> [--host <host>] [--port <port>]
 
-    account       Account to connect to
+    account       Account to connect to, or server when connecting anonymously
+    anonymous     Connect anonymously, the server assigns a temporary JID
+    host          Server host to connect to, bypassing SRV resolution
+    port          Server port to connect to
 
 Description:
     Connect to the given account.
@@ -355,21 +697,26 @@ Examples:
     /connect myaccount
     /connect account@server.tld
     /connect account@server.tld/resource
-    /connect account@server.tld:5223
+    /connect account@server.tld --host chat.server.tld --port 5222
+    /connect account@server.tld --host localhost
+    /connect --anonymous anon.server.tld
 "#,
 {
+    anonymous: Flag,
+    host: Named<String>,
+    port: Named<u16>,
     account_name: String = {
         completion: (|aparte, _command| {
             aparte.config.accounts.iter().map(|(name, _)| name.clone()).collect()
         })
     },
-    password: Password<String>
+    password: Option<Password<String>>
 },
 |aparte, _command| {
-    let account = {
-        if let Some((_, account)) = aparte.config.accounts.iter().find(|(name, _)| *name == &account_name) {
+    let mut account = {
+        if let Some((_, account)) = aparte.config.accounts.iter().find(|(name, account)| *name == &account_name || account.jid == account_name) {
             account.clone()
-        } else if !account_name.contains("@") {
+        } else if !account_name.contains("@") && !anonymous {
             return Err(format!("Unknown account or invalid jid {}", account_name));
         } else if let Ok(jid) = Jid::from_str(&account_name) {
             ConnectionInfo {
@@ -377,13 +724,51 @@ Examples:
                 server: None,
                 port: None,
                 autoconnect: false,
+                transport: Default::default(),
+                websocket_url: None,
+                bosh_url: None,
+                anonymous: false,
+                direct_tls: false,
+                certificate_fingerprint: None,
+                password_command: None,
+                nick: None,
+                autoexec: Vec::new(),
             }
         } else {
             return Err(format!("Unknown account or invalid jid {}", account_name));
         }
     };
 
-    aparte.schedule(Event::Connect(account, password));
+    if anonymous {
+        account.anonymous = true;
+    }
+    if host.is_some() {
+        account.server = host.clone();
+    }
+    if port.is_some() {
+        account.port = port;
+    }
+
+    let password_command = account.password_command.clone();
+    match (password, password_command) {
+        _ if account.anonymous => aparte.schedule(Event::Connect(account, Password(String::new()))),
+        (Some(password), _) => aparte.schedule(Event::Connect(account, password)),
+        (None, Some(password_command)) => {
+            let password = account::run_password_command(&password_command)?;
+            aparte.schedule(Event::Connect(account, Password(password)));
+        }
+        (None, None) => {
+            // Keep overrides for when the command is replayed with the password
+            let mut args = vec!["connect".to_string(), account_name];
+            if let Some(host) = host {
+                args.push(format!("--host={}", host));
+            }
+            if let Some(port) = port {
+                args.push(format!("--port={}", port));
+            }
+            aparte.schedule(Event::ReadPassword(Command { args, .._command }));
+        }
+    }
 
     Ok(())
 });
@@ -391,14 +776,16 @@ Examples:
 command_def!(win,
 r#"Usage: /win <window>
 
-    window        Name of the window to switch to
+    window        Name or number of the window to switch to
 
 Description:
-    Switch to a given window.
+    Switch to a given window. Alt-1 to Alt-9 switch to the nine first
+    windows.
 
 Examples:
     /win console
-    /win contact@server.tld"#,
+    /win contact@server.tld
+    /win 2"#,
 {
     window: String = {
         completion: (|aparte, _command| {
@@ -412,13 +799,416 @@ Examples:
     Ok(())
 });
 
+command_def!(window_move,
+r#"Usage: /window move <position>
+
+    position      New number of the current window
+
+Description:
+    Move the current window, the following windows are renumbered.
+
+Examples:
+    /window move 2"#,
+{
+    position: usize
+},
+|aparte, _command| {
+    aparte.schedule(Event::MoveWindow(position));
+    Ok(())
+});
+
+command_def!(window_swap,
+r#"Usage: /window swap <first> <second>
+
+    first         Number of a window
+    second        Number of another window
+
+Description:
+    Swap the numbers of two windows.
+
+Examples:
+    /window swap 2 5"#,
+{
+    first: usize,
+    second: usize
+},
+|aparte, _command| {
+    aparte.schedule(Event::SwapWindows(first, second));
+    Ok(())
+});
+
+command_def!(window_rename,
+r#"Usage: /window rename [<alias>]
+
+    alias         Name displayed for the current window
+
+Description:
+    Display the current window under another name in the status and title
+    bars. The alias of a bookmarked channel is saved as the bookmark name.
+    Without alias, the window name is displayed again.
+
+Examples:
+    /window rename ops
+    /window rename"#,
+{
+    alias: Option<String>
+},
+|aparte, _command| {
+    let window = {
+        let ui = aparte.get_mod::<mods::ui::UIMod>();
+        ui.current_window().cloned()
+    };
+    let window = window.ok_or(format!("No current window"))?;
+
+    if let (Ok(jid), Some(account)) = (BareJid::from_str(&window), aparte.current_account()) {
+        let rename = {
+            let mut bookmarks = aparte.get_mod_mut::<mods::bookmarks::BookmarksMod>();
+            bookmarks.rename(&jid, alias.clone())
+        };
+        if let Some(rename) = rename {
+            aparte.send(&account, rename);
+        }
+    }
+
+    aparte.schedule(Event::WindowAlias(window, alias));
+    Ok(())
+});
+
+command_def!(window,
+r#"/window move|swap|rename"#,
+{
+    action: Command = {
+        children: {
+            "move": window_move,
+            "swap": window_swap,
+            "rename": window_rename,
+        }
+    },
+});
+
+command_def!(notify,
+r#"Usage: /notify <level> [<window>]
+
+    level         One of none, activity, highlight or all
+    window        Name of the window, the current one by default
+
+Description:
+    Choose what new messages of a window trigger:
+      none        nothing
+      activity    the window is marked as unread
+      highlight   the window is marked as unread, messages addressed to
+                  you ring the bell
+      all         the window is marked as unread, messages ring the bell
+
+Examples:
+    /notify none
+    /notify highlight channel@conference.server.tld"#,
+{
+    level: NotifyLevel = {
+        completion: (|_aparte, _command| {
+            vec![
+                "none".to_string(),
+                "activity".to_string(),
+                "highlight".to_string(),
+                "all".to_string(),
+            ]
+        })
+    },
+    window: Option<String> = {
+        completion: (|aparte, _command| {
+            let ui = aparte.get_mod::<mods::ui::UIMod>();
+            ui.get_windows()
+        })
+    }
+},
+|aparte, _command| {
+    let current = {
+        let ui = aparte.get_mod::<mods::ui::UIMod>();
+        ui.current_window().cloned()
+    };
+    let window = window.or(current).ok_or(format!("No current window"))?;
+    aparte.schedule(Event::NotifyLevel(window, level));
+    Ok(())
+});
+
+command_def!(dnd,
+r#"Usage: /dnd [on|off]
+
+Description:
+    Enable or disable do not disturb mode, toggle it without argument.
+    In do not disturb mode, messages neither ring the bell nor show
+    desktop notifications.
+
+Examples:
+    /dnd
+    /dnd off"#,
+{
+    state: Option<String> = {
+        completion: (|_aparte, _command| {
+            vec!["on".to_string(), "off".to_string()]
+        })
+    }
+},
+|aparte, _command| {
+    let state = match state.as_deref() {
+        None => None,
+        Some("on") => Some(true),
+        Some("off") => Some(false),
+        Some(state) => return Err(format!("Invalid state {}", state)),
+    };
+    aparte.schedule(Event::DoNotDisturb(state));
+    Ok(())
+});
+
+/// Parse durations like 45s, 30m, 2h, 1d or 1h30m
+fn parse_duration(duration: &str) -> Result<chrono::Duration, String> {
+    let invalid = || {
+        format!(
+            "Invalid duration {}, expected something like 30m or 1h30m",
+            duration
+        )
+    };
+    let mut total = chrono::Duration::zero();
+    let mut number = String::new();
+    for c in duration.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: i64 = number.parse().map_err(|_| invalid())?;
+        number.clear();
+        let part = match c {
+            's' => chrono::Duration::try_seconds(value),
+            'm' => chrono::Duration::try_minutes(value),
+            'h' => chrono::Duration::try_hours(value),
+            'd' => chrono::Duration::try_days(value),
+            _ => return Err(invalid()),
+        };
+        total = part
+            .and_then(|part| total.checked_add(&part))
+            .ok_or_else(invalid)?;
+    }
+    if !number.is_empty() || total <= chrono::Duration::zero() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+command_def!(mute,
+r#"Usage: /mute [<duration>]
+
+    duration      How long to mute the window, like 30m, 2h or 1h30m, until
+                  /unmute by default
+
+Description:
+    Mute the current window: its new messages neither mark it as unread, ring
+    the bell nor show desktop notifications. The window is unmuted once the
+    duration has passed.
+
+Examples:
+    /mute
+    /mute 2h"#,
+{
+    duration: Option<String>
+},
+|aparte, _command| {
+    let window = {
+        let ui = aparte.get_mod::<mods::ui::UIMod>();
+        ui.current_window().cloned()
+    };
+    let window = window.ok_or(format!("No current window"))?;
+    let until = match duration {
+        Some(duration) => {
+            let now: DateTime<FixedOffset> = LocalTz::now().into();
+            let until = now.checked_add_signed(parse_duration(&duration)?);
+            Some(until.ok_or(format!("Invalid duration {}, too long", duration))?)
+        }
+        None => None,
+    };
+    aparte.schedule(Event::Mute(window, until));
+    Ok(())
+});
+
+command_def!(
+    unmute,
+    r#"Usage: /unmute
+
+Description:
+    Unmute the current window before its /mute duration has passed.
+
+Examples:
+    /unmute"#,
+    {},
+    |aparte, _command| {
+        let window = {
+            let ui = aparte.get_mod::<mods::ui::UIMod>();
+            ui.current_window().cloned()
+        };
+        let window = window.ok_or(format!("No current window"))?;
+        aparte.schedule(Event::Unmute(window));
+        Ok(())
+    }
+);
+
+command_def!(buffer_clear,
+r#"Usage: /buffer clear [<window>]
+
+    window        Name of the window, the current one by default
+
+Description:
+    Empty the messages displayed in a window. Windows keep a limited
+    number of messages, set with the `scrollback` section of the
+    configuration.
+
+Examples:
+    /buffer clear
+    /buffer clear console"#,
+{
+    window: Option<String> = {
+        completion: (|aparte, _command| {
+            let ui = aparte.get_mod::<mods::ui::UIMod>();
+            ui.get_windows()
+        })
+    }
+},
+|aparte, _command| {
+    let current = {
+        let ui = aparte.get_mod::<mods::ui::UIMod>();
+        ui.current_window().cloned()
+    };
+    let window = window.or(current).ok_or(format!("No current window"))?;
+    aparte.schedule(Event::ClearBuffer(window));
+    Ok(())
+});
+
+command_def!(clear,
+r#"Usage: /clear [--all]
+
+    all           Clear every window instead of the current one
+
+Description:
+    Empty the messages displayed in the current window, or in every
+    window. Same as /buffer clear.
+
+Examples:
+    /clear
+    /clear --all"#,
+{
+    all: Flag
+},
+|aparte, _command| {
+    let windows = {
+        let ui = aparte.get_mod::<mods::ui::UIMod>();
+        match all {
+            true => ui.get_windows(),
+            false => ui.current_window().cloned().into_iter().collect(),
+        }
+    };
+    if windows.is_empty() {
+        return Err(format!("No current window"));
+    }
+    for window in windows {
+        aparte.schedule(Event::ClearBuffer(window));
+    }
+    Ok(())
+});
+
+command_def!(buffer,
+r#"/buffer clear"#,
+{
+    action: Command = {
+        children: {
+            "clear": buffer_clear,
+        }
+    },
+});
+
+command_def!(loglevel,
+r#"Usage: /loglevel [<level>]
+
+    level         Default level followed by per module levels
+
+Description:
+    Change the levels of the debug log file until exit, or show them.
+    The log file is in the aparte data directory, stanzas are logged
+    at debug level by the xmpp module.
+
+Examples:
+    /loglevel
+    /loglevel debug
+    /loglevel info,xmpp=debug,aparte::mods::mam=trace"#,
+{
+    level: Option<String>
+},
+|aparte, _command| {
+    match level {
+        Some(level) => {
+            aparte.set_log_level(&level)?;
+            aparte.log(format!("Log level set to {}", level));
+        }
+        None => {
+            let message = format!("Log level: {}", aparte.log_level);
+            aparte.log(message);
+        }
+    }
+    Ok(())
+});
+
+command_def!(set,
+r#"Usage: /set [<key> [<value>]]
+
+    key           Name of the setting
+    value         New value of the setting
+
+Description:
+    List settings, show one of them or change its value. Changed values
+    are saved to the configuration file.
+
+Examples:
+    /set
+    /set time_format
+    /set time_format %H:%M
+    /set preview.rows 4"#,
+{
+    key: Option<String> = {
+        completion: (|aparte, _command| {
+            aparte.settings.iter().map(|(key, _)| key.clone()).collect()
+        })
+    },
+    value: Option<String>
+},
+|aparte, _command| {
+    match (key, value) {
+        (None, _) => {
+            let settings = aparte
+                .settings
+                .iter()
+                .map(|(key, setting)| format!("    {} = {}", key, setting.value))
+                .collect::<Vec<String>>();
+            aparte.log(format!("Settings:\n{}", settings.join("\n")));
+            Ok(())
+        }
+        (Some(key), None) => {
+            let setting = aparte
+                .settings
+                .get(&key)
+                .ok_or(format!("Unknown setting {}", key))?;
+            let message = format!("{} = {}\n    {}", key, setting.value, setting.help);
+            aparte.log(message);
+            Ok(())
+        }
+        (Some(key), Some(value)) => aparte.set(&key, &value),
+    }
+});
+
 command_def!(close,
 r#"Usage: /close [<window>]
 
     window        Name of the window to close
 
 Description:
-    Close the current or a given window.
+    Close the current or a given window. Closing a channel window also
+    leaves the channel.
 
 Examples:
     /close
@@ -444,62 +1234,199 @@ Examples:
     Ok(())
 });
 
+command_def!(search,
+r#"Usage: /search [<term>]
+
+    term          Text to search for
+
+Description:
+    Search backward through the current window and highlight matches.
+    Use Ctrl-R to jump to the previous match and Ctrl-S to the next one.
+    Without term, clear the current search.
+
+Examples:
+    /search hello
+    /search"#,
+{
+    term: Option<String>
+},
+|aparte, _command| {
+    aparte.schedule(Event::Search(term.clone()));
+    Ok(())
+});
+
+command_def!(split,
+r#"Usage: /split <orientation>
+
+    orientation   Either horizontal or vertical
+
+Description:
+    Split the focused pane in two, showing an empty pane below (horizontal)
+    or on the right (vertical). Use /win to display a window in the focused
+    pane, Alt-o to move focus to the next pane and Alt-+ or Alt-- to resize
+    it.
+
+Examples:
+    /split horizontal
+    /split vertical"#,
+{
+    orientation: String = {
+        completion: (|_aparte, _command| {
+            vec!["horizontal".to_string(), "vertical".to_string()]
+        })
+    }
+},
+|aparte, _command| {
+    // Panes of a vertical split are laid out side by side
+    let orientation = match orientation.as_str() {
+        "horizontal" => Orientation::Vertical,
+        "vertical" => Orientation::Horizontal,
+        _ => return Err(format!("Unknown orientation {}", orientation)),
+    };
+    aparte.schedule(Event::Split(orientation));
+    Ok(())
+});
+
+command_def!(
+    unsplit,
+    r#"Usage: /unsplit
+
+Description:
+    Close the focused pane.
+
+Examples:
+    /unsplit"#,
+    {},
+    |aparte, _command| {
+        aparte.schedule(Event::Unsplit);
+        Ok(())
+    }
+);
+
+command_def!(open,
+r#"Usage: /open [<number>]
+
+    number        Number of the URL in the current window
+
+Description:
+    Open a URL of the current window with xdg-open. URLs are numbered
+    in brackets after them, the last one is opened by default.
+
+Examples:
+    /open
+    /open 2"#,
+{
+    number: Option<usize>
+},
+|aparte, _command| {
+    aparte.schedule(Event::OpenUrl(number));
+    Ok(())
+});
+
+command_def!(copyurl,
+r#"Usage: /copyurl [<number>]
+
+    number        Number of the URL in the current window
+
+Description:
+    Copy a URL of the current window to the clipboard, the last one by
+    default.
+
+Examples:
+    /copyurl
+    /copyurl 2"#,
+{
+    number: Option<usize>
+},
+|aparte, _command| {
+    aparte.schedule(Event::CopyUrl(number));
+    Ok(())
+});
+
+command_def!(
+    roster_toggle,
+    r#"Usage: /roster toggle
+
+Description:
+    Hide or show the roster of the console, or the occupant list of the
+    current channel. Alt-r does the same.
+
+Examples:
+    /roster toggle"#,
+    {},
+    |aparte, _command| {
+        aparte.schedule(Event::ToggleRoster);
+        Ok(())
+    }
+);
+
+command_def!(roster_filter,
+r#"Usage: /roster filter [<text>]
+
+    text          Text contacts must contain
+
+Description:
+    Only display contacts whose name or jid contains the given text.
+    Without text, display all contacts again.
+
+Examples:
+    /roster filter romeo
+    /roster filter"#,
+{
+    text: Option<String>
+},
+|aparte, _command| {
+    aparte.schedule(Event::RosterFilter(text.clone()));
+    Ok(())
+});
+
+command_def!(roster,
+r#"/roster toggle|filter"#,
+{
+    action: Command = {
+        children: {
+            "toggle": roster_toggle,
+            "filter": roster_filter,
+        }
+    },
+});
+
 command_def!(leave,
-r#"Usage: /leave [<window>]
+r#"Usage: /leave [<message>] [--window <window>]
 
+    message       Message sent to the occupants of the channel
     window        Name of the channel to leave
 
 Description:
-    Close the current or a given channel.
+    Leave the current or a given channel. Its window is kept, dimmed in the
+    status bar, until it is closed or the channel is joined again with /join.
 
 Examples:
     /leave
-    /leave channel@conversation.server.tld"#,
+    /leave "Good night"
+    /leave --window channel@conversation.server.tld"#,
 {
-    window: Option<String> = {
-        completion: (|aparte, _command| {
-            let ui = aparte.get_mod::<mods::ui::UIMod>();
-            ui.get_windows().iter().map(|window| {
-                if let Some(account) = aparte.current_account() {
-                    if let Ok(jid) = BareJid::from_str(&window) {
-                        let conversation_mod = aparte.get_mod::<mods::conversation::ConversationMod>();
-                        conversation_mod.get(&account, &jid).cloned()
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }).filter_map(|conversation| {
-                if let Some(Conversation::Channel(channel)) = conversation {
-                    Some(channel.jid.into())
-                } else {
-                    None
-                }
-            }).collect()
-        })
-    }
+    window: Named<String>,
+    message: Option<String>
 },
 |aparte, _command| {
     let current =  {
         let ui = aparte.get_mod::<mods::ui::UIMod>();
         ui.current_window().cloned()
     };
-    let window = window.or(current).clone();
-    if let Some(window) = window {
-        if let Some(account) = aparte.current_account() {
-            if let Ok(jid) = BareJid::from_str(&window) {
-                let conversation =  {
-                    let conversation_mod = aparte.get_mod::<mods::conversation::ConversationMod>();
-                    conversation_mod.get(&account, &jid).cloned()
-                };
-                if let Some(Conversation::Channel(channel)) = conversation {
-                    aparte.schedule(Event::Leave(channel));
-                }
-            }
+    let window = window.or(current).ok_or(format!("No current window"))?;
+    let account = aparte.current_account().ok_or(format!("No connection found"))?;
+    let conversation = BareJid::from_str(&window).ok().and_then(|jid| {
+        let conversation_mod = aparte.get_mod::<mods::conversation::ConversationMod>();
+        conversation_mod.get(&account, &jid).cloned()
+    });
+    match conversation {
+        Some(Conversation::Channel(channel)) => {
+            aparte.schedule(Event::Leave(channel, message));
+            Ok(())
         }
+        _ => Err(format!("Not in channel {}", window)),
     }
-    Ok(())
 });
 
 command_def!(msg,
@@ -541,9 +1468,7 @@ Example:
                 let from: Jid = account.clone().into();
                 let timestamp = LocalTz::now();
                 let message = Message::outgoing_chat(id, timestamp.into(), &from, &jid, &bodies);
-                aparte.schedule(Event::Message(Some(account.clone()), message.clone()));
-
-                aparte.send(&account, Element::try_from(message).unwrap());
+                aparte.schedule(Event::SendMessage(account.clone(), message));
             }
             Ok(())
         },
@@ -553,82 +1478,164 @@ Example:
     }
 });
 
-command_def!(join,
-r#"/join <channel>
+command_def!(join,
+r#"/join <channel> [<nick>] [--password <password>]
+
+    channel       Channel JID or bookmark name to join
+    nick          Nick in the channel, see the nick setting of accounts
+    password      Password of the channel
+Description:
+    Open a window and join a given channel. The nick and password of the
+    bookmark of the channel are used unless given.
+
+Example:
+    /join channel@conference.server.tld
+    /join channel@conference.server.tld needle
+    /join channel@conference.server.tld --password hunter2"#,
+{
+    password: Named<String>,
+    muc: String = {
+        completion: (|aparte, _command| {
+            let bookmarks = aparte.get_mod::<mods::bookmarks::BookmarksMod>();
+            bookmarks.bookmarks_by_name.iter().map(|(a, _)| a.clone()).chain(bookmarks.bookmarks_by_jid.iter().map(|(a, _)| a.to_string())).collect()
+        })
+    },
+    nick: Option<String>,
+},
+|aparte, _command| {
+    let account = aparte.current_account().ok_or(format!("No connection found"))?;
+    let (room, nick, bookmark) = {
+        let bookmarks = aparte.get_mod::<mods::bookmarks::BookmarksMod>();
+        match bookmarks.get_by_name(&muc) {
+            Some(bookmark) => (bookmark.jid.clone(), nick, Some(bookmark)),
+            None => {
+                let (room, resource) = match Jid::from_str(&muc) {
+                    Ok(Jid::Full(jid)) => (BareJid::from(jid.clone()), Some(jid.resource)),
+                    Ok(Jid::Bare(jid)) => (jid, None),
+                    Err(err) => return Err(format!("Invalid JID {}: {}", muc, err)),
+                };
+                let bookmark = bookmarks.bookmarks.iter().find(|bookmark| bookmark.jid == room).cloned();
+                (room, nick.or(resource), bookmark)
+            }
+        }
+    };
+    let nick = nick.or(bookmark.as_ref().and_then(|bookmark| bookmark.nick.clone()));
+    let password = password.or(bookmark.and_then(|bookmark| bookmark.password));
+    aparte.schedule(Event::Join {
+        account,
+        channel: match nick {
+            Some(nick) => Jid::Full(room.with_resource(nick)),
+            None => Jid::Bare(room),
+        },
+        password,
+        user_request: true
+    });
+    Ok(())
+});
+
+command_def!(presence,
+r#"/presence <jid> [<show>] [<status>]
+
+    jid           Contact, transport or resource to send the presence to
+    show          available, chat, away, xa, dnd or unavailable, available by default
+    status        Status message
 
-    channel       Channel JID to join
 Description:
-    Open a window and join a given channel.
+    Send a presence to a single entity instead of every contact, for instance
+    to appear online to a transport. Sending unavailable to a full JID signs
+    off from this resource only.
 
 Example:
-    /join channel@conference.server.tld"#,
+    /presence juliet@capulet.lit away "In the orchard"
+    /presence icq.montague.lit
+    /presence juliet@capulet.lit/balcony unavailable"#,
 {
-    muc: String = {
+    jid: Jid = {
         completion: (|aparte, _command| {
-            let bookmarks = aparte.get_mod::<mods::bookmarks::BookmarksMod>();
-            bookmarks.bookmarks_by_name.iter().map(|(a, _)| a.clone()).chain(bookmarks.bookmarks_by_jid.iter().map(|(a, _)| a.to_string())).collect()
+            let contact = aparte.get_mod::<mods::contact::ContactMod>();
+            contact.contacts.iter().map(|(_, contact)| contact.jid.to_string()).collect()
+        })
+    },
+    show: Option<String> = {
+        completion: (|_aparte, _command| {
+            PRESENCE_SHOWS.iter().map(|show| show.to_string()).collect()
         })
     },
+    status: Option<String>,
 },
-|aparte, _command| {
-    let account = aparte.current_account().ok_or(format!("No connection found"))?;
-    match Jid::from_str(&muc) {
-        Ok(jid) => {
-            aparte.schedule(Event::Join {
-                account,
-                channel: jid,
-                user_request: true
-            });
-            Ok(())
-        },
-        Err(_) => {
-            let jid = {
-                let bookmarks = aparte.get_mod::<mods::bookmarks::BookmarksMod>();
-                match bookmarks.get_by_name(&muc) {
-                    Some(bookmark) => {
-                        match bookmark.nick {
-                            Some(nick) => Ok(Jid::Full(bookmark.jid.with_resource(nick))),
-                            None => Ok(Jid::Bare(bookmark.jid.clone())),
-                        }
-                    },
-                    None => match Jid::from_str(&muc) {
-                        Ok(jid) => Ok(jid),
-                        Err(e) => Err(e.to_string()),
-                    }
-                }
-            };
-
-            match jid {
-                Ok(jid) => {
-                    aparte.schedule(Event::Join {
-                        account,
-                        channel: jid,
-                        user_request: true
-                    });
-                    Ok(())
-                },
-                Err(e) => Err(e),
-            }
-        }
+|aparte, command| {
+    let account = command
+        .account
+        .clone()
+        .or_else(|| aparte.current_account())
+        .ok_or(format!("No connection found"))?;
+    let show = show.unwrap_or("available".to_string());
+    let mut presence = match show.as_str() {
+        "unavailable" => Presence::new(PresenceType::Unavailable),
+        _ => Presence::new(PresenceType::None),
+    };
+    presence.show = match show.as_str() {
+        "available" | "unavailable" => None,
+        "chat" => Some(PresenceShow::Chat),
+        "away" => Some(PresenceShow::Away),
+        "xa" => Some(PresenceShow::Xa),
+        "dnd" => Some(PresenceShow::Dnd),
+        _ => return Err(format!("Invalid show {}, expected one of {}", show, PRESENCE_SHOWS.join(", "))),
+    };
+    if let Some(status) = status {
+        presence.statuses.insert(String::new(), status);
     }
+    presence.to = Some(jid.clone());
+    aparte.send(&account, presence.into());
+    aparte.log(format!("Sent {} presence to {}", show, jid));
+    Ok(())
 });
 
-command_def!(
-    quit,
-    r#"/quit
+command_def!(quit,
+r#"/quit [<status>]
+
+    status        Status message sent to contacts along with the unavailable presence
 
 Description:
-    Quit Aparté.
+    Quit Aparté. Asks for confirmation while messages are pending, use
+    /quit again to quit anyway.
 
 Example:
-    /quit"#,
-    {},
-    |aparte, _command| {
-        aparte.schedule(Event::Quit);
+    /quit
+    /quit "See you tomorrow""#,
+{
+    status: Option<String>
+},
+|aparte, _command| {
+    let pending = aparte.get_mod::<mods::outbox::OutboxMod>().pending_count();
+    if pending > 0 && !aparte.quit_requested {
+        aparte.quit_requested = true;
+        return Err(format!(
+            "{} messages written while disconnected are not sent yet, they will be lost.\nUse /quit again to quit anyway.",
+            pending
+        ));
+    }
 
-        Ok(())
+    let accounts = aparte.connections.keys().cloned().collect::<Vec<_>>();
+    for account in accounts.iter() {
+        let mut presence = Presence::new(PresenceType::Unavailable);
+        if let Some(status) = &status {
+            presence.statuses.insert(String::new(), status.clone());
+        }
+        aparte.send(account, presence.into());
     }
-);
+
+    match accounts.is_empty() {
+        true => aparte.schedule(Event::Quit),
+        // Give connections some time to send the last presence
+        false => aparte.spawn(async {
+            tokio::time::sleep(QUIT_DELAY).await;
+            Event::Quit
+        }),
+    }
+
+    Ok(())
+});
 
 command_def!(help,
 r#"/help [command]
@@ -636,9 +1643,10 @@ r#"/help [command]
     command       Name of command
 
 Description:
-    Print help of a given command.
+    Print help of a given command, or list available commands.
 
 Examples:
+    /help
     /help win"#,
 {
     cmd: Option<String> = {
@@ -649,7 +1657,8 @@ Examples:
 },
 |aparte, _command| {
     if let Some(cmd) = cmd {
-        let help = match aparte.command_parsers.get(&cmd) {
+        let cmd = cmd.trim_start_matches('/');
+        let help = match aparte.command_parsers.get(cmd) {
             Some(command) => Ok(command.help.to_string()),
             None => Err(format!("Unknown command {}", cmd)),
         }?;
@@ -657,11 +1666,48 @@ Examples:
         aparte.log(help);
         Ok(())
     } else {
-        aparte.log(format!("Available commands: {}", aparte.command_parsers.iter().map(|c| c.0.to_string()).collect::<Vec<String>>().join(", ")));
+        let mut commands = aparte.command_parsers.values().collect::<Vec<_>>();
+        commands.sort_by_key(|command| command.name);
+        let width = commands.iter().map(|command| command.name.len()).max().unwrap_or(0);
+        let mut help = vec![String::from("Available commands:")];
+        for command in commands {
+            help.push(format!("    /{:width$}  {}", command.name, command.summary(), width = width));
+        }
+        help.push(String::from("Type /help <command> for its usage"));
+        aparte.log(help.join("\n"));
         Ok(())
     }
 });
 
+command_def!(
+    trust,
+    r#"/trust
+
+Description:
+    Trust the server certificate that was rejected during the last connection
+    attempt, remember it and reconnect.
+
+Example:
+    /trust"#,
+    {},
+    |aparte, _command| {
+        match aparte.untrusted_certificate.take() {
+            Some((account, connection_info, password, fingerprint)) => {
+                TrustStore::new(&aparte.data_dir)
+                    .trust(&account.domain, &fingerprint)
+                    .map_err(|err| format!("Cannot store trusted certificate: {}", err))?;
+                aparte.log(format!(
+                    "Certificate {} is now trusted for {}",
+                    fingerprint, account.domain
+                ));
+                aparte.schedule(Event::Connect(connection_info, password));
+                Ok(())
+            }
+            None => Err(format!("No certificate waiting for approval")),
+        }
+    }
+);
+
 mod me {
     use chrono::Local as LocalTz;
     use std::collections::HashMap;
@@ -755,51 +1801,63 @@ Examples:
             parse,
             exec,
             autocompletions: vec![],
+            named: vec![],
+            flags: vec![],
+            subcommands: vec![],
         }
     }
 }
 
 impl Aparte {
-    pub fn new(config_path: PathBuf) -> Self {
-        let mut config_file = match OpenOptions::new()
+    pub fn new(
+        config_path: PathBuf,
+        data_dir: PathBuf,
+        config_dir: PathBuf,
+        display: mods::ui::DisplayMode,
+    ) -> Result<Self, Error> {
+        let mut config_file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(config_path)
-        {
-            Err(err) => panic!("Cannot read config file {}", err),
-            Ok(config_file) => config_file,
-        };
+            .open(&config_path)
+            .map_err(|err| Error::Config(format!("{}: {}", config_path.display(), err)))?;
 
         let mut config_str = String::new();
-        if let Err(e) = config_file.read_to_string(&mut config_str) {
-            panic!("Cannot read config file {}", e);
-        }
+        config_file
+            .read_to_string(&mut config_str)
+            .map_err(|err| Error::Config(format!("{}: {}", config_path.display(), err)))?;
 
-        let config = match config_str.len() {
-            0 => Config {
-                accounts: HashMap::new(),
-            },
+        // A malformed configuration is reported once the console can show it
+        let (config, config_error) = match config_str.len() {
+            0 => (Config::default(), None),
             _ => match toml::from_str(&config_str) {
-                Err(err) => {
-                    error!("Malformed config file: {}", err);
-                    Config {
-                        accounts: HashMap::new(),
-                    }
-                }
-                Ok(config) => config,
+                Err(err) => (Config::default(), Some(Error::Config(err.to_string()))),
+                Ok(config) => (config, None),
             },
         };
 
         let mut aparte = Self {
             command_parsers: Rc::new(HashMap::new()),
             mods: Rc::new(HashMap::new()),
+            mods_order: Vec::new(),
+            event_consumed: false,
             connections: HashMap::new(),
             current_connection: None,
             event_queue: Vec::new(),
             send_queue: VecDeque::new(),
             event_channel: None,
+            untrusted_certificate: None,
             config: config,
+            config_path,
+            data_dir: data_dir.clone(),
+            config_dir,
+            settings: Settings::new(),
+            logger: None,
+            log_level: env::var("RUST_LOG").unwrap_or("info".to_string()),
+            startup_account: None,
+            startup_commands: Vec::new(),
+            quit_requested: false,
+            echo_stanzas: false,
         };
 
         aparte.add_mod(Mod::Completion(mods::completion::CompletionMod::new()));
@@ -808,12 +1866,36 @@ impl Aparte {
         aparte.add_mod(Mod::Conversation(mods::conversation::ConversationMod::new()));
         aparte.add_mod(Mod::Disco(mods::disco::DiscoMod::new()));
         aparte.add_mod(Mod::Bookmarks(mods::bookmarks::BookmarksMod::new()));
-        aparte.add_mod(Mod::UI(mods::ui::UIMod::new()));
+        aparte.add_mod(Mod::UI(mods::ui::UIMod::new(display, &data_dir)?));
         aparte.add_mod(Mod::Mam(mods::mam::MamMod::new()));
         aparte.add_mod(Mod::Messages(mods::messages::MessagesMod::new()));
         aparte.add_mod(Mod::Correction(mods::correction::CorrectionMod::new()));
+        aparte.add_mod(Mod::ChatStates(mods::chatstates::ChatStatesMod::new()));
+        aparte.add_mod(Mod::Preview(mods::preview::PreviewMod::new()));
+        aparte.add_mod(Mod::Scripting(mods::scripting::ScriptingMod::new()));
+        aparte.add_mod(Mod::Plugins(mods::plugins::PluginsMod::new()));
+        aparte.add_mod(Mod::Archive(mods::archive::ArchiveMod::new()));
+        aparte.add_mod(Mod::Triggers(mods::triggers::TriggersMod::new()));
+        aparte.add_mod(Mod::DBus(mods::dbus::DBusMod::new()));
+        aparte.add_mod(Mod::Socket(mods::socket::SocketMod::new()));
+        aparte.add_mod(Mod::Outbox(mods::outbox::OutboxMod::new()));
+        aparte.add_mod(Mod::Whois(mods::whois::WhoisMod::new()));
+        aparte.add_mod(Mod::Affiliations(mods::affiliations::AffiliationsMod::new()));
+        aparte.add_mod(Mod::Ignore(mods::ignore::IgnoreMod::new(&data_dir)));
+        aparte.add_mod(Mod::Rooms(mods::rooms::RoomsMod::new()));
+        aparte.add_mod(Mod::Captcha(mods::captcha::CaptchaMod::new()));
+        aparte.add_mod(Mod::Ping(mods::ping::PingMod::new()));
+        aparte.add_mod(Mod::XmlConsole(mods::xml_console::XmlConsoleMod::new()));
+        aparte.add_mod(Mod::Invisible(mods::invisible::InvisibleMod::new()));
+        aparte.add_mod(Mod::Invitations(mods::invitations::InvitationsMod::new()));
+        aparte.add_mod(Mod::Session(mods::session::SessionMod::new(&data_dir)));
+        aparte.add_mod(Mod::Events(mods::events::EventsMod::new()));
+
+        if let Some(err) = config_error {
+            aparte.error(err);
+        }
 
-        aparte
+        Ok(aparte)
     }
 
     pub fn add_command(&mut self, command_parser: CommandParser) {
@@ -916,7 +1998,144 @@ impl Aparte {
                     RefCell::new(Mod::Correction(r#mod)),
                 );
             }
+            Mod::ChatStates(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::chatstates::ChatStatesMod>(),
+                    RefCell::new(Mod::ChatStates(r#mod)),
+                );
+            }
+            Mod::Preview(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::preview::PreviewMod>(),
+                    RefCell::new(Mod::Preview(r#mod)),
+                );
+            }
+            Mod::Scripting(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::scripting::ScriptingMod>(),
+                    RefCell::new(Mod::Scripting(r#mod)),
+                );
+            }
+            Mod::Plugins(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::plugins::PluginsMod>(),
+                    RefCell::new(Mod::Plugins(r#mod)),
+                );
+            }
+            Mod::Archive(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::archive::ArchiveMod>(),
+                    RefCell::new(Mod::Archive(r#mod)),
+                );
+            }
+            Mod::Triggers(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::triggers::TriggersMod>(),
+                    RefCell::new(Mod::Triggers(r#mod)),
+                );
+            }
+            Mod::DBus(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::dbus::DBusMod>(),
+                    RefCell::new(Mod::DBus(r#mod)),
+                );
+            }
+            Mod::Socket(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::socket::SocketMod>(),
+                    RefCell::new(Mod::Socket(r#mod)),
+                );
+            }
+            Mod::Outbox(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::outbox::OutboxMod>(),
+                    RefCell::new(Mod::Outbox(r#mod)),
+                );
+            }
+            Mod::Whois(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::whois::WhoisMod>(),
+                    RefCell::new(Mod::Whois(r#mod)),
+                );
+            }
+            Mod::Affiliations(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::affiliations::AffiliationsMod>(),
+                    RefCell::new(Mod::Affiliations(r#mod)),
+                );
+            }
+            Mod::Ignore(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::ignore::IgnoreMod>(),
+                    RefCell::new(Mod::Ignore(r#mod)),
+                );
+            }
+            Mod::Rooms(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::rooms::RoomsMod>(),
+                    RefCell::new(Mod::Rooms(r#mod)),
+                );
+            }
+            Mod::Captcha(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::captcha::CaptchaMod>(),
+                    RefCell::new(Mod::Captcha(r#mod)),
+                );
+            }
+            Mod::Ping(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::ping::PingMod>(),
+                    RefCell::new(Mod::Ping(r#mod)),
+                );
+            }
+            Mod::XmlConsole(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::xml_console::XmlConsoleMod>(),
+                    RefCell::new(Mod::XmlConsole(r#mod)),
+                );
+            }
+            Mod::Invisible(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::invisible::InvisibleMod>(),
+                    RefCell::new(Mod::Invisible(r#mod)),
+                );
+            }
+            Mod::Invitations(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::invitations::InvitationsMod>(),
+                    RefCell::new(Mod::Invitations(r#mod)),
+                );
+            }
+            Mod::Session(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::session::SessionMod>(),
+                    RefCell::new(Mod::Session(r#mod)),
+                );
+            }
+            Mod::Events(r#mod) => {
+                mods.insert(
+                    TypeId::of::<mods::events::EventsMod>(),
+                    RefCell::new(Mod::Events(r#mod)),
+                );
+            }
         }
+
+        let mut order = mods
+            .iter()
+            .map(|(type_id, r#mod)| (*type_id, r#mod.borrow().priority()))
+            .collect::<Vec<_>>();
+        order.sort_by_key(|(_, priority)| -priority);
+        self.mods_order = order.into_iter().map(|(type_id, _)| type_id).collect();
+    }
+
+    /// Stop the event being dispatched from reaching the following mods and the core
+    pub fn consume_event(&mut self) {
+        self.event_consumed = true;
+    }
+
+    /// Schedule a `SentStanza` event for each stanza sent from now on, or stop doing so
+    pub fn echo_stanzas(&mut self, echo: bool) {
+        self.echo_stanzas = echo;
     }
 
     pub fn get_mod<'a, T>(&'a self) -> Ref<'a, T>
@@ -941,16 +2160,29 @@ impl Aparte {
         }
     }
 
-    pub fn add_connection(&mut self, account: Account, sink: mpsc::Sender<Element>) {
+    pub fn add_connection(&mut self, account: Account, sink: mpsc::UnboundedSender<Element>) {
         let connection = Connection {
             account: account.clone(),
             sink,
+            connected: false,
         };
 
         self.connections.insert(account.clone(), connection);
         self.current_connection = Some(account.clone());
     }
 
+    /// Move a connection to the JID bound by the server
+    fn rebind_connection(&mut self, account: &Account, jid: &FullJid) {
+        if let Some(mut connection) = self.connections.remove(account) {
+            connection.account = jid.clone();
+            self.connections.insert(jid.clone(), connection);
+        }
+
+        if self.current_connection.as_ref() == Some(account) {
+            self.current_connection = Some(jid.clone());
+        }
+    }
+
     pub fn current_account(&self) -> Option<Account> {
         self.current_connection.clone()
     }
@@ -959,12 +2191,29 @@ impl Aparte {
         self.add_command(help::new());
         self.add_command(connect::new());
         self.add_command(win::new());
+        self.add_command(window::new());
         self.add_command(close::new());
+        self.add_command(buffer::new());
+        self.add_command(clear::new());
+        self.add_command(set::new());
+        self.add_command(loglevel::new());
+        self.add_command(notify::new());
+        self.add_command(dnd::new());
+        self.add_command(mute::new());
+        self.add_command(unmute::new());
+        self.add_command(search::new());
+        self.add_command(split::new());
+        self.add_command(unsplit::new());
+        self.add_command(roster::new());
+        self.add_command(open::new());
+        self.add_command(copyurl::new());
         self.add_command(leave::new());
         self.add_command(msg::new());
         self.add_command(join::new());
+        self.add_command(presence::new());
         self.add_command(quit::new());
         self.add_command(me::new());
+        self.add_command(trust::new());
 
         let mods = Rc::clone(&self.mods);
         for (_, r#mod) in mods.iter() {
@@ -977,14 +2226,16 @@ impl Aparte {
     }
 
     pub fn run(mut self) {
-        let mut input_event_stream = {
+        let input_event_stream = {
             let ui = self.get_mod::<mods::ui::UIMod>();
             ui.event_stream()
         };
 
         let (tx, mut rx) = mpsc::channel(32);
         let tx_for_signal = tx.clone();
+        let tx_for_tick = tx.clone();
         let tx_for_event = tx.clone();
+        let tx_for_quit = tx.clone();
         self.event_channel = Some(tx);
 
         let mut rt = TokioRuntime::new().unwrap();
@@ -1002,22 +2253,50 @@ impl Aparte {
 
         rt.spawn(async move {
             loop {
-                match input_event_stream.next().await {
-                    Some(event) => {
-                        if let Err(err) = tx_for_event.send(event).await {
-                            error!("Cannot send event to internal channel: {}", err);
-                            break;
+                // Wake up at the start of each minute
+                let elapsed = LocalTz::now().timestamp() % 60;
+                tokio::time::sleep(Duration::from_secs((60 - elapsed) as u64)).await;
+                if let Err(err) = tx_for_tick.send(Event::Tick).await {
+                    error!("Cannot send tick to internal channel: {}", err);
+                    break;
+                }
+            }
+        });
+
+        // Quit cleanly when killed, or interrupted when headless
+        rt.spawn(async move {
+            let mut sigterm = unix::signal(unix::SignalKind::terminate()).unwrap();
+            let mut sigint = unix::signal(unix::SignalKind::interrupt()).unwrap();
+            tokio::select! {
+                _ = sigterm.recv() => {}
+                _ = sigint.recv() => {}
+            }
+            if let Err(err) = tx_for_quit.send(Event::Quit).await {
+                error!("Cannot send Quit event to internal channel: {}", err);
+            }
+        });
+
+        // No input when headless
+        if let Some(mut input_event_stream) = input_event_stream {
+            rt.spawn(async move {
+                loop {
+                    match input_event_stream.next().await {
+                        Some(event) => {
+                            if let Err(err) = tx_for_event.send(event).await {
+                                error!("Cannot send event to internal channel: {}", err);
+                                break;
+                            }
                         }
-                    }
-                    None => {
-                        if let Err(err) = tx_for_event.send(Event::Quit).await {
-                            error!("Cannot send Quit event to internal channel: {}", err);
+                        None => {
+                            if let Err(err) = tx_for_event.send(Event::Quit).await {
+                                error!("Cannot send Quit event to internal channel: {}", err);
+                            }
+                            break;
                         }
-                        break;
                     }
                 }
-            }
-        });
+            });
+        }
 
         let local_set = tokio::task::LocalSet::new();
         local_set.block_on(&mut rt, async move {
@@ -1029,6 +2308,10 @@ impl Aparte {
 
             while let Some(event) = rx.recv().await {
                 self.schedule(event);
+                // Events received meanwhile are handled in the same batch, rendered once
+                while let Ok(event) = rx.try_recv() {
+                    self.schedule(event);
+                }
                 if self.event_loop().await.is_err() {
                     // Quit event return err
                     break;
@@ -1041,29 +2324,66 @@ impl Aparte {
         self.log(color::rainbow(WELCOME));
         self.log(format!("Version: {}", VERSION));
 
-        for (_, account) in self.config.accounts.clone() {
-            if account.autoconnect {
-                self.schedule(Event::RawCommand(
-                    None,
-                    "console".to_string(),
-                    format!("/connect {}", account.jid),
-                ));
+        match self.startup_account.clone() {
+            Some(account) => self.schedule(Event::RawCommand(
+                None,
+                "console".to_string(),
+                format!("/connect {}", account),
+            )),
+            None => {
+                for (_, account) in self.config.accounts.clone() {
+                    if account.autoconnect {
+                        self.schedule(Event::RawCommand(
+                            None,
+                            "console".to_string(),
+                            format!("/connect {}", account.jid),
+                        ));
+                    }
+                }
             }
         }
+
+        let mut commands = self.config.autoexec.clone();
+        if let Ok(rc) = fs::read_to_string(self.config_path.with_file_name("rc")) {
+            commands.extend(config::parse_rc(&rc));
+        }
+        commands.extend(self.startup_commands.drain(..));
+        self.autoexec(None, commands);
+    }
+
+    /// Account and commands given on the command line
+    pub fn set_startup(&mut self, account: Option<String>, commands: Vec<String>) {
+        self.startup_account = account;
+        self.startup_commands = commands;
+    }
+
+    /// Run commands from the console, as if typed by the user
+    fn autoexec(&mut self, account: Option<Account>, commands: Vec<String>) {
+        for command in commands {
+            self.schedule(Event::RawCommand(
+                account.clone(),
+                "console".to_string(),
+                command,
+            ));
+        }
     }
 
     pub fn send(&mut self, account: &Account, stanza: Element) {
         self.send_queue.push_back((account.clone(), stanza));
     }
 
-    async fn send_loop(&mut self) {
+    fn send_loop(&mut self) {
         for (account, stanza) in self.send_queue.drain(..) {
             let mut raw = Vec::<u8>::new();
             stanza.write_to(&mut raw).unwrap();
-            debug!("SEND: {}", String::from_utf8(raw).unwrap());
+            debug!(target: "xmpp", "SEND: {}", String::from_utf8(raw).unwrap());
+            if self.echo_stanzas {
+                self.event_queue
+                    .push(Event::SentStanza(account.clone(), stanza.clone()));
+            }
             match self.connections.get_mut(&account) {
                 Some(connection) => {
-                    if let Err(e) = connection.sink.send(stanza).await {
+                    if let Err(e) = connection.sink.send(stanza) {
                         warn!("Cannot send stanza: {}", e);
                     }
                 }
@@ -1086,113 +2406,168 @@ impl Aparte {
                 jid.with_resource(format!("aparte_{}", rand_string))
             }
             Err(err) => {
-                self.log(format!(
-                    "Cannot connect as {}: {}",
-                    connection_info.jid, err
-                ));
+                self.error(Error::Jid(connection_info.jid.clone(), err));
                 return;
             }
         };
 
         self.log(format!("Connecting as {}", account));
-        let mut client = match TokioXmppClient::new(&account.to_string(), &password.0) {
-            Ok(client) => client,
-            Err(err) => {
-                self.log(format!("Cannot connect as {}: {}", account, err));
-                return;
-            }
-        };
+        let client = Client::new(
+            account.clone(),
+            connection_info.clone(),
+            password.0.clone(),
+            TrustStore::new(&self.data_dir),
+        );
 
-        client.set_reconnect(true);
-
-        let (connection_channel, mut rx) = mpsc::channel(32);
+        let (connection_channel, mut rx) = mpsc::unbounded_channel();
 
         self.add_connection(account.clone(), connection_channel);
 
-        let (mut writer, mut reader) = client.split();
-        // XXX could use self.rt.spawn if client was impl Send
-        task::spawn_local(async move {
-            while let Some(element) = rx.recv().await {
-                if let Err(err) = writer.send(XmppPacket::Stanza(element)).await {
-                    error!("cannot send Stanza to internal channel: {}", err);
-                    break;
-                }
-            }
-        });
-
         let event_channel = match &self.event_channel {
             Some(event_channel) => event_channel.clone(),
             None => unreachable!(),
         };
 
-        let reconnect = true;
+        let connection_info = connection_info.clone();
+        let mut limiter = RateLimiter::new(
+            self.config.send.rate,
+            self.config.send.burst,
+            Instant::now(),
+        );
+        // XXX could use self.rt.spawn if client was impl Send
         task::spawn_local(async move {
-            while let Some(event) = reader.next().await {
-                debug!("XMPP Event: {:?}", event);
-                match event {
-                    XmppEvent::Disconnected(XmppError::Auth(e)) => {
+            let mut account = account;
+            // Stanzas waiting for the rate limit or for the connection to be back
+            let mut pending = VecDeque::<Element>::new();
+            loop {
+                let event = match client.connect().await {
+                    Ok((mut session, jid)) => {
                         if let Err(err) = event_channel
-                            .send(Event::AuthError(account.clone(), format!("{}", e)))
+                            .send(Event::Connected(account.clone(), jid.clone()))
                             .await
                         {
                             error!("Cannot send event to internal channel: {}", err);
-                        };
-                        break;
+                            return;
+                        }
+
+                        // Server might have assigned another JID
+                        if let Jid::Full(jid) = jid {
+                            account = jid;
+                        }
+
+                        loop {
+                            // Only messages are limited, not to slow down the session setup
+                            let delay = pending.front().map(|stanza| match stanza.name() {
+                                "message" => limiter.delay(Instant::now()),
+                                _ => Duration::from_secs(0),
+                            });
+                            tokio::select! {
+                                stanza = session.recv() => match stanza {
+                                    Some(Ok(stanza)) => {
+                                        debug!(target: "xmpp", "RECV: {}", String::from(&stanza));
+                                        if let Err(err) = event_channel
+                                            .send(Event::Stanza(account.clone(), stanza))
+                                            .await
+                                        {
+                                            error!("Cannot send stanza to internal channel: {}", err);
+                                            return;
+                                        }
+                                    }
+                                    Some(Err(err)) => {
+                                        break Event::Disconnected(account.clone(), err.to_string())
+                                    }
+                                    None => {
+                                        break Event::Disconnected(
+                                            account.clone(),
+                                            ClientError::Disconnected.to_string(),
+                                        )
+                                    }
+                                },
+                                element = rx.recv() => match element {
+                                    Some(element) => pending.push_back(element),
+                                    // Connection has been dropped
+                                    None => return,
+                                },
+                                _ = tokio::time::sleep(delay.unwrap_or_default()), if delay.is_some() => {
+                                    let stanza = pending.pop_front().unwrap();
+                                    if stanza.name() == "message" {
+                                        limiter.take(Instant::now());
+                                    }
+                                    if let Err(err) = session.send(stanza.clone()).await {
+                                        // Sent again once reconnected
+                                        pending.push_front(stanza);
+                                        break Event::Disconnected(account.clone(), err.to_string());
+                                    }
+                                }
+                            }
+                        }
                     }
-                    XmppEvent::Disconnected(e) => {
+                    Err(ClientError::Auth(err)) => {
                         if let Err(err) = event_channel
-                            .send(Event::Disconnected(account.clone(), format!("{}", e)))
+                            .send(Event::AuthError(account.clone(), err))
                             .await
                         {
                             error!("Cannot send event to internal channel: {}", err);
                         };
-                        if !reconnect {
-                            break;
-                        }
-                    }
-                    XmppEvent::Online {
-                        bound_jid: jid,
-                        resumed: true,
-                    } => {
-                        debug!("Reconnected to {}", jid);
+                        return;
                     }
-                    XmppEvent::Online {
-                        bound_jid: jid,
-                        resumed: false,
-                    } => {
+                    Err(ClientError::UntrustedCertificate {
+                        fingerprint,
+                        reason,
+                    }) => {
                         if let Err(err) = event_channel
-                            .send(Event::Connected(account.clone(), jid))
+                            .send(Event::UntrustedCertificate {
+                                account: account.clone(),
+                                connection_info: connection_info.clone(),
+                                password: password.clone(),
+                                fingerprint,
+                                reason,
+                            })
                             .await
                         {
                             error!("Cannot send event to internal channel: {}", err);
-                            break;
-                        }
-                    }
-                    XmppEvent::Stanza(stanza) => {
-                        debug!("RECV: {}", String::from(&stanza));
-                        if let Err(err) = event_channel
-                            .send(Event::Stanza(account.clone(), stanza))
-                            .await
-                        {
-                            error!("Cannot send stanza to internal channel: {}", err);
-                            break;
-                        }
+                        };
+                        return;
                     }
-                }
+                    Err(err) => Event::Disconnected(account.clone(), err.to_string()),
+                };
+
+                if let Err(err) = event_channel.send(event).await {
+                    error!("Cannot send event to internal channel: {}", err);
+                    return;
+                };
+
+                tokio::time::sleep(RECONNECT_DELAY).await;
             }
         });
     }
 
     pub async fn event_loop(&mut self) -> Result<(), ()> {
         while self.event_queue.len() > 0 {
-            let event = self.event_queue.remove(0);
+            let event = match self.event_queue.remove(0) {
+                // Mods must only ever see the JID bound by the server
+                Event::Connected(account, Jid::Full(jid)) if account != jid => {
+                    self.rebind_connection(&account, &jid);
+                    Event::Connected(jid.clone(), Jid::Full(jid))
+                }
+                event => event,
+            };
             debug!("Event: {:?}", event);
             {
                 let mods = Rc::clone(&self.mods);
-                for (_, r#mod) in mods.iter() {
-                    r#mod.borrow_mut().on_event(self, &event);
+                self.event_consumed = false;
+                for type_id in self.mods_order.clone() {
+                    mods[&type_id].borrow_mut().on_event(self, &event);
+                    if self.event_consumed {
+                        debug!("Event consumed by {:?}", mods[&type_id]);
+                        break;
+                    }
                 }
-                self.send_loop().await;
+                self.send_loop();
+            }
+
+            if self.event_consumed {
+                continue;
             }
 
             match event {
@@ -1219,11 +2594,40 @@ impl Aparte {
                     self.connect(&account, password).await;
                 }
                 Event::Connected(account, _) => {
-                    self.log(format!("Connected as {}", account));
+                    let reconnected = match self.connections.get_mut(&account) {
+                        Some(connection) => std::mem::replace(&mut connection.connected, true),
+                        None => false,
+                    };
+                    // A new session needs the initial presence again, account commands only run
+                    // on the first connection
                     let mut presence = Presence::new(PresenceType::None);
                     presence.show = Some(PresenceShow::Chat);
-
                     self.send(&account, presence.into());
+                    if reconnected {
+                        self.log(format!("Reconnected as {}", account));
+                    } else {
+                        self.log(format!("Connected as {}", account));
+                        let bare: BareJid = account.clone().into();
+                        let commands = self
+                            .config
+                            .accounts
+                            .values()
+                            .filter(|info| BareJid::from_str(&info.jid).ok() == Some(bare.clone()))
+                            .flat_map(|info| info.autoexec.clone())
+                            .collect();
+                        self.autoexec(Some(account), commands);
+                    }
+                }
+                Event::SetPresence(show, status) => {
+                    let accounts = self.connections.keys().cloned().collect::<Vec<_>>();
+                    for account in accounts {
+                        let mut presence = Presence::new(PresenceType::None);
+                        presence.show = show.clone();
+                        if let Some(status) = &status {
+                            presence.statuses.insert(String::new(), status.clone());
+                        }
+                        self.send(&account, presence.into());
+                    }
                 }
                 Event::Disconnected(account, err) => {
                     self.log(format!("Connection lost for {}: {}", account, err));
@@ -1231,6 +2635,24 @@ impl Aparte {
                 Event::AuthError(account, err) => {
                     self.log(format!("Authentication error for {}: {}", account, err));
                 }
+                Event::UntrustedCertificate {
+                    account,
+                    connection_info,
+                    password,
+                    fingerprint,
+                    reason,
+                } => {
+                    self.log(format!(
+                        "Certificate of {} is not trusted: {}",
+                        account.domain, reason
+                    ));
+                    self.log(format!(
+                        "SHA-256 fingerprint: {}\nTrust this certificate? Use /trust to accept it and reconnect.",
+                        fingerprint
+                    ));
+                    self.untrusted_certificate =
+                        Some((account, connection_info, password, fingerprint));
+                }
                 Event::Stanza(account, stanza) => {
                     self.handle_stanza(account, stanza);
                 }
@@ -1240,21 +2662,21 @@ impl Aparte {
                 Event::Join {
                     account,
                     channel,
+                    password,
                     user_request,
                 } => {
                     let to = match channel.clone() {
                         Jid::Full(jid) => jid,
-                        Jid::Bare(jid) => {
-                            let node = account.node.clone().unwrap();
-                            jid.with_resource(node)
-                        }
+                        Jid::Bare(jid) => jid.with_resource(self.default_nick(&account)),
                     };
                     let from: Jid = account.clone().into();
 
+                    let mut muc = Muc::new();
+                    muc.password = password;
                     let mut presence = Presence::new(PresenceType::None);
                     presence = presence.with_to(Jid::Full(to.clone()));
                     presence = presence.with_from(from);
-                    presence.add_payload(Muc::new());
+                    presence.add_payload(muc);
                     self.send(&account, presence.into());
 
                     // Successful join
@@ -1265,11 +2687,15 @@ impl Aparte {
                         user_request,
                     });
                 }
-                Event::Leave(channel) => {
+                Event::Leave(channel, status) => {
                     // Send presence in the channel
                     let mut presence = Presence::new(PresenceType::Unavailable);
-                    presence = presence.with_to(channel.jid.clone());
+                    let to = channel.jid.clone().with_resource(channel.nick.clone());
+                    presence = presence.with_to(Jid::Full(to));
                     presence = presence.with_from(channel.account.clone());
+                    if let Some(status) = status {
+                        presence.statuses.insert(String::new(), status);
+                    }
                     presence.add_payload(Muc::new());
                     self.send(&channel.account, presence.into());
                 }
@@ -1278,9 +2704,10 @@ impl Aparte {
                 }
                 _ => {}
             }
-            self.send_loop().await;
+            self.send_loop();
         }
 
+        self.get_mod_mut::<mods::ui::UIMod>().render();
         Ok(())
     }
 
@@ -1288,11 +2715,91 @@ impl Aparte {
         self.event_queue.push(event);
     }
 
+    /// Run a future in background, the event it resolves to is then scheduled
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = Event> + 'static,
+    {
+        let event_channel = match &self.event_channel {
+            Some(event_channel) => event_channel.clone(),
+            None => unreachable!(),
+        };
+
+        task::spawn_local(async move {
+            let event = future.await;
+            if let Err(err) = event_channel.send(event).await {
+                error!("Cannot send event to internal channel: {}", err);
+            }
+        });
+    }
+
+    /// Channel to schedule events from background tasks
+    pub fn event_sender(&self) -> mpsc::Sender<Event> {
+        match &self.event_channel {
+            Some(event_channel) => event_channel.clone(),
+            None => unreachable!(),
+        }
+    }
+
+    /// Change a setting, notify mods and save it to the config file
+    pub fn set(&mut self, key: &str, raw: &str) -> Result<(), String> {
+        let value = self.settings.parse(key, raw)?;
+        self.settings.set(key, value.clone())?;
+        self.schedule(Event::Setting(key.to_string(), value.clone()));
+
+        let config = fs::read_to_string(&self.config_path)
+            .map_err(|err| format!("Cannot read config file: {}", err))?;
+        let config = settings::persist(&config, key, &value)?;
+        fs::write(&self.config_path, config)
+            .map_err(|err| format!("Cannot save config file: {}", err))
+    }
+
+    /// Take the handle of the logger, applying the configured levels unless RUST_LOG is set
+    pub fn set_logger(&mut self, logger: ReconfigurationHandle) {
+        self.logger = Some(logger);
+        if env::var("RUST_LOG").is_err() {
+            if let Some(level) = self.config.log.level.clone() {
+                if let Err(err) = self.set_log_level(&level) {
+                    self.log(err);
+                }
+            }
+        }
+    }
+
+    pub fn set_log_level(&mut self, level: &str) -> Result<(), String> {
+        let specification = LogSpecification::parse(level)
+            .map_err(|err| format!("Invalid log level {}: {}", level, err))?;
+        match &mut self.logger {
+            Some(logger) => logger.set_new_spec(specification),
+            None => return Err("Logging is disabled".to_string()),
+        }
+        self.log_level = level.to_string();
+        Ok(())
+    }
+
     pub fn log(&mut self, message: String) {
         let message = Message::log(message);
         self.schedule(Event::Message(None, message));
     }
 
+    /// Nick used in channels joined without one: the configured one, or the JID local part
+    fn default_nick(&self, account: &Account) -> String {
+        let bare: BareJid = account.clone().into();
+        let configured = self
+            .config
+            .accounts
+            .values()
+            .find(|info| BareJid::from_str(&info.jid).map_or(false, |jid| jid == bare))
+            .and_then(|info| info.nick.clone());
+        configured.unwrap_or(account.node.clone().unwrap_or(account.resource.clone()))
+    }
+
+    /// Report an error in the log file and in the console
+    pub fn error(&mut self, err: Error) {
+        error!("{}", err);
+        self.log(err.to_string());
+    }
+
     fn handle_stanza(&mut self, account: Account, stanza: Element) {
         if let Ok(message) = XmppParsersMessage::try_from(stanza.clone()) {
             self.handle_xmpp_message(account, message, None);
@@ -1339,3 +2846,23 @@ impl Aparte {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s"), Ok(chrono::Duration::seconds(45)));
+        assert_eq!(parse_duration("2h"), Ok(chrono::Duration::hours(2)));
+        assert_eq!(parse_duration("1h30m"), Ok(chrono::Duration::minutes(90)));
+        assert_eq!(parse_duration("1d"), Ok(chrono::Duration::days(1)));
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("2w").is_err());
+        assert!(parse_duration("0m").is_err());
+        assert!(parse_duration("9223372036854775807d").is_err());
+        assert!(parse_duration("100000000000d100000000000d").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+    }
+}
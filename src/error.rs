@@ -0,0 +1,66 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::io::Error as IoError;
+use xmpp_parsers::JidParseError;
+
+/// Errors of aparté itself, as opposed to those of commands which are plain messages
+#[derive(Debug)]
+pub enum Error {
+    Io(IoError),
+    Config(String),
+    /// The log file cannot be set up
+    Log(String),
+    Jid(String, JidParseError),
+    /// The terminal cannot be set up or queried
+    Terminal(IoError),
+    /// A standard directory is unknown on this system
+    Directory(&'static str),
+    /// A mod failed to start
+    Init,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Config(err) => write!(f, "Invalid configuration: {}", err),
+            Error::Log(err) => write!(f, "Cannot start logger: {}", err),
+            Error::Jid(jid, err) => write!(f, "Invalid JID {}: {:?}", jid, err),
+            Error::Terminal(err) => write!(f, "Terminal error: {}", err),
+            Error::Directory(name) => write!(f, "Cannot find the {} directory", name),
+            Error::Init => write!(f, "Cannot initialize aparté"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// Commands report errors as messages
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use xmpp_parsers::BareJid;
+
+    #[test]
+    fn test_error_to_command_error() {
+        let jid = "@example.org";
+        let err = BareJid::from_str(jid).map_err(|err| Error::Jid(jid.to_string(), err));
+        let message: Result<BareJid, String> = err.map_err(String::from);
+        assert!(message.unwrap_err().starts_with("Invalid JID @example.org"));
+    }
+}
@@ -11,6 +11,7 @@ extern crate dirs;
 extern crate flexi_logger;
 extern crate futures;
 extern crate rpassword;
+extern crate structopt;
 extern crate tokio;
 extern crate tokio_xmpp;
 extern crate xmpp_parsers;
@@ -18,6 +19,8 @@ extern crate xmpp_parsers;
 #[macro_use]
 mod terminus;
 mod account;
+mod aesgcm;
+mod client;
 mod config;
 mod contact;
 mod conversation;
@@ -27,45 +30,147 @@ mod message;
 mod command;
 mod color;
 mod cursor;
+mod error;
 mod i18n;
 mod mods;
+mod ratelimit;
+mod screen;
+mod settings;
+mod theme;
+mod url;
 mod word;
 
+use flexi_logger::writers::LogWriter;
+use flexi_logger::{Cleanup, Criterion, DeferredNow, Naming};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use structopt::StructOpt;
+
 use crate::core::Aparte;
+use crate::error::Error;
+use crate::mods::ui::DisplayMode;
 
-fn main() {
-    let data_dir = dirs::data_dir().unwrap();
-    let aparte_data = data_dir.join("aparte");
+/// Size above which the log file is rotated
+const LOG_FILE_SIZE: u64 = 10 * 1024 * 1024;
+/// Number of rotated log files kept
+const LOG_FILES: usize = 4;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "aparte", about = "Simple XMPP console client")]
+struct Args {
+    /// Configuration file, instead of ~/.config/aparte/config.toml
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Connect to this account instead of those with autoconnect
+    #[structopt(long)]
+    account: Option<String>,
+    /// Log everything at debug level to this file
+    #[structopt(long, parse(from_os_str))]
+    debug: Option<PathBuf>,
+    /// Print messages one after the other without colors, for screen readers
+    #[structopt(long)]
+    accessible: bool,
+    /// Print messages one after the other in the terminal scrollback
+    #[structopt(long, conflicts_with = "accessible")]
+    plain: bool,
+    /// Run without terminal, driven by the control socket, DBus and scripts
+    #[structopt(long, conflicts_with_all = &["accessible", "plain"])]
+    headless: bool,
+    /// Commands run on startup, after those of the configuration
+    commands: Vec<String>,
+}
+
+/// Log writer appending to a single file, without rotation
+struct DebugLogWriter(Mutex<File>);
 
-    if let Err(e) = std::fs::create_dir_all(&aparte_data) {
-        panic!("Cannot create aparte data dir: {}", e);
+impl LogWriter for DebugLogWriter {
+    fn write(&self, now: &mut DeferredNow, record: &log::Record) -> std::io::Result<()> {
+        let mut file = self.0.lock().unwrap();
+        flexi_logger::detailed_format(&mut *file, now, record)?;
+        writeln!(file)
     }
 
-    let file_writer = flexi_logger::writers::FileLogWriter::builder()
-        .directory(aparte_data)
-        .suppress_timestamp()
-        .try_build()
-        .unwrap();
-    let log_target = flexi_logger::LogTarget::Writer(Box::new(file_writer));
-    let logger = flexi_logger::Logger::with_env_or_str("info").log_target(log_target);
-    if let Err(e) = logger.start() {
-        panic!("Cannot start logger: {}", e);
+    fn flush(&self) -> std::io::Result<()> {
+        self.0.lock().unwrap().flush()
     }
 
-    let conf_dir = dirs::config_dir().unwrap();
-    let aparte_conf = conf_dir.join("aparte");
+    fn max_log_level(&self) -> log::LevelFilter {
+        log::LevelFilter::Trace
+    }
+}
+
+fn main() {
+    let args = Args::from_args();
 
-    if let Err(e) = std::fs::create_dir_all(&aparte_conf) {
-        panic!("Cannot create aparte data dir: {}", e);
+    if let Err(err) = run(args) {
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
+}
+
+fn run(args: Args) -> Result<(), Error> {
+    let aparte_data = dirs::data_dir()
+        .ok_or(Error::Directory("data"))?
+        .join("aparte");
+    let aparte_conf = dirs::config_dir()
+        .ok_or(Error::Directory("configuration"))?
+        .join("aparte");
+
+    std::fs::create_dir_all(&aparte_data)?;
+
+    let log_target = match &args.debug {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            flexi_logger::LogTarget::Writer(Box::new(DebugLogWriter(Mutex::new(file))))
+        }
+        None => {
+            let file_writer = flexi_logger::writers::FileLogWriter::builder()
+                .directory(&aparte_data)
+                .suppress_timestamp()
+                .format(flexi_logger::detailed_format)
+                .rotate(
+                    Criterion::Size(LOG_FILE_SIZE),
+                    Naming::Numbers,
+                    Cleanup::KeepLogFiles(LOG_FILES),
+                )
+                .try_build()
+                .map_err(|err| Error::Log(err.to_string()))?;
+            flexi_logger::LogTarget::Writer(Box::new(file_writer))
+        }
+    };
+    let logger = flexi_logger::Logger::with_env_or_str("info").log_target(log_target);
+    let logger = logger.start().map_err(|err| Error::Log(err.to_string()))?;
 
-    let config = aparte_conf.join("config.toml");
+    let config = match args.config {
+        Some(config) => config,
+        None => {
+            std::fs::create_dir_all(&aparte_conf)?;
+
+            aparte_conf.join("config.toml")
+        }
+    };
 
     info!("Starting aparté");
 
-    let mut aparte = Aparte::new(config);
+    let display = match (args.accessible, args.plain, args.headless) {
+        (true, _, _) => DisplayMode::Accessible,
+        (_, true, _) => DisplayMode::Plain,
+        (_, _, true) => DisplayMode::Headless,
+        _ => DisplayMode::Fullscreen,
+    };
+
+    let mut aparte = Aparte::new(config, aparte_data, aparte_conf, display)?;
+    aparte.set_logger(logger);
+    if args.debug.is_some() {
+        aparte.set_log_level("debug").map_err(Error::Log)?;
+    }
+    aparte.set_startup(args.account, args.commands);
 
-    aparte.init().unwrap();
+    aparte.init().map_err(|_| Error::Init)?;
 
     aparte.run();
+
+    Ok(())
 }
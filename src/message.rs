@@ -3,17 +3,72 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use chrono::{DateTime, FixedOffset, Local as LocalTz};
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
 use std::hash;
+use std::str::FromStr;
+use unicode_segmentation::UnicodeSegmentation;
 use uuid::Uuid;
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType as XmppParsersMessageType};
-use xmpp_parsers::{BareJid, Jid};
+use xmpp_parsers::message_correct::Replace;
+use xmpp_parsers::{BareJid, Element, Jid};
 
 use crate::account::Account;
 use crate::i18n;
 
+pub const NS_REPLY: &str = "urn:xmpp:reply:0";
+pub const NS_FALLBACK: &str = "urn:xmpp:fallback:0";
+
+/// Number of messages remembered as seen by `LiveMessages`, older ones are forgotten
+const SEEN_COUNT: usize = 1024;
+
+/// Incoming messages received live, each one once. Messages older than its creation come from
+/// archives, corrections come back with the id of the message they correct.
+pub struct LiveMessages {
+    started: DateTime<FixedOffset>,
+    /// Messages already seen by account, sender and id
+    seen: HashSet<(Option<Account>, String, String)>,
+    /// Seen messages, oldest first
+    order: VecDeque<(Option<Account>, String, String)>,
+}
+
+impl LiveMessages {
+    pub fn new() -> Self {
+        Self {
+            started: LocalTz::now().into(),
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Whether the message is live and seen for the first time, it is then remembered
+    pub fn first_seen(
+        &mut self,
+        account: &Option<Account>,
+        message: &VersionedXmppMessage,
+    ) -> bool {
+        if message.get_original_timestamp() < &self.started {
+            return false;
+        }
+        let key = (
+            account.clone(),
+            message.from_full.to_string(),
+            message.id.clone(),
+        );
+        if !self.seen.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > SEEN_COUNT {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct XmppMessageVersion {
     pub id: String,
@@ -46,8 +101,9 @@ impl PartialOrd for XmppMessageVersion {
 }
 
 impl XmppMessageVersion {
-    pub fn get_best_body<'a>(&'a self, prefered_langs: Vec<&str>) -> &'a String {
-        i18n::get_best(&self.bodies, prefered_langs).unwrap().1
+    /// Body in the prefered language, empty for versions without body like some corrections
+    pub fn get_best_body<'a>(&'a self, prefered_langs: Vec<&str>) -> &'a str {
+        i18n::get_best(&self.bodies, prefered_langs).map_or("", |(_, body)| body)
     }
 }
 
@@ -61,6 +117,114 @@ pub struct VersionedXmppMessage {
     pub history: Vec<XmppMessageVersion>,
     pub type_: XmppMessageType,
     pub direction: Direction,
+    /// Resource of another of our clients an outgoing message was sent from, as told by carbons
+    pub device: Option<String>,
+    /// Message this one replies to (XEP-0461)
+    pub reply: Option<Reply>,
+}
+
+/// Reference to a replied message, with the text it quotes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub id: String,
+    pub to: Option<Jid>,
+    pub quote: String,
+}
+
+impl Reply {
+    pub fn new(message: &VersionedXmppMessage) -> Self {
+        Self {
+            id: message.id.clone(),
+            to: Some(message.from_full.clone()),
+            quote: message.get_last_body().to_string(),
+        }
+    }
+
+    /// Reply of a received message, and its bodies without the fallback quoting the replied
+    /// message, which is used as quote
+    fn parse(
+        message: &XmppParsersMessage,
+        mut bodies: HashMap<String, String>,
+    ) -> (HashMap<String, String>, Option<Self>) {
+        let reply = message
+            .payloads
+            .iter()
+            .find(|payload| payload.is("reply", NS_REPLY));
+        let (id, to) = match reply.and_then(|reply| reply.attr("id")) {
+            Some(id) => (
+                id.to_string(),
+                reply
+                    .and_then(|reply| reply.attr("to"))
+                    .and_then(|to| Jid::from_str(to).ok()),
+            ),
+            None => return (bodies, None),
+        };
+
+        // Fallback offsets are counted in characters
+        let range = message
+            .payloads
+            .iter()
+            .find(|payload| {
+                payload.is("fallback", NS_FALLBACK) && payload.attr("for") == Some(NS_REPLY)
+            })
+            .and_then(|fallback| fallback.get_child("body", NS_FALLBACK))
+            .and_then(|body| {
+                let start = body.attr("start")?.parse::<usize>().ok()?;
+                let end = body.attr("end")?.parse::<usize>().ok()?;
+                Some((start, end))
+            });
+        let mut quote = String::new();
+        if let Some((start, end)) = range {
+            for body in bodies.values_mut() {
+                let fallback = body.chars().skip(start).take(end.saturating_sub(start));
+                quote = fallback
+                    .collect::<String>()
+                    .lines()
+                    .map(|line| line.trim_start_matches('>').trim())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+                    .trim()
+                    .to_string();
+                *body = body
+                    .chars()
+                    .take(start)
+                    .chain(body.chars().skip(end))
+                    .collect();
+            }
+        }
+
+        (bodies, Some(Self { id, to, quote }))
+    }
+
+    /// Add the reply reference and the fallback quoting the replied message to an outgoing
+    /// message
+    fn add_to(&self, message: &mut XmppParsersMessage) {
+        let fallback: String = self
+            .quote
+            .lines()
+            .map(|line| format!("> {}\n", line))
+            .collect();
+        for body in message.bodies.values_mut() {
+            body.0 = format!("{}{}", fallback, body.0);
+        }
+
+        let mut reply = Element::builder("reply", NS_REPLY).attr("id", self.id.clone());
+        if let Some(to) = &self.to {
+            reply = reply.attr("to", to.to_string());
+        }
+        message.payloads.push(reply.build());
+        message.payloads.push(
+            Element::builder("fallback", NS_FALLBACK)
+                .attr("for", NS_REPLY)
+                .append(
+                    Element::builder("body", NS_FALLBACK)
+                        .attr("start", "0")
+                        .attr("end", fallback.chars().count().to_string())
+                        .build(),
+                )
+                .build(),
+        );
+    }
 }
 
 impl VersionedXmppMessage {
@@ -70,7 +234,7 @@ impl VersionedXmppMessage {
     }
     pub fn get_last_body<'a>(&'a self) -> &'a str {
         let last = self.history.iter().max().unwrap();
-        &last.get_best_body(vec![])
+        last.get_best_body(vec![])
     }
 
     pub fn get_original_timestamp<'a>(&'a self) -> &'a DateTime<FixedOffset> {
@@ -108,6 +272,20 @@ impl VersionedXmppMessage {
     pub fn has_multiple_version(&self) -> bool {
         self.history.len() > 1
     }
+
+    /// Send the last version of a corrected message as a correction (XEP-0308) of the original
+    fn add_correction_to(&self, message: &mut XmppParsersMessage) {
+        if self.has_multiple_version() {
+            let last = self.history.iter().max().unwrap();
+            message.id = Some(last.id.clone());
+            message.payloads.push(
+                Replace {
+                    id: self.id.clone(),
+                }
+                .into(),
+            );
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -151,6 +329,7 @@ impl Message {
                 .iter()
                 .map(|(lang, body)| (lang.clone(), body.0.clone()))
                 .collect();
+            let (bodies, reply) = Reply::parse(message, bodies);
             let delay = match delay {
                 Some(delay) => Some(delay.clone()),
                 None => message
@@ -169,7 +348,7 @@ impl Message {
                     if from.clone().node() == account.node
                         && from.clone().domain() == account.domain
                     {
-                        Ok(Message::outgoing_chat(
+                        let mut message = Message::outgoing_chat(
                             id,
                             delay
                                 .map(|delay| delay.stamp.0)
@@ -177,7 +356,13 @@ impl Message {
                             &from,
                             &to,
                             &bodies,
-                        ))
+                        );
+                        if let (Message::Xmpp(message), Jid::Full(from)) = (&mut message, &from) {
+                            if from.resource != account.resource {
+                                message.device = Some(from.resource.clone());
+                            }
+                        }
+                        Ok(message)
                     } else {
                         Ok(Message::incoming_chat(
                             id,
@@ -201,6 +386,12 @@ impl Message {
                 )),
                 _ => Err(()),
             }
+            .map(|mut message| {
+                if let Message::Xmpp(message) = &mut message {
+                    message.reply = reply;
+                }
+                message
+            })
         } else {
             Err(())
         }
@@ -290,6 +481,8 @@ impl Message {
             history: vec![version],
             type_: XmppMessageType::Chat,
             direction: Direction::Incoming,
+            device: None,
+            reply: None,
         })
     }
 
@@ -327,6 +520,8 @@ impl Message {
             history: vec![version],
             type_: XmppMessageType::Chat,
             direction: Direction::Outgoing,
+            device: None,
+            reply: None,
         })
     }
 
@@ -364,6 +559,8 @@ impl Message {
             history: vec![version],
             type_: XmppMessageType::Channel,
             direction: Direction::Incoming,
+            device: None,
+            reply: None,
         })
     }
 
@@ -401,6 +598,8 @@ impl Message {
             history: vec![version],
             type_: XmppMessageType::Channel,
             direction: Direction::Outgoing,
+            device: None,
+            reply: None,
         })
     }
 
@@ -437,6 +636,31 @@ impl Message {
     }
 }
 
+/// Rows aligned under their headers, for listings in log messages
+pub fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let width = |cell: &str| cell.graphemes(true).count();
+    let mut widths: Vec<usize> = headers.iter().map(|header| width(header)).collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(width(cell));
+        }
+    }
+
+    let headers: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+    std::iter::once(&headers)
+        .chain(rows.iter())
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(widths.iter())
+                .map(|(cell, size)| format!("{}{}", cell, " ".repeat(size - width(cell))))
+                .collect();
+            cells.join("  ").trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 impl hash::Hash for Message {
     fn hash<H: hash::Hasher>(&self, state: &mut H) {
         self.id().hash(state)
@@ -487,6 +711,10 @@ impl TryFrom<Message> for xmpp_parsers::Element {
                                 (lang.clone(), xmpp_parsers::message::Body(body.clone()))
                             })
                             .collect();
+                        if let Some(reply) = &message.reply {
+                            reply.add_to(&mut xmpp_message);
+                        }
+                        message.add_correction_to(&mut xmpp_message);
                         Ok(xmpp_message.into())
                     }
                     XmppMessageType::Channel => {
@@ -501,6 +729,10 @@ impl TryFrom<Message> for xmpp_parsers::Element {
                                 (lang.clone(), xmpp_parsers::message::Body(body.clone()))
                             })
                             .collect();
+                        if let Some(reply) = &message.reply {
+                            reply.add_to(&mut xmpp_message);
+                        }
+                        message.add_correction_to(&mut xmpp_message);
                         Ok(xmpp_message.into())
                     }
                 },
@@ -509,3 +741,168 @@ impl TryFrom<Message> for xmpp_parsers::Element {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_message_without_body() {
+        let contact = Jid::from_str("contact@example.org/phone").unwrap();
+        let us = Jid::from_str("me@example.org/aparte").unwrap();
+        let message =
+            Message::incoming_chat("1", LocalTz::now().into(), &contact, &us, &HashMap::new());
+
+        match message {
+            Message::Xmpp(message) => assert_eq!(message.get_last_body(), ""),
+            _ => panic!("Not an xmpp message"),
+        }
+    }
+
+    #[test]
+    fn test_message_sent_from_other_device() {
+        // Given
+        let account = Account::from_str("me@example.org/aparte").unwrap();
+        let carbon: xmpp_parsers::Element = "<message xmlns='jabber:client' type='chat' from='me@example.org/phone' to='contact@example.org'><body>hi</body></message>".parse().unwrap();
+        let carbon = XmppParsersMessage::try_from(carbon).unwrap();
+
+        // When
+        let message = Message::from_xmpp(&account, &carbon, &None).unwrap();
+
+        // Then
+        match message {
+            Message::Xmpp(message) => {
+                assert_eq!(message.direction, Direction::Outgoing);
+                assert_eq!(message.to.to_string(), "contact@example.org");
+                assert_eq!(message.device, Some("phone".to_string()));
+            }
+            _ => panic!("Not an xmpp message"),
+        }
+    }
+
+    #[test]
+    fn test_reply() {
+        // Given
+        let account = Account::from_str("me@example.org/aparte").unwrap();
+        let incoming: xmpp_parsers::Element = "<message xmlns='jabber:client' type='chat' from='contact@example.org/phone' to='me@example.org/aparte' id='2'><body>&gt; how are you?\nfine</body><reply xmlns='urn:xmpp:reply:0' id='1' to='me@example.org/aparte'/><fallback xmlns='urn:xmpp:fallback:0' for='urn:xmpp:reply:0'><body start='0' end='15'/></fallback></message>".parse().unwrap();
+        let incoming = XmppParsersMessage::try_from(incoming).unwrap();
+
+        // When
+        let message = Message::from_xmpp(&account, &incoming, &None).unwrap();
+
+        // Then
+        let message = match message {
+            Message::Xmpp(message) => message,
+            _ => panic!("Not an xmpp message"),
+        };
+        assert_eq!(message.get_last_body(), "fine");
+        let reply = message.reply.clone().unwrap();
+        assert_eq!(reply.id, "1");
+        assert_eq!(reply.quote, "how are you?");
+
+        // When
+        let us = Jid::Full(account.clone());
+        let contact = Jid::from_str("contact@example.org/phone").unwrap();
+        let mut bodies = HashMap::new();
+        bodies.insert(String::new(), "good".to_string());
+        let mut answer = Message::outgoing_chat("3", LocalTz::now().into(), &us, &contact, &bodies);
+        if let Message::Xmpp(answer) = &mut answer {
+            answer.reply = Some(Reply::new(&message));
+        }
+        let element = xmpp_parsers::Element::try_from(answer).unwrap();
+
+        // Then
+        let sent = XmppParsersMessage::try_from(element).unwrap();
+        assert_eq!(sent.bodies[""].0, "> fine\ngood");
+        let (bodies, reply) = Reply::parse(
+            &sent,
+            [(String::new(), sent.bodies[""].0.clone())]
+                .iter()
+                .cloned()
+                .collect(),
+        );
+        assert_eq!(bodies[""], "good");
+        assert_eq!(reply.unwrap().quote, "fine");
+    }
+
+    #[test]
+    fn test_sent_correction() {
+        // Given
+        let us = Jid::from_str("me@example.org/aparte").unwrap();
+        let contact = Jid::from_str("contact@example.org").unwrap();
+        let bodies = |body: &str| {
+            let mut bodies = HashMap::new();
+            bodies.insert(String::new(), body.to_string());
+            bodies
+        };
+        let mut message = match Message::outgoing_chat(
+            "1",
+            LocalTz::now().into(),
+            &us,
+            &contact,
+            &bodies("tpyo"),
+        ) {
+            Message::Xmpp(message) => message,
+            _ => panic!("Not an xmpp message"),
+        };
+
+        // When
+        message.history.push(XmppMessageVersion {
+            id: "2".to_string(),
+            timestamp: LocalTz::now().into(),
+            bodies: bodies("typo"),
+        });
+        let element = xmpp_parsers::Element::try_from(Message::Xmpp(message)).unwrap();
+
+        // Then
+        let sent = XmppParsersMessage::try_from(element).unwrap();
+        assert_eq!(sent.id, Some("2".to_string()));
+        assert_eq!(sent.bodies[""].0, "typo");
+        let replace = Replace::try_from(sent.payloads[0].clone()).unwrap();
+        assert_eq!(replace.id, "1");
+    }
+
+    #[test]
+    fn test_live_messages() {
+        // Given
+        let mut live = LiveMessages::new();
+        let account = Some(Account::from_str("romeo@montague.lit/aparte").unwrap());
+        let us = Jid::from_str("romeo@montague.lit/aparte").unwrap();
+        let juliet = Jid::from_str("juliet@capulet.lit/balcony").unwrap();
+        let tybalt = Jid::from_str("tybalt@capulet.lit/street").unwrap();
+        let mut bodies = HashMap::new();
+        bodies.insert(String::new(), "Good night".to_string());
+        let started = live.started;
+        let message = |id: &str, from: &Jid, timestamp| match Message::incoming_chat(
+            id, timestamp, from, &us, &bodies,
+        ) {
+            Message::Xmpp(message) => message,
+            _ => unreachable!(),
+        };
+        let first = message("1", &juliet, started);
+        let same_id = message("1", &tybalt, started);
+        let messages: Vec<_> = (0..SEEN_COUNT)
+            .map(|id| message(&format!("other-{}", id), &juliet, started))
+            .collect();
+        let archived = message("2", &juliet, started - chrono::Duration::minutes(1));
+
+        // When
+        let seen = live.first_seen(&account, &first);
+        let seen_again = live.first_seen(&account, &first);
+        let other_sender = live.first_seen(&account, &same_id);
+        for message in &messages {
+            live.first_seen(&account, message);
+        }
+        let forgotten = live.first_seen(&account, &first);
+        let from_archive = live.first_seen(&account, &archived);
+
+        // Then
+        assert!(seen);
+        assert!(!seen_again);
+        assert!(other_sender);
+        assert!(forgotten);
+        assert!(!from_archive);
+        assert_eq!(live.seen.len(), SEEN_COUNT);
+    }
+}
@@ -0,0 +1,409 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::{BareJid, Element, Jid};
+
+use crate::command::{Command, CommandParser};
+use crate::conversation::Channel;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{table, Message};
+use crate::mods::conversation::ConversationMod;
+
+const NS_MUC_ADMIN: &str = "http://jabber.org/protocol/muc#admin";
+
+/// Affiliations that can be listed, all of them can be given along with "none"
+const AFFILIATIONS: [&str; 4] = ["owner", "admin", "member", "outcast"];
+
+command_def!(
+    affiliations,
+    r#"/affiliations [<affiliation>]
+
+    affiliation   One of owner, admin, member or outcast, all of them by default
+
+Description:
+    List the affiliations of the current channel. Only owners and admins are
+    usually allowed to.
+
+Example:
+    /affiliations
+    /affiliations outcast
+"#,
+    {
+        affiliation: Option<String> = {
+            completion: (|_aparte, _command| {
+                AFFILIATIONS.iter().map(|affiliation| affiliation.to_string()).collect()
+            })
+        }
+    },
+    |aparte, command| {
+        let channel = ConversationMod::current_channel(aparte, &command)?;
+        let affiliations = match affiliation {
+            Some(affiliation) if AFFILIATIONS.contains(&affiliation.as_str()) => {
+                vec![affiliation]
+            }
+            Some(affiliation) => {
+                return Err(format!(
+                    "Unknown affiliation {}, expected one of {}",
+                    affiliation,
+                    AFFILIATIONS.join(", ")
+                ))
+            }
+            None => AFFILIATIONS
+                .iter()
+                .map(|affiliation| affiliation.to_string())
+                .collect(),
+        };
+        AffiliationsMod::list(aparte, &channel, affiliations, command.context.clone());
+        Ok(())
+    }
+);
+
+command_def!(
+    affiliate,
+    r#"/affiliate <jid> <affiliation> [<reason>]
+
+    jid           Address of the user, or nick of an occupant whose address is known
+    affiliation   One of owner, admin, member, outcast or none
+    reason        Optional reason given to the user
+
+Description:
+    Change the affiliation of a user in the current channel. An outcast is
+    banned, none removes any affiliation.
+
+Example:
+    /affiliate romeo@montague.lit member
+    /affiliate Tybalt outcast "Too quarrelsome"
+"#,
+    {
+        jid: String = {
+            completion: (|aparte, command| {
+                match ConversationMod::current_channel(aparte, &command) {
+                    Ok(channel) => channel
+                        .occupants
+                        .values()
+                        .filter(|occupant| occupant.jid.is_some())
+                        .map(|occupant| occupant.nick.clone())
+                        .collect(),
+                    Err(_) => Vec::new(),
+                }
+            })
+        },
+        affiliation: String = {
+            completion: (|_aparte, _command| {
+                let mut affiliations: Vec<String> =
+                    AFFILIATIONS.iter().map(|affiliation| affiliation.to_string()).collect();
+                affiliations.push("none".to_string());
+                affiliations
+            })
+        },
+        reason: Option<String>
+    },
+    |aparte, command| {
+        let channel = ConversationMod::current_channel(aparte, &command)?;
+        if !AFFILIATIONS.contains(&affiliation.as_str()) && affiliation != "none" {
+            return Err(format!(
+                "Unknown affiliation {}, expected one of {} or none",
+                affiliation,
+                AFFILIATIONS.join(", ")
+            ));
+        }
+        let user = match channel.occupants.get(&jid) {
+            Some(occupant) => occupant
+                .jid
+                .clone()
+                .ok_or(format!("The address of {} is unknown", jid))?,
+            None => BareJid::from_str(&jid)
+                .map_err(|err| format!("Invalid JID {}: {}", jid, err))?,
+        };
+        let window = command.context.clone();
+        AffiliationsMod::affiliate(aparte, &channel, user, affiliation, reason, window);
+        Ok(())
+    }
+);
+
+/// One line of an affiliation list
+#[derive(Debug, Clone, PartialEq)]
+struct Item {
+    jid: String,
+    affiliation: String,
+    nick: Option<String>,
+    reason: Option<String>,
+}
+
+impl Item {
+    fn parse(query: &Element) -> Vec<Item> {
+        query
+            .children()
+            .filter(|child| child.is("item", NS_MUC_ADMIN))
+            .filter_map(|item| {
+                Some(Item {
+                    jid: item.attr("jid")?.to_string(),
+                    affiliation: item.attr("affiliation")?.to_string(),
+                    nick: item.attr("nick").map(|nick| nick.to_string()),
+                    reason: item
+                        .get_child("reason", NS_MUC_ADMIN)
+                        .map(|reason| reason.text())
+                        .filter(|reason| !reason.is_empty()),
+                })
+            })
+            .collect()
+    }
+}
+
+/// An affiliation list waiting for the answers to its queries
+struct Listing {
+    channel: BareJid,
+    window: String,
+    pending: usize,
+    items: Vec<Item>,
+    errors: Vec<String>,
+}
+
+impl Listing {
+    fn summary(&self) -> String {
+        let mut items = self.items.clone();
+        items.sort_by_key(|item| {
+            let rank = AFFILIATIONS
+                .iter()
+                .position(|affiliation| *affiliation == item.affiliation);
+            (rank, item.jid.clone())
+        });
+        let rows: Vec<Vec<String>> = items
+            .into_iter()
+            .map(|item| {
+                vec![
+                    item.jid,
+                    item.affiliation,
+                    item.nick.unwrap_or_default(),
+                    item.reason.unwrap_or_default(),
+                ]
+            })
+            .collect();
+
+        let mut lines = vec![format!("Affiliations of {}", self.channel)];
+        if rows.is_empty() {
+            lines.push("No affiliation".to_string());
+        } else {
+            lines.push(table(&["JID", "Affiliation", "Nick", "Reason"], &rows));
+        }
+        lines.extend(self.errors.iter().cloned());
+        lines.join("\n")
+    }
+}
+
+enum Request {
+    List(String, String),
+    Affiliate {
+        channel: BareJid,
+        user: BareJid,
+        affiliation: String,
+        window: String,
+    },
+}
+
+/// XEP-0045 affiliation lists of channels
+pub struct AffiliationsMod {
+    /// Request of each sent iq
+    iqs: HashMap<String, Request>,
+    listings: HashMap<String, Listing>,
+}
+
+impl AffiliationsMod {
+    pub fn new() -> Self {
+        Self {
+            iqs: HashMap::new(),
+            listings: HashMap::new(),
+        }
+    }
+
+    fn admin_iq(channel: &BareJid, payload: fn(Element) -> IqType, item: Element) -> Iq {
+        let query = Element::builder("query", NS_MUC_ADMIN).append(item).build();
+        Iq {
+            from: None,
+            to: Some(Jid::Bare(channel.clone())),
+            id: Uuid::new_v4().to_hyphenated().to_string(),
+            payload: payload(query),
+        }
+    }
+
+    fn list(aparte: &mut Aparte, channel: &Channel, affiliations: Vec<String>, window: String) {
+        let id = Uuid::new_v4().to_hyphenated().to_string();
+        let iqs: Vec<Iq> = affiliations
+            .iter()
+            .map(|affiliation| {
+                let item = Element::builder("item", NS_MUC_ADMIN)
+                    .attr("affiliation", affiliation.as_str())
+                    .build();
+                Self::admin_iq(&channel.jid, IqType::Get, item)
+            })
+            .collect();
+
+        {
+            let mut admin = aparte.get_mod_mut::<AffiliationsMod>();
+            for (iq, affiliation) in iqs.iter().zip(affiliations) {
+                admin
+                    .iqs
+                    .insert(iq.id.clone(), Request::List(id.clone(), affiliation));
+            }
+            admin.listings.insert(
+                id,
+                Listing {
+                    channel: channel.jid.clone(),
+                    window,
+                    pending: iqs.len(),
+                    items: Vec::new(),
+                    errors: Vec::new(),
+                },
+            );
+        }
+        for iq in iqs {
+            aparte.send(&channel.account, iq.into());
+        }
+    }
+
+    fn affiliate(
+        aparte: &mut Aparte,
+        channel: &Channel,
+        user: BareJid,
+        affiliation: String,
+        reason: Option<String>,
+        window: String,
+    ) {
+        let mut item = Element::builder("item", NS_MUC_ADMIN)
+            .attr("affiliation", affiliation.as_str())
+            .attr("jid", user.to_string());
+        if let Some(reason) = reason {
+            item = item.append(
+                Element::builder("reason", NS_MUC_ADMIN)
+                    .append(reason)
+                    .build(),
+            );
+        }
+        let iq = Self::admin_iq(&channel.jid, IqType::Set, item.build());
+
+        aparte.get_mod_mut::<AffiliationsMod>().iqs.insert(
+            iq.id.clone(),
+            Request::Affiliate {
+                channel: channel.jid.clone(),
+                user,
+                affiliation,
+                window,
+            },
+        );
+        aparte.send(&channel.account, iq.into());
+    }
+
+    fn handle_iq(&mut self, aparte: &mut Aparte, iq: &Iq) {
+        let request = match self.iqs.remove(&iq.id) {
+            Some(request) => request,
+            None => return,
+        };
+        let error = match &iq.payload {
+            IqType::Error(err) => Some(match err.texts.get("en") {
+                Some(text) => text.clone(),
+                None => format!("{:?}", err.defined_condition),
+            }),
+            _ => None,
+        };
+
+        match request {
+            Request::List(id, affiliation) => {
+                let listing = match self.listings.get_mut(&id) {
+                    Some(listing) => listing,
+                    None => return,
+                };
+                match (&iq.payload, error) {
+                    (IqType::Result(Some(query)), _) => listing.items.extend(Item::parse(query)),
+                    (_, Some(error)) => listing
+                        .errors
+                        .push(format!("Cannot list {}: {}", affiliation, error)),
+                    _ => {}
+                }
+                listing.pending -= 1;
+                if listing.pending == 0 {
+                    if let Some(listing) = self.listings.remove(&id) {
+                        let message = Message::log(listing.summary());
+                        aparte.schedule(Event::Info(listing.window, message));
+                    }
+                }
+            }
+            Request::Affiliate {
+                channel,
+                user,
+                affiliation,
+                window,
+            } => {
+                let text = match error {
+                    Some(error) => format!(
+                        "Cannot make {} {} of {}: {}",
+                        user, affiliation, channel, error
+                    ),
+                    None => format!("{} is now {} of {}", user, affiliation, channel),
+                };
+                aparte.schedule(Event::Info(window, Message::log(text)));
+            }
+        }
+    }
+}
+
+impl ModTrait for AffiliationsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(affiliations::new());
+        aparte.add_command(affiliate::new());
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Iq(_, iq) => self.handle_iq(aparte, iq),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for AffiliationsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0045: Multi-User Chat affiliations")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listing_summary() {
+        // Given
+        let query: Element = "<query xmlns='http://jabber.org/protocol/muc#admin'>
+            <item affiliation='member' jid='romeo@montague.lit' nick='Romeo'/>
+            <item affiliation='owner' jid='juliet@capulet.lit'/>
+            <item affiliation='outcast' jid='tybalt@capulet.lit'><reason>Too quarrelsome</reason></item>
+        </query>"
+            .parse()
+            .unwrap();
+        let listing = Listing {
+            channel: BareJid::from_str("balcony@chat.shakespeare.lit").unwrap(),
+            window: "balcony@chat.shakespeare.lit".to_string(),
+            pending: 0,
+            items: Item::parse(&query),
+            errors: vec!["Cannot list admin: Forbidden".to_string()],
+        };
+
+        // Then
+        assert_eq!(
+            listing.summary(),
+            "Affiliations of balcony@chat.shakespeare.lit
+JID                 Affiliation  Nick   Reason
+juliet@capulet.lit  owner
+romeo@montague.lit  member       Romeo
+tybalt@capulet.lit  outcast             Too quarrelsome
+Cannot list admin: Forbidden"
+        );
+    }
+}
@@ -0,0 +1,1064 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use chrono::{DateTime, Duration, FixedOffset};
+use rusqlite::{params, Connection, Params, Statement};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+use xmpp_parsers::{BareJid, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Direction, LogMessage, Message, VersionedXmppMessage, XmppMessageType};
+use crate::mods::ui::UIMod;
+
+/// Number of messages loaded at once in a window
+const HISTORY_COUNT: u32 = 100;
+
+/// Number of messages printed by /last by default
+const LAST_COUNT: u32 = 10;
+
+/// Maximum number of messages listed by /grep
+const GREP_COUNT: u32 = 200;
+
+/// Window listing the results of /grep
+const GREP_WINDOW: &str = "grep";
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS messages (
+    account TEXT NOT NULL,
+    conversation TEXT NOT NULL,
+    id TEXT NOT NULL,
+    type TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    from_jid TEXT NOT NULL,
+    to_jid TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    timestamp TEXT NOT NULL,
+    body TEXT NOT NULL,
+    PRIMARY KEY (account, conversation, id)
+);
+CREATE INDEX IF NOT EXISTS messages_time ON messages (account, conversation, time);
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts
+    USING fts5(body, from_jid, content='messages', content_rowid='rowid');
+CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts (rowid, body, from_jid) VALUES (new.rowid, new.body, new.from_jid);
+END;
+CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, body, from_jid)
+        VALUES ('delete', old.rowid, old.body, old.from_jid);
+END;
+CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, body, from_jid)
+        VALUES ('delete', old.rowid, old.body, old.from_jid);
+    INSERT INTO messages_fts (rowid, body, from_jid) VALUES (new.rowid, new.body, new.from_jid);
+END;
+CREATE TABLE IF NOT EXISTS read_state (
+    account TEXT NOT NULL,
+    conversation TEXT NOT NULL,
+    last_read TEXT,
+    unread INTEGER NOT NULL,
+    highlighted INTEGER NOT NULL,
+    PRIMARY KEY (account, conversation)
+);
+CREATE TABLE IF NOT EXISTS archive_ids (
+    account TEXT NOT NULL,
+    conversation TEXT NOT NULL,
+    type TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    id TEXT NOT NULL,
+    PRIMARY KEY (account, conversation)
+);
+"#;
+
+command_def!(
+    last,
+    r#"/last [<n>] [<filter>]
+
+    n           Number of messages, 10 by default
+    filter      Only messages whose body or sender has words starting with
+                those of the filter, ignoring case
+
+Description:
+    Print the last messages of the current conversation from the local
+    archive, including those no longer in the window.
+
+Examples:
+    /last
+    /last 50
+    /last 5 romeo
+    /last release"#,
+    {
+        first: Option<String>,
+        second: Option<String>
+    },
+    |aparte, command| {
+        let (count, filter) = match first.as_ref().map(|first| first.parse::<u32>()) {
+            Some(Ok(count)) => (count, second),
+            _ => (LAST_COUNT, first),
+        };
+        let account = command
+            .account
+            .clone()
+            .or_else(|| aparte.current_account())
+            .ok_or(format!("No connection found"))?;
+        let conversation = BareJid::from_str(&command.context)
+            .map_err(|_| format!("Not in a conversation"))?;
+        let messages = {
+            let archive = aparte.get_mod::<ArchiveMod>();
+            let connection = archive
+                .connection
+                .as_ref()
+                .ok_or(format!("Message archive is not available"))?;
+            let filter = filter.as_deref().unwrap_or("");
+            ArchiveMod::search(connection, &account, Some(&conversation), filter, count)
+                .map_err(|err| format!("Cannot read message archive: {}", err))?
+        };
+        let text = match messages.is_empty() {
+            true => format!("No archived message in {}", conversation),
+            false => messages
+                .iter()
+                .map(format_archived)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+        aparte.schedule(Event::Info(command.context.clone(), Message::log(text)));
+        Ok(())
+    }
+);
+
+command_def!(
+    grep,
+    r#"/grep [--all] <pattern>
+/grep --open <n>
+
+    --all       Search every conversation instead of the current one
+    pattern     Words searched in message bodies and senders, by their start
+                and ignoring case
+    --open n    Open the conversation of the nth result around the match
+
+Description:
+    Search the local archive and list the matching messages, numbered, in
+    the grep window. It can be closed with /close like any other window.
+
+Examples:
+    /grep release
+    /grep --all "see you"
+    /grep --open 3"#,
+    {
+        all: Flag,
+        open: Named<usize>,
+        pattern: Option<String>
+    },
+    |aparte, command| {
+        if let Some(number) = open {
+            return open_result(aparte, number);
+        }
+        let pattern = pattern.ok_or(format!("Missing pattern argument"))?;
+        let account = command
+            .account
+            .clone()
+            .or_else(|| aparte.current_account())
+            .ok_or(format!("No connection found"))?;
+        let conversation = match all {
+            true => None,
+            false => Some(BareJid::from_str(&command.context).map_err(|_| {
+                format!("Not in a conversation, use --all to search every conversation")
+            })?),
+        };
+        let results = {
+            let mut archive = aparte.get_mod_mut::<ArchiveMod>();
+            let messages = {
+                let connection = archive
+                    .connection
+                    .as_ref()
+                    .ok_or(format!("Message archive is not available"))?;
+                ArchiveMod::search(connection, &account, conversation.as_ref(), &pattern, GREP_COUNT)
+                    .map_err(|err| format!("Cannot read message archive: {}", err))?
+            };
+            archive.results = messages
+                .into_iter()
+                .map(|message| (account.clone(), message))
+                .collect();
+            format_results(&archive.results, all)
+        };
+        match results.is_empty() {
+            true => aparte.log(format!("No archived message matches {}", pattern)),
+            false => aparte.schedule(Event::Results(GREP_WINDOW.to_string(), results)),
+        }
+        Ok(())
+    }
+);
+
+/// Open the conversation of a /grep result with the messages preceding the match, and search
+/// the match in it
+fn open_result(aparte: &mut Aparte, number: usize) -> Result<(), String> {
+    let (account, message) = {
+        let archive = aparte.get_mod::<ArchiveMod>();
+        match archive.results.get(number.wrapping_sub(1)) {
+            Some((account, Message::Xmpp(message))) => (account.clone(), message.clone()),
+            _ => return Err(format!("No result {}", number)),
+        }
+    };
+    let conversation = archived_conversation(&message).clone();
+    let timestamp = *message.get_original_timestamp();
+    let context = {
+        let archive = aparte.get_mod::<ArchiveMod>();
+        let connection = archive
+            .connection
+            .as_ref()
+            .ok_or(format!("Message archive is not available"))?;
+        let before = timestamp + Duration::milliseconds(1);
+        ArchiveMod::load(connection, &account, &conversation, Some(before))
+            .map_err(|err| format!("Cannot read message archive: {}", err))?
+    };
+
+    match message.type_ {
+        XmppMessageType::Chat => aparte.schedule(Event::Chat {
+            account: account.clone(),
+            contact: conversation.clone(),
+        }),
+        XmppMessageType::Channel => aparte.schedule(Event::Win(conversation.to_string())),
+    }
+    for message in context {
+        aparte.schedule(Event::Message(Some(account.clone()), message));
+    }
+    let line = message.get_last_body().lines().next().unwrap_or("");
+    aparte.schedule(Event::Search(Some(line.to_string())));
+    Ok(())
+}
+
+/// Numbered /grep results, dated by their message, with their conversation when searching all
+fn format_results(results: &[(Account, Message)], all: bool) -> Vec<Message> {
+    results
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (_, message))| match message {
+            Message::Xmpp(message) => Some((index, message)),
+            Message::Log(_) => None,
+        })
+        .map(|(index, message)| {
+            let conversation = match all {
+                true => format!("{} ", archived_conversation(message)),
+                false => String::new(),
+            };
+            Message::Log(LogMessage {
+                id: Uuid::new_v4().to_string(),
+                timestamp: *message.get_original_timestamp(),
+                body: format!(
+                    "[{}] {}{}: {}",
+                    index + 1,
+                    conversation,
+                    archived_author(message),
+                    message.get_last_body()
+                ),
+            })
+        })
+        .collect()
+}
+
+/// One line summary of an archived message, with its date and author
+fn format_archived(message: &Message) -> String {
+    match message {
+        Message::Xmpp(message) => format!(
+            "{} {}: {}",
+            message.get_original_timestamp().format("%Y-%m-%d %H:%M"),
+            archived_author(message),
+            message.get_last_body()
+        ),
+        Message::Log(message) => message.body.clone(),
+    }
+}
+
+/// Full-text query matching the words starting with each word of `filter`, empty when it has no
+/// word
+fn fts_query(filter: &str) -> String {
+    filter
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Nick of channel occupants, address of chat contacts
+fn archived_author(message: &VersionedXmppMessage) -> String {
+    match (&message.type_, &message.from_full) {
+        (XmppMessageType::Channel, Jid::Full(from)) => from.resource.clone(),
+        _ => message.from.to_string(),
+    }
+}
+
+/// Conversation a message belongs to
+fn archived_conversation(message: &VersionedXmppMessage) -> &BareJid {
+    match message.direction {
+        Direction::Incoming => &message.from,
+        Direction::Outgoing => &message.to,
+    }
+}
+
+/// What has been read of the conversation of a window, kept across restarts
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadState {
+    pub account: Account,
+    pub conversation: BareJid,
+    /// Last message shown while the window was the current one
+    pub last_read: Option<String>,
+    /// Incoming messages received since then
+    pub unread: usize,
+    /// Some of the unread messages are addressed to us
+    pub highlighted: bool,
+}
+
+/// Local archive of the messages of every conversation
+pub struct ArchiveMod {
+    connection: Option<Connection>,
+    /// Results of the last /grep, for /grep --open
+    results: Vec<(Account, Message)>,
+}
+
+impl ArchiveMod {
+    pub fn new() -> Self {
+        Self {
+            connection: None,
+            results: Vec::new(),
+        }
+    }
+
+    fn open(connection: Connection) -> rusqlite::Result<Connection> {
+        let indexed: bool = connection.query_row(
+            "SELECT count(*) > 0 FROM sqlite_master WHERE name = 'messages_fts'",
+            params![],
+            |row| row.get(0),
+        )?;
+        connection.execute_batch(SCHEMA)?;
+        // Archives created before the search index need it built once
+        if !indexed {
+            connection.execute(
+                "INSERT INTO messages_fts (messages_fts) VALUES ('rebuild')",
+                params![],
+            )?;
+        }
+        Ok(connection)
+    }
+
+    fn store(
+        connection: &Connection,
+        account: &Account,
+        message: &VersionedXmppMessage,
+    ) -> rusqlite::Result<()> {
+        let account: BareJid = account.clone().into();
+        let conversation = archived_conversation(message);
+        let type_ = match message.type_ {
+            XmppMessageType::Chat => "chat",
+            XmppMessageType::Channel => "channel",
+        };
+        let direction = match message.direction {
+            Direction::Incoming => "incoming",
+            Direction::Outgoing => "outgoing",
+        };
+        let timestamp = message.get_original_timestamp();
+
+        // Corrections keep the id of the original message, only the last version is kept. Updating
+        // the row instead of replacing it keeps the search index in sync through its triggers.
+        // Only the sender can correct a message, another one reusing its id doesn't overwrite it.
+        connection.execute(
+            "INSERT INTO messages \
+             (account, conversation, id, type, direction, from_jid, to_jid, time, timestamp, body) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10) \
+             ON CONFLICT (account, conversation, id) DO UPDATE SET \
+             type = excluded.type, direction = excluded.direction, from_jid = excluded.from_jid, \
+             to_jid = excluded.to_jid, time = excluded.time, timestamp = excluded.timestamp, \
+             body = excluded.body \
+             WHERE excluded.from_jid = messages.from_jid",
+            params![
+                account.to_string(),
+                conversation.to_string(),
+                message.id,
+                type_,
+                direction,
+                message.from_full.to_string(),
+                message.to_full.to_string(),
+                timestamp.timestamp_millis(),
+                timestamp.to_rfc3339(),
+                message.get_last_body(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Last messages of a conversation sent before the given date, oldest first
+    fn load(
+        connection: &Connection,
+        account: &Account,
+        conversation: &BareJid,
+        before: Option<DateTime<FixedOffset>>,
+    ) -> rusqlite::Result<Vec<Message>> {
+        let account: BareJid = account.clone().into();
+        let before = before.map_or(i64::MAX, |before| before.timestamp_millis());
+
+        let mut statement = connection.prepare(
+            "SELECT id, type, direction, from_jid, to_jid, timestamp, body FROM messages \
+             WHERE account = ?1 AND conversation = ?2 AND time < ?3 \
+             ORDER BY time DESC LIMIT ?4",
+        )?;
+        let mut messages = Self::read(
+            &mut statement,
+            params![
+                account.to_string(),
+                conversation.to_string(),
+                before,
+                HISTORY_COUNT
+            ],
+        )?;
+        messages.reverse();
+
+        Ok(messages)
+    }
+
+    /// Last messages whose body or sender has words starting with each word of `filter`, ignoring
+    /// case, in a conversation or in all of them, oldest first
+    fn search(
+        connection: &Connection,
+        account: &Account,
+        conversation: Option<&BareJid>,
+        filter: &str,
+        count: u32,
+    ) -> rusqlite::Result<Vec<Message>> {
+        let account = BareJid::from(account.clone()).to_string();
+        let conversation = conversation.map(|conversation| conversation.to_string());
+
+        let mut messages = match filter.trim().is_empty() {
+            true => {
+                let mut statement = connection.prepare(
+                    "SELECT id, type, direction, from_jid, to_jid, timestamp, body FROM messages \
+                     WHERE account = ?1 AND (?2 IS NULL OR conversation = ?2) \
+                     ORDER BY time DESC LIMIT ?3",
+                )?;
+                Self::read(&mut statement, params![account, conversation, count])?
+            }
+            false => {
+                let query = fts_query(filter);
+                if query.is_empty() {
+                    return Ok(Vec::new());
+                }
+                let mut statement = connection.prepare(
+                    "SELECT messages.id, messages.type, messages.direction, messages.from_jid, \
+                     messages.to_jid, messages.timestamp, messages.body \
+                     FROM messages_fts JOIN messages ON messages.rowid = messages_fts.rowid \
+                     WHERE messages_fts MATCH ?3 \
+                     AND messages.account = ?1 AND (?2 IS NULL OR messages.conversation = ?2) \
+                     ORDER BY messages.time DESC LIMIT ?4",
+                )?;
+                Self::read(&mut statement, params![account, conversation, query, count])?
+            }
+        };
+        messages.reverse();
+
+        Ok(messages)
+    }
+
+    /// Replace the read states with those of the windows open when quitting
+    fn store_read_states(connection: &Connection, states: &[ReadState]) -> rusqlite::Result<()> {
+        connection.execute("DELETE FROM read_state", params![])?;
+        for state in states {
+            connection.execute(
+                "INSERT OR REPLACE INTO read_state \
+                 (account, conversation, last_read, unread, highlighted) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    BareJid::from(state.account.clone()).to_string(),
+                    state.conversation.to_string(),
+                    state.last_read,
+                    state.unread as i64,
+                    state.highlighted,
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Read state of a conversation, unread messages are counted from the last read one when it
+    /// has been archived
+    fn load_read_state(
+        connection: &Connection,
+        account: &Account,
+        conversation: &BareJid,
+    ) -> rusqlite::Result<Option<ReadState>> {
+        let bare = BareJid::from(account.clone()).to_string();
+        let conversation_str = conversation.to_string();
+        let mut statement = connection.prepare(
+            "SELECT last_read, unread, highlighted, \
+             (SELECT time FROM messages \
+              WHERE messages.account = read_state.account \
+              AND messages.conversation = read_state.conversation \
+              AND messages.id = read_state.last_read) \
+             FROM read_state WHERE account = ?1 AND conversation = ?2",
+        )?;
+        let mut rows = statement.query_map(params![bare, conversation_str], |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, bool>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+            ))
+        })?;
+        let (last_read, stored, highlighted, last_read_time) = match rows.next() {
+            Some(row) => row?,
+            None => return Ok(None),
+        };
+
+        // Messages received while not connected are archived on the next connection, the stored
+        // count is only used when the last read message isn't archived
+        let unread = match last_read_time {
+            Some(time) => connection.query_row(
+                "SELECT count(*) FROM messages \
+                 WHERE account = ?1 AND conversation = ?2 AND direction = 'incoming' AND time > ?3",
+                params![bare, conversation_str, time],
+                |row| row.get::<_, i64>(0),
+            )?,
+            None => stored,
+        };
+
+        Ok(Some(ReadState {
+            account: account.clone(),
+            conversation: conversation.clone(),
+            last_read,
+            unread: unread as usize,
+            highlighted: highlighted && unread > 0,
+        }))
+    }
+
+    /// Remember the newest archive id (XEP-0359 stanza-id) of a conversation, an older one is
+    /// ignored. A single statement, the stored id can't be left half updated.
+    fn store_archive_id(
+        connection: &Connection,
+        account: &Account,
+        type_: XmppMessageType,
+        conversation: &BareJid,
+        time: DateTime<FixedOffset>,
+        id: &str,
+    ) -> rusqlite::Result<()> {
+        let account: BareJid = account.clone().into();
+        let type_ = match type_ {
+            XmppMessageType::Chat => "chat",
+            XmppMessageType::Channel => "channel",
+        };
+        connection.execute(
+            "INSERT INTO archive_ids (account, conversation, type, time, id) \
+             VALUES (?1, ?2, ?3, ?4, ?5) \
+             ON CONFLICT (account, conversation) DO UPDATE SET \
+             type = excluded.type, time = excluded.time, id = excluded.id \
+             WHERE excluded.time > archive_ids.time",
+            params![
+                account.to_string(),
+                conversation.to_string(),
+                type_,
+                time.timestamp_millis(),
+                id,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn load_archive_id(
+        connection: &Connection,
+        account: &Account,
+        conversation: &BareJid,
+    ) -> rusqlite::Result<Option<String>> {
+        let account: BareJid = account.clone().into();
+        let mut statement = connection
+            .prepare("SELECT id FROM archive_ids WHERE account = ?1 AND conversation = ?2")?;
+        let mut rows = statement.query_map(
+            params![account.to_string(), conversation.to_string()],
+            |row| row.get::<_, String>(0),
+        )?;
+        rows.next().transpose()
+    }
+
+    fn load_chat_archive_ids(
+        connection: &Connection,
+        account: &Account,
+    ) -> rusqlite::Result<Vec<(BareJid, String)>> {
+        let account: BareJid = account.clone().into();
+        let mut statement = connection.prepare(
+            "SELECT conversation, id FROM archive_ids \
+             WHERE account = ?1 AND type = 'chat' ORDER BY conversation",
+        )?;
+        let rows = statement.query_map(params![account.to_string()], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut chats = Vec::new();
+        for row in rows {
+            let (chat, id) = row?;
+            match BareJid::from_str(&chat) {
+                Ok(chat) => chats.push((chat, id)),
+                Err(_) => warn!("Invalid archived conversation {}", chat),
+            }
+        }
+
+        Ok(chats)
+    }
+
+    /// Remember the archive id of a message of a conversation, to catch up from it on connection
+    pub fn update_archive_id(
+        &self,
+        account: &Account,
+        type_: XmppMessageType,
+        conversation: &BareJid,
+        time: DateTime<FixedOffset>,
+        id: &str,
+    ) {
+        if let Some(connection) = &self.connection {
+            let stored = Self::store_archive_id(connection, account, type_, conversation, time, id);
+            if let Err(err) = stored {
+                warn!("Cannot save archive id {}: {}", id, err);
+            }
+        }
+    }
+
+    /// Newest archive id received in a conversation
+    pub fn archive_id(&self, account: &Account, conversation: &BareJid) -> Option<String> {
+        let connection = self.connection.as_ref()?;
+        match Self::load_archive_id(connection, account, conversation) {
+            Ok(id) => id,
+            Err(err) => {
+                warn!("Cannot load archive id of {}: {}", conversation, err);
+                None
+            }
+        }
+    }
+
+    /// Chats of an account, with their newest archive id
+    pub fn chat_archive_ids(&self, account: &Account) -> Vec<(BareJid, String)> {
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => return Vec::new(),
+        };
+        match Self::load_chat_archive_ids(connection, account) {
+            Ok(chats) => chats,
+            Err(err) => {
+                warn!("Cannot load archive ids of {}: {}", account, err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Messages selected by a statement, in its order
+    fn read<P: Params>(statement: &mut Statement, params: P) -> rusqlite::Result<Vec<Message>> {
+        let rows = statement.query_map(params, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, String>(6)?,
+            ))
+        })?;
+
+        let mut messages = Vec::new();
+        for row in rows {
+            let (id, type_, direction, from, to, timestamp, body) = row?;
+            let (from, to, timestamp) = match (
+                Jid::from_str(&from),
+                Jid::from_str(&to),
+                DateTime::parse_from_rfc3339(&timestamp),
+            ) {
+                (Ok(from), Ok(to), Ok(timestamp)) => (from, to, timestamp),
+                _ => {
+                    warn!("Invalid archived message {}", id);
+                    continue;
+                }
+            };
+            let mut bodies = HashMap::new();
+            bodies.insert(String::new(), body);
+
+            let message = match (type_.as_str(), direction.as_str()) {
+                ("chat", "incoming") => Message::incoming_chat(id, timestamp, &from, &to, &bodies),
+                ("chat", _) => Message::outgoing_chat(id, timestamp, &from, &to, &bodies),
+                ("channel", "incoming") => {
+                    Message::incoming_channel(id, timestamp, &from, &to, &bodies)
+                }
+                _ => Message::outgoing_channel(id, timestamp, &from, &to, &bodies),
+            };
+            messages.push(message);
+        }
+
+        Ok(messages)
+    }
+
+    fn load_history(
+        &self,
+        aparte: &mut Aparte,
+        account: &Account,
+        conversation: &BareJid,
+        before: Option<DateTime<FixedOffset>>,
+    ) {
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        match Self::load(connection, account, conversation, before) {
+            Ok(messages) => {
+                for message in messages {
+                    aparte.schedule(Event::Message(Some(account.clone()), message));
+                }
+            }
+            Err(err) => warn!("Cannot load history of {}: {}", conversation, err),
+        }
+
+        // Once the window is open, tell what was left unread in it
+        if before.is_none() {
+            match Self::load_read_state(connection, account, conversation) {
+                Ok(Some(state)) => aparte.schedule(Event::ReadState(state)),
+                Ok(None) => {}
+                Err(err) => warn!("Cannot load read state of {}: {}", conversation, err),
+            }
+        }
+    }
+}
+
+impl ModTrait for ArchiveMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(last::new());
+        aparte.add_command(grep::new());
+
+        let path = aparte.data_dir.join("archive.sqlite");
+        match Connection::open(&path).and_then(Self::open) {
+            Ok(connection) => self.connection = Some(connection),
+            Err(err) => aparte.log(format!("Cannot open message archive: {}", err)),
+        }
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Message(Some(account), Message::Xmpp(message)) => {
+                if let Some(connection) = &self.connection {
+                    if let Err(err) = Self::store(connection, account, message) {
+                        warn!("Cannot archive message {}: {}", message.id, err);
+                    }
+                }
+            }
+            Event::Chat { account, contact } => {
+                self.load_history(aparte, account, contact, None);
+            }
+            Event::Join {
+                account, channel, ..
+            } => {
+                self.load_history(aparte, account, &channel.clone().into(), None);
+            }
+            Event::LoadChatHistory {
+                account,
+                contact,
+                from,
+            } => {
+                self.load_history(aparte, account, contact, *from);
+            }
+            Event::LoadChannelHistory { account, jid, from } => {
+                self.load_history(aparte, account, jid, *from);
+            }
+            Event::Quit => {
+                if let Some(connection) = &self.connection {
+                    let states = aparte.get_mod::<UIMod>().read_states();
+                    if let Err(err) = Self::store_read_states(connection, &states) {
+                        warn!("Cannot save read states: {}", err);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for ArchiveMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Local message archive")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_store_and_load() {
+        // Given
+        let connection = ArchiveMod::open(Connection::open_in_memory().unwrap()).unwrap();
+        let account = Account::from_str("me@example.org/aparte").unwrap();
+        let contact = Jid::from_str("contact@example.org/phone").unwrap();
+        let us = Jid::Full(account.clone());
+        let bodies = |body: &str| {
+            let mut bodies = HashMap::new();
+            bodies.insert(String::new(), body.to_string());
+            bodies
+        };
+        let first = FixedOffset::east(3600).ymd(2021, 5, 1).and_hms(10, 0, 0);
+        let second = FixedOffset::east(0).ymd(2021, 5, 1).and_hms(10, 30, 0);
+
+        // When
+        for message in vec![
+            Message::outgoing_chat("2", second, &us, &contact, &bodies("fine")),
+            Message::incoming_chat("1", first, &contact, &us, &bodies("how are you?")),
+        ] {
+            if let Message::Xmpp(message) = message {
+                ArchiveMod::store(&connection, &account, &message).unwrap();
+            }
+        }
+
+        // Then
+        let conversation = BareJid::from_str("contact@example.org").unwrap();
+        let messages = ArchiveMod::load(&connection, &account, &conversation, None).unwrap();
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| message.id())
+                .collect::<Vec<_>>(),
+            vec!["1", "2"]
+        );
+        match &messages[0] {
+            Message::Xmpp(message) => {
+                assert_eq!(message.direction, Direction::Incoming);
+                assert_eq!(message.from_full, contact);
+                assert_eq!(message.get_last_body(), "how are you?");
+                assert_eq!(message.get_original_timestamp(), &first);
+            }
+            _ => panic!("Not an xmpp message"),
+        }
+
+        let older = ArchiveMod::load(&connection, &account, &conversation, Some(second)).unwrap();
+        assert_eq!(older.len(), 1);
+
+        let found =
+            ArchiveMod::search(&connection, &account, Some(&conversation), "HOW", 10).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id(), "1");
+        let found = ArchiveMod::search(&connection, &account, None, "contact@", 10).unwrap();
+        assert_eq!(found.len(), 1);
+        let last = ArchiveMod::search(&connection, &account, Some(&conversation), "", 1).unwrap();
+        assert_eq!(last[0].id(), "2");
+    }
+
+    #[test]
+    fn test_search_index_follows_corrections() {
+        // Given
+        let connection = ArchiveMod::open(Connection::open_in_memory().unwrap()).unwrap();
+        let account = Account::from_str("me@example.org/aparte").unwrap();
+        let contact = Jid::from_str("contact@example.org/phone").unwrap();
+        let us = Jid::Full(account.clone());
+        let timestamp = FixedOffset::east(0).ymd(2021, 5, 1).and_hms(10, 0, 0);
+        let store = |body: &str| {
+            let mut bodies = HashMap::new();
+            bodies.insert(String::new(), body.to_string());
+            if let Message::Xmpp(message) =
+                Message::outgoing_chat("1", timestamp, &us, &contact, &bodies)
+            {
+                ArchiveMod::store(&connection, &account, &message).unwrap();
+            }
+        };
+
+        // When
+        store("see you tomorow");
+        store("see you tomorrow");
+
+        // Then
+        let search = |filter| ArchiveMod::search(&connection, &account, None, filter, 10).unwrap();
+        assert_eq!(search("tomorow").len(), 0);
+        assert_eq!(search("TOMORR").len(), 1);
+        assert_eq!(search("see \"tomorrow\"").len(), 1);
+        assert_eq!(search("see nobody").len(), 0);
+        assert_eq!(search("!!").len(), 0);
+        assert_eq!(fts_query("c++ \"quoted\""), "\"c++\"* \"\"\"quoted\"\"\"*");
+    }
+
+    #[test]
+    fn test_store_keeps_sender() {
+        // Given
+        let connection = ArchiveMod::open(Connection::open_in_memory().unwrap()).unwrap();
+        let account = Account::from_str("me@example.org/aparte").unwrap();
+        let room = Jid::from_str("room@conference.example.org/me").unwrap();
+        let timestamp = FixedOffset::east(0).ymd(2021, 5, 1).and_hms(10, 0, 0);
+        let store = |nick: &str, body: &str| {
+            let from = Jid::from_str(&format!("room@conference.example.org/{}", nick)).unwrap();
+            let mut bodies = HashMap::new();
+            bodies.insert(String::new(), body.to_string());
+            if let Message::Xmpp(message) =
+                Message::incoming_channel("1", timestamp, &from, &room, &bodies)
+            {
+                ArchiveMod::store(&connection, &account, &message).unwrap();
+            }
+        };
+
+        // When
+        store("alice", "hello");
+        store("mallory", "goodbye");
+        store("alice", "hello all");
+
+        // Then
+        let conversation = BareJid::from_str("room@conference.example.org").unwrap();
+        let messages = ArchiveMod::load(&connection, &account, &conversation, None).unwrap();
+        assert_eq!(messages.len(), 1);
+        match &messages[0] {
+            Message::Xmpp(message) => {
+                assert_eq!(
+                    message.from_full.to_string(),
+                    "room@conference.example.org/alice"
+                );
+                assert_eq!(message.get_last_body(), "hello all");
+            }
+            _ => panic!("Not an xmpp message"),
+        }
+    }
+
+    #[test]
+    fn test_read_state() {
+        // Given
+        let connection = ArchiveMod::open(Connection::open_in_memory().unwrap()).unwrap();
+        let account = Account::from_str("me@example.org/aparte").unwrap();
+        let contact = Jid::from_str("contact@example.org/phone").unwrap();
+        let us = Jid::Full(account.clone());
+        let mut bodies = HashMap::new();
+        bodies.insert(String::new(), "ping".to_string());
+        for (id, minute) in vec![("1", 0), ("2", 1), ("3", 2)] {
+            let timestamp = FixedOffset::east(0).ymd(2021, 5, 1).and_hms(10, minute, 0);
+            if let Message::Xmpp(message) =
+                Message::incoming_chat(id, timestamp, &contact, &us, &bodies)
+            {
+                ArchiveMod::store(&connection, &account, &message).unwrap();
+            }
+        }
+        let conversation = BareJid::from_str("contact@example.org").unwrap();
+        let other = BareJid::from_str("other@example.org").unwrap();
+        let states = vec![
+            ReadState {
+                account: account.clone(),
+                conversation: conversation.clone(),
+                last_read: Some("1".to_string()),
+                unread: 0,
+                highlighted: true,
+            },
+            ReadState {
+                account: account.clone(),
+                conversation: other.clone(),
+                last_read: None,
+                unread: 3,
+                highlighted: false,
+            },
+        ];
+
+        // When
+        ArchiveMod::store_read_states(&connection, &states).unwrap();
+
+        // Then
+        let state = ArchiveMod::load_read_state(&connection, &account, &conversation).unwrap();
+        assert_eq!(
+            state,
+            Some(ReadState {
+                unread: 2,
+                ..states[0].clone()
+            })
+        );
+        let state = ArchiveMod::load_read_state(&connection, &account, &other).unwrap();
+        assert_eq!(state, Some(states[1].clone()));
+
+        ArchiveMod::store_read_states(&connection, &states[1..]).unwrap();
+        let state = ArchiveMod::load_read_state(&connection, &account, &conversation).unwrap();
+        assert_eq!(state, None);
+    }
+
+    #[test]
+    fn test_archive_ids() {
+        // Given
+        let connection = ArchiveMod::open(Connection::open_in_memory().unwrap()).unwrap();
+        let account = Account::from_str("romeo@montague.lit/aparte").unwrap();
+        let juliet = BareJid::from_str("juliet@capulet.lit").unwrap();
+        let verona = BareJid::from_str("verona@chat.shakespeare.lit").unwrap();
+        let time = |time: &str| DateTime::parse_from_rfc3339(time).unwrap();
+        let store = |type_, conversation, stamp, id| {
+            ArchiveMod::store_archive_id(
+                &connection,
+                &account,
+                type_,
+                conversation,
+                time(stamp),
+                id,
+            )
+            .unwrap()
+        };
+        store(
+            XmppMessageType::Chat,
+            &juliet,
+            "2021-05-01T10:00:00Z",
+            "first",
+        );
+
+        // When
+        store(
+            XmppMessageType::Chat,
+            &juliet,
+            "2021-05-01T09:00:00Z",
+            "older",
+        );
+        store(
+            XmppMessageType::Chat,
+            &juliet,
+            "2021-05-01T11:00:00Z",
+            "second",
+        );
+        store(
+            XmppMessageType::Channel,
+            &verona,
+            "2021-05-01T12:00:00Z",
+            "room",
+        );
+
+        // Then
+        assert_eq!(
+            ArchiveMod::load_archive_id(&connection, &account, &juliet).unwrap(),
+            Some("second".to_string())
+        );
+        assert_eq!(
+            ArchiveMod::load_archive_id(&connection, &account, &verona).unwrap(),
+            Some("room".to_string())
+        );
+        assert_eq!(
+            ArchiveMod::load_chat_archive_ids(&connection, &account).unwrap(),
+            vec![(juliet, "second".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_format_results() {
+        // Given
+        let account = Account::from_str("me@example.org/aparte").unwrap();
+        let channel = Jid::from_str("verona@chat.shakespeare.lit/Romeo").unwrap();
+        let us = Jid::Full(account.clone());
+        let mut bodies = HashMap::new();
+        bodies.insert(String::new(), "good night".to_string());
+        let timestamp = FixedOffset::east(0).ymd(2021, 5, 1).and_hms(23, 0, 0);
+        let message = Message::incoming_channel("1", timestamp, &channel, &us, &bodies);
+
+        // When
+        let results = format_results(&[(account, message)], true);
+
+        // Then
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].body(),
+            "[1] verona@chat.shakespeare.lit Romeo: good night"
+        );
+        assert_eq!(results[0].timestamp(), &timestamp);
+    }
+}
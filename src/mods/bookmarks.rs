@@ -22,14 +22,16 @@ use crate::account::Account;
 use crate::command::{Command, CommandParser};
 use crate::contact;
 use crate::core::{Aparte, Event, ModTrait};
+use crate::message::table;
 use crate::mods::disco;
 
 command_def!(bookmark_add,
-r#"/bookmark add <bookmark> <conference> [autojoin=on|off]
+r#"/bookmark add <bookmark> <conference> [nick=<nick>] [password=<password>] [autojoin=true|false]
 
     bookmark    The bookmark friendly name
     conference  The conference room jid
     nick        Your nick in the conference
+    password    Password of the conference room
     autojoin    Wether the conference room should be automatically joined on startup
 
 Description:
@@ -38,12 +40,14 @@ Description:
 Examples:
     /bookmark add aparte aparte@conference.fariello.eu
     /bookmark add aparte aparte@conference.fariello.eu nick=needle
-    /bookmark add aparte aparte@conference.fariello.eu autojoin=on
+    /bookmark add aparte aparte@conference.fariello.eu autojoin=true
+    /bookmark add secret secret@conference.fariello.eu password=hunter2
 "#,
 {
     name: String,
     conference: BareJid,
     nick: Named<String>,
+    password: Named<String>,
     autojoin: Named<bool>
 },
 |aparte, _command| {
@@ -56,7 +60,7 @@ Examples:
         jid: conference.into(),
         name: Some(name),
         nick: nick,
-        password: None,
+        password: password,
         autojoin: autojoin,
         extensions: None,
     };
@@ -70,18 +74,25 @@ Examples:
 });
 
 command_def!(
-    bookmark_del,
-    r#"/bookmark del <bookmark>
+    bookmark_remove,
+    r#"/bookmark remove <bookmark>
 
-    bookmark    The bookmark friendly name
+    bookmark    The bookmark friendly name, or its conference room jid
 
 Description:
     Delete a bookmark
 
 Examples:
-    /bookmark del aparte
+    /bookmark remove aparte
+    /bookmark remove aparte@conference.fariello.eu
 "#,
-    { conference: BareJid },
+    {
+        conference: BareJid = {
+            completion: (|aparte, _command| {
+                aparte.get_mod::<BookmarksMod>().names()
+            })
+        }
+    },
     |aparte, _command| {
         let account = aparte
             .current_account()
@@ -92,16 +103,20 @@ Examples:
         } {
             aparte.schedule(Event::DeletedBookmark(bookmark.jid));
             aparte.send(&account, delete);
+            Ok(())
+        } else {
+            Err(format!("Unknown bookmark {}", conference))
         }
-        Ok(())
     }
 );
 
 command_def!(bookmark_edit,
-r#"/bookmark edit <bookmark> [<conference>] [autojoin=on|off]
+r#"/bookmark edit <bookmark> [<conference>] [nick=<nick>] [password=<password>] [autojoin=true|false]
 
-    bookmark    The bookmark friendly name
-    conference  The conference room jid
+    bookmark    The bookmark friendly name, or its conference room jid
+    conference  The new conference room jid
+    nick        Your nick in the conference, empty to remove it
+    password    Password of the conference room, empty to remove it
     autojoin    Wether the conference room should be automatically joined on startup
 
 Description:
@@ -112,10 +127,16 @@ Examples:
     /bookmark edit aparte aparte@conference.fariello.eu
     /bookmark edit aparte nick=needle
     /bookmark edit aparte aparte@conference.fariello.eu autojoin=false
+    /bookmark edit aparte password=
 "#,
 {
-    name: String,
+    name: String = {
+        completion: (|aparte, _command| {
+            aparte.get_mod::<BookmarksMod>().names()
+        })
+    },
     nick: Named<String>,
+    password: Named<String>,
     autojoin: Named<bool>,
     conference: Option<BareJid>,
 },
@@ -123,7 +144,7 @@ Examples:
     let account = aparte.current_account().ok_or(format!("No connection found"))?;
     if let Some(edit) = {
         let mut bookmarks = aparte.get_mod_mut::<BookmarksMod>();
-        bookmarks.edit(name.clone(), conference, nick, autojoin)
+        bookmarks.edit(name.clone(), conference, nick, password, autojoin)
     } {
         aparte.send(&account, edit);
         Ok(())
@@ -132,13 +153,60 @@ Examples:
     }
 });
 
+command_def!(
+    bookmark_list,
+    r#"/bookmark list
+
+Description:
+    List bookmarks
+"#,
+    {},
+    |aparte, _command| {
+        let rows: Vec<Vec<String>> = {
+            let bookmarks = aparte.get_mod::<BookmarksMod>();
+            let mut bookmarks = bookmarks.bookmarks.clone();
+            bookmarks.sort();
+            bookmarks
+                .into_iter()
+                .map(|bookmark| {
+                    vec![
+                        bookmark.name.unwrap_or_default(),
+                        bookmark.jid.to_string(),
+                        bookmark.nick.unwrap_or_default(),
+                        match bookmark.autojoin {
+                            true => "yes".to_string(),
+                            false => "no".to_string(),
+                        },
+                        match bookmark.password {
+                            Some(_) => "yes".to_string(),
+                            None => String::new(),
+                        },
+                    ]
+                })
+                .collect()
+        };
+        match rows.is_empty() {
+            true => aparte.log("No bookmark".to_string()),
+            false => aparte.log(format!(
+                "Bookmarks:\n{}",
+                table(
+                    &["Name", "Conference", "Nick", "Autojoin", "Password"],
+                    &rows
+                )
+            )),
+        }
+        Ok(())
+    }
+);
+
 command_def!(bookmark,
-r#"/bookmark add|del|edit"#,
+r#"/bookmark add|remove|list|edit"#,
 {
     action: Command = {
         children: {
             "add": bookmark_add,
-            "del": bookmark_del,
+            "remove": bookmark_remove,
+            "list": bookmark_list,
             "edit": bookmark_edit,
         }
     },
@@ -177,7 +245,7 @@ impl Bookmarks {
                 jid: bookmark.jid.clone(),
                 name: Some(bookmark.name.clone().unwrap_or(bookmark.jid.to_string())),
                 nick: bookmark.nick.clone(),
-                password: None,
+                password: bookmark.password.clone(),
             })
             .collect();
         let storage = bookmarks::Storage {
@@ -379,7 +447,7 @@ impl Bookmarks2 {
                     },
                     name: bookmark.name,
                     nick: bookmark.nick,
-                    password: None,
+                    password: bookmark.password,
                     extensions: Some(vec![]),
                 }
                 .into(),
@@ -505,6 +573,8 @@ pub struct BookmarksMod {
     pub bookmarks: Vec<contact::Bookmark>,
     pub bookmarks_by_name: HashMap<String, usize>,
     pub bookmarks_by_jid: HashMap<Jid, usize>,
+    /// Account of each sent retrieval of the bookmarks, by iq id
+    retrievals: HashMap<String, Account>,
 }
 
 impl BookmarksMod {
@@ -514,6 +584,7 @@ impl BookmarksMod {
             bookmarks: vec![],
             bookmarks_by_name: HashMap::new(),
             bookmarks_by_jid: HashMap::new(),
+            retrievals: HashMap::new(),
         }
     }
 
@@ -533,6 +604,7 @@ impl BookmarksMod {
 
     fn add(&mut self, bookmark: contact::Bookmark) -> Element {
         self.bookmarks.push(bookmark.clone());
+        self.update_indexes();
 
         match &self.backend {
             Backend::Bookmarks(backend) => backend.update(&self.bookmarks),
@@ -545,10 +617,17 @@ impl BookmarksMod {
         name: String,
         jid: Option<BareJid>,
         nick: Option<String>,
+        password: Option<String>,
         autojoin: Option<bool>,
     ) -> Option<Element> {
-        if let Some(index) = self.bookmarks_by_name.get(&name) {
-            let mut bookmark = self.bookmarks.get_mut(*index).unwrap();
+        let index = self.bookmarks_by_name.get(&name).cloned().or_else(|| {
+            let jid = BareJid::from_str(&name).ok()?;
+            self.bookmarks
+                .iter()
+                .position(|bookmark| bookmark.jid == jid)
+        });
+        if let Some(index) = index {
+            let mut bookmark = self.bookmarks.get_mut(index).unwrap();
             match jid {
                 Some(jid) => bookmark.jid = jid,
                 None => {}
@@ -558,26 +637,51 @@ impl BookmarksMod {
                 Some(nick) => bookmark.nick = Some(nick),
                 None => {}
             }
+            match password {
+                Some(password) if password == "" => bookmark.password = None,
+                Some(password) => bookmark.password = Some(password),
+                None => {}
+            }
             match autojoin {
                 Some(autojoin) => bookmark.autojoin = autojoin,
                 None => {}
             }
 
+            let bookmark = bookmark.clone();
+            self.update_indexes();
+
             Some(match &self.backend {
                 Backend::Bookmarks(backend) => backend.update(&self.bookmarks),
-                Backend::Bookmarks2(backend) => backend.add(bookmark.clone()),
+                Backend::Bookmarks2(backend) => backend.add(bookmark),
             })
         } else {
             None
         }
     }
 
+    /// Change the name of the bookmark of a conference, None resets it
+    pub fn rename(&mut self, jid: &BareJid, name: Option<String>) -> Option<Element> {
+        let bookmark = self
+            .bookmarks
+            .iter_mut()
+            .find(|bookmark| &bookmark.jid == jid)?;
+        bookmark.name = name;
+        let bookmark = bookmark.clone();
+        self.update_indexes();
+
+        Some(match &self.backend {
+            Backend::Bookmarks(backend) => backend.update(&self.bookmarks),
+            Backend::Bookmarks2(backend) => backend.add(bookmark),
+        })
+    }
+
     fn delete(&mut self, conference: BareJid) -> Option<(contact::Bookmark, Element)> {
         if let Some(index) = self.bookmarks.iter().position(|b| {
             (conference.node.is_none() && b.name == Some(conference.to_string()))
                 || (!conference.node.is_none() && b.jid == conference)
         }) {
             let bookmark = self.bookmarks.remove(index);
+            self.update_indexes();
 
             Some((
                 bookmark,
@@ -646,6 +750,7 @@ impl BookmarksMod {
                 aparte.schedule(Event::Join {
                     account: account.clone(),
                     channel: jid,
+                    password: bookmark.password.clone(),
                     user_request: false,
                 });
             }
@@ -657,6 +762,17 @@ impl BookmarksMod {
         }
     }
 
+    /// Names of the bookmarks, or their conference when they have none
+    fn names(&self) -> Vec<String> {
+        self.bookmarks
+            .iter()
+            .map(|bookmark| match &bookmark.name {
+                Some(name) => name.clone(),
+                None => bookmark.jid.to_string(),
+            })
+            .collect()
+    }
+
     pub fn get_by_name(&self, name: &str) -> Option<contact::Bookmark> {
         match self.bookmarks_by_name.get(name) {
             Some(index) => self.bookmarks.get(*index).cloned(),
@@ -685,24 +801,39 @@ impl ModTrait for BookmarksMod {
                 for elem in self.init_backend(aparte).drain(..) {
                     aparte.send(account, elem);
                 }
-                aparte.send(account, self.retreive());
+                let retrieval = self.retreive();
+                if let Some(id) = retrieval.attr("id") {
+                    self.retrievals.insert(id.to_string(), account.clone());
+                }
+                aparte.send(account, retrieval);
             }
-            Event::Iq(account, iq) => match iq.payload.clone() {
-                IqType::Result(Some(el)) => {
-                    if let Ok(PubSub::Items(items)) = PubSub::try_from(el) {
-                        match &items.node.0 as &str {
-                            ns::BOOKMARKS | ns::BOOKMARKS2 => self.handle_bookmarks(
-                                aparte,
-                                account,
-                                &items.node,
-                                items.items.iter().cloned().map(|item| item.0).collect(),
-                            ),
-                            _ => {}
+            Event::Iq(account, iq) => {
+                match iq.payload.clone() {
+                    IqType::Result(Some(el)) => {
+                        if let Ok(PubSub::Items(items)) = PubSub::try_from(el) {
+                            match &items.node.0 as &str {
+                                ns::BOOKMARKS | ns::BOOKMARKS2 => self.handle_bookmarks(
+                                    aparte,
+                                    account,
+                                    &items.node,
+                                    items.items.iter().cloned().map(|item| item.0).collect(),
+                                ),
+                                _ => {}
+                            }
                         }
                     }
+                    _ => {}
                 }
-                _ => {}
-            },
+                // Errors mean there are no bookmarks
+                match iq.payload {
+                    IqType::Result(_) | IqType::Error(_) => {
+                        if let Some(account) = self.retrievals.remove(&iq.id) {
+                            aparte.schedule(Event::Bookmarks(account));
+                        }
+                    }
+                    _ => {}
+                }
+            }
             Event::PubSub(account, pubsub_event) => match pubsub_event {
                 PubSubEvent::PublishedItems { node, items } => match &node.0 as &str {
                     ns::BOOKMARKS | ns::BOOKMARKS2 => self.handle_bookmarks(
@@ -0,0 +1,453 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+use xmpp_parsers::data_forms::{DataForm, DataFormType, Field, FieldType};
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::message::Message as XmppParsersMessage;
+use xmpp_parsers::muc::user::{MucUser, Status};
+use xmpp_parsers::presence::{Presence, Type as PresenceType};
+use xmpp_parsers::{BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::i18n;
+use crate::message::Message;
+use crate::mods::disco;
+use crate::mods::messages;
+
+const NS_CAPTCHA: &str = "urn:xmpp:captcha";
+const NS_OOB: &str = "jabber:x:oob";
+
+/// Fields a challenge can be answered in, the first one of the form is used
+const ANSWER_FIELDS: [&str; 9] = [
+    "ocr",
+    "qa",
+    "picture_q",
+    "picture_recog",
+    "audio_recog",
+    "speech_q",
+    "speech_recog",
+    "video_q",
+    "video_recog",
+];
+
+command_def!(
+    captcha,
+    r#"/captcha [room=<room>] <answer>
+
+    room      Room asking for the CAPTCHA, the last one that asked by default
+    answer    Answer to the challenge
+
+Description:
+    Answer a CAPTCHA a room asked for when joining it. The challenge is shown
+    in the window of the room, with the address of the image or page to look
+    at when it has one.
+
+Examples:
+    /captcha 7nHL3
+    /captcha room=verona@chat.shakespeare.lit "Montague""#,
+    {
+        room: Named<String>,
+        answer: String
+    },
+    |aparte, command| {
+        let account = command
+            .account
+            .clone()
+            .or_else(|| aparte.current_account())
+            .ok_or(format!("No connection found"))?;
+        let room = match room {
+            Some(room) => {
+                Some(BareJid::from_str(&room).map_err(|err| format!("Invalid room {}: {}", room, err))?)
+            }
+            None => None,
+        };
+        let iq = {
+            let mut captcha = aparte.get_mod_mut::<CaptchaMod>();
+            let challenge = captcha
+                .take(&account, room.as_ref())
+                .ok_or(format!("No CAPTCHA to answer"))?;
+            let id = Uuid::new_v4().to_hyphenated().to_string();
+            let iq = Iq {
+                from: None,
+                to: Some(Jid::Bare(challenge.room.clone())),
+                id: id.clone(),
+                payload: IqType::Set(challenge.answer(&answer)),
+            };
+            captcha.iqs.insert(id, challenge.room);
+            iq
+        };
+        aparte.send(&account, iq.into());
+        Ok(())
+    }
+);
+
+/// Challenge sent by a room before letting us in (XEP-0158)
+#[derive(Debug, Clone)]
+struct Challenge {
+    room: BareJid,
+    /// Values of the form fields echoed back with the answer
+    from: Option<String>,
+    challenge: Option<String>,
+    sid: Option<String>,
+    /// Field the answer goes in, and its question
+    field: String,
+    label: Option<String>,
+    /// Instructions of the message body
+    instructions: Option<String>,
+    /// Image, sound or web page to solve the challenge with
+    urls: Vec<String>,
+}
+
+impl Challenge {
+    fn parse(message: &XmppParsersMessage) -> Option<Self> {
+        let room = match &message.from {
+            Some(from) => BareJid::from(from.clone()),
+            None => return None,
+        };
+        let form = message
+            .payloads
+            .iter()
+            .find(|payload| payload.is("captcha", NS_CAPTCHA))?
+            .get_child("x", xmpp_parsers::ns::DATA_FORMS)?;
+        let form = DataForm::try_from(form.clone()).ok()?;
+        let value = |var: &str| {
+            form.fields
+                .iter()
+                .find(|field| field.var == var)
+                .and_then(|field| field.values.first())
+                .cloned()
+        };
+        let field = form
+            .fields
+            .iter()
+            .find(|field| ANSWER_FIELDS.contains(&field.var.as_str()))?;
+
+        let media = field
+            .media
+            .iter()
+            .flat_map(|media| media.uris.iter())
+            .map(|uri| uri.uri.clone())
+            .filter(|uri| uri.starts_with("http://") || uri.starts_with("https://"));
+        let oob = message
+            .payloads
+            .iter()
+            .filter(|payload| payload.is("x", NS_OOB))
+            .filter_map(|oob| oob.get_child("url", NS_OOB))
+            .map(|url| url.text());
+        let mut urls = Vec::new();
+        for url in media.chain(oob) {
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+
+        Some(Self {
+            room,
+            from: value("from"),
+            challenge: value("challenge"),
+            sid: value("sid"),
+            field: field.var.clone(),
+            label: field.label.clone(),
+            instructions: i18n::get_best(&message.bodies, vec![]).map(|(_, body)| body.0.clone()),
+            urls,
+        })
+    }
+
+    /// Captcha element submitting the answer
+    fn answer(&self, answer: &str) -> Element {
+        let echoed = vec![
+            ("from", self.from.clone()),
+            ("challenge", self.challenge.clone()),
+            ("sid", self.sid.clone()),
+            (self.field.as_str(), Some(answer.to_string())),
+        ];
+        let fields = echoed
+            .into_iter()
+            .filter_map(|(var, value)| {
+                Some(Field {
+                    var: var.to_string(),
+                    type_: FieldType::default(),
+                    label: None,
+                    required: false,
+                    options: vec![],
+                    values: vec![value?],
+                    media: vec![],
+                })
+            })
+            .collect();
+        let form = DataForm {
+            type_: DataFormType::Submit,
+            form_type: Some(String::from(NS_CAPTCHA)),
+            title: None,
+            instructions: None,
+            fields,
+        };
+        Element::builder("captcha", NS_CAPTCHA)
+            .append(Element::from(form))
+            .build()
+    }
+}
+
+impl fmt::Display for Challenge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} asks for a CAPTCHA", self.room)?;
+        if let Some(instructions) = &self.instructions {
+            write!(f, "\n{}", instructions)?;
+        }
+        if let Some(label) = &self.label {
+            write!(f, "\n{}", label)?;
+        }
+        for url in &self.urls {
+            write!(f, "\n{}", url)?;
+        }
+        write!(f, "\nAnswer with /captcha <answer>")
+    }
+}
+
+/// CAPTCHA challenges of rooms (XEP-0158)
+pub struct CaptchaMod {
+    /// Challenges waiting for an answer, the last received at the end
+    pending: Vec<(Account, Challenge)>,
+    /// Room of each sent answer
+    iqs: HashMap<String, BareJid>,
+    /// Rooms being joined, the only ones that can ask for a CAPTCHA
+    joining: HashSet<(Account, BareJid)>,
+}
+
+impl CaptchaMod {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            iqs: HashMap::new(),
+            joining: HashSet::new(),
+        }
+    }
+
+    /// Follow joins until the room lets us in or refuses us
+    fn track_joins(&mut self, event: &Event) {
+        match event {
+            Event::Join {
+                account, channel, ..
+            } => {
+                self.joining
+                    .insert((account.clone(), BareJid::from(channel.clone())));
+            }
+            Event::Leave(channel, _) => {
+                self.joining
+                    .remove(&(channel.account.clone(), channel.jid.clone()));
+            }
+            Event::Presence(account, presence) if Self::ends_join(presence) => {
+                if let Some(from) = &presence.from {
+                    self.joining
+                        .remove(&(account.clone(), BareJid::from(from.clone())));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether a presence is our own in a room, or an error
+    fn ends_join(presence: &Presence) -> bool {
+        presence.type_ == PresenceType::Error
+            || presence.payloads.iter().any(|payload| {
+                MucUser::try_from(payload.clone()).map_or(false, |muc_user| {
+                    muc_user.status.contains(&Status::SelfPresence)
+                })
+            })
+    }
+
+    /// Remove the challenge of a room, or the last one received
+    fn take(&mut self, account: &Account, room: Option<&BareJid>) -> Option<Challenge> {
+        let index = self.pending.iter().rposition(|(pending, challenge)| {
+            pending == account && room.map_or(true, |room| challenge.room == *room)
+        })?;
+        Some(self.pending.remove(index).1)
+    }
+
+    fn handle_iq(&mut self, aparte: &mut Aparte, iq: &Iq) {
+        let room = match self.iqs.remove(&iq.id) {
+            Some(room) => room,
+            None => return,
+        };
+        let info = match &iq.payload {
+            IqType::Error(error) => format!(
+                "CAPTCHA rejected ({}), join {} again to get a new one",
+                messages::error_condition(error),
+                room
+            ),
+            _ => format!("CAPTCHA accepted"),
+        };
+        aparte.schedule(Event::Info(room.to_string(), Message::log(info)));
+    }
+}
+
+impl ModTrait for CaptchaMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(captcha::new());
+        let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+        disco.add_feature(NS_CAPTCHA)
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        _account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) -> f64 {
+        match message
+            .payloads
+            .iter()
+            .any(|payload| payload.is("captcha", NS_CAPTCHA))
+        {
+            true => 1f64,
+            false => 0f64,
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) {
+        // Anyone can send a challenge, only rooms we are joining need an answer
+        let room = message.from.clone().map(BareJid::from);
+        match room {
+            Some(room) if self.joining.contains(&(account.clone(), room.clone())) => {}
+            _ => {
+                aparte.log(format!(
+                    "Ignore a CAPTCHA from {}, not being joined",
+                    room.map_or(String::new(), |room| room.to_string())
+                ));
+                return;
+            }
+        }
+
+        match Challenge::parse(message) {
+            Some(challenge) => {
+                let window = challenge.room.to_string();
+                let info = Message::log(challenge.to_string());
+                // A new challenge of a room replaces the previous one
+                self.take(account, Some(&challenge.room));
+                self.pending.push((account.clone(), challenge));
+                aparte.schedule(Event::Info(window, info));
+            }
+            None => aparte.log(format!(
+                "Cannot answer the CAPTCHA of {}: unsupported challenge",
+                message
+                    .from
+                    .as_ref()
+                    .map_or(String::new(), |from| from.to_string())
+            )),
+        }
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Iq(_, iq) => self.handle_iq(aparte, iq),
+            event => self.track_joins(event),
+        }
+    }
+}
+
+impl fmt::Display for CaptchaMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0158: CAPTCHA Forms")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge() {
+        // Given
+        let message: Element = "<message xmlns='jabber:client' from='verona@chat.shakespeare.lit' to='romeo@montague.lit/orchard' id='F3A6292C'><body>Your messages to verona@chat.shakespeare.lit are being blocked. To unblock them, visit http://chat.shakespeare.lit/challenge.html?F3A6292C</body><x xmlns='jabber:x:oob'><url>http://chat.shakespeare.lit/challenge.html?F3A6292C</url></x><captcha xmlns='urn:xmpp:captcha'><x xmlns='jabber:x:data' type='form'><field type='hidden' var='FORM_TYPE'><value>urn:xmpp:captcha</value></field><field type='hidden' var='from'><value>verona@chat.shakespeare.lit</value></field><field type='hidden' var='challenge'><value>F3A6292C</value></field><field type='hidden' var='sid'><value>1</value></field><field label='Enter the text you see' var='ocr'><media xmlns='urn:xmpp:media-element'><uri type='image/jpeg'>cid:sha1+f24030b8d91d233bac14777be5ab531ca3b9f102@bob.xmpp.org</uri><uri type='image/jpeg'>http://chat.shakespeare.lit/challenge.jpg</uri></media></field></x></captcha></message>".parse().unwrap();
+        let message = XmppParsersMessage::try_from(message).unwrap();
+
+        // When
+        let challenge = Challenge::parse(&message).unwrap();
+        let answer = challenge.answer("7nHL3");
+
+        // Then
+        assert_eq!(challenge.field, "ocr");
+        assert_eq!(challenge.label, Some("Enter the text you see".to_string()));
+        assert_eq!(
+            challenge.urls,
+            vec![
+                "http://chat.shakespeare.lit/challenge.jpg".to_string(),
+                "http://chat.shakespeare.lit/challenge.html?F3A6292C".to_string(),
+            ]
+        );
+        let form = DataForm::try_from(
+            answer
+                .get_child("x", xmpp_parsers::ns::DATA_FORMS)
+                .unwrap()
+                .clone(),
+        )
+        .unwrap();
+        assert_eq!(form.form_type, Some(NS_CAPTCHA.to_string()));
+        let values: Vec<(String, Vec<String>)> = form
+            .fields
+            .into_iter()
+            .map(|field| (field.var, field.values))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                (
+                    "from".to_string(),
+                    vec!["verona@chat.shakespeare.lit".to_string()]
+                ),
+                ("challenge".to_string(), vec!["F3A6292C".to_string()]),
+                ("sid".to_string(), vec!["1".to_string()]),
+                ("ocr".to_string(), vec!["7nHL3".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_track_joins() {
+        // Given
+        let mut captcha = CaptchaMod::new();
+        let account = Account::from_str("romeo@montague.lit/orchard").unwrap();
+        let room = BareJid::from_str("verona@chat.shakespeare.lit").unwrap();
+        let joining =
+            |captcha: &CaptchaMod| captcha.joining.contains(&(account.clone(), room.clone()));
+        let presence: Element = "<presence xmlns='jabber:client' from='verona@chat.shakespeare.lit/romeo' to='romeo@montague.lit/orchard'><x xmlns='http://jabber.org/protocol/muc#user'><item affiliation='none' role='participant'/><status code='110'/></x></presence>".parse().unwrap();
+        let presence = Presence::try_from(presence).unwrap();
+        let other: Element = "<presence xmlns='jabber:client' from='verona@chat.shakespeare.lit/juliet' to='romeo@montague.lit/orchard'><x xmlns='http://jabber.org/protocol/muc#user'><item affiliation='none' role='participant'/></x></presence>".parse().unwrap();
+        let other = Presence::try_from(other).unwrap();
+
+        // When
+        let before = joining(&captcha);
+        captcha.track_joins(&Event::Join {
+            account: account.clone(),
+            channel: Jid::Full(room.clone().with_resource("romeo")),
+            password: None,
+            user_request: true,
+        });
+        let asked = joining(&captcha);
+        captcha.track_joins(&Event::Presence(account.clone(), other));
+        let occupant = joining(&captcha);
+        captcha.track_joins(&Event::Presence(account.clone(), presence));
+        let joined = joining(&captcha);
+
+        // Then
+        assert!(!before);
+        assert!(asked);
+        assert!(occupant);
+        assert!(!joined);
+    }
+}
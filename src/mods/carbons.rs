@@ -1,6 +1,7 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashSet, VecDeque};
 use std::convert::TryFrom;
 use std::fmt;
 use uuid::Uuid;
@@ -14,13 +15,43 @@ use xmpp_parsers::Element;
 
 use crate::account::Account;
 use crate::core::{Aparte, Event, ModTrait};
+use crate::message::Message;
 use crate::mods::disco;
 
-pub struct CarbonsMod {}
+/// Number of messages remembered to detect duplicated carbons
+const RECENT_MESSAGES: usize = 100;
+
+pub struct CarbonsMod {
+    /// Ids of messages received as carbons
+    forwarded: HashSet<String>,
+    /// Recently received messages, by id and body
+    recent: VecDeque<(String, String)>,
+}
 
 impl CarbonsMod {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            forwarded: HashSet::new(),
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Whether a carbon duplicates an already received message
+    ///
+    /// Corrections keep the id of the message but change its body, they aren't duplicates.
+    fn is_duplicate(&mut self, id: &str, body: &str) -> bool {
+        let message = (id.to_string(), body.to_string());
+        if self.recent.contains(&message) {
+            return self.forwarded.contains(id);
+        }
+
+        if self.recent.len() == RECENT_MESSAGES {
+            if let Some((id, _)) = self.recent.pop_front() {
+                self.forwarded.remove(&id);
+            }
+        }
+        self.recent.push_back(message);
+        false
     }
 
     fn enable(&self) -> Element {
@@ -31,6 +62,9 @@ impl CarbonsMod {
 
     fn handle_carbon(&mut self, aparte: &mut Aparte, account: &Account, forwarded: Forwarded) {
         if let Some(message) = forwarded.stanza {
+            if let Some(id) = &message.id {
+                self.forwarded.insert(id.clone());
+            }
             aparte.schedule(Event::RawMessage(account.clone(), message, forwarded.delay));
         }
     }
@@ -78,9 +112,20 @@ impl ModTrait for CarbonsMod {
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
         match event {
             Event::Connected(account, _jid) => aparte.send(account, self.enable()),
+            Event::Message(_, Message::Xmpp(message)) => {
+                if self.is_duplicate(&message.id, message.get_last_body()) {
+                    debug!("Drop duplicated carbon {}", message.id);
+                    aparte.consume_event();
+                }
+            }
             _ => {}
         }
     }
+
+    /// Duplicated carbons must be dropped before other mods see them
+    fn priority(&self) -> i32 {
+        10
+    }
 }
 
 impl fmt::Display for CarbonsMod {
@@ -88,3 +133,22 @@ impl fmt::Display for CarbonsMod {
         write!(f, "XEP-0280: Message Carbons")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_duplicate() {
+        let mut carbons = CarbonsMod::new();
+        carbons.forwarded.insert("carbon".to_string());
+
+        assert!(!carbons.is_duplicate("carbon", "hello"));
+        assert!(carbons.is_duplicate("carbon", "hello"));
+        // Correction
+        assert!(!carbons.is_duplicate("carbon", "hello!"));
+        // Only carbons are dropped
+        assert!(!carbons.is_duplicate("direct", "hi"));
+        assert!(!carbons.is_duplicate("direct", "hi"));
+    }
+}
@@ -0,0 +1,99 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::convert::TryFrom;
+use std::fmt;
+use xmpp_parsers::chatstates::ChatState;
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType as XmppParsersMessageType};
+use xmpp_parsers::ns;
+
+use crate::account::Account;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Direction, Message, XmppMessageType};
+use crate::mods::disco;
+
+pub struct ChatStatesMod {}
+
+impl ChatStatesMod {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    fn get_chat_state(message: &XmppParsersMessage) -> Option<ChatState> {
+        message
+            .payloads
+            .iter()
+            .find_map(|payload| ChatState::try_from(payload.clone()).ok())
+    }
+}
+
+impl ModTrait for ChatStatesMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
+        disco.add_feature(ns::CHATSTATES)
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        _account: &Account,
+        message: &XmppParsersMessage,
+        delay: &Option<Delay>,
+    ) -> f64 {
+        // Only standalone notifications, messages with a body are handled by the messages mod
+        if delay.is_some() || !message.bodies.is_empty() || !message.subjects.is_empty() {
+            return 0f64;
+        }
+
+        match Self::get_chat_state(message) {
+            Some(_) => 0.01f64,
+            None => 0f64,
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) {
+        let state = match Self::get_chat_state(message) {
+            Some(state) => state,
+            None => return,
+        };
+
+        // Ignore our own notifications sent from another resource
+        match Message::get_direction_from_xmpp(account, message) {
+            Ok(Direction::Incoming) => {}
+            _ => return,
+        }
+
+        let from = match &message.from {
+            Some(from) => from.clone(),
+            None => return,
+        };
+
+        let type_ = match message.type_ {
+            XmppParsersMessageType::Chat => XmppMessageType::Chat,
+            XmppParsersMessageType::Groupchat => XmppMessageType::Channel,
+            _ => return,
+        };
+
+        aparte.schedule(Event::ChatState {
+            account: account.clone(),
+            from,
+            type_,
+            state,
+        });
+    }
+
+    fn on_event(&mut self, _aparte: &mut Aparte, _event: &Event) {}
+}
+
+impl fmt::Display for ChatStatesMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0085: Chat State Notifications")
+    }
+}
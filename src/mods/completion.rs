@@ -4,6 +4,8 @@
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
 use std::fmt;
+use std::fs;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::str::FromStr;
 use xmpp_parsers::BareJid;
@@ -20,14 +22,95 @@ pub struct CompletionMod {
     /// List of possible completions for current raw_buf
     completions: Option<Vec<String>>,
     /// Index of currently displayed completion
-    current_completion: usize,
+    current_completion: Option<usize>,
+    /// Input and cursor completions are built for, each completion replaces the word under
+    /// this cursor so that repeated Tab cycles instead of appending
+    origin: Option<(String, Cursor)>,
+}
+
+/// Index of the completion following `current`, or preceding it when going backward
+fn cycle(current: Option<usize>, count: usize, backward: bool) -> usize {
+    match (current, backward) {
+        (None, false) => 0,
+        (None, true) => count - 1,
+        (Some(current), false) => (current + 1) % count,
+        (Some(current), true) => (current + count - 1) % count,
+    }
+}
+
+/// Files and directories completing the path being typed in the argument under the cursor, to be
+/// used as the completion of commands taking local paths
+pub fn complete_path(command: &Command) -> Vec<String> {
+    path_completions(command.args.get(command.cursor).map_or("", String::as_str))
+}
+
+/// Local path typed by the user, a leading `~` being the home directory
+pub fn expand_path(typed: &str) -> PathBuf {
+    match (typed.strip_prefix("~"), dirs::home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(typed),
+    }
+}
+
+/// Files and directories completing `typed`, directories ending with a slash. A leading `~` is
+/// read as the home directory and kept in completions. Hidden files are only completed once
+/// their leading dot is typed.
+fn path_completions(typed: &str) -> Vec<String> {
+    let (dir, prefix) = match typed.rfind('/') {
+        Some(slash) => typed.split_at(slash + 1),
+        None => ("", typed),
+    };
+    let path = match dir.is_empty() {
+        true => PathBuf::from("."),
+        false => expand_path(dir),
+    };
+    let entries = match fs::read_dir(&path) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut completions: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            match entry.path().is_dir() {
+                true => Some(format!("{}{}/", dir, name)),
+                false => Some(format!("{}{}", dir, name)),
+            }
+        })
+        .collect();
+    completions.sort();
+    completions
+}
+
+/// Nicks starting with `prefix`, ignoring case, followed by ", " when the line starts with them
+fn nick_completions<'a, I>(nicks: I, own: &str, prefix: &str, line_start: bool) -> Vec<String>
+where
+    I: Iterator<Item = &'a String>,
+{
+    let prefix = prefix.to_lowercase();
+    let append = if line_start { ", " } else { " " };
+    let mut nicks: Vec<&String> = nicks
+        .filter(|nick| nick.as_str() != own && nick.to_lowercase().starts_with(&prefix))
+        .collect();
+    nicks.sort_by_key(|nick| nick.to_lowercase());
+    nicks
+        .iter()
+        .map(|nick| format!("{}{}", nick, append))
+        .collect()
 }
 
 impl CompletionMod {
     pub fn new() -> CompletionMod {
         CompletionMod {
             completions: None,
-            current_completion: 0,
+            current_completion: None,
+            origin: None,
         }
     }
 
@@ -38,16 +121,23 @@ impl CompletionMod {
         context: &str,
         raw_buf: &str,
         cursor: Cursor,
+        backward: bool,
     ) {
         if self.completions.is_none() {
             self.build_completions(aparte, account, context, raw_buf, &cursor);
+            self.origin = Some((raw_buf.to_string(), cursor.clone()));
         }
+        let (raw_buf, cursor) = match &self.origin {
+            Some((raw_buf, cursor)) => (raw_buf.as_str(), cursor.clone()),
+            None => (raw_buf, cursor),
+        };
 
         if let Some(completions) = &self.completions {
             if completions.len() > 0 {
                 let mut completed_buf = String::new();
                 let mut new_index = 0;
-                let completion = completions[self.current_completion].clone();
+                let current = cycle(self.current_completion, completions.len(), backward);
+                let completion = completions[current].clone();
                 if raw_buf.starts_with("/") {
                     if let Ok(mut command) = Command::parse_with_cursor(
                         account.clone(),
@@ -83,13 +173,13 @@ impl CompletionMod {
                         .join("");
                 }
 
-                self.current_completion += 1;
-                self.current_completion %= completions.len();
+                self.current_completion = Some(current);
 
                 aparte.schedule(Event::Completed(
                     completed_buf.clone(),
                     Cursor::from_index(&completed_buf, new_index).unwrap(),
                 ));
+                aparte.schedule(Event::CompletionCandidates(completions.clone(), current));
             }
         }
     }
@@ -119,11 +209,7 @@ impl CompletionMod {
                 } else {
                     let command_parsers = Rc::clone(&aparte.command_parsers);
                     if let Some(parser) = command_parsers.get(&command.args[0]) {
-                        if command.cursor - 1 < parser.autocompletions.len() {
-                            if let Some(completion) = &parser.autocompletions[command.cursor - 1] {
-                                completions = completion(aparte, command.clone())
-                            }
-                        }
+                        completions = parser.complete(aparte, command.clone());
                     }
                 }
 
@@ -144,7 +230,7 @@ impl CompletionMod {
                     scored.sort_by(|a, b| b.0.cmp(&a.0));
                     Some(scored.iter().map(|(_, c)| c).cloned().collect())
                 };
-                self.current_completion = 0;
+                self.current_completion = None;
             }
         } else {
             let conversation = BareJid::from_str(context);
@@ -158,23 +244,13 @@ impl CompletionMod {
                             Words::new(&raw_buf[..cursor.index(&raw_buf)]).collect::<Vec<_>>();
                         let current_word = *words.last().unwrap_or(&"");
 
-                        let append = if words.len() <= 1 { ": " } else { " " };
-
-                        // Collect completion candidates
-                        self.completions = Some(
-                            channel
-                                .occupants
-                                .iter()
-                                .filter_map(|(_, occupant)| {
-                                    if occupant.nick.starts_with(current_word) {
-                                        Some(occupant.nick.clone() + append)
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect(),
-                        );
-                        self.current_completion = 0;
+                        self.completions = Some(nick_completions(
+                            channel.occupants.keys(),
+                            &channel.nick,
+                            current_word,
+                            words.len() <= 1,
+                        ));
+                        self.current_completion = None;
                     }
                 }
                 _ => {}
@@ -184,7 +260,8 @@ impl CompletionMod {
 
     pub fn reset_completion(&mut self) {
         self.completions = None;
-        self.current_completion = 0;
+        self.current_completion = None;
+        self.origin = None;
     }
 }
 
@@ -200,7 +277,8 @@ impl ModTrait for CompletionMod {
                 context,
                 raw_buf,
                 cursor,
-            } => self.autocomplete(aparte, account, context, raw_buf, cursor.clone()),
+                backward,
+            } => self.autocomplete(aparte, account, context, raw_buf, cursor.clone(), *backward),
             Event::ResetCompletion => self.reset_completion(),
             _ => {}
         }
@@ -212,3 +290,68 @@ impl fmt::Display for CompletionMod {
         write!(f, "Autocompletion")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle() {
+        assert_eq!(cycle(None, 3, false), 0);
+        assert_eq!(cycle(Some(2), 3, false), 0);
+        assert_eq!(cycle(None, 3, true), 2);
+        assert_eq!(cycle(Some(0), 3, true), 2);
+        assert_eq!(cycle(Some(2), 3, true), 1);
+    }
+
+    #[test]
+    fn test_path_completions() {
+        // Given
+        let dir = std::env::temp_dir().join(format!("aparte-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("pictures")).unwrap();
+        fs::write(dir.join("picture.png"), "").unwrap();
+        fs::write(dir.join(".pidfile"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        let typed = format!("{}/pi", dir.display());
+
+        // When
+        let completions = path_completions(&typed);
+        let hidden = path_completions(&format!("{}/.", dir.display()));
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Then
+        assert_eq!(
+            completions,
+            vec![
+                format!("{}/picture.png", dir.display()),
+                format!("{}/pictures/", dir.display()),
+            ]
+        );
+        assert_eq!(hidden, vec![format!("{}/.pidfile", dir.display())]);
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(expand_path("~/notes.txt"), home.join("notes.txt"));
+        }
+        assert_eq!(
+            expand_path("~romeo/notes.txt"),
+            PathBuf::from("~romeo/notes.txt")
+        );
+    }
+
+    #[test]
+    fn test_nick_completions() {
+        let nicks: Vec<String> = vec!["romeo", "Juliet", "Rosaline", "Mercutio"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(
+            nick_completions(nicks.iter(), "Mercutio", "", true),
+            vec!["Juliet, ", "romeo, ", "Rosaline, "]
+        );
+        assert_eq!(
+            nick_completions(nicks.iter(), "Mercutio", "Ro", false),
+            vec!["romeo ", "Rosaline "]
+        );
+        assert!(nick_completions(nicks.iter(), "Mercutio", "Merc", true).is_empty());
+    }
+}
@@ -5,8 +5,10 @@ use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
 use uuid::Uuid;
+use xmpp_parsers::caps::Caps;
 use xmpp_parsers::iq::{Iq, IqType};
-use xmpp_parsers::{ns, presence, roster, BareJid, Element, Jid};
+use xmpp_parsers::presence::{self, Presence};
+use xmpp_parsers::{ns, roster, BareJid, Element, Jid};
 
 use crate::account::Account;
 use crate::contact;
@@ -31,6 +33,45 @@ impl From<roster::Item> for contact::Contact {
             subscription: item.subscription.clone(),
             presence: contact::Presence::Unavailable,
             groups: groups,
+            resources: HashMap::new(),
+            avatar: None,
+        }
+    }
+}
+
+const NS_VCARD_UPDATE: &str = "vcard-temp:x:update";
+
+/// Avatar hash of a presence: None when it doesn't tell, Some(None) when there is no avatar
+fn avatar_hash(presence: &Presence) -> Option<Option<String>> {
+    let photo = presence
+        .payloads
+        .iter()
+        .find(|payload| payload.is("x", NS_VCARD_UPDATE))?
+        .get_child("photo", NS_VCARD_UPDATE)?
+        .text();
+    match photo.trim() {
+        "" => Some(None),
+        hash => Some(Some(hash.to_string())),
+    }
+}
+
+impl From<&Presence> for contact::Resource {
+    fn from(presence: &Presence) -> Self {
+        let client = presence
+            .payloads
+            .iter()
+            .find_map(|payload| Caps::try_from(payload.clone()).ok())
+            .map(|caps| caps.node);
+        Self {
+            presence: presence.into(),
+            priority: presence.priority,
+            status: presence
+                .statuses
+                .values()
+                .next()
+                .filter(|status| !status.is_empty())
+                .cloned(),
+            client,
         }
     }
 }
@@ -101,13 +142,23 @@ impl ModTrait for ContactMod {
                         jid,
                     };
                     if let Some(contact) = self.contacts.get_mut(&index) {
-                        contact.presence = match presence.show {
-                            Some(presence::Show::Away) => contact::Presence::Away,
-                            Some(presence::Show::Chat) => contact::Presence::Chat,
-                            Some(presence::Show::Dnd) => contact::Presence::Dnd,
-                            Some(presence::Show::Xa) => contact::Presence::Xa,
-                            None => contact::Presence::Available,
-                        };
+                        match (from, &presence.type_) {
+                            (Jid::Full(from), presence::Type::None)
+                            | (Jid::Full(from), presence::Type::Unavailable) => {
+                                contact.update_resource(&from.resource, presence.into())
+                            }
+                            (Jid::Bare(_), presence::Type::Unavailable) => {
+                                contact.clear_resources()
+                            }
+                            (Jid::Bare(_), presence::Type::None) => {
+                                contact.presence = presence.into()
+                            }
+                            // Subscriptions and errors don't change the presence
+                            _ => {}
+                        }
+                        if let Some(avatar) = avatar_hash(presence) {
+                            contact.avatar = avatar;
+                        }
                         aparte.schedule(Event::ContactUpdate(account.clone(), contact.clone()));
                     }
                 }
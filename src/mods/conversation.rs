@@ -4,11 +4,15 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 use xmpp_parsers::{muc, BareJid, Jid};
 
 use crate::account::Account;
+use crate::command::Command;
+use crate::contact;
 use crate::conversation;
 use crate::core::{Aparte, Event, ModTrait};
+use crate::i18n;
 
 #[derive(Eq, PartialEq, Hash)]
 struct ConversationIndex {
@@ -39,6 +43,25 @@ impl ConversationMod {
         };
         self.conversations.get(&index)
     }
+
+    /// Channel of the window a command has been run from
+    pub fn current_channel(
+        aparte: &Aparte,
+        command: &Command,
+    ) -> Result<conversation::Channel, String> {
+        let account = command
+            .account
+            .clone()
+            .or_else(|| aparte.current_account())
+            .ok_or(format!("No connection found"))?;
+        let conversation = BareJid::from_str(&command.context)
+            .ok()
+            .and_then(|jid| aparte.get_mod::<Self>().get(&account, &jid).cloned());
+        match conversation {
+            Some(conversation::Conversation::Channel(channel)) => Ok(channel),
+            _ => Err(format!("Not in a channel")),
+        }
+    }
 }
 
 impl From<muc::user::Role> for conversation::Role {
@@ -122,22 +145,43 @@ impl ModTrait for ConversationMod {
                                         jid: occupant_jid,
                                         affiliation: item.affiliation.into(),
                                         role: item.role.into(),
+                                        presence: contact::Presence::from(presence),
+                                        status: i18n::get_best(&presence.statuses, vec![])
+                                            .map(|(_, status)| status.clone()),
                                     };
                                     aparte.schedule(Event::Occupant {
                                         account: index.account.clone(),
                                         conversation: index.jid.clone(),
                                         occupant: occupant.clone(),
                                     });
-                                    channel.occupants.insert(occupant.nick.clone(), occupant);
+                                    match occupant.presence {
+                                        contact::Presence::Unavailable => {
+                                            channel.occupants.remove(&occupant.nick);
+                                        }
+                                        _ => {
+                                            channel
+                                                .occupants
+                                                .insert(occupant.nick.clone(), occupant);
+                                        }
+                                    }
                                 }
                             }
                         }
                     }
                 }
             }
-            Event::Leave(channel) => {
+            Event::Leave(channel, _) => {
                 self.conversations.remove(&channel.clone().into());
             }
+            // Windows are shared by accounts, channels are forgotten once left
+            Event::Close(window) => {
+                if let Ok(jid) = BareJid::from_str(window) {
+                    self.conversations.retain(|index, conversation| {
+                        index.jid != jid
+                            || !matches!(conversation, conversation::Conversation::Chat(_))
+                    });
+                }
+            }
             _ => {}
         }
     }
@@ -0,0 +1,171 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use chrono::{DateTime, FixedOffset, Local as LocalTz};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use xmpp_parsers::presence::Show as PresenceShow;
+use zbus::{dbus_interface, fdo, ConnectionBuilder};
+
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Direction, Message};
+
+const BUS_NAME: &str = "eu.fariello.Aparte";
+const OBJECT_PATH: &str = "/eu/fariello/Aparte";
+
+/// Number of unread messages by window
+type UnreadCounts = Arc<Mutex<HashMap<String, u32>>>;
+
+/// Object exposed on the session bus, calls are turned into events of the running client
+struct Remote {
+    events: mpsc::Sender<Event>,
+    unread: UnreadCounts,
+}
+
+impl Remote {
+    async fn schedule(&self, event: Event) -> fdo::Result<()> {
+        self.events
+            .send(event)
+            .await
+            .map_err(|err| fdo::Error::Failed(err.to_string()))
+    }
+}
+
+#[dbus_interface(name = "eu.fariello.Aparte1")]
+impl Remote {
+    /// Send a message to the conversation of an open window
+    async fn send_message(&self, window: &str, text: &str) -> fdo::Result<()> {
+        self.schedule(Event::SendText(window.to_string(), text.to_string()))
+            .await
+    }
+
+    /// Set the presence of every connected account, show is one of "", away, chat, dnd or xa
+    async fn set_presence(&self, show: &str, status: &str) -> fdo::Result<()> {
+        let show = parse_show(show).map_err(fdo::Error::InvalidArgs)?;
+        let status = match status {
+            "" => None,
+            status => Some(status.to_string()),
+        };
+        self.schedule(Event::SetPresence(show, status)).await
+    }
+
+    /// Open a chat with a contact
+    async fn open_conversation(&self, jid: &str) -> fdo::Result<()> {
+        self.schedule(Event::RawCommand(
+            None,
+            "console".to_string(),
+            format!("/msg {}", jid),
+        ))
+        .await
+    }
+
+    /// Number of unread messages of each window having some
+    async fn unread_counts(&self) -> HashMap<String, u32> {
+        self.unread.lock().unwrap().clone()
+    }
+}
+
+fn parse_show(show: &str) -> Result<Option<PresenceShow>, String> {
+    match show {
+        "" => Ok(None),
+        "away" => Ok(Some(PresenceShow::Away)),
+        "chat" => Ok(Some(PresenceShow::Chat)),
+        "dnd" => Ok(Some(PresenceShow::Dnd)),
+        "xa" => Ok(Some(PresenceShow::Xa)),
+        _ => Err(format!("Unknown presence {}", show)),
+    }
+}
+
+pub struct DBusMod {
+    unread: UnreadCounts,
+    current_window: Option<String>,
+    /// Messages received before are history, they aren't counted as unread
+    started: DateTime<FixedOffset>,
+}
+
+impl DBusMod {
+    pub fn new() -> Self {
+        Self {
+            unread: Arc::new(Mutex::new(HashMap::new())),
+            current_window: Some("console".to_string()),
+            started: LocalTz::now().into(),
+        }
+    }
+
+    fn serve(&self, aparte: &mut Aparte) {
+        let remote = Remote {
+            events: aparte.event_sender(),
+            unread: Arc::clone(&self.unread),
+        };
+
+        aparte.spawn(async move {
+            let connection = ConnectionBuilder::session()
+                .and_then(|builder| builder.name(BUS_NAME))
+                .and_then(|builder| builder.serve_at(OBJECT_PATH, remote));
+            match connection {
+                Ok(connection) => match connection.build().await {
+                    // The service lives as long as its connection
+                    Ok(_connection) => std::future::pending().await,
+                    Err(err) => Event::Message(
+                        None,
+                        Message::log(format!("Cannot start DBus service: {}", err)),
+                    ),
+                },
+                Err(err) => Event::Message(
+                    None,
+                    Message::log(format!("Cannot start DBus service: {}", err)),
+                ),
+            }
+        });
+    }
+}
+
+impl ModTrait for DBusMod {
+    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Start => {
+                if aparte.config.remote.dbus {
+                    self.serve(aparte);
+                }
+            }
+            Event::Message(_, Message::Xmpp(message))
+                if message.direction == Direction::Incoming
+                    && message.get_original_timestamp() >= &self.started =>
+            {
+                let window = message.from.to_string();
+                if Some(&window) != self.current_window.as_ref() {
+                    *self.unread.lock().unwrap().entry(window).or_insert(0) += 1;
+                }
+            }
+            Event::Win(window) => {
+                self.unread.lock().unwrap().remove(window);
+                self.current_window = Some(window.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for DBusMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DBus remote control")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_show() {
+        assert_eq!(parse_show(""), Ok(None));
+        assert_eq!(parse_show("dnd"), Ok(Some(PresenceShow::Dnd)));
+        assert!(parse_show("busy").is_err());
+    }
+}
@@ -4,16 +4,22 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
-use std::str::FromStr;
 use uuid::Uuid;
+use xmpp_parsers::data_forms::{DataForm, DataFormType, Field, FieldType};
 use xmpp_parsers::disco;
 use xmpp_parsers::iq::{Iq, IqType};
-use xmpp_parsers::{Element, Jid};
+use xmpp_parsers::{ns, BareJid, Element, Jid};
 
 use crate::account::Account;
+use crate::config::DiscoConfig;
 use crate::core::{Aparte, Event, ModTrait};
 
+const NS_SOFTWARE_INFO: &str = "urn:xmpp:dataforms:softwareinfo";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 pub struct DiscoMod {
+    config: DiscoConfig,
+    /// Features of the enabled plugins
     client_features: Vec<String>,
     server_features: HashMap<Account, Vec<String>>,
 }
@@ -21,6 +27,7 @@ pub struct DiscoMod {
 impl DiscoMod {
     pub fn new() -> Self {
         Self {
+            config: DiscoConfig::default(),
             client_features: Vec::new(),
             server_features: HashMap::new(),
         }
@@ -36,21 +43,75 @@ impl DiscoMod {
     pub fn has_feature(&self, account: &Account, feature: &str) -> bool {
         self.server_features
             .get(account)
-            .unwrap()
+            .map_or(false, |features| features.iter().any(|i| i == feature))
+    }
+
+    /// What the client advertises, sorted so that it can be hashed for entity capabilities
+    pub fn info(&self, node: Option<String>) -> disco::DiscoInfoResult {
+        let identity = disco::Identity {
+            category: String::from("client"),
+            type_: self.config.type_.clone(),
+            lang: self.config.lang.clone(),
+            name: Some(self.config.name.clone()),
+        };
+
+        let mut features: Vec<String> = self
+            .client_features
             .iter()
-            .any(|i| i == feature)
+            .chain(std::iter::once(&String::from(ns::DISCO_INFO)))
+            .filter(|feature| !self.config.hidden_features.contains(*feature))
+            .cloned()
+            .collect();
+        features.sort();
+        features.dedup();
+
+        let mut extensions = Vec::new();
+        if self.config.version {
+            let field = |var: &str, value: &str| Field {
+                var: var.to_string(),
+                type_: FieldType::default(),
+                label: None,
+                required: false,
+                options: vec![],
+                values: vec![value.to_string()],
+                media: vec![],
+            };
+            extensions.push(DataForm {
+                type_: DataFormType::Result_,
+                form_type: Some(String::from(NS_SOFTWARE_INFO)),
+                title: None,
+                instructions: None,
+                fields: vec![
+                    field("os", std::env::consts::OS),
+                    field("software", &self.config.name),
+                    field("software_version", VERSION),
+                ],
+            });
+        }
+
+        disco::DiscoInfoResult {
+            node,
+            identities: vec![identity],
+            features: features
+                .into_iter()
+                .map(|var| disco::Feature { var })
+                .collect(),
+            extensions,
+        }
     }
 
     pub fn disco(&mut self, jid: Jid) -> Element {
         let id = Uuid::new_v4().to_hyphenated().to_string();
         let query = disco::DiscoInfoQuery { node: None };
-        let iq = Iq::from_get(id, query).with_to(Jid::from_str(&jid.domain()).unwrap());
+        let iq = Iq::from_get(id, query).with_to(Jid::Bare(BareJid::domain(&jid.domain())));
         iq.into()
     }
 }
 
 impl ModTrait for DiscoMod {
-    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        self.config = aparte.config.disco.clone();
+
         Ok(())
     }
 
@@ -69,6 +130,17 @@ impl ModTrait for DiscoMod {
                         }
                     }
                 }
+                IqType::Get(el) => {
+                    if let Ok(query) = disco::DiscoInfoQuery::try_from(el) {
+                        let iq = Iq {
+                            from: None,
+                            to: iq.from.clone(),
+                            id: iq.id.clone(),
+                            payload: IqType::Result(Some(self.info(query.node).into())),
+                        };
+                        aparte.send(account, iq.into());
+                    }
+                }
                 _ => {}
             },
             _ => {}
@@ -81,3 +153,42 @@ impl fmt::Display for DiscoMod {
         write!(f, "XEP-0030: Service Discovery")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info() {
+        // Given
+        let mut disco = DiscoMod::new();
+        disco.config.hidden_features = vec![ns::CHATSTATES.to_string()];
+        disco.add_feature(ns::MESSAGE_CORRECT).unwrap();
+        disco.add_feature(ns::CHATSTATES).unwrap();
+        disco.add_feature(ns::CARBONS).unwrap();
+        disco.add_feature(ns::MESSAGE_CORRECT).unwrap();
+
+        // When
+        let info = disco.info(Some(String::from("https://aparte.dev#ver")));
+        let element: Element = info.clone().into();
+
+        // Then
+        let features: Vec<&str> = info
+            .features
+            .iter()
+            .map(|feature| feature.var.as_str())
+            .collect();
+        assert_eq!(
+            features,
+            vec![ns::DISCO_INFO, ns::CARBONS, ns::MESSAGE_CORRECT]
+        );
+        assert_eq!(info.identities[0].category, "client");
+        assert_eq!(info.identities[0].type_, "console");
+        assert_eq!(
+            info.extensions[0].form_type,
+            Some(NS_SOFTWARE_INFO.to_string())
+        );
+        assert_eq!(element.attr("node"), Some("https://aparte.dev#ver"));
+        assert!(disco::DiscoInfoResult::try_from(element).is_ok());
+    }
+}
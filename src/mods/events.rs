@@ -0,0 +1,300 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+use tokio::sync::broadcast;
+use xmpp_parsers::presence::{Presence, Type as PresenceType};
+use xmpp_parsers::Jid;
+
+use crate::account::Account;
+use crate::config::EventOutput;
+use crate::contact;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::i18n;
+use crate::message::{Direction, LiveMessages, Message, VersionedXmppMessage, XmppMessageType};
+use crate::mods::invitations::Invitation;
+use crate::mods::socket;
+use crate::mods::ui::UIMod;
+
+/// Number of events kept for a slow reader of the socket, older ones are missed
+const SOCKET_BACKLOG: usize = 256;
+
+/// Incoming event written as a line of JSON
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OutputEvent {
+    Message {
+        account: String,
+        conversation: String,
+        /// "chat" or "channel"
+        kind: String,
+        /// Address of the contact, or nick of the occupant
+        from: String,
+        id: String,
+        body: String,
+        timestamp: String,
+    },
+    Presence {
+        account: String,
+        from: String,
+        presence: String,
+        status: Option<String>,
+    },
+    Invitation {
+        account: String,
+        room: String,
+        inviter: String,
+        reason: Option<String>,
+    },
+}
+
+impl OutputEvent {
+    fn message(account: &Account, message: &VersionedXmppMessage) -> Self {
+        let (kind, from) = match &message.type_ {
+            XmppMessageType::Chat => ("chat", message.from.to_string()),
+            XmppMessageType::Channel => match &message.from_full {
+                Jid::Full(from) => ("channel", from.resource.clone()),
+                Jid::Bare(from) => ("channel", from.to_string()),
+            },
+        };
+        OutputEvent::Message {
+            account: account.to_string(),
+            conversation: message.from.to_string(),
+            kind: kind.to_string(),
+            from,
+            id: message.id.clone(),
+            body: message.get_last_body().to_string(),
+            timestamp: message.get_original_timestamp().to_rfc3339(),
+        }
+    }
+
+    /// Presence of a contact or an occupant, subscriptions and errors aren't written
+    fn presence(account: &Account, presence: &Presence) -> Option<Self> {
+        match presence.type_ {
+            PresenceType::None | PresenceType::Unavailable => {}
+            _ => return None,
+        }
+        let show = match contact::Presence::from(presence) {
+            contact::Presence::Unavailable => "unavailable",
+            contact::Presence::Available => "available",
+            contact::Presence::Away => "away",
+            contact::Presence::Chat => "chat",
+            contact::Presence::Dnd => "dnd",
+            contact::Presence::Xa => "xa",
+        };
+        Some(OutputEvent::Presence {
+            account: account.to_string(),
+            from: presence.from.as_ref()?.to_string(),
+            presence: show.to_string(),
+            status: i18n::get_best(&presence.statuses, vec![]).map(|(_, status)| status.clone()),
+        })
+    }
+
+    fn invitation(account: &Account, invitation: &Invitation) -> Self {
+        OutputEvent::Invitation {
+            account: account.to_string(),
+            room: invitation.room.to_string(),
+            inviter: invitation.inviter.to_string(),
+            reason: invitation.reason.clone(),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        format!("{}\n", serde_json::to_string(self).unwrap())
+    }
+}
+
+/// Copy events to a reader of the socket until it leaves
+async fn write_events(mut stream: UnixStream, mut events: broadcast::Receiver<String>) {
+    loop {
+        match events.recv().await {
+            Ok(line) => {
+                if stream.write_all(line.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                warn!("Events socket reader missed {} events", count)
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+enum Output {
+    Stdout,
+    /// Lines sent to every reader of the socket
+    Socket(broadcast::Sender<String>),
+}
+
+/// Incoming messages, presences and invitations written as JSON, one per line, for other programs
+pub struct EventsMod {
+    output: Option<Output>,
+    path: Option<PathBuf>,
+    /// Messages already written, they are received again when corrected
+    live: LiveMessages,
+}
+
+impl EventsMod {
+    pub fn new() -> Self {
+        Self {
+            output: None,
+            path: None,
+            live: LiveMessages::new(),
+        }
+    }
+
+    fn start(&mut self, aparte: &mut Aparte) {
+        let headless = aparte.get_mod::<UIMod>().is_headless();
+        match aparte.config.remote.events {
+            // The terminal is ours otherwise
+            Some(EventOutput::Stdout) if !headless => aparte
+                .log("Events can only be written to the standard output when headless".to_string()),
+            Some(EventOutput::Stdout) => self.output = Some(Output::Stdout),
+            Some(EventOutput::Socket) => self.listen(aparte),
+            None => {}
+        }
+    }
+
+    fn listen(&mut self, aparte: &mut Aparte) {
+        let (listener, path) = match socket::bind(&aparte.data_dir, "events.sock") {
+            Ok(bound) => bound,
+            Err(err) => {
+                aparte.log(format!("Cannot create events socket: {}", err));
+                return;
+            }
+        };
+
+        info!("Writing events to {}", path.display());
+        self.path = Some(path);
+        let (readers, _) = broadcast::channel(SOCKET_BACKLOG);
+        self.output = Some(Output::Socket(readers.clone()));
+        aparte.spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(write_events(stream, readers.subscribe()));
+                    }
+                    Err(err) => {
+                        return Event::Message(
+                            None,
+                            Message::log(format!("Events socket closed: {}", err)),
+                        )
+                    }
+                }
+            }
+        });
+    }
+
+    fn write(&self, event: OutputEvent) {
+        let line = event.to_line();
+        match &self.output {
+            Some(Output::Stdout) => {
+                let mut stdout = std::io::stdout();
+                if let Err(err) = stdout
+                    .write_all(line.as_bytes())
+                    .and_then(|_| stdout.flush())
+                {
+                    warn!("Cannot write event: {}", err);
+                }
+            }
+            // Sending fails when nobody reads the socket
+            Some(Output::Socket(readers)) => {
+                let _ = readers.send(line);
+            }
+            None => {}
+        }
+    }
+}
+
+impl ModTrait for EventsMod {
+    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Start => self.start(aparte),
+            Event::Quit => {
+                if let Some(path) = self.path.take() {
+                    let _ = fs::remove_file(path);
+                }
+            }
+            _ if self.output.is_none() => {}
+            Event::Message(Some(account), Message::Xmpp(message))
+                if message.direction == Direction::Incoming =>
+            {
+                // Messages loaded from the history aren't events
+                if self.live.first_seen(&Some(account.clone()), message) {
+                    self.write(OutputEvent::message(account, message));
+                }
+            }
+            Event::Presence(account, presence) => {
+                if let Some(event) = OutputEvent::presence(account, presence) {
+                    self.write(event);
+                }
+            }
+            Event::Invitation(account, invitation) => {
+                self.write(OutputEvent::invitation(account, invitation))
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for EventsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Event output")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use xmpp_parsers::presence::Show as PresenceShow;
+    use xmpp_parsers::{BareJid, FullJid};
+
+    #[test]
+    fn test_output_event() {
+        // Given
+        let account = FullJid::from_str("romeo@montague.lit/aparte").unwrap();
+        let mut presence = Presence::new(PresenceType::None);
+        presence.from = Some(Jid::from_str("juliet@capulet.lit/balcony").unwrap());
+        presence.show = Some(PresenceShow::Away);
+        presence
+            .statuses
+            .insert(String::new(), "Dreaming".to_string());
+        let mut subscription = Presence::new(PresenceType::Subscribe);
+        subscription.from = Some(Jid::from_str("tybalt@capulet.lit").unwrap());
+        let invitation = Invitation {
+            room: BareJid::from_str("verona@chat.shakespeare.lit").unwrap(),
+            inviter: BareJid::from_str("juliet@capulet.lit").unwrap(),
+            reason: None,
+            password: Some("cauldronburn".to_string()),
+            mediated: true,
+        };
+
+        // When
+        let presence = OutputEvent::presence(&account, &presence);
+        let subscription = OutputEvent::presence(&account, &subscription);
+        let invitation = OutputEvent::invitation(&account, &invitation);
+
+        // Then
+        assert_eq!(
+            presence.unwrap().to_line(),
+            "{\"type\":\"presence\",\"account\":\"romeo@montague.lit/aparte\",\"from\":\"juliet@capulet.lit/balcony\",\"presence\":\"away\",\"status\":\"Dreaming\"}\n"
+        );
+        assert!(subscription.is_none());
+        assert_eq!(
+            invitation.to_line(),
+            "{\"type\":\"invitation\",\"account\":\"romeo@montague.lit/aparte\",\"room\":\"verona@chat.shakespeare.lit\",\"inviter\":\"juliet@capulet.lit\",\"reason\":null}\n"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use xmpp_parsers::{BareJid, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::conversation::Conversation;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Direction, Message, XmppMessageType};
+use crate::mods::conversation::ConversationMod;
+
+command_def!(
+    ignore,
+    r#"/ignore [<jid|nick>]
+
+    jid|nick      Occupant to ignore, by nick or by address
+
+Description:
+    Hide the messages of an occupant of the current channel, only for you.
+    Without argument, list the ignored occupants of the current channel.
+    Ignored occupants are remembered across restarts.
+
+Example:
+    /ignore Tybalt
+    /ignore tybalt@capulet.lit
+"#,
+    {
+        target: Option<String> = {
+            completion: (|aparte, command| {
+                match ConversationMod::current_channel(aparte, &command) {
+                    Ok(channel) => channel.occupants.keys().cloned().collect(),
+                    Err(_) => Vec::new(),
+                }
+            })
+        }
+    },
+    |aparte, command| {
+        let channel = ConversationMod::current_channel(aparte, &command)?;
+        match target {
+            Some(target) => {
+                let saved = {
+                    let mut ignore = aparte.get_mod_mut::<IgnoreMod>();
+                    ignore.list.add(&channel.jid, &target);
+                    ignore.save()
+                };
+                aparte.log(format!("Ignoring {} in {}", target, channel.jid));
+                saved
+            }
+            None => {
+                let ignored = aparte.get_mod::<IgnoreMod>().list.get(&channel.jid);
+                match ignored.is_empty() {
+                    true => aparte.log(format!("Nobody is ignored in {}", channel.jid)),
+                    false => aparte.log(format!(
+                        "Ignored in {}: {}",
+                        channel.jid,
+                        ignored.join(", ")
+                    )),
+                }
+                Ok(())
+            }
+        }
+    }
+);
+
+command_def!(
+    unignore,
+    r#"/unignore <jid|nick>
+
+    jid|nick      Occupant to show again, as given to /ignore
+
+Description:
+    Show the messages of an ignored occupant of the current channel again.
+"#,
+    {
+        target: String = {
+            completion: (|aparte, command| {
+                match ConversationMod::current_channel(aparte, &command) {
+                    Ok(channel) => aparte.get_mod::<IgnoreMod>().list.get(&channel.jid),
+                    Err(_) => Vec::new(),
+                }
+            })
+        }
+    },
+    |aparte, command| {
+        let channel = ConversationMod::current_channel(aparte, &command)?;
+        let saved = {
+            let mut ignore = aparte.get_mod_mut::<IgnoreMod>();
+            if !ignore.list.remove(&channel.jid, &target) {
+                return Err(format!("{} isn't ignored in {}", target, channel.jid));
+            }
+            ignore.save()
+        };
+        aparte.log(format!("No longer ignoring {} in {}", target, channel.jid));
+        saved
+    }
+);
+
+/// Nicks and addresses ignored in each channel, stored one `<channel> <nick|jid>` per line
+#[derive(Debug, Default, PartialEq)]
+struct IgnoreList {
+    ignored: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl IgnoreList {
+    fn parse(content: &str) -> Self {
+        let mut list = Self::default();
+        for line in content.lines() {
+            let mut fields = line.trim().splitn(2, ' ');
+            match (fields.next().map(BareJid::from_str), fields.next()) {
+                (Some(Ok(channel)), Some(target)) => list.add(&channel, target.trim()),
+                _ => warn!("Ignore invalid ignore list line {}", line),
+            }
+        }
+        list
+    }
+
+    fn serialize(&self) -> String {
+        self.ignored
+            .iter()
+            .flat_map(|(channel, targets)| {
+                targets
+                    .iter()
+                    .map(move |target| format!("{} {}\n", channel, target))
+            })
+            .collect()
+    }
+
+    fn add(&mut self, channel: &BareJid, target: &str) {
+        self.ignored
+            .entry(channel.to_string())
+            .or_default()
+            .insert(target.to_string());
+    }
+
+    fn remove(&mut self, channel: &BareJid, target: &str) -> bool {
+        let channel = channel.to_string();
+        let removed = match self.ignored.get_mut(&channel) {
+            Some(targets) => targets.remove(target),
+            None => false,
+        };
+        if self.ignored.get(&channel).map_or(false, BTreeSet::is_empty) {
+            self.ignored.remove(&channel);
+        }
+        removed
+    }
+
+    fn get(&self, channel: &BareJid) -> Vec<String> {
+        self.ignored
+            .get(&channel.to_string())
+            .map_or(Vec::new(), |targets| targets.iter().cloned().collect())
+    }
+
+    /// Whether an occupant is ignored, by nick or by its address when it is known
+    fn is_ignored(&self, channel: &BareJid, nick: &str, jid: Option<&BareJid>) -> bool {
+        match self.ignored.get(&channel.to_string()) {
+            Some(targets) => {
+                targets.contains(nick)
+                    || jid.map_or(false, |jid| targets.contains(&jid.to_string()))
+            }
+            None => false,
+        }
+    }
+}
+
+/// Client side ignore of channel occupants
+pub struct IgnoreMod {
+    path: PathBuf,
+    list: IgnoreList,
+}
+
+impl IgnoreMod {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            path: data_dir.join("ignored"),
+            list: IgnoreList::default(),
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        fs::write(&self.path, self.list.serialize())
+            .map_err(|err| format!("Cannot save ignore list: {}", err))
+    }
+
+    fn is_ignored(
+        &self,
+        aparte: &Aparte,
+        account: &Option<Account>,
+        channel: &BareJid,
+        from: &Jid,
+    ) -> bool {
+        let nick = match from {
+            Jid::Full(from) => &from.resource,
+            Jid::Bare(_) => return false,
+        };
+        let conversation_mod = aparte.get_mod::<ConversationMod>();
+        let conversation = account
+            .as_ref()
+            .and_then(|account| conversation_mod.get(account, channel));
+        let jid = match conversation {
+            Some(Conversation::Channel(channel)) => channel
+                .occupants
+                .get(nick)
+                .and_then(|occupant| occupant.jid.clone()),
+            _ => None,
+        };
+        self.list.is_ignored(channel, nick, jid.as_ref())
+    }
+}
+
+impl ModTrait for IgnoreMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(ignore::new());
+        aparte.add_command(unignore::new());
+
+        match fs::read_to_string(&self.path) {
+            Ok(content) => self.list = IgnoreList::parse(&content),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => aparte.log(format!("Cannot read ignore list: {}", err)),
+        }
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Message(account, Message::Xmpp(message))
+                if message.direction == Direction::Incoming
+                    && message.type_ == XmppMessageType::Channel =>
+            {
+                if self.is_ignored(aparte, account, &message.from, &message.from_full) {
+                    aparte.consume_event();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Ignored messages must be consumed before they are displayed
+    fn priority(&self) -> i32 {
+        5
+    }
+}
+
+impl fmt::Display for IgnoreMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Ignore")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_list() {
+        // Given
+        let channel = BareJid::from_str("verona@chat.shakespeare.lit").unwrap();
+        let tybalt = BareJid::from_str("tybalt@capulet.lit").unwrap();
+        let mut list = IgnoreList::parse(
+            "verona@chat.shakespeare.lit Mercutio\nverona@chat.shakespeare.lit tybalt@capulet.lit\n",
+        );
+
+        // When
+        list.add(&channel, "Benvolio");
+        assert!(list.remove(&channel, "Mercutio"));
+        assert!(!list.remove(&channel, "Romeo"));
+
+        // Then
+        assert!(list.is_ignored(&channel, "Benvolio", None));
+        assert!(list.is_ignored(&channel, "Prince of Cats", Some(&tybalt)));
+        assert!(!list.is_ignored(&channel, "Mercutio", None));
+        assert_eq!(
+            list.serialize(),
+            "verona@chat.shakespeare.lit Benvolio\nverona@chat.shakespeare.lit tybalt@capulet.lit\n"
+        );
+        assert_eq!(IgnoreList::parse(&list.serialize()), list);
+    }
+}
@@ -0,0 +1,137 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::Element;
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::mods::disco;
+use crate::mods::messages;
+
+const NS_INVISIBLE: &str = "urn:xmpp:invisible:0";
+
+command_def!(
+    invisible,
+    r#"/invisible
+
+Description:
+    Appear offline to every contact while staying connected, when the server
+    supports it. Presences sent to a single contact with /presence still reach
+    it. Invisibility lasts until /visible or the end of the connection.
+
+Example:
+    /invisible"#,
+    {},
+    |aparte, command| { InvisibleMod::set(aparte, &command, true) }
+);
+
+command_def!(
+    visible,
+    r#"/visible
+
+Description:
+    Appear to contacts again after /invisible.
+
+Example:
+    /visible"#,
+    {},
+    |aparte, command| { InvisibleMod::set(aparte, &command, false) }
+);
+
+/// Appear offline while connected (XEP-0186)
+pub struct InvisibleMod {
+    /// Account and asked invisibility of each sent iq
+    iqs: HashMap<String, (Account, bool)>,
+}
+
+impl InvisibleMod {
+    pub fn new() -> Self {
+        Self {
+            iqs: HashMap::new(),
+        }
+    }
+
+    fn set(aparte: &mut Aparte, command: &Command, invisible: bool) -> Result<(), String> {
+        let account = command
+            .account
+            .clone()
+            .or_else(|| aparte.current_account())
+            .ok_or(format!("No connection found"))?;
+        if !aparte
+            .get_mod::<disco::DiscoMod>()
+            .has_feature(&account, NS_INVISIBLE)
+        {
+            return Err(format!(
+                "The server of {} doesn't support invisibility",
+                account
+            ));
+        }
+
+        let name = match invisible {
+            true => "invisible",
+            false => "visible",
+        };
+        let id = Uuid::new_v4().to_hyphenated().to_string();
+        let iq = Iq {
+            from: None,
+            to: None,
+            id: id.clone(),
+            payload: IqType::Set(Element::builder(name, NS_INVISIBLE).build()),
+        };
+        aparte
+            .get_mod_mut::<InvisibleMod>()
+            .iqs
+            .insert(id, (account.clone(), invisible));
+        aparte.send(&account, iq.into());
+        Ok(())
+    }
+
+    fn handle_iq(&mut self, aparte: &mut Aparte, iq: &Iq) {
+        let (account, invisible) = match self.iqs.remove(&iq.id) {
+            Some(request) => request,
+            None => return,
+        };
+        match (&iq.payload, invisible) {
+            (IqType::Error(error), _) => aparte.log(format!(
+                "Cannot change the visibility of {}: {}",
+                account,
+                messages::error_condition(error)
+            )),
+            (_, true) => {
+                aparte.log(format!("{} is now invisible", account));
+                aparte.schedule(Event::Invisible(account, true));
+            }
+            (_, false) => {
+                aparte.log(format!("{} is now visible", account));
+                aparte.schedule(Event::Invisible(account, false));
+            }
+        }
+    }
+}
+
+impl ModTrait for InvisibleMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(invisible::new());
+        aparte.add_command(visible::new());
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Iq(_, iq) => self.handle_iq(aparte, iq),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for InvisibleMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0186: Invisible Command")
+    }
+}
@@ -0,0 +1,222 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::str::FromStr;
+use xmpp_parsers::delay::Delay;
+use xmpp_parsers::message::Message as XmppParsersMessage;
+use xmpp_parsers::{ns, BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::core::{Aparte, Event, ModTrait};
+
+const NS_CONFERENCE: &str = "jabber:x:conference";
+
+/// Invitation to join a channel, sent by the channel on behalf of an occupant (XEP-0045) or
+/// directly by the inviter (XEP-0249)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invitation {
+    pub room: BareJid,
+    pub inviter: BareJid,
+    pub reason: Option<String>,
+    pub password: Option<String>,
+    /// Sent by the channel, the inviter is then only claimed by the channel
+    pub mediated: bool,
+}
+
+impl Invitation {
+    fn parse(message: &XmppParsersMessage) -> Option<Self> {
+        let from = BareJid::from(message.from.clone()?);
+        message
+            .payloads
+            .iter()
+            .find_map(|payload| Self::parse_mediated(&from, payload))
+            .or_else(|| {
+                message
+                    .payloads
+                    .iter()
+                    .find_map(|payload| Self::parse_direct(&from, payload))
+            })
+    }
+
+    fn parse_mediated(room: &BareJid, payload: &Element) -> Option<Self> {
+        if !payload.is("x", ns::MUC_USER) {
+            return None;
+        }
+        let invite = payload.get_child("invite", ns::MUC_USER)?;
+        Some(Self {
+            room: room.clone(),
+            inviter: BareJid::from(Jid::from_str(invite.attr("from")?).ok()?),
+            reason: invite
+                .get_child("reason", ns::MUC_USER)
+                .map(|reason| reason.text())
+                .filter(|reason| !reason.is_empty()),
+            password: payload
+                .get_child("password", ns::MUC_USER)
+                .map(|password| password.text()),
+            mediated: true,
+        })
+    }
+
+    fn parse_direct(inviter: &BareJid, payload: &Element) -> Option<Self> {
+        if !payload.is("x", NS_CONFERENCE) {
+            return None;
+        }
+        Some(Self {
+            room: BareJid::from_str(payload.attr("jid")?).ok()?,
+            inviter: inviter.clone(),
+            reason: payload
+                .attr("reason")
+                .filter(|reason| !reason.is_empty())
+                .map(|reason| reason.to_string()),
+            password: payload
+                .attr("password")
+                .map(|password| password.to_string()),
+            mediated: false,
+        })
+    }
+
+    /// Whether the sender of the invitation, or its domain, is one of the given addresses: the
+    /// inviter of a direct invitation, the channel of a mediated one
+    fn is_trusted(&self, trusted: &[String]) -> bool {
+        let sender = match self.mediated {
+            true => &self.room,
+            false => &self.inviter,
+        };
+        let address = sender.to_string();
+        trusted
+            .iter()
+            .any(|trusted| *trusted == address || *trusted == sender.domain)
+    }
+}
+
+impl fmt::Display for Invitation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} invites you to {}", self.inviter, self.room)?;
+        if let Some(reason) = &self.reason {
+            write!(f, ": {}", reason)?;
+        }
+        match &self.password {
+            Some(password) => write!(f, "\nJoin with /join {} --password {}", self.room, password),
+            None => write!(f, "\nJoin with /join {}", self.room),
+        }
+    }
+}
+
+/// Invitations to channels, joined right away when coming from trusted addresses
+pub struct InvitationsMod {
+    auto_accept: Vec<String>,
+}
+
+impl InvitationsMod {
+    pub fn new() -> Self {
+        Self {
+            auto_accept: Vec::new(),
+        }
+    }
+}
+
+impl ModTrait for InvitationsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        self.auto_accept = aparte.config.invitations.auto_accept.clone();
+
+        Ok(())
+    }
+
+    fn can_handle_xmpp_message(
+        &mut self,
+        _aparte: &mut Aparte,
+        _account: &Account,
+        message: &XmppParsersMessage,
+        _delay: &Option<Delay>,
+    ) -> f64 {
+        match Invitation::parse(message) {
+            Some(_) => 1f64,
+            None => 0f64,
+        }
+    }
+
+    fn handle_xmpp_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+        delay: &Option<Delay>,
+    ) {
+        let invitation = match Invitation::parse(message) {
+            Some(invitation) => invitation,
+            None => return,
+        };
+        // Archived invitations have already been dealt with
+        if delay.is_none() {
+            aparte.schedule(Event::Invitation(account.clone(), invitation.clone()));
+        }
+        if delay.is_none() && invitation.is_trusted(&self.auto_accept) {
+            aparte.log(format!(
+                "Joining {} on invitation of {}",
+                invitation.room, invitation.inviter
+            ));
+            aparte.schedule(Event::Join {
+                account: account.clone(),
+                channel: Jid::Bare(invitation.room),
+                password: invitation.password,
+                user_request: false,
+            });
+        } else {
+            aparte.log(invitation.to_string());
+        }
+    }
+}
+
+impl fmt::Display for InvitationsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Channel invitations")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_invitation() {
+        // Given
+        let mediated: Element = "<message xmlns='jabber:client' from='verona@chat.shakespeare.lit' to='romeo@montague.lit'><x xmlns='http://jabber.org/protocol/muc#user'><invite from='juliet@capulet.lit/balcony'><reason>Hey Romeo, come to the ball</reason></invite><password>cauldronburn</password></x></message>".parse().unwrap();
+        let direct: Element = "<message xmlns='jabber:client' from='ops@bots.capulet.lit/incident' to='romeo@montague.lit'><x xmlns='jabber:x:conference' jid='incident-42@chat.capulet.lit' reason=''/></message>".parse().unwrap();
+
+        // When
+        let mediated = Invitation::parse(&XmppParsersMessage::try_from(mediated).unwrap()).unwrap();
+        let direct = Invitation::parse(&XmppParsersMessage::try_from(direct).unwrap()).unwrap();
+
+        // Then
+        assert_eq!(
+            mediated,
+            Invitation {
+                room: BareJid::from_str("verona@chat.shakespeare.lit").unwrap(),
+                inviter: BareJid::from_str("juliet@capulet.lit").unwrap(),
+                reason: Some("Hey Romeo, come to the ball".to_string()),
+                password: Some("cauldronburn".to_string()),
+                mediated: true,
+            }
+        );
+        assert_eq!(
+            direct,
+            Invitation {
+                room: BareJid::from_str("incident-42@chat.capulet.lit").unwrap(),
+                inviter: BareJid::from_str("ops@bots.capulet.lit").unwrap(),
+                reason: None,
+                password: None,
+                mediated: false,
+            }
+        );
+        let trusted = vec![
+            "bots.capulet.lit".to_string(),
+            "nurse@capulet.lit".to_string(),
+        ];
+        assert!(direct.is_trusted(&trusted));
+        assert!(!mediated.is_trusted(&trusted));
+        assert!(!mediated.is_trusted(&["juliet@capulet.lit".to_string()]));
+        assert!(mediated.is_trusted(&["chat.shakespeare.lit".to_string()]));
+    }
+}
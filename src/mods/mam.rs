@@ -1,42 +1,68 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
-use chrono::{DateTime, FixedOffset};
-use std::collections::HashMap;
+use chrono::{DateTime, FixedOffset, Local as LocalTz};
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 use xmpp_parsers::data_forms::{DataForm, DataFormType, Field, FieldType};
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::iq::{Iq, IqType};
 use xmpp_parsers::mam;
-use xmpp_parsers::message::Message as XmppParsersMessage;
+use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType as XmppParsersMessageType};
 use xmpp_parsers::ns;
-use xmpp_parsers::rsm::SetQuery;
+use xmpp_parsers::rsm::{SetQuery, SetResult};
+use xmpp_parsers::stanza_id::StanzaId;
 use xmpp_parsers::{BareJid, Jid};
 
 use crate::account::Account;
 use crate::core::{Aparte, Event, ModTrait};
+use crate::message::XmppMessageType;
+use crate::mods::archive::ArchiveMod;
+
+/// Maximum number of messages asked at once
+const PAGE_SIZE: usize = 100;
+
+/// Maximum number of messages retrieved when catching up with a conversation
+const CATCH_UP_COUNT: usize = 1000;
 
 struct Query {
     jid: BareJid,
     with: Option<BareJid>,
     from: Option<DateTime<FixedOffset>>,
     count: usize,
+    /// Archive id to catch up from, pages are then retrieved oldest first
+    after: Option<String>,
 }
 
 impl Query {
     pub fn start(&self) -> (String, Iq) {
-        // Start with before set to empty string in order to force xmpp_parser to generate a
-        // <before/> element and to ensure we get last page first
-        self.query(Some("".to_string()))
+        match &self.after {
+            Some(after) => self.query(None, Some(after.clone())),
+            // Start with before set to empty string in order to force xmpp_parser to generate a
+            // <before/> element and to ensure we get last page first
+            None => self.query(Some("".to_string()), None),
+        }
+    }
+
+    pub fn cont(&self, set: SetResult) -> Option<(String, Iq)> {
+        match &self.after {
+            Some(_) => set.last.map(|last| self.query(None, Some(last))),
+            None => set.first.map(|first| self.query(Some(first), None)),
+        }
     }
 
-    pub fn cont(&self, before: String) -> (String, Iq) {
-        self.query(Some(before))
+    /// Conversation the retrieved messages belong to
+    fn conversation(&self) -> (XmppMessageType, &BareJid) {
+        match &self.with {
+            Some(with) => (XmppMessageType::Chat, with),
+            None => (XmppMessageType::Channel, &self.jid),
+        }
     }
 
-    fn query(&self, before: Option<String>) -> (String, Iq) {
+    fn query(&self, before: Option<String>, after: Option<String>) -> (String, Iq) {
         let mut fields = Vec::new();
 
         if let Some(end) = self.from {
@@ -73,8 +99,8 @@ impl Query {
         };
 
         let set = SetQuery {
-            max: Some(self.count),
-            after: None,
+            max: Some(self.count.min(PAGE_SIZE)),
+            after,
             before,
             index: None,
         };
@@ -101,6 +127,26 @@ pub struct MamMod {
 
     /// Mapping between iq ids and query ids
     iq2id: HashMap<String, String>,
+
+    /// Archive ids (XEP-0359 stanza-ids) of the messages already received, with the archive that
+    /// assigned them, received live or from a query
+    received: HashSet<(BareJid, String)>,
+}
+
+/// Archive id given to a live message by its archive: the channel for groupchat messages, our
+/// own account otherwise. Other stanza-ids can be forged by the sender.
+fn archive_id(account: &Account, message: &XmppParsersMessage) -> Option<(BareJid, String)> {
+    let archive: BareJid = match (&message.type_, &message.from) {
+        (XmppParsersMessageType::Groupchat, Some(from)) => from.clone().into(),
+        (XmppParsersMessageType::Groupchat, None) => return None,
+        _ => account.clone().into(),
+    };
+    message
+        .payloads
+        .iter()
+        .filter_map(|payload| StanzaId::try_from(payload.clone()).ok())
+        .find(|stanza_id| BareJid::from(stanza_id.by.clone()) == archive)
+        .map(|stanza_id| (archive, stanza_id.id))
 }
 
 impl MamMod {
@@ -108,9 +154,57 @@ impl MamMod {
         Self {
             queries: HashMap::new(),
             iq2id: HashMap::new(),
+            received: HashSet::new(),
         }
     }
 
+    /// Conversation of a live message, when its archive assigned it an id
+    fn conversation(
+        account: &Account,
+        message: &XmppParsersMessage,
+    ) -> Option<(XmppMessageType, BareJid)> {
+        let from: Option<BareJid> = message.from.clone().map(BareJid::from);
+        let to: Option<BareJid> = message.to.clone().map(BareJid::from);
+        let us: BareJid = account.clone().into();
+        match message.type_ {
+            XmppParsersMessageType::Groupchat => from.map(|from| (XmppMessageType::Channel, from)),
+            _ if from.as_ref() == Some(&us) => to.map(|to| (XmppMessageType::Chat, to)),
+            _ => from.map(|from| (XmppMessageType::Chat, from)),
+        }
+    }
+
+    /// Retrieve the messages of a conversation archived after the given id
+    fn catch_up(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        kind: XmppMessageType,
+        conversation: BareJid,
+        after: String,
+    ) {
+        info!(
+            "Catching up with {} from archive id {}",
+            conversation, after
+        );
+        let query = match kind {
+            XmppMessageType::Chat => Query {
+                jid: account.clone().into(),
+                with: Some(conversation),
+                from: None,
+                count: CATCH_UP_COUNT,
+                after: Some(after),
+            },
+            XmppMessageType::Channel => Query {
+                jid: conversation,
+                with: None,
+                from: None,
+                count: CATCH_UP_COUNT,
+                after: Some(after),
+            },
+        };
+        self.query(aparte, account, query);
+    }
+
     fn query(&mut self, aparte: &mut Aparte, account: &Account, query: Query) {
         let (queryid, iq) = query.start();
         self.queries.insert(queryid.clone(), query);
@@ -121,9 +215,21 @@ impl MamMod {
     fn handle_result(&mut self, aparte: &mut Aparte, account: &Account, result: mam::Result_) {
         if let Some(id) = &result.queryid {
             if let Some(query) = self.queries.get_mut(&id.0) {
-                query.count -= 1;
+                query.count = query.count.saturating_sub(1);
+                // Already received live or from a previous query
+                if !self.received.insert((query.jid.clone(), result.id.clone())) {
+                    return;
+                }
                 match (result.forwarded.delay, result.forwarded.stanza) {
                     (Some(delay), Some(message)) => {
+                        let (kind, conversation) = query.conversation();
+                        aparte.get_mod::<ArchiveMod>().update_archive_id(
+                            account,
+                            kind,
+                            conversation,
+                            delay.stamp.0,
+                            &result.id,
+                        );
                         aparte.schedule(Event::RawMessage(account.clone(), message, Some(delay)));
                     }
                     _ => {}
@@ -133,15 +239,14 @@ impl MamMod {
     }
 
     fn handle_fin(&mut self, aparte: &mut Aparte, account: &Account, query: Query, fin: mam::Fin) {
-        if fin.complete == mam::Complete::False {
-            if let Some(start) = fin.set.first {
+        if fin.complete == mam::Complete::False && query.count > 0 {
+            if let Some((queryid, iq)) = query.cont(fin.set) {
                 info!(
                     "Continuing MAM retrieval for {} with {:?} from {:?}",
                     query.jid,
                     query.with.clone().map(|jid| jid.to_string()),
                     query.from
                 );
-                let (queryid, iq) = query.cont(start);
                 self.queries.insert(queryid.clone(), query);
                 self.iq2id.insert(iq.id.clone(), queryid);
                 aparte.send(account, iq.into());
@@ -186,16 +291,52 @@ impl ModTrait for MamMod {
 
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
         match event {
+            Event::Stanza(account, stanza) => {
+                if let Ok(message) = XmppParsersMessage::try_from(stanza.clone()) {
+                    if let Some((archive, id)) = archive_id(account, &message) {
+                        if !self.received.insert((archive, id.clone())) {
+                            aparte.consume_event();
+                        } else if let Some((kind, conversation)) =
+                            Self::conversation(account, &message)
+                        {
+                            let now = LocalTz::now().into();
+                            aparte.get_mod::<ArchiveMod>().update_archive_id(
+                                account,
+                                kind,
+                                &conversation,
+                                now,
+                                &id,
+                            );
+                        }
+                    }
+                }
+            }
+            Event::Connected(account, _) => {
+                let chats = aparte.get_mod::<ArchiveMod>().chat_archive_ids(account);
+                for (chat, after) in chats {
+                    self.catch_up(aparte, account, XmppMessageType::Chat, chat, after);
+                }
+            }
             Event::Join {
                 account, channel, ..
             } => {
-                let query = Query {
-                    jid: channel.clone().into(),
-                    with: None,
-                    from: None,
-                    count: 100,
-                };
-                self.query(aparte, account, query);
+                let channel: BareJid = channel.clone().into();
+                let after = aparte.get_mod::<ArchiveMod>().archive_id(account, &channel);
+                match after {
+                    Some(after) => {
+                        self.catch_up(aparte, account, XmppMessageType::Channel, channel, after)
+                    }
+                    None => {
+                        let query = Query {
+                            jid: channel,
+                            with: None,
+                            from: None,
+                            count: 100,
+                            after: None,
+                        };
+                        self.query(aparte, account, query);
+                    }
+                }
             }
             Event::Chat { account, contact } => {
                 let query = Query {
@@ -203,6 +344,7 @@ impl ModTrait for MamMod {
                     with: Some(contact.clone()),
                     from: None,
                     count: 100,
+                    after: None,
                 };
                 self.query(aparte, account, query);
             }
@@ -212,6 +354,7 @@ impl ModTrait for MamMod {
                     with: None,
                     from: from.clone(),
                     count: 100,
+                    after: None,
                 };
                 self.query(aparte, account, query);
             }
@@ -225,6 +368,7 @@ impl ModTrait for MamMod {
                     with: Some(contact.clone()),
                     from: from.clone(),
                     count: 100,
+                    after: None,
                 };
                 self.query(aparte, account, query);
             }
@@ -251,3 +395,40 @@ impl fmt::Display for MamMod {
         write!(f, "XEP-0313: Message Archive Management")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xmpp_parsers::Element;
+
+    fn message(xml: &str) -> XmppParsersMessage {
+        XmppParsersMessage::try_from(xml.parse::<Element>().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_archive_id() {
+        // Given
+        let account = Account::from_str("romeo@montague.lit/orchard").unwrap();
+        let channel = message(
+            "<message xmlns='jabber:client' type='groupchat' from='verona@chat.shakespeare.lit/Juliet'>
+                <stanza-id xmlns='urn:xmpp:sid:0' by='juliet@capulet.lit' id='forged'/>
+                <stanza-id xmlns='urn:xmpp:sid:0' by='verona@chat.shakespeare.lit' id='room-id'/>
+            </message>",
+        );
+        let forged = message(
+            "<message xmlns='jabber:client' type='chat' from='juliet@capulet.lit/balcony'>
+                <stanza-id xmlns='urn:xmpp:sid:0' by='juliet@capulet.lit' id='forged'/>
+            </message>",
+        );
+
+        // Then
+        assert_eq!(
+            archive_id(&account, &channel),
+            Some((
+                BareJid::from_str("verona@chat.shakespeare.lit").unwrap(),
+                "room-id".to_string()
+            ))
+        );
+        assert_eq!(archive_id(&account, &forged), None);
+    }
+}
@@ -7,10 +7,12 @@ use std::fmt;
 use xmpp_parsers::delay::Delay;
 use xmpp_parsers::message::{Message as XmppParsersMessage, MessageType as XmppParsersMessageType};
 use xmpp_parsers::ns;
+use xmpp_parsers::stanza_error::StanzaError;
+use xmpp_parsers::Element;
 
 use crate::account::Account;
 use crate::core::{Aparte, Event, ModTrait};
-use crate::message::Message;
+use crate::message::{Direction, Message, NS_REPLY};
 use crate::mods::disco;
 
 pub struct MessagesMod {
@@ -44,6 +46,45 @@ impl MessagesMod {
         messages.insert(message.id().to_string(), message.clone());
     }
 
+    /// Quote replied messages we know with their own text rather than the sender's fallback
+    fn with_replied_quote(&self, account: &Account, mut message: Message) -> Message {
+        if let Message::Xmpp(message) = &mut message {
+            if let Some(reply) = &mut message.reply {
+                if let Some(Message::Xmpp(replied)) = self.get(&Some(account.clone()), &reply.id) {
+                    reply.quote = replied.get_last_body().to_string();
+                }
+            }
+        }
+        message
+    }
+
+    /// Annotate our message bounced by an error message, which has its id
+    fn handle_error_message(
+        &mut self,
+        aparte: &mut Aparte,
+        account: &Account,
+        message: &XmppParsersMessage,
+    ) {
+        let error = message
+            .payloads
+            .iter()
+            .find_map(|payload| StanzaError::try_from(payload.clone()).ok());
+        let (id, error) = match (&message.id, error) {
+            (Some(id), Some(error)) => (id, error),
+            _ => return,
+        };
+        match self.get(&Some(account.clone()), id) {
+            Some(Message::Xmpp(sent)) if sent.direction == Direction::Outgoing => {
+                aparte.schedule(Event::MessageError {
+                    account: account.clone(),
+                    id: id.clone(),
+                    error: Some(error_condition(&error)),
+                });
+            }
+            _ => {}
+        }
+    }
+
     fn handle_headline_message(
         &mut self,
         aparte: &mut Aparte,
@@ -63,7 +104,8 @@ impl MessagesMod {
 impl ModTrait for MessagesMod {
     fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
         let mut disco = aparte.get_mod_mut::<disco::DiscoMod>();
-        disco.add_feature(ns::MESSAGE_CORRECT)
+        disco.add_feature(ns::MESSAGE_CORRECT)?;
+        disco.add_feature(NS_REPLY)
     }
 
     fn can_handle_xmpp_message(
@@ -88,6 +130,7 @@ impl ModTrait for MessagesMod {
                     0.01f64
                 }
             }
+            XmppParsersMessageType::Error => 0.01f64,
             XmppParsersMessageType::Headline => {
                 if message
                     .payloads
@@ -113,12 +156,14 @@ impl ModTrait for MessagesMod {
         match message.type_ {
             XmppParsersMessageType::Chat => {
                 if let Ok(message) = Message::from_xmpp(account, message, delay) {
+                    let message = self.with_replied_quote(account, message);
                     aparte.schedule(Event::Message(Some(account.clone()), message));
                 }
             }
             XmppParsersMessageType::Groupchat => {
                 if !message.bodies.is_empty() {
                     if let Ok(message) = Message::from_xmpp(account, message, delay) {
+                        let message = self.with_replied_quote(account, message);
                         aparte.schedule(Event::Message(Some(account.clone()), message));
                     }
                 }
@@ -142,7 +187,7 @@ impl ModTrait for MessagesMod {
             XmppParsersMessageType::Headline => {
                 self.handle_headline_message(aparte, account, message, delay)
             }
-            XmppParsersMessageType::Error => {}
+            XmppParsersMessageType::Error => self.handle_error_message(aparte, account, message),
             XmppParsersMessageType::Normal => {}
         };
     }
@@ -155,8 +200,37 @@ impl ModTrait for MessagesMod {
     }
 }
 
+/// Condition of a stanza error, like item-not-found, followed by its text when given
+pub fn error_condition(error: &StanzaError) -> String {
+    let condition = Element::from(error.defined_condition.clone())
+        .name()
+        .to_string();
+    match error.texts.values().next() {
+        Some(text) => format!("{}: {}", condition, text),
+        None => condition,
+    }
+}
+
 impl fmt::Display for MessagesMod {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Message store")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_condition() {
+        // Given
+        let error: Element = "<error xmlns='jabber:client' type='cancel'><item-not-found xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/><text xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'>No such user</text></error>".parse().unwrap();
+        let error = StanzaError::try_from(error).unwrap();
+
+        // When
+        let condition = error_condition(&error);
+
+        // Then
+        assert_eq!(condition, "item-not-found: No such user");
+    }
+}
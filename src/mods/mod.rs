@@ -1,13 +1,33 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+pub mod affiliations;
+pub mod archive;
 pub mod bookmarks;
+pub mod captcha;
 pub mod carbons;
+pub mod chatstates;
 pub mod completion;
 pub mod contact;
 pub mod conversation;
 pub mod correction;
+pub mod dbus;
 pub mod disco;
+pub mod events;
+pub mod ignore;
+pub mod invisible;
+pub mod invitations;
 pub mod mam;
 pub mod messages;
+pub mod outbox;
+pub mod ping;
+pub mod plugins;
+pub mod preview;
+pub mod rooms;
+pub mod scripting;
+pub mod session;
+pub mod socket;
+pub mod triggers;
 pub mod ui;
+pub mod whois;
+pub mod xml_console;
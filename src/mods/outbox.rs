@@ -0,0 +1,176 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::Message;
+use crate::mods::messages::MessagesMod;
+
+command_def!(
+    resend,
+    r#"/resend
+
+Description:
+    Try again to send the pending messages, those written while disconnected,
+    and the messages that bounced with an error. Pending messages are otherwise
+    sent as soon as their account is connected, and kept pending while it
+    isn't.
+"#,
+    {},
+    |aparte, _command| {
+        let (pending, bounced) = {
+            let mut outbox = aparte.get_mod_mut::<OutboxMod>();
+            (
+                std::mem::take(&mut outbox.pending),
+                std::mem::take(&mut outbox.bounced),
+            )
+        };
+        match pending.len() + bounced.len() {
+            0 => aparte.log("No pending message".to_string()),
+            count => aparte.log(format!("Sending {} pending messages", count)),
+        }
+        for (account, message) in bounced {
+            aparte.schedule(Event::MessageError {
+                account: account.clone(),
+                id: message.id().to_string(),
+                error: None,
+            });
+            OutboxMod::send(aparte, account, message);
+        }
+        for (account, message) in pending {
+            OutboxMod::send(aparte, account, message);
+        }
+        Ok(())
+    }
+);
+
+/// Messages written while their account is disconnected, sent once it is back, and messages
+/// that bounced, sent again with /resend
+pub struct OutboxMod {
+    online: HashSet<Account>,
+    pending: Vec<(Account, Message)>,
+    bounced: Vec<(Account, Message)>,
+}
+
+impl OutboxMod {
+    pub fn new() -> Self {
+        Self {
+            online: HashSet::new(),
+            pending: Vec::new(),
+            bounced: Vec::new(),
+        }
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Remove the pending messages of an account, oldest first
+    fn take(&mut self, account: &Account) -> Vec<(Account, Message)> {
+        let (taken, kept) = self
+            .pending
+            .drain(..)
+            .partition(|(pending, _)| pending == account);
+        self.pending = kept;
+        taken
+    }
+
+    fn send(aparte: &mut Aparte, account: Account, message: Message) {
+        aparte.schedule(Event::Pending {
+            id: message.id().to_string(),
+            pending: false,
+        });
+        aparte.schedule(Event::SendMessage(account, message));
+    }
+}
+
+impl ModTrait for OutboxMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(resend::new());
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connected(account, _) => {
+                self.online.insert(account.clone());
+                for (account, message) in self.take(account) {
+                    Self::send(aparte, account, message);
+                }
+            }
+            Event::Disconnected(account, _) | Event::AuthError(account, _) => {
+                self.online.remove(account);
+            }
+            Event::MessageError {
+                account,
+                id,
+                error: Some(error),
+            } => {
+                let message = aparte
+                    .get_mod::<MessagesMod>()
+                    .get(&Some(account.clone()), id)
+                    .cloned();
+                if let Some(Message::Xmpp(message)) = message {
+                    let info = format!("Message not delivered ({}), use /resend to retry", error);
+                    aparte.schedule(Event::Info(message.to.to_string(), Message::log(info)));
+                    self.bounced
+                        .retain(|(_, bounced)| bounced.id() != message.id);
+                    self.bounced.push((account.clone(), Message::Xmpp(message)));
+                }
+            }
+            Event::SendMessage(account, message) if !self.online.contains(account) => {
+                aparte.consume_event();
+                aparte.schedule(Event::Pending {
+                    id: message.id().to_string(),
+                    pending: true,
+                });
+                aparte.schedule(Event::Message(Some(account.clone()), message.clone()));
+                self.pending.push((account.clone(), message.clone()));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for OutboxMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Outbox")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_take_keeps_other_accounts() {
+        // Given
+        let mut outbox = OutboxMod::new();
+        let me = Account::from_str("me@example.org/aparte").unwrap();
+        let other = Account::from_str("other@example.org/aparte").unwrap();
+        for (account, body) in vec![(&me, "1"), (&other, "2"), (&me, "3")] {
+            outbox
+                .pending
+                .push((account.clone(), Message::log(body.to_string())));
+        }
+
+        // When
+        let taken = outbox.take(&me);
+
+        // Then
+        assert_eq!(
+            taken
+                .iter()
+                .map(|(_, message)| message.body())
+                .collect::<Vec<_>>(),
+            vec!["1", "3"]
+        );
+        assert_eq!(outbox.pending.len(), 1);
+    }
+}
@@ -0,0 +1,132 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::ping::Ping;
+use xmpp_parsers::{BareJid, Jid};
+
+use crate::account::Account;
+use crate::core::{Aparte, Event, ModTrait};
+
+/// Health of the link to the server of an account
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Latency {
+    /// Round trip time of the last answered ping
+    Rtt(Duration),
+    /// Time the last ping has been waiting for an answer
+    Stale(Duration),
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Latency::Rtt(rtt) => write!(f, "{}ms", rtt.as_millis()),
+            Latency::Stale(waiting) => write!(f, "no answer for {}s", waiting.as_secs()),
+        }
+    }
+}
+
+/// Measure the latency of connections by pinging their server every minute (XEP-0199)
+pub struct PingMod {
+    /// Unanswered ping of each connected account, with when it has been sent
+    pending: HashMap<Account, Option<(String, Instant)>>,
+}
+
+impl PingMod {
+    pub fn new() -> Self {
+        Self {
+            pending: HashMap::new(),
+        }
+    }
+
+    fn ping(&mut self, aparte: &mut Aparte, account: &Account) {
+        let id = Uuid::new_v4().to_hyphenated().to_string();
+        let iq = Iq {
+            from: None,
+            to: Some(Jid::Bare(BareJid::domain(&account.domain))),
+            id: id.clone(),
+            payload: IqType::Get(Ping.into()),
+        };
+        self.pending
+            .insert(account.clone(), Some((id, Instant::now())));
+        aparte.send(account, iq.into());
+    }
+
+    /// Ping again, unless the last ping is still unanswered
+    fn tick(&mut self, aparte: &mut Aparte) {
+        let accounts: Vec<Account> = self.pending.keys().cloned().collect();
+        for account in accounts {
+            match self.pending.get(&account) {
+                Some(Some((_, sent))) => {
+                    let latency = Latency::Stale(sent.elapsed());
+                    aparte.schedule(Event::Latency(account, latency));
+                }
+                _ => self.ping(aparte, &account),
+            }
+        }
+    }
+
+    fn handle_iq(&mut self, aparte: &mut Aparte, account: &Account, iq: &Iq) {
+        let sent = match self.pending.get(account) {
+            Some(Some((id, sent))) if *id == iq.id => *sent,
+            _ => return,
+        };
+        // An error answer still tells the server is reachable
+        match iq.payload {
+            IqType::Result(_) | IqType::Error(_) => {
+                self.pending.insert(account.clone(), None);
+                let latency = Latency::Rtt(sent.elapsed());
+                aparte.schedule(Event::Latency(account.clone(), latency));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ModTrait for PingMod {
+    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connected(account, _) => self.ping(aparte, account),
+            Event::Disconnected(account, _) => {
+                self.pending.remove(account);
+            }
+            Event::Tick => self.tick(aparte),
+            Event::Iq(account, iq) => self.handle_iq(aparte, account, iq),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for PingMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XEP-0199: XMPP Ping")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_display() {
+        // Given
+        let rtt = Latency::Rtt(Duration::from_micros(42_700));
+        let stale = Latency::Stale(Duration::from_millis(61_500));
+
+        // When
+        let rtt = rtt.to_string();
+        let stale = stale.to_string();
+
+        // Then
+        assert_eq!(rtt, "42ms");
+        assert_eq!(stale, "no answer for 61s");
+    }
+}
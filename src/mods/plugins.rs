@@ -0,0 +1,377 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use libloading::Library;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs;
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::Path;
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Direction, LiveMessages, Message, XmppMessageType};
+
+/// Version of the plugin interface, bumped on any change of `PluginApi`
+///
+/// Must be kept in sync with `include/aparte_plugin.h`.
+pub const PLUGIN_API_VERSION: u32 = 1;
+
+/// Symbol every plugin exports, returning 0 on success
+const PLUGIN_INIT: &[u8] = b"aparte_plugin_init\0";
+
+type PluginInit = unsafe extern "C" fn(api: *const PluginApi) -> c_int;
+
+pub type CommandCallback =
+    extern "C" fn(api: *const PluginApi, argc: usize, argv: *const *const c_char);
+pub type MessageCallback = extern "C" fn(
+    api: *const PluginApi,
+    window: *const c_char,
+    from: *const c_char,
+    body: *const c_char,
+);
+pub type ConnectedCallback = extern "C" fn(api: *const PluginApi, account: *const c_char);
+
+/// Functions offered to plugins, every string is a NUL terminated UTF-8 string
+///
+/// Strings given to plugins are only valid during the call.
+#[repr(C)]
+pub struct PluginApi {
+    pub version: u32,
+    /// Host state, to be left untouched by plugins
+    pub host: *mut c_void,
+    pub log: extern "C" fn(api: *const PluginApi, text: *const c_char),
+    /// Run a command, as if typed in the current window
+    pub command: extern "C" fn(api: *const PluginApi, line: *const c_char),
+    pub send: extern "C" fn(api: *const PluginApi, window: *const c_char, text: *const c_char),
+    /// Only allowed in `aparte_plugin_init`, returns 0 on success
+    pub register_command: extern "C" fn(
+        api: *const PluginApi,
+        name: *const c_char,
+        help: *const c_char,
+        callback: CommandCallback,
+    ) -> c_int,
+    pub on_message: extern "C" fn(api: *const PluginApi, callback: MessageCallback),
+    pub on_connected: extern "C" fn(api: *const PluginApi, callback: ConnectedCallback),
+}
+
+/// Effect requested by a plugin, applied once the plugin returns
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Log(String),
+    Command(String),
+    Send(String, String),
+}
+
+#[derive(Default)]
+struct Host {
+    loading: bool,
+    actions: Vec<Action>,
+    commands: HashMap<String, (String, CommandCallback)>,
+    message_hooks: Vec<MessageCallback>,
+    connected_hooks: Vec<ConnectedCallback>,
+}
+
+/// Read a string given by a plugin
+unsafe fn from_c(string: *const c_char) -> String {
+    match string.is_null() {
+        true => String::new(),
+        false => CStr::from_ptr(string).to_string_lossy().into_owned(),
+    }
+}
+
+/// Convert a string given to a plugin, interior NUL bytes are dropped
+fn to_c(string: &str) -> CString {
+    CString::new(string.replace('\0', "")).unwrap()
+}
+
+unsafe fn host<'a>(api: *const PluginApi) -> &'a RefCell<Host> {
+    &*((*api).host as *const RefCell<Host>)
+}
+
+extern "C" fn log(api: *const PluginApi, text: *const c_char) {
+    let (host, text) = unsafe { (host(api), from_c(text)) };
+    host.borrow_mut().actions.push(Action::Log(text));
+}
+
+extern "C" fn command(api: *const PluginApi, line: *const c_char) {
+    let (host, line) = unsafe { (host(api), from_c(line)) };
+    host.borrow_mut().actions.push(Action::Command(line));
+}
+
+extern "C" fn send(api: *const PluginApi, window: *const c_char, text: *const c_char) {
+    let (host, window, text) = unsafe { (host(api), from_c(window), from_c(text)) };
+    host.borrow_mut().actions.push(Action::Send(window, text));
+}
+
+extern "C" fn register_command(
+    api: *const PluginApi,
+    name: *const c_char,
+    help: *const c_char,
+    callback: CommandCallback,
+) -> c_int {
+    let (host, name, help) = unsafe { (host(api), from_c(name), from_c(help)) };
+    let mut host = host.borrow_mut();
+    let name = name.trim_start_matches('/').to_string();
+    if !host.loading || name.is_empty() || host.commands.contains_key(&name) {
+        return -1;
+    }
+    host.commands.insert(name, (help, callback));
+    0
+}
+
+extern "C" fn on_message(api: *const PluginApi, callback: MessageCallback) {
+    unsafe { host(api) }
+        .borrow_mut()
+        .message_hooks
+        .push(callback);
+}
+
+extern "C" fn on_connected(api: *const PluginApi, callback: ConnectedCallback) {
+    unsafe { host(api) }
+        .borrow_mut()
+        .connected_hooks
+        .push(callback);
+}
+
+pub struct PluginsMod {
+    /// Boxed so that its address given to plugins stays the same
+    api: Box<PluginApi>,
+    host: Box<RefCell<Host>>,
+    /// Unloading a library invalidates its callbacks, libraries are kept until exit
+    libraries: Vec<Library>,
+    /// History and corrections aren't new messages for message hooks
+    live: LiveMessages,
+}
+
+impl PluginsMod {
+    pub fn new() -> Self {
+        let host = Box::new(RefCell::new(Host::default()));
+        let api = Box::new(PluginApi {
+            version: PLUGIN_API_VERSION,
+            host: &*host as *const RefCell<Host> as *mut c_void,
+            log,
+            command,
+            send,
+            register_command,
+            on_message,
+            on_connected,
+        });
+
+        Self {
+            api,
+            host,
+            libraries: Vec::new(),
+            live: LiveMessages::new(),
+        }
+    }
+
+    fn take_actions(&self) -> Vec<Action> {
+        self.host.borrow_mut().actions.drain(..).collect()
+    }
+
+    fn load(&mut self, path: &Path) -> Result<(), String> {
+        let error = |err: String| format!("Plugin {}: {}", path.display(), err);
+
+        // Loading a library runs its initializers, plugins are trusted like any installed binary
+        let library = unsafe { Library::new(path) }.map_err(|err| error(err.to_string()))?;
+        let init = unsafe { library.get::<PluginInit>(PLUGIN_INIT) }
+            .map_err(|err| error(err.to_string()))?;
+
+        self.host.borrow_mut().loading = true;
+        let result = unsafe { init(&*self.api) };
+        self.host.borrow_mut().loading = false;
+
+        // Keep the library loaded even on failure, it may have registered callbacks
+        self.libraries.push(library);
+
+        match result {
+            0 => Ok(()),
+            code => Err(error(format!("initialization failed ({})", code))),
+        }
+    }
+
+    fn apply(aparte: &mut Aparte, actions: Vec<Action>, account: &Option<Account>, context: &str) {
+        for action in actions {
+            match action {
+                Action::Log(text) => aparte.log(text),
+                Action::Command(line) => aparte.schedule(Event::RawCommand(
+                    account.clone(),
+                    context.to_string(),
+                    line,
+                )),
+                Action::Send(window, text) => aparte.schedule(Event::SendText(window, text)),
+            }
+        }
+    }
+}
+
+fn parse_plugin_command(
+    account: &Option<Account>,
+    context: &str,
+    buf: &str,
+) -> Result<Command, String> {
+    Command::new(account.clone(), context.to_string(), buf.to_string())
+}
+
+fn exec_plugin_command(aparte: &mut Aparte, command: Command) -> Result<(), String> {
+    let actions = {
+        let plugins = aparte.get_mod::<PluginsMod>();
+        let callback = plugins
+            .host
+            .borrow()
+            .commands
+            .get(&command.args[0])
+            .map(|(_, callback)| *callback)
+            .ok_or(format!("Unknown command {}", command.args[0]))?;
+
+        let args = command.args.iter().map(|arg| to_c(arg)).collect::<Vec<_>>();
+        let argv = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+        callback(&*plugins.api, argv.len(), argv.as_ptr());
+        plugins.take_actions()
+    };
+
+    PluginsMod::apply(aparte, actions, &command.account, &command.context);
+    Ok(())
+}
+
+impl ModTrait for PluginsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let dir = aparte.config_dir.join("plugins");
+        let mut paths = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension()
+                        .map_or(false, |ext| ext == std::env::consts::DLL_EXTENSION)
+                })
+                .collect::<Vec<_>>(),
+            Err(_) => return Ok(()),
+        };
+        paths.sort();
+
+        for path in paths {
+            if let Err(err) = self.load(&path) {
+                aparte.log(err);
+            }
+            let actions = self.take_actions();
+            Self::apply(aparte, actions, &None, "console");
+        }
+
+        let commands = self.host.borrow().commands.clone();
+        for (name, (help, _)) in commands {
+            if aparte.command_parsers.contains_key(&name) {
+                aparte.log(format!("Plugin command /{} already exists", name));
+                self.host.borrow_mut().commands.remove(&name);
+                continue;
+            }
+            aparte.add_command(CommandParser {
+                // Commands live as long as the client
+                name: Box::leak(name.into_boxed_str()),
+                help,
+                parse: parse_plugin_command,
+                exec: exec_plugin_command,
+                autocompletions: Vec::new(),
+                named: Vec::new(),
+                flags: Vec::new(),
+                subcommands: Vec::new(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Message(account, Message::Xmpp(message))
+                if message.direction == Direction::Incoming
+                    && self.live.first_seen(account, message) =>
+            {
+                let window = message.from.to_string();
+                let from = match &message.type_ {
+                    XmppMessageType::Channel => match &message.from_full {
+                        xmpp_parsers::Jid::Full(from) => from.resource.clone(),
+                        xmpp_parsers::Jid::Bare(from) => from.to_string(),
+                    },
+                    XmppMessageType::Chat => message.from.to_string(),
+                };
+                let (c_window, from, body) =
+                    (to_c(&window), to_c(&from), to_c(message.get_last_body()));
+
+                let hooks = self.host.borrow().message_hooks.clone();
+                for hook in hooks {
+                    hook(&*self.api, c_window.as_ptr(), from.as_ptr(), body.as_ptr());
+                }
+                Self::apply(aparte, self.take_actions(), account, &window);
+            }
+            Event::Connected(account, _) => {
+                let c_account = to_c(&account.to_string());
+                let hooks = self.host.borrow().connected_hooks.clone();
+                for hook in hooks {
+                    hook(&*self.api, c_account.as_ptr());
+                }
+                Self::apply(
+                    aparte,
+                    self.take_actions(),
+                    &Some(account.clone()),
+                    "console",
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for PluginsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Native plugins")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn echo(api: *const PluginApi, argc: usize, argv: *const *const c_char) {
+        let args = unsafe { std::slice::from_raw_parts(argv, argc) };
+        let api = unsafe { &*api };
+        (api.send)(api, args[1], args[2]);
+    }
+
+    #[test]
+    fn test_plugin_api() {
+        // Given
+        let plugins = PluginsMod::new();
+        let api = &*plugins.api;
+        let (name, help) = (to_c("/echo"), to_c("Echo"));
+
+        // When
+        (api.log)(api, to_c("hello").as_ptr());
+        let refused = (api.register_command)(api, name.as_ptr(), help.as_ptr(), echo);
+        plugins.host.borrow_mut().loading = true;
+        let registered = (api.register_command)(api, name.as_ptr(), help.as_ptr(), echo);
+
+        // Then
+        assert_eq!(refused, -1);
+        assert_eq!(registered, 0);
+        assert_eq!(
+            plugins.take_actions(),
+            vec![Action::Log("hello".to_string())]
+        );
+
+        let callback = plugins.host.borrow().commands.get("echo").unwrap().1;
+        let args = vec![to_c("echo"), to_c("room@example.org"), to_c("hi")];
+        let argv = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+        callback(api, argv.len(), argv.as_ptr());
+        assert_eq!(
+            plugins.take_actions(),
+            vec![Action::Send(
+                "room@example.org".to_string(),
+                "hi".to_string()
+            )]
+        );
+    }
+}
@@ -0,0 +1,381 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageOutputFormat};
+use std::collections::{BTreeSet, HashSet};
+use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::aesgcm::EncryptedUrl;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::Message;
+use crate::terminus::Graphic;
+use crate::url;
+
+const IMAGE_EXTENSIONS: [&str; 6] = [".png", ".jpg", ".jpeg", ".gif", ".webp", ".bmp"];
+
+/// Pixel height assumed for a terminal line when the terminal doesn't scale images itself
+const SIXEL_ROW_HEIGHT: u32 = 12;
+
+/// Identifiers of kitty images, so that each preview can be deleted independently
+static KITTY_IMAGE_ID: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Kitty,
+    Iterm,
+    Sixel,
+}
+
+impl Protocol {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "kitty" => Ok(Protocol::Kitty),
+            "iterm" | "iterm2" => Ok(Protocol::Iterm),
+            "sixel" => Ok(Protocol::Sixel),
+            _ => Err(format!("Unknown graphics protocol {}", value)),
+        }
+    }
+
+    /// Guess the protocol supported by the terminal from the environment
+    pub fn detect() -> Option<Self> {
+        let term = env::var("TERM").unwrap_or_default();
+        let term_program = env::var("TERM_PROGRAM").unwrap_or_default();
+
+        if term.contains("kitty") || env::var("KITTY_WINDOW_ID").is_ok() {
+            Some(Protocol::Kitty)
+        } else if term_program == "iTerm.app" || term_program == "WezTerm" {
+            Some(Protocol::Iterm)
+        } else if term.contains("mlterm") || term.contains("foot") {
+            Some(Protocol::Sixel)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct PreviewMod {
+    protocol: Option<Protocol>,
+    max_size: u64,
+    rows: u16,
+    /// Messages whose preview has already been requested
+    requested: HashSet<String>,
+}
+
+impl PreviewMod {
+    pub fn new() -> Self {
+        Self {
+            protocol: None,
+            max_size: 0,
+            rows: 0,
+            requested: HashSet::new(),
+        }
+    }
+
+    /// URL of the image attached to the message, if its body is only this URL
+    fn get_image_url(body: &str) -> Option<String> {
+        let body = body.trim();
+        let urls = url::find_urls(body);
+        match urls.as_slice() {
+            [range] if range.start == 0 && range.end == body.len() => {
+                let path = body.split(|c| c == '?' || c == '#').next().unwrap();
+                let path = path.to_lowercase();
+                match IMAGE_EXTENSIONS.iter().any(|ext| path.ends_with(ext)) {
+                    true => Some(body.to_string()),
+                    false => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Download a file, decrypting it when shared with an aesgcm:// URL
+pub async fn download(url: &str, max_size: u64) -> Result<Vec<u8>, String> {
+    match EncryptedUrl::is_encrypted(url) {
+        true => {
+            let encrypted = EncryptedUrl::parse(url)?;
+            let data = download_plain(&encrypted.url, max_size).await?;
+            encrypted.decrypt(&data)
+        }
+        false => download_plain(url, max_size).await,
+    }
+}
+
+async fn download_plain(url: &str, max_size: u64) -> Result<Vec<u8>, String> {
+    let mut response = reqwest::get(url)
+        .await
+        .map_err(|err| format!("Cannot download {}: {}", url, err))?;
+
+    if response.content_length().unwrap_or(0) > max_size {
+        return Err(format!("{} is too big to be previewed", url));
+    }
+
+    // The announced length can't be trusted
+    let mut data = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| format!("Cannot download {}: {}", url, err))?
+    {
+        data.extend_from_slice(&chunk);
+        if data.len() as u64 > max_size {
+            return Err(format!("{} is too big to be previewed", url));
+        }
+    }
+
+    Ok(data)
+}
+
+fn encode(data: &[u8], protocol: Protocol, rows: u16) -> Result<Graphic, String> {
+    let image = image::load_from_memory(data).map_err(|err| format!("Invalid image: {}", err))?;
+
+    match protocol {
+        Protocol::Kitty => {
+            let png = encode_png(&image.thumbnail(480, 480))?;
+            let id = KITTY_IMAGE_ID.fetch_add(1, Ordering::Relaxed);
+            Ok(Graphic {
+                rows,
+                draw: kitty(&png, id, rows),
+                clear: format!("\x1b_Ga=d,d=i,i={},q=2\x1b\\", id),
+            })
+        }
+        Protocol::Iterm => {
+            let png = encode_png(&image.thumbnail(480, 480))?;
+            Ok(Graphic {
+                rows,
+                draw: format!(
+                    "\x1b]1337;File=inline=1;size={};height={};preserveAspectRatio=1:{}\x07",
+                    png.len(),
+                    rows,
+                    base64::encode(&png)
+                ),
+                clear: String::new(),
+            })
+        }
+        Protocol::Sixel => {
+            let height = u32::from(rows) * SIXEL_ROW_HEIGHT;
+            let image = image.resize(height * 4, height, FilterType::Triangle);
+            Ok(Graphic {
+                rows,
+                draw: sixel(&image),
+                clear: String::new(),
+            })
+        }
+    }
+}
+
+fn encode_png(image: &DynamicImage) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(|err| format!("Cannot encode image: {}", err))?;
+    Ok(png)
+}
+
+/// Transmit and display a PNG image with the kitty graphics protocol
+fn kitty(png: &[u8], id: u32, rows: u16) -> String {
+    let data = base64::encode(png);
+    let chunks = data.as_bytes().chunks(4096).collect::<Vec<&[u8]>>();
+    let mut output = String::new();
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        // Base64 is ASCII, chunks are valid UTF-8
+        let chunk = std::str::from_utf8(chunk).unwrap();
+        match i {
+            0 => output.push_str(&format!(
+                "\x1b_Gf=100,a=T,i={},r={},C=1,q=2,m={};{}\x1b\\",
+                id, rows, more, chunk
+            )),
+            _ => output.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk)),
+        }
+    }
+
+    output
+}
+
+/// Encode an image as sixels, using the 6x6x6 color cube as palette
+fn sixel(image: &DynamicImage) -> String {
+    let image = image.to_rgb8();
+    let (width, height) = image.dimensions();
+    let level = |c: u8| (usize::from(c) * 5 + 127) / 255;
+    let color = |x: u32, y: u32| {
+        let pixel = image.get_pixel(x, y);
+        level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2])
+    };
+
+    let mut output = String::from("\x1bPq");
+    for i in 0..216 {
+        output.push_str(&format!(
+            "#{};2;{};{};{}",
+            i,
+            i / 36 * 20,
+            i / 6 % 6 * 20,
+            i % 6 * 20
+        ));
+    }
+
+    for top in (0..height).step_by(6) {
+        let bottom = std::cmp::min(top + 6, height);
+        let colors = (top..bottom)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| color(x, y))
+            .collect::<BTreeSet<usize>>();
+
+        for c in colors {
+            output.push_str(&format!("#{}", c));
+            for x in 0..width {
+                let mut bits = 0;
+                for y in top..bottom {
+                    if color(x, y) == c {
+                        bits |= 1 << (y - top);
+                    }
+                }
+                output.push((63 + bits) as u8 as char);
+            }
+            // Go back to the start of the band for the next color
+            output.push('$');
+        }
+        output.push('-');
+    }
+    output.push_str("\x1b\\");
+
+    output
+}
+
+impl ModTrait for PreviewMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let config = aparte.config.preview.clone();
+        aparte.settings.register_validated(
+            "preview.rows",
+            toml::Value::Integer(i64::from(config.rows)),
+            "Number of lines of image previews",
+            |value| match value.as_integer() {
+                Some(rows) if rows > 0 && rows <= i64::from(u16::MAX) => Ok(()),
+                _ => Err("Preview rows must be a positive number".to_string()),
+            },
+        );
+        aparte.settings.register_validated(
+            "preview.max_size",
+            toml::Value::Integer(config.max_size as i64),
+            "Size in bytes above which images aren't previewed",
+            |value| match value.as_integer() {
+                Some(size) if size >= 0 => Ok(()),
+                _ => Err("Preview max size can't be negative".to_string()),
+            },
+        );
+
+        if !config.enabled {
+            return Ok(());
+        }
+
+        self.max_size = config.max_size;
+        self.rows = config.rows;
+        self.protocol = match &config.protocol {
+            Some(protocol) => match Protocol::parse(protocol) {
+                Ok(protocol) => Some(protocol),
+                Err(err) => {
+                    aparte.log(err);
+                    None
+                }
+            },
+            None => Protocol::detect(),
+        };
+
+        if self.protocol.is_none() {
+            aparte.log("Image previews are disabled: the terminal graphics protocol is unknown, set it with `protocol` in the `preview` section".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Message(_, Message::Xmpp(message)) => {
+                let protocol = match self.protocol {
+                    Some(protocol) => protocol,
+                    None => return,
+                };
+
+                if self.requested.contains(&message.id) {
+                    return;
+                }
+
+                if let Some(url) = Self::get_image_url(message.get_last_body()) {
+                    self.requested.insert(message.id.clone());
+                    let id = message.id.clone();
+                    let max_size = self.max_size;
+                    let rows = self.rows;
+                    aparte.spawn(async move {
+                        let data = match download(&url, max_size).await {
+                            Ok(data) => data,
+                            Err(err) => return Event::Message(None, Message::log(err)),
+                        };
+
+                        // Decoding is CPU bound, keep it away from the event loop
+                        let graphic =
+                            tokio::task::spawn_blocking(move || encode(&data, protocol, rows))
+                                .await;
+                        match graphic {
+                            Ok(Ok(graphic)) => Event::Preview { id, graphic },
+                            Ok(Err(err)) => Event::Message(None, Message::log(err)),
+                            Err(err) => Event::Message(
+                                None,
+                                Message::log(format!("Cannot preview {}: {}", url, err)),
+                            ),
+                        }
+                    });
+                }
+            }
+            Event::Setting(key, toml::Value::Integer(value)) => match key.as_str() {
+                "preview.rows" => self.rows = *value as u16,
+                "preview.max_size" => self.max_size = *value as u64,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for PreviewMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Image previews")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_image_url() {
+        assert_eq!(
+            PreviewMod::get_image_url("https://upload.example.org/abc/cat.JPG"),
+            Some("https://upload.example.org/abc/cat.JPG".to_string())
+        );
+        assert_eq!(
+            PreviewMod::get_image_url("https://example.org/cat.png?size=1"),
+            Some("https://example.org/cat.png?size=1".to_string())
+        );
+        assert_eq!(
+            PreviewMod::get_image_url("look https://example.org/cat.png"),
+            None
+        );
+        assert_eq!(PreviewMod::get_image_url("https://example.org/"), None);
+        assert_eq!(
+            PreviewMod::get_image_url("aesgcm://example.org/cat.jpg#0a1b"),
+            Some("aesgcm://example.org/cat.jpg#0a1b".to_string())
+        );
+    }
+
+    #[test]
+    fn test_kitty_chunks() {
+        let output = kitty(&[0u8; 4000], 7, 3);
+        assert!(output.starts_with("\x1b_Gf=100,a=T,i=7,r=3,C=1,q=2,m=1;"));
+        assert!(output.ends_with("\x1b\\"));
+        assert_eq!(output.matches("\x1b_G").count(), 2);
+    }
+}
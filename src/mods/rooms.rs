@@ -0,0 +1,407 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use uuid::Uuid;
+use xmpp_parsers::disco::{DiscoInfoQuery, DiscoInfoResult, DiscoItemsQuery};
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::rsm::{SetQuery, SetResult};
+use xmpp_parsers::{ns, BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+
+/// Number of rooms asked at once to the conference service
+const PAGE_SIZE: usize = 50;
+
+/// Rooms listed at most, big services host many thousands of them
+const MAX_ROOMS: usize = 500;
+
+/// Room details asked at once, the others wait for their answers
+const INFO_REQUESTS: usize = 5;
+
+const NS_MUC_ROOMINFO: &str = "http://jabber.org/protocol/muc#roominfo";
+
+command_def!(
+    rooms,
+    r#"/rooms [<service>]
+
+    service     Conference service, the one of your server by default
+
+Description:
+    List the public rooms of a conference service in the rooms window, with
+    their name, address, occupant count and description. Select a room with
+    Up and Down, and join it with Enter.
+
+Examples:
+    /rooms
+    /rooms chat.shakespeare.lit"#,
+    {
+        service: Option<String>
+    },
+    |aparte, command| {
+        let account = command
+            .account
+            .clone()
+            .or_else(|| aparte.current_account())
+            .ok_or(format!("No connection found"))?;
+        match service {
+            Some(service) => {
+                let service = BareJid::from_str(&service)
+                    .map_err(|err| format!("Invalid service {}: {}", service, err))?;
+                RoomsMod::list(aparte, &account, service);
+            }
+            None => RoomsMod::find_service(aparte, &account),
+        }
+        Ok(())
+    }
+);
+
+/// Public room of a conference service
+#[derive(Debug, Clone)]
+pub struct Room {
+    pub jid: BareJid,
+    pub name: Option<String>,
+    pub occupants: Option<u32>,
+    pub description: Option<String>,
+}
+
+impl Room {
+    /// Complete a room with what its disco#info tells
+    fn update(&mut self, info: &DiscoInfoResult) {
+        if let Some(name) = info
+            .identities
+            .iter()
+            .find(|identity| identity.category == "conference")
+            .and_then(|identity| identity.name.clone())
+        {
+            self.name = Some(name);
+        }
+        let form = info
+            .extensions
+            .iter()
+            .find(|form| form.form_type.as_deref() == Some(NS_MUC_ROOMINFO));
+        if let Some(form) = form {
+            let value = |var: &str| {
+                form.fields
+                    .iter()
+                    .find(|field| field.var == var)
+                    .and_then(|field| field.values.first())
+                    .filter(|value| !value.is_empty())
+                    .cloned()
+            };
+            self.occupants = value("muc#roominfo_occupants").and_then(|count| count.parse().ok());
+            self.description = value("muc#roominfo_description");
+        }
+    }
+}
+
+impl Hash for Room {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.jid.hash(state);
+    }
+}
+
+impl PartialEq for Room {
+    fn eq(&self, other: &Self) -> bool {
+        self.jid == other.jid
+    }
+}
+
+impl Eq for Room {}
+
+/// What a sent iq asks for
+enum Request {
+    /// Services of the server, to find its conference service
+    Services,
+    /// Identity of a service, whether it is a conference one
+    ServiceInfo(BareJid),
+    /// Page of the rooms of a service
+    Rooms(BareJid),
+    /// Details of a room
+    RoomInfo(Room),
+}
+
+/// Browse the public rooms of conference services (XEP-0030 with XEP-0059 paging)
+pub struct RoomsMod {
+    iqs: HashMap<String, (Account, Request)>,
+    /// Service being listed for each account, with the number of rooms already listed
+    listing: HashMap<Account, (BareJid, usize)>,
+    /// Services whose identity is still awaited while looking for a conference service
+    searching: HashMap<Account, usize>,
+    /// Listed rooms whose details are still to be asked, with the number of unanswered requests
+    infos: HashMap<Account, (VecDeque<Room>, usize)>,
+}
+
+impl RoomsMod {
+    pub fn new() -> Self {
+        Self {
+            iqs: HashMap::new(),
+            listing: HashMap::new(),
+            searching: HashMap::new(),
+            infos: HashMap::new(),
+        }
+    }
+
+    /// Build an iq and remember what its answer is for
+    fn iq(&mut self, account: &Account, to: BareJid, payload: Element, request: Request) -> Iq {
+        let id = Uuid::new_v4().to_hyphenated().to_string();
+        self.iqs.insert(id.clone(), (account.clone(), request));
+        Iq {
+            from: None,
+            to: Some(Jid::Bare(to)),
+            id,
+            payload: IqType::Get(payload),
+        }
+    }
+
+    /// Look for the conference service among the services of the server, and list its rooms
+    fn find_service(aparte: &mut Aparte, account: &Account) {
+        let iq = {
+            let mut rooms = aparte.get_mod_mut::<RoomsMod>();
+            rooms.listing.remove(account);
+            let server = BareJid::domain(&account.domain);
+            let query = DiscoItemsQuery { node: None }.into();
+            rooms.iq(account, server, query, Request::Services)
+        };
+        aparte.send(account, iq.into());
+    }
+
+    fn list(aparte: &mut Aparte, account: &Account, service: BareJid) {
+        let iq = aparte
+            .get_mod_mut::<RoomsMod>()
+            .start_listing(account, service.clone());
+        aparte.schedule(Event::RoomDirectory {
+            account: account.clone(),
+            service,
+        });
+        aparte.send(account, iq.into());
+    }
+
+    /// Forget previous listings of the account and ask for the first page of a service
+    fn start_listing(&mut self, account: &Account, service: BareJid) -> Iq {
+        self.searching.remove(account);
+        self.listing.insert(account.clone(), (service.clone(), 0));
+        if let Some((rooms, _)) = self.infos.get_mut(account) {
+            rooms.clear();
+        }
+        self.page(account, service, None)
+    }
+
+    fn page(&mut self, account: &Account, service: BareJid, after: Option<String>) -> Iq {
+        let set = SetQuery {
+            max: Some(PAGE_SIZE),
+            after,
+            before: None,
+            index: None,
+        };
+        let mut query: Element = DiscoItemsQuery { node: None }.into();
+        query.append_child(set.into());
+        self.iq(account, service.clone(), query, Request::Rooms(service))
+    }
+
+    /// Ask the details of the next listed rooms, a few at a time not to flood the service
+    fn ask_infos(&mut self, aparte: &mut Aparte, account: &Account) {
+        loop {
+            let room = match self.infos.get_mut(account) {
+                Some((rooms, asked)) if *asked < INFO_REQUESTS => match rooms.pop_front() {
+                    Some(room) => {
+                        *asked += 1;
+                        room
+                    }
+                    None => return,
+                },
+                _ => return,
+            };
+            let query = DiscoInfoQuery { node: None }.into();
+            let iq = self.iq(account, room.jid.clone(), query, Request::RoomInfo(room));
+            aparte.send(account, iq.into());
+        }
+    }
+
+    fn handle_iq(&mut self, aparte: &mut Aparte, iq: &Iq) {
+        let (account, request) = match self.iqs.remove(&iq.id) {
+            Some(request) => request,
+            None => return,
+        };
+        let payload = match &iq.payload {
+            IqType::Result(Some(payload)) => Some(payload.clone()),
+            _ => None,
+        };
+
+        match request {
+            Request::Services => match payload {
+                Some(payload) => {
+                    let services = items(&payload);
+                    if services.is_empty() {
+                        aparte.log(format!("No conference service found on {}", account.domain));
+                        return;
+                    }
+                    self.searching.insert(account.clone(), services.len());
+                    for (service, _) in services {
+                        let query = DiscoInfoQuery { node: None }.into();
+                        let request = Request::ServiceInfo(service.clone());
+                        let iq = self.iq(&account, service, query, request);
+                        aparte.send(&account, iq.into());
+                    }
+                }
+                None => aparte.log(format!("Cannot list the services of {}", account.domain)),
+            },
+            Request::ServiceInfo(service) => {
+                let remaining = match self.searching.get_mut(&account) {
+                    Some(remaining) => {
+                        *remaining -= 1;
+                        *remaining
+                    }
+                    None => return,
+                };
+                let conference = payload
+                    .and_then(|payload| DiscoInfoResult::try_from(payload).ok())
+                    .map_or(false, |info| {
+                        info.identities.iter().any(|identity| {
+                            identity.category == "conference" && identity.type_ == "text"
+                        })
+                    });
+                if conference {
+                    let iq = self.start_listing(&account, service.clone());
+                    aparte.schedule(Event::RoomDirectory {
+                        account: account.clone(),
+                        service,
+                    });
+                    aparte.send(&account, iq.into());
+                } else if remaining == 0 {
+                    self.searching.remove(&account);
+                    aparte.log(format!("No conference service found on {}", account.domain));
+                }
+            }
+            Request::Rooms(service) => {
+                let payload = match payload {
+                    Some(payload) => payload,
+                    None => {
+                        aparte.log(format!("Cannot list the rooms of {}", service));
+                        return;
+                    }
+                };
+                // Answers for a directory closed by a later /rooms are ignored
+                let listed = match self.listing.get_mut(&account) {
+                    Some((listing, listed)) if *listing == service => listed,
+                    _ => return,
+                };
+                let rooms = items(&payload);
+                *listed += rooms.len();
+                let more = *listed < MAX_ROOMS;
+                let (pending, _) = self.infos.entry(account.clone()).or_default();
+                for (jid, name) in rooms {
+                    let room = Room {
+                        jid,
+                        name,
+                        occupants: None,
+                        description: None,
+                    };
+                    aparte.schedule(Event::DirectoryRoom(account.clone(), room.clone()));
+                    pending.push_back(room);
+                }
+                self.ask_infos(aparte, &account);
+
+                let last = payload
+                    .get_child("set", ns::RSM)
+                    .and_then(|set| SetResult::try_from(set.clone()).ok())
+                    .and_then(|set| set.last);
+                match last {
+                    Some(last) if more => {
+                        let iq = self.page(&account, service, Some(last));
+                        aparte.send(&account, iq.into());
+                    }
+                    Some(_) => aparte.log(format!(
+                        "Only the first {} rooms of {} are listed",
+                        MAX_ROOMS, service
+                    )),
+                    None => {}
+                }
+            }
+            Request::RoomInfo(mut room) => {
+                if let Some((_, asked)) = self.infos.get_mut(&account) {
+                    *asked = asked.saturating_sub(1);
+                }
+                let info = payload.and_then(|payload| DiscoInfoResult::try_from(payload).ok());
+                if let Some(info) = info {
+                    room.update(&info);
+                    aparte.schedule(Event::DirectoryRoom(account.clone(), room));
+                }
+                self.ask_infos(aparte, &account);
+            }
+        }
+    }
+}
+
+/// Address and name of the items of a disco#items answer, ignoring the paging set
+fn items(payload: &Element) -> Vec<(BareJid, Option<String>)> {
+    payload
+        .children()
+        .filter(|child| child.is("item", ns::DISCO_ITEMS))
+        .filter_map(|item| {
+            let jid = BareJid::from_str(item.attr("jid")?).ok()?;
+            Some((jid, item.attr("name").map(|name| name.to_string())))
+        })
+        .collect()
+}
+
+impl ModTrait for RoomsMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(rooms::new());
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Iq(_, iq) => self.handle_iq(aparte, iq),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for RoomsMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Room directory")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rooms() {
+        // Given
+        let page: Element = "<query xmlns='http://jabber.org/protocol/disco#items'><item jid='verona@chat.shakespeare.lit' name='Verona'/><item jid='mantua@chat.shakespeare.lit'/><set xmlns='http://jabber.org/protocol/rsm'><last>mantua@chat.shakespeare.lit</last><count>2</count></set></query>".parse().unwrap();
+        let info: Element = "<query xmlns='http://jabber.org/protocol/disco#info'><identity category='conference' type='text' name='Fair Verona'/><feature var='http://jabber.org/protocol/disco#info'/><feature var='http://jabber.org/protocol/muc'/><x xmlns='jabber:x:data' type='result'><field var='FORM_TYPE' type='hidden'><value>http://jabber.org/protocol/muc#roominfo</value></field><field var='muc#roominfo_description'><value>Where we lay our scene</value></field><field var='muc#roominfo_occupants'><value>12</value></field></x></query>".parse().unwrap();
+
+        // When
+        let items = items(&page);
+        let (jid, name) = items[0].clone();
+        let mut room = Room {
+            jid,
+            name,
+            occupants: None,
+            description: None,
+        };
+        room.update(&DiscoInfoResult::try_from(info).unwrap());
+
+        // Then
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1].1, None);
+        assert_eq!(
+            room.jid,
+            BareJid::from_str("verona@chat.shakespeare.lit").unwrap()
+        );
+        assert_eq!(room.name, Some("Fair Verona".to_string()));
+        assert_eq!(room.occupants, Some(12));
+        assert_eq!(room.description, Some("Where we lay our scene".to_string()));
+    }
+}
@@ -0,0 +1,432 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use rhai::{Array, Dynamic, Engine, FuncArgs, Scope, AST};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Direction, LiveMessages, Message, XmppMessageType};
+use crate::mods::completion::{complete_path, expand_path};
+
+command_def!(
+    script,
+    r#"/script <path>
+
+    path      Rhai script to load
+
+Description:
+    Load a script like the ones of ~/.config/aparte/scripts, its hooks and
+    commands are then available until exit.
+
+Example:
+    /script ~/hello.rhai
+"#,
+    {
+        path: String = {
+            completion: (|_aparte, command| complete_path(&command))
+        }
+    },
+    |aparte, _command| {
+        aparte.schedule(Event::LoadScript(expand_path(&path)));
+        Ok(())
+    }
+);
+
+/// Effect requested by a script, applied once the script returns
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Log(String),
+    Command(String),
+    Send(String, String),
+    RegisterCommand {
+        name: String,
+        function: String,
+        help: String,
+    },
+}
+
+struct Script {
+    name: String,
+    /// Functions of the script, top level statements are only run once when loading it
+    ast: AST,
+    functions: HashSet<String>,
+}
+
+pub struct ScriptingMod {
+    engine: Engine,
+    scripts: Vec<Script>,
+    actions: Rc<RefCell<Vec<Action>>>,
+    /// Script and function implementing each command registered by scripts
+    commands: HashMap<String, (usize, String)>,
+    /// History and corrections aren't new messages for `on_message`
+    live: LiveMessages,
+}
+
+impl ScriptingMod {
+    pub fn new() -> Self {
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let mut engine = Engine::new();
+
+        // Writing to stdout would garble the terminal
+        let queue = Rc::clone(&actions);
+        engine.on_print(move |text| queue.borrow_mut().push(Action::Log(text.to_string())));
+        let queue = Rc::clone(&actions);
+        engine.on_debug(move |text, _, _| queue.borrow_mut().push(Action::Log(text.to_string())));
+
+        let queue = Rc::clone(&actions);
+        engine.register_fn("command", move |line: &str| {
+            queue.borrow_mut().push(Action::Command(line.to_string()))
+        });
+        let queue = Rc::clone(&actions);
+        engine.register_fn("send", move |window: &str, text: &str| {
+            queue
+                .borrow_mut()
+                .push(Action::Send(window.to_string(), text.to_string()))
+        });
+        let queue = Rc::clone(&actions);
+        engine.register_fn(
+            "register_command",
+            move |name: &str, function: &str, help: &str| {
+                queue.borrow_mut().push(Action::RegisterCommand {
+                    name: name.trim_start_matches('/').to_string(),
+                    function: function.to_string(),
+                    help: help.to_string(),
+                })
+            },
+        );
+
+        Self {
+            engine,
+            scripts: Vec::new(),
+            actions,
+            commands: HashMap::new(),
+            live: LiveMessages::new(),
+        }
+    }
+
+    fn take_actions(&self) -> Vec<Action> {
+        self.actions.borrow_mut().drain(..).collect()
+    }
+
+    /// Compile a script and run its top level statements
+    fn load(&mut self, name: &str, source: &str) -> Result<Vec<Action>, String> {
+        let ast = self
+            .engine
+            .compile(source)
+            .map_err(|err| format!("Script {}: {}", name, err))?;
+
+        let result = self.engine.eval_ast::<Dynamic>(&ast);
+        let actions = self.take_actions();
+        result.map_err(|err| format!("Script {}: {}", name, err))?;
+
+        self.scripts.push(Script {
+            name: name.to_string(),
+            functions: ast
+                .iter_functions()
+                .map(|function| function.name.to_string())
+                .collect(),
+            ast: ast.clone_functions_only(),
+        });
+
+        Ok(actions)
+    }
+
+    fn call(
+        &self,
+        index: usize,
+        function: &str,
+        args: impl FuncArgs,
+    ) -> Result<Vec<Action>, String> {
+        let script = &self.scripts[index];
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &script.ast, function, args);
+        let actions = self.take_actions();
+        result
+            .map(|_| actions)
+            .map_err(|err| format!("Script {}: {}", script.name, err))
+    }
+
+    /// Call `function` in every script defining it
+    fn hook(
+        &self,
+        aparte: &mut Aparte,
+        function: &str,
+        args: impl FuncArgs + Clone,
+        account: &Option<Account>,
+        context: &str,
+    ) {
+        for (index, script) in self.scripts.iter().enumerate() {
+            if !script.functions.contains(function) {
+                continue;
+            }
+
+            match self.call(index, function, args.clone()) {
+                Ok(actions) => Self::apply(aparte, actions, account, context),
+                Err(err) => aparte.log(err),
+            }
+        }
+    }
+
+    fn apply(aparte: &mut Aparte, actions: Vec<Action>, account: &Option<Account>, context: &str) {
+        for action in actions {
+            match action {
+                Action::Log(text) => aparte.log(text),
+                Action::Command(line) => aparte.schedule(Event::RawCommand(
+                    account.clone(),
+                    context.to_string(),
+                    line,
+                )),
+                Action::Send(window, text) => aparte.schedule(Event::SendText(window, text)),
+                Action::RegisterCommand { name, .. } => aparte.log(format!(
+                    "Cannot register /{}: commands can only be registered when a script is loaded",
+                    name
+                )),
+            }
+        }
+    }
+
+    /// Load a script file, registering the commands it asks for
+    fn load_file(&mut self, aparte: &mut Aparte, path: &Path) {
+        let name = path
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or("")
+            .to_string();
+        let actions = match read_script(path).and_then(|source| self.load(&name, &source)) {
+            Ok(actions) => actions,
+            Err(err) => {
+                aparte.log(err);
+                return;
+            }
+        };
+
+        let index = self.scripts.len() - 1;
+        let mut others = Vec::new();
+        for action in actions {
+            match action {
+                Action::RegisterCommand {
+                    name,
+                    function,
+                    help,
+                } => {
+                    if let Err(err) = self.register_command(aparte, index, name, function, help) {
+                        aparte.log(err);
+                    }
+                }
+                action => others.push(action),
+            }
+        }
+        Self::apply(aparte, others, &None, "console");
+    }
+
+    fn register_command(
+        &mut self,
+        aparte: &mut Aparte,
+        index: usize,
+        name: String,
+        function: String,
+        help: String,
+    ) -> Result<(), String> {
+        let script = &self.scripts[index];
+        if !script.functions.contains(&function) {
+            return Err(format!(
+                "Script {}: unknown function {} for /{}",
+                script.name, function, name
+            ));
+        }
+        if aparte.command_parsers.contains_key(&name) {
+            return Err(format!(
+                "Script {}: command /{} already exists",
+                script.name, name
+            ));
+        }
+
+        aparte.add_command(CommandParser {
+            // Commands live as long as the client
+            name: Box::leak(name.clone().into_boxed_str()),
+            help,
+            parse: parse_script_command,
+            exec: exec_script_command,
+            autocompletions: Vec::new(),
+            named: Vec::new(),
+            flags: Vec::new(),
+            subcommands: Vec::new(),
+        });
+        self.commands.insert(name, (index, function));
+
+        Ok(())
+    }
+}
+
+fn parse_script_command(
+    account: &Option<Account>,
+    context: &str,
+    buf: &str,
+) -> Result<Command, String> {
+    Command::new(account.clone(), context.to_string(), buf.to_string())
+}
+
+/// Call the script function registered for the command with its arguments as an array
+fn exec_script_command(aparte: &mut Aparte, command: Command) -> Result<(), String> {
+    let actions = {
+        let scripting = aparte.get_mod::<ScriptingMod>();
+        let (index, function) = scripting
+            .commands
+            .get(&command.args[0])
+            .cloned()
+            .ok_or(format!("Unknown command {}", command.args[0]))?;
+        let args = command.args[1..]
+            .iter()
+            .cloned()
+            .map(Dynamic::from)
+            .collect::<Array>();
+        scripting.call(index, &function, (args,))?
+    };
+
+    ScriptingMod::apply(aparte, actions, &command.account, &command.context);
+    Ok(())
+}
+
+impl ModTrait for ScriptingMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(script::new());
+
+        let dir = aparte.config_dir.join("scripts");
+        let mut paths = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().map_or(false, |ext| ext == "rhai"))
+                .collect::<Vec<_>>(),
+            Err(_) => return Ok(()),
+        };
+        paths.sort();
+
+        for path in paths {
+            self.load_file(aparte, &path);
+        }
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::LoadScript(path) => self.load_file(aparte, path),
+            Event::Message(account, Message::Xmpp(message))
+                if message.direction == Direction::Incoming
+                    && self.live.first_seen(account, message) =>
+            {
+                let window = message.from.to_string();
+                let from = match &message.type_ {
+                    XmppMessageType::Channel => match &message.from_full {
+                        xmpp_parsers::Jid::Full(from) => from.resource.clone(),
+                        xmpp_parsers::Jid::Bare(from) => from.to_string(),
+                    },
+                    XmppMessageType::Chat => message.from.to_string(),
+                };
+                let body = message.get_last_body().to_string();
+                self.hook(
+                    aparte,
+                    "on_message",
+                    (window.clone(), from, body),
+                    account,
+                    &window,
+                );
+            }
+            Event::Connected(account, _) => self.hook(
+                aparte,
+                "on_connected",
+                (account.to_string(),),
+                &Some(account.clone()),
+                "console",
+            ),
+            _ => {}
+        }
+    }
+}
+
+fn read_script(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))
+}
+
+impl fmt::Display for ScriptingMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Scripting")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_call() {
+        // Given
+        let mut scripting = ScriptingMod::new();
+        let source = r#"
+            fn greet(args) {
+                send("room@conference.example.org", "Hello " + args[0]);
+            }
+
+            fn on_message(window, from, body) {
+                if body == "ping" {
+                    command("/me pongs " + from);
+                }
+            }
+
+            print("loaded");
+            register_command("/greet", "greet", "Greet someone");
+        "#;
+
+        // When
+        let actions = scripting.load("greeter", source).unwrap();
+
+        // Then
+        assert_eq!(
+            actions,
+            vec![
+                Action::Log("loaded".to_string()),
+                Action::RegisterCommand {
+                    name: "greet".to_string(),
+                    function: "greet".to_string(),
+                    help: "Greet someone".to_string(),
+                },
+            ]
+        );
+        assert!(scripting.scripts[0].functions.contains("on_message"));
+
+        let args = vec![Dynamic::from("bob".to_string())];
+        assert_eq!(
+            scripting.call(0, "greet", (args,)).unwrap(),
+            vec![Action::Send(
+                "room@conference.example.org".to_string(),
+                "Hello bob".to_string()
+            )]
+        );
+
+        let args = (
+            "room@conference.example.org".to_string(),
+            "alice".to_string(),
+            "ping".to_string(),
+        );
+        assert_eq!(
+            scripting.call(0, "on_message", args).unwrap(),
+            vec![Action::Command("/me pongs alice".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_load_error() {
+        let mut scripting = ScriptingMod::new();
+        assert!(scripting.load("broken", "fn (").is_err());
+        assert!(scripting.load("failing", "undefined()").is_err());
+        assert!(scripting.scripts.is_empty());
+    }
+}
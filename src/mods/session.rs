@@ -0,0 +1,333 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use xmpp_parsers::{BareJid, FullJid, Jid};
+
+use crate::account::Account;
+use crate::contact::Bookmark;
+use crate::conversation::Conversation;
+use crate::core::{Aparte, Event, ModTrait};
+use crate::mods::bookmarks::BookmarksMod;
+use crate::mods::ui::UIMod;
+
+/// Conversation window open when quitting
+#[derive(Debug, Clone, PartialEq)]
+enum SessionWindow {
+    Chat { account: BareJid, contact: BareJid },
+    Channel { account: BareJid, channel: FullJid },
+}
+
+impl SessionWindow {
+    fn account(&self) -> &BareJid {
+        match self {
+            SessionWindow::Chat { account, .. } | SessionWindow::Channel { account, .. } => account,
+        }
+    }
+
+    fn window(&self) -> String {
+        match self {
+            SessionWindow::Chat { contact, .. } => contact.to_string(),
+            SessionWindow::Channel { channel, .. } => BareJid::from(channel.clone()).to_string(),
+        }
+    }
+}
+
+impl From<&Conversation> for SessionWindow {
+    fn from(conversation: &Conversation) -> Self {
+        match conversation {
+            Conversation::Chat(chat) => SessionWindow::Chat {
+                account: chat.account.clone().into(),
+                contact: chat.contact.clone(),
+            },
+            Conversation::Channel(channel) => SessionWindow::Channel {
+                account: channel.account.clone().into(),
+                channel: channel.jid.clone().with_resource(channel.nick.clone()),
+            },
+        }
+    }
+}
+
+/// Windows open when quitting, and the current one
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Session {
+    windows: Vec<SessionWindow>,
+    current: Option<String>,
+}
+
+impl Session {
+    fn parse(content: &str) -> Self {
+        let mut session = Self::default();
+        for line in content.lines() {
+            // Nicks can contain spaces, the address is kept whole at the end of the line
+            let fields: Vec<&str> = line.splitn(3, ' ').collect();
+            let window = match fields.as_slice() {
+                ["current", window] => {
+                    session.current = Some(window.to_string());
+                    continue;
+                }
+                ["chat", account, contact] => {
+                    match (BareJid::from_str(account), BareJid::from_str(contact)) {
+                        (Ok(account), Ok(contact)) => {
+                            Some(SessionWindow::Chat { account, contact })
+                        }
+                        _ => None,
+                    }
+                }
+                ["channel", account, channel] => {
+                    match (BareJid::from_str(account), FullJid::from_str(channel)) {
+                        (Ok(account), Ok(channel)) => {
+                            Some(SessionWindow::Channel { account, channel })
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            match window {
+                Some(window) => session.windows.push(window),
+                None => warn!("Ignore invalid session line {}", line),
+            }
+        }
+        session
+    }
+
+    fn serialize(&self) -> String {
+        let mut content = String::new();
+        for window in &self.windows {
+            match window {
+                SessionWindow::Chat { account, contact } => {
+                    content.push_str(&format!("chat {} {}\n", account, contact))
+                }
+                SessionWindow::Channel { account, channel } => {
+                    content.push_str(&format!("channel {} {}\n", account, channel))
+                }
+            }
+        }
+        if let Some(current) = &self.current {
+            content.push_str(&format!("current {}\n", current));
+        }
+        content
+    }
+}
+
+/// Join of a restored channel with the password of its bookmark, none when its bookmark already
+/// joins it
+fn rejoin(account: &Account, channel: &FullJid, bookmarks: &[Bookmark]) -> Option<Event> {
+    let room = BareJid::from(channel.clone());
+    let bookmark = bookmarks.iter().find(|bookmark| bookmark.jid == room);
+    match bookmark {
+        Some(bookmark) if bookmark.autojoin => None,
+        _ => Some(Event::Join {
+            account: account.clone(),
+            channel: Jid::Full(channel.clone()),
+            password: bookmark.and_then(|bookmark| bookmark.password.clone()),
+            user_request: false,
+        }),
+    }
+}
+
+/// Reopen the conversation windows of the last session once their account is connected, channels
+/// once its bookmarks are known
+pub struct SessionMod {
+    path: PathBuf,
+    /// Windows of the last session not reopened yet
+    pending: Session,
+    /// Current window of the last session, while it is being reopened
+    current: Option<String>,
+}
+
+impl SessionMod {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            path: data_dir.join("session"),
+            pending: Session::default(),
+            current: None,
+        }
+    }
+
+    fn save(&self, aparte: &Aparte) {
+        let session = {
+            let ui = aparte.get_mod::<UIMod>();
+            let (conversations, current) = ui.open_conversations();
+            Session {
+                windows: conversations.iter().map(SessionWindow::from).collect(),
+                current,
+            }
+        };
+        if let Err(err) = fs::write(&self.path, session.serialize()) {
+            warn!("Cannot save session: {}", err);
+        }
+    }
+
+    /// Windows of the last session of an account, chats or channels
+    fn take(&mut self, account: &Account, channels: bool) -> Vec<SessionWindow> {
+        let bare: BareJid = account.clone().into();
+        let (restored, pending): (Vec<SessionWindow>, Vec<SessionWindow>) =
+            self.pending.windows.drain(..).partition(|window| {
+                *window.account() == bare
+                    && matches!(window, SessionWindow::Channel { .. }) == channels
+            });
+        self.pending.windows = pending;
+        restored
+    }
+
+    fn restore(&mut self, aparte: &mut Aparte, account: &Account) {
+        let restored = self.take(account, false);
+        for window in restored.iter() {
+            if let SessionWindow::Chat { contact, .. } = window {
+                aparte.schedule(Event::Chat {
+                    account: account.clone(),
+                    contact: contact.clone(),
+                });
+            }
+        }
+
+        // Opening a chat switches to it, go back to the current window once they are all open.
+        // Channels windows only exist once joined, see on_event.
+        if let Some(current) = &self.current {
+            if restored.iter().any(|window| window.window() == *current) {
+                aparte.schedule(Event::Win(current.clone()));
+                self.current = None;
+            }
+        }
+    }
+
+    fn restore_channels(&mut self, aparte: &mut Aparte, account: &Account) {
+        let restored = self.take(account, true);
+        let joins: Vec<Event> = {
+            let bookmarks = aparte.get_mod::<BookmarksMod>();
+            restored
+                .iter()
+                .filter_map(|window| match window {
+                    SessionWindow::Channel { channel, .. } => {
+                        rejoin(account, channel, &bookmarks.bookmarks)
+                    }
+                    SessionWindow::Chat { .. } => None,
+                })
+                .collect()
+        };
+        for join in joins {
+            aparte.schedule(join);
+        }
+    }
+}
+
+impl ModTrait for SessionMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => {
+                self.pending = Session::parse(&content);
+                self.current = self.pending.current.clone();
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => aparte.log(format!("Cannot read last session: {}", err)),
+        }
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Connected(account, _) => self.restore(aparte, account),
+            Event::Bookmarks(account) => self.restore_channels(aparte, account),
+            Event::Joined { channel, .. } => {
+                let window = BareJid::from(channel.clone()).to_string();
+                if self.current.as_ref() == Some(&window) {
+                    aparte.schedule(Event::Win(window));
+                    self.current = None;
+                }
+            }
+            Event::Quit => self.save(aparte),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for SessionMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Session restore")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        // Given
+        let session = Session {
+            windows: vec![
+                SessionWindow::Chat {
+                    account: BareJid::from_str("romeo@montague.lit").unwrap(),
+                    contact: BareJid::from_str("juliet@capulet.lit").unwrap(),
+                },
+                SessionWindow::Channel {
+                    account: BareJid::from_str("romeo@montague.lit").unwrap(),
+                    channel: FullJid::from_str("verona@chat.shakespeare.lit/Romeo M").unwrap(),
+                },
+            ],
+            current: Some("verona@chat.shakespeare.lit".to_string()),
+        };
+
+        // When
+        let content = session.serialize();
+
+        // Then
+        assert_eq!(
+            content,
+            "chat romeo@montague.lit juliet@capulet.lit\nchannel romeo@montague.lit verona@chat.shakespeare.lit/Romeo M\ncurrent verona@chat.shakespeare.lit\n"
+        );
+        assert_eq!(Session::parse(&content), session);
+        assert_eq!(
+            Session::parse("chat romeo@montague.lit\nwindow console\n"),
+            Session::default()
+        );
+    }
+
+    #[test]
+    fn test_rejoin() {
+        // Given
+        let account = Account::from_str("romeo@montague.lit/orchard").unwrap();
+        let bookmark = |room: &str, autojoin, password: Option<&str>| Bookmark {
+            jid: BareJid::from_str(room).unwrap(),
+            name: None,
+            nick: None,
+            autojoin,
+            password: password.map(|password| password.to_string()),
+            extensions: None,
+        };
+        let bookmarks = vec![
+            bookmark("verona@chat.shakespeare.lit", true, None),
+            bookmark("crypt@chat.shakespeare.lit", false, Some("poison")),
+        ];
+        let channel = |room: &str| FullJid::from_str(&format!("{}/Romeo", room)).unwrap();
+        let password = |join: Option<Event>| match join {
+            Some(Event::Join { password, .. }) => Some(password),
+            _ => None,
+        };
+
+        // When
+        let autojoined = rejoin(
+            &account,
+            &channel("verona@chat.shakespeare.lit"),
+            &bookmarks,
+        );
+        let bookmarked = rejoin(&account, &channel("crypt@chat.shakespeare.lit"), &bookmarks);
+        let other = rejoin(
+            &account,
+            &channel("mantua@chat.shakespeare.lit"),
+            &bookmarks,
+        );
+
+        // Then
+        assert!(autojoined.is_none());
+        assert_eq!(password(bookmarked), Some(Some("poison".to_string())));
+        assert_eq!(password(other), Some(None));
+    }
+}
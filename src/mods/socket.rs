@@ -0,0 +1,158 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::Message;
+
+/// Command of a line written to the control socket, other lines are ignored
+fn command_from_line(line: &str) -> Option<String> {
+    let line = line.trim();
+    match line.starts_with('/') && line.len() > 1 {
+        true => Some(line.to_string()),
+        false => None,
+    }
+}
+
+async fn handle_client(stream: UnixStream, events: mpsc::Sender<Event>) {
+    let mut lines = BufReader::new(stream).lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => match command_from_line(&line) {
+                Some(command) => {
+                    let event = Event::RawCommand(None, "console".to_string(), command);
+                    if events.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                None => warn!("Ignore control socket line {}", line),
+            },
+            Ok(None) => return,
+            Err(err) => {
+                warn!("Cannot read control socket: {}", err);
+                return;
+            }
+        }
+    }
+}
+
+/// Listen on a socket of a runtime directory only we can access, or of the data directory
+pub fn bind(data_dir: &Path, name: &str) -> io::Result<(UnixListener, PathBuf)> {
+    let dir = match dirs::runtime_dir() {
+        Some(dir) => dir.join("aparte"),
+        None => data_dir.to_path_buf(),
+    };
+    let path = dir.join(name);
+
+    // Anyone able to use the socket can act as us, or read our messages, it must never be
+    // reachable by others, even right after being created
+    fs::create_dir_all(&dir)?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+
+    // A previous instance may have left its socket behind, a running one still listens on it
+    if path.exists() {
+        match std::os::unix::net::UnixStream::connect(&path) {
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is used by another instance", path.display()),
+                ))
+            }
+            Err(_) => fs::remove_file(&path)?,
+        }
+    }
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    Ok((listener, path))
+}
+
+/// Unix socket where other programs write commands to run, one per line
+pub struct SocketMod {
+    path: Option<PathBuf>,
+}
+
+impl SocketMod {
+    pub fn new() -> Self {
+        Self { path: None }
+    }
+
+    fn listen(&mut self, aparte: &mut Aparte) {
+        let (listener, path) = match bind(&aparte.data_dir, "control.sock") {
+            Ok(bound) => bound,
+            Err(err) => {
+                aparte.log(format!("Cannot create control socket: {}", err));
+                return;
+            }
+        };
+
+        info!("Listening for commands on {}", path.display());
+        self.path = Some(path);
+        let events = aparte.event_sender();
+        aparte.spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(handle_client(stream, events.clone()));
+                    }
+                    Err(err) => {
+                        return Event::Message(
+                            None,
+                            Message::log(format!("Control socket closed: {}", err)),
+                        )
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl ModTrait for SocketMod {
+    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Start => {
+                if aparte.config.remote.socket {
+                    self.listen(aparte);
+                }
+            }
+            Event::Quit => {
+                if let Some(path) = self.path.take() {
+                    let _ = fs::remove_file(path);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for SocketMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Control socket")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_from_line() {
+        assert_eq!(
+            command_from_line("/msg ops@example.org \"deploy finished\"\n"),
+            Some("/msg ops@example.org \"deploy finished\"".to_string())
+        );
+        assert_eq!(command_from_line("deploy finished"), None);
+        assert_eq!(command_from_line(" / "), None);
+    }
+}
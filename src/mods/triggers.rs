@@ -0,0 +1,331 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::{Direction, LiveMessages, Message};
+
+/// What a trigger does when a message matches
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerAction {
+    /// Ring the terminal bell
+    Bell,
+    /// Drop the message before it is displayed
+    Hide,
+    /// Run a command in the window of the message, `$1` is replaced by the first group, each group
+    /// staying a single argument
+    Command(String),
+}
+
+impl FromStr for TriggerAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bell" => Ok(TriggerAction::Bell),
+            "hide" => Ok(TriggerAction::Hide),
+            command if command.starts_with('/') => {
+                let parsed = Command::new(None, String::new(), command.to_string())?;
+                match parsed.args[0].contains('$') {
+                    true => Err(format!(
+                        "Invalid trigger action {}, the command cannot come from the message",
+                        s
+                    )),
+                    false => Ok(TriggerAction::Command(command.to_string())),
+                }
+            }
+            _ => Err(format!(
+                "Invalid trigger action {}, expected bell, hide or a command",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TriggerAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TriggerAction::Bell => write!(f, "bell"),
+            TriggerAction::Hide => write!(f, "hide"),
+            TriggerAction::Command(command) => write!(f, "{}", command),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pattern: Regex,
+    action: TriggerAction,
+    /// Only match messages of this window
+    window: Option<String>,
+}
+
+impl Trigger {
+    pub fn new(pattern: &str, action: &str, window: Option<String>) -> Result<Self, String> {
+        Ok(Self {
+            pattern: Regex::new(pattern)
+                .map_err(|err| format!("Invalid trigger pattern {}: {}", pattern, err))?,
+            action: action.parse()?,
+            window,
+        })
+    }
+
+    /// Action triggered by a message, with the groups of the pattern expanded in commands
+    fn matches(&self, window: &str, body: &str) -> Option<TriggerAction> {
+        if self.window.as_ref().map_or(false, |only| only != window) {
+            return None;
+        }
+
+        let captures = self.pattern.captures(body)?;
+        match &self.action {
+            TriggerAction::Command(command) => {
+                // Groups are expanded in each argument, so that quotes in the message cannot
+                // split them or add others
+                let mut command = Command::new(None, String::new(), command.clone()).ok()?;
+                for arg in command.args.iter_mut() {
+                    let mut expanded = String::new();
+                    captures.expand(arg, &mut expanded);
+                    if !is_option(arg) && is_option(&expanded) {
+                        warn!("Ignore trigger {}, the message adds an option", self);
+                        return None;
+                    }
+                    *arg = expanded;
+                }
+                Some(TriggerAction::Command(command.assemble()))
+            }
+            action => Some(action.clone()),
+        }
+    }
+}
+
+/// Whether a command argument is taken as an option, like --password or window=<window>
+fn is_option(arg: &str) -> bool {
+    match arg.find('=') {
+        _ if arg.starts_with('-') => true,
+        Some(end) => arg[..end]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        None => false,
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.window {
+            Some(window) => write!(f, "{} in {}: {}", self.pattern, window, self.action),
+            None => write!(f, "{}: {}", self.pattern, self.action),
+        }
+    }
+}
+
+command_def!(trigger_add,
+r#"/trigger add <pattern> <action> [window=<window>]
+
+    pattern     Regular expression matched against incoming messages
+    action      bell, hide, or a command where $1 is replaced by the first group
+    window      Only match the messages of this window
+
+Description:
+    Add a trigger, until exit. Triggers can be saved in the configuration.
+
+Examples:
+    /trigger add deploy bell
+    /trigger add 'spam.*' hide window=room@conference.example.org
+    /trigger add '^!echo (.*)' '/me echoes $1'
+"#,
+{
+    pattern: String,
+    action: String,
+    window: Named<String>
+},
+|aparte, _command| {
+    let trigger = Trigger::new(&pattern, &action, window)?;
+    aparte.log(format!("Trigger added: {}", trigger));
+    aparte.get_mod_mut::<TriggersMod>().triggers.push(trigger);
+    Ok(())
+});
+
+command_def!(trigger_del,
+r#"/trigger del <index>
+
+    index       Position of the trigger given by /trigger list
+
+Description:
+    Delete a trigger
+
+Examples:
+    /trigger del 1
+"#,
+{
+    index: usize
+},
+|aparte, _command| {
+    let trigger = {
+        let mut triggers = aparte.get_mod_mut::<TriggersMod>();
+        match index {
+            index if index >= 1 && index <= triggers.triggers.len() => {
+                triggers.triggers.remove(index - 1)
+            }
+            _ => return Err(format!("Unknown trigger {}", index)),
+        }
+    };
+    aparte.log(format!("Trigger deleted: {}", trigger));
+    Ok(())
+});
+
+command_def!(
+    trigger_list,
+    r#"/trigger list
+
+Description:
+    List triggers
+"#,
+    {},
+    |aparte, _command| {
+        let triggers = aparte
+            .get_mod::<TriggersMod>()
+            .triggers
+            .iter()
+            .enumerate()
+            .map(|(index, trigger)| format!("    {}. {}", index + 1, trigger))
+            .collect::<Vec<String>>();
+        match triggers.is_empty() {
+            true => aparte.log("No trigger".to_string()),
+            false => aparte.log(format!("Triggers:\n{}", triggers.join("\n"))),
+        }
+        Ok(())
+    }
+);
+
+command_def!(trigger,
+r#"/trigger add|del|list"#,
+{
+    action: Command = {
+        children: {
+            "add": trigger_add,
+            "del": trigger_del,
+            "list": trigger_list,
+        }
+    },
+});
+
+pub struct TriggersMod {
+    triggers: Vec<Trigger>,
+    /// History and messages that already triggered don't trigger commands nor bells again
+    live: LiveMessages,
+}
+
+impl TriggersMod {
+    pub fn new() -> Self {
+        Self {
+            triggers: Vec::new(),
+            live: LiveMessages::new(),
+        }
+    }
+}
+
+impl ModTrait for TriggersMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(trigger::new());
+
+        for config in aparte.config.triggers.clone() {
+            match Trigger::new(&config.pattern, &config.action, config.window) {
+                Ok(trigger) => self.triggers.push(trigger),
+                Err(err) => aparte.log(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Message(account, Message::Xmpp(message))
+                if message.direction == Direction::Incoming =>
+            {
+                let window = message.from.to_string();
+                let live = self.live.first_seen(account, message);
+
+                let actions = self
+                    .triggers
+                    .iter()
+                    .filter_map(|trigger| trigger.matches(&window, message.get_last_body()))
+                    .collect::<Vec<_>>();
+                for action in actions {
+                    match action {
+                        TriggerAction::Hide => aparte.consume_event(),
+                        TriggerAction::Bell if live => {
+                            aparte.schedule(Event::Notification(String::new()))
+                        }
+                        TriggerAction::Command(command) if live => aparte
+                            .schedule(Event::RawCommand(account.clone(), window.clone(), command)),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Hidden messages must be consumed before they are displayed
+    fn priority(&self) -> i32 {
+        5
+    }
+}
+
+impl fmt::Display for TriggersMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Triggers")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigger_matches() {
+        let trigger = Trigger::new(
+            "^!echo (.*)",
+            "/me echoes $1",
+            Some("room@conference.example.org".to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            trigger.matches("room@conference.example.org", "!echo hello"),
+            Some(TriggerAction::Command("/me echoes hello".to_string()))
+        );
+        assert_eq!(
+            trigger.matches("room@conference.example.org", "echo hello"),
+            None
+        );
+        assert_eq!(trigger.matches("juliet@example.org", "!echo hello"), None);
+        assert_eq!(
+            trigger.matches("room@conference.example.org", "!echo it's \"fine\""),
+            Some(TriggerAction::Command(
+                "/me echoes \"it's \\\"fine\\\"\"".to_string()
+            ))
+        );
+        assert_eq!(
+            trigger.matches("room@conference.example.org", "!echo --password=x"),
+            None
+        );
+        assert_eq!(
+            trigger.matches("room@conference.example.org", "!echo window=x"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_trigger_new() {
+        assert!(Trigger::new("spam", "hide", None).is_ok());
+        assert!(Trigger::new("spam(", "hide", None).is_err());
+        assert!(Trigger::new("spam", "drop", None).is_err());
+        assert!(Trigger::new("^!(.*)", "/$1", None).is_err());
+    }
+}
@@ -2,42 +2,72 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 use backtrace::Backtrace;
+use chrono::format::{Item, StrftimeItems};
 use chrono::offset::{Local, TimeZone};
 use chrono::Local as LocalTz;
+use chrono::{DateTime, FixedOffset};
 use futures::task::{AtomicWaker, Context, Poll};
 use futures::Stream;
 use linked_hash_set::LinkedHashSet;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::ffi::OsStr;
 use std::fmt;
+use std::fs;
 use std::hash::{Hash, Hasher};
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use std::io::{Read, Stdout, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
 use std::panic;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::process::{Command as ProcessCommand, Stdio};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 use termion::color;
-use termion::event::{parse_event as termion_parse_event, Event as TermionEvent, Key};
+use termion::event::{
+    parse_event as termion_parse_event, Event as TermionEvent, Key, MouseButton, MouseEvent,
+};
 use termion::get_tty;
+use termion::input::MouseTerminal;
 use termion::raw::IntoRawMode;
 use termion::screen::AlternateScreen;
+use unicode_segmentation::UnicodeSegmentation;
 use uuid::Uuid;
+use xmpp_parsers::chatstates::ChatState;
 use xmpp_parsers::{BareJid, Jid};
 
-use crate::color::id_to_rgb;
+use crate::account::Account;
+use crate::aesgcm::EncryptedUrl;
 use crate::command::Command;
+use crate::config::{
+    Highlights, NotifyConfig, NotifyLevel, OfflineContacts, ScrollbackConfig, SideWidth,
+};
 use crate::conversation::{Channel, Chat, Conversation};
 use crate::core::{Aparte, Event, ModTrait};
 use crate::cursor::Cursor;
+use crate::error::Error;
 use crate::i18n;
-use crate::message::{Direction, Message, XmppMessageType};
+use crate::message::{
+    Direction, Message, Reply, VersionedXmppMessage, XmppMessageType, XmppMessageVersion,
+};
+use crate::mods::archive::ReadState;
+use crate::mods::ping::Latency;
+use crate::mods::preview;
+use crate::mods::rooms::Room;
 use crate::terminus::{
-    self, BufferedWin, Dimension, FrameLayout, Input, Layout, Layouts, LinearLayout, ListView,
-    Orientation, Screen, View, Window as _,
+    self, BufferedWin, Dimension, FrameLayout, Graphic, Input, Layout, Layouts, LinearLayout,
+    ListView, Orientation, Screen, View, Window as _,
 };
+use crate::theme::{self, Theme};
+use crate::url;
 use crate::{contact, conversation};
 
 enum UIEvent {
@@ -45,11 +75,77 @@ enum UIEvent {
     Validate(Rc<RefCell<Option<(String, bool)>>>),
     GetInput(Rc<RefCell<Option<(String, Cursor, bool)>>>),
     AddWindow(String, Option<Box<dyn View<UIEvent, Stdout>>>),
+    /// New messages in a window, with whether one is addressed to us
+    Activity(String, bool),
+    /// Windows have been reordered
+    WindowsOrder(Vec<String>),
+    CopyMode(CopyModeAction),
+    /// Text displayed in front of the input
+    Prompt(String),
+    /// Get the width of the side list of the current window
+    SidePaneWidth(Rc<RefCell<Option<u16>>>),
+    /// Fix the width of side lists
+    ResizeSidePane(u16),
+    /// Move the selection of the room directory by the given number of rooms
+    MoveSelection(isize),
+    /// Get the room selected in the room directory
+    SelectedRoom(Rc<RefCell<Option<Room>>>),
+    /// Search the current window, with whether the term has been found
+    Search(String, Rc<RefCell<Option<bool>>>),
+}
+
+enum CopyModeAction {
+    Start,
+    Stop,
+    Move(isize),
+    PageMove(isize),
+    Mark,
+    Yank(Rc<RefCell<Option<String>>>),
+    YankItem(Rc<RefCell<Option<String>>>),
+    /// Message under the cursor
+    Item(Rc<RefCell<Option<Message>>>),
+}
+
+fn copy_mode(view: &mut BufferedWin<UIEvent, Stdout, Message>, action: &CopyModeAction) {
+    match action {
+        CopyModeAction::Start => view.start_copy(),
+        CopyModeAction::Stop => view.stop_copy(),
+        CopyModeAction::Move(count) => view.move_copy_cursor(*count),
+        CopyModeAction::PageMove(count) => view.page_copy_cursor(*count),
+        CopyModeAction::Mark => view.toggle_copy_mark(),
+        CopyModeAction::Yank(result) => *result.borrow_mut() = view.get_copy_selection(),
+        CopyModeAction::YankItem(result) => *result.borrow_mut() = view.get_copy_item(),
+        CopyModeAction::Item(result) => *result.borrow_mut() = view.get_copy_cursor_item().cloned(),
+    }
+}
+
+thread_local! {
+    /// Names displayed instead of the window names
+    static ALIASES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+fn set_window_alias(window: &str, alias: Option<&str>) {
+    ALIASES.with(|aliases| match alias {
+        Some(alias) => aliases
+            .borrow_mut()
+            .insert(window.to_string(), alias.to_string()),
+        None => aliases.borrow_mut().remove(window),
+    });
+}
+
+/// Name displayed for a window
+fn window_label(window: &str) -> String {
+    ALIASES.with(|aliases| match aliases.borrow().get(window) {
+        Some(alias) => alias.clone(),
+        None => window.to_string(),
+    })
 }
 
 struct TitleBar {
     name: Option<String>,
     subjects: HashMap<String, HashMap<String, String>>,
+    /// Composing participants of each window
+    composing: HashMap<String, (XmppMessageType, HashSet<String>)>,
     dirty: bool,
 }
 
@@ -58,6 +154,7 @@ impl TitleBar {
         Self {
             name: None,
             subjects: HashMap::new(),
+            composing: HashMap::new(),
             dirty: true,
         }
     }
@@ -76,6 +173,39 @@ impl TitleBar {
         }
         self.subjects.insert(jid, subjects);
     }
+
+    fn set_composing(&mut self, from: &Jid, type_: &XmppMessageType, composing: bool) {
+        let window = BareJid::from(from.clone()).to_string();
+        let participant = match (type_, from) {
+            (XmppMessageType::Channel, Jid::Full(from)) => from.resource.clone(),
+            _ => from.to_string(),
+        };
+
+        let (_, participants) = self
+            .composing
+            .entry(window.clone())
+            .or_insert((type_.clone(), HashSet::new()));
+        let changed = match composing {
+            true => participants.insert(participant),
+            false => participants.remove(&participant),
+        };
+
+        if changed && Some(&window) == self.name.as_ref() {
+            self.dirty = true;
+        }
+    }
+
+    fn get_title(&self, name: &str) -> String {
+        let label = window_label(name);
+        match self.composing.get(name) {
+            Some((_, participants)) if participants.is_empty() => label,
+            Some((XmppMessageType::Chat, _)) => format!("{} is typing…", label),
+            Some((XmppMessageType::Channel, participants)) => {
+                format!("{} ({} typing…)", label, participants.len())
+            }
+            None => label,
+        }
+    }
 }
 
 impl<W> View<UIEvent, W> for TitleBar
@@ -83,6 +213,7 @@ where
     W: Write,
 {
     fn render(&mut self, dimension: &Dimension, screen: &mut Screen<W>) {
+        let theme = theme::current();
         save_cursor!(screen);
 
         vprint!(
@@ -93,8 +224,8 @@ where
         vprint!(
             screen,
             "{}{}{}",
-            color::Bg(color::Blue),
-            color::Fg(color::White),
+            color::Bg(theme.title_bar_bg),
+            color::Fg(theme.title_bar_fg),
             termion::style::Bold,
         );
 
@@ -108,7 +239,7 @@ where
 
         if let Some(name) = &self.name {
             let clean_name = terminus::term_string_visible_truncate(
-                name,
+                &self.get_title(name),
                 dimension.w.unwrap().into(),
                 Some("…"),
             );
@@ -141,7 +272,6 @@ where
         );
 
         restore_cursor!(screen);
-        flush!(screen);
         self.dirty = false;
     }
 
@@ -154,6 +284,11 @@ where
             UIEvent::Core(Event::ChangeWindow(name)) => {
                 self.set_name(name);
             }
+            UIEvent::Core(Event::WindowAlias(window, _)) => {
+                if Some(window) == self.name.as_ref() {
+                    self.dirty = true;
+                }
+            }
             UIEvent::Core(Event::Subject(_, jid, subjects)) => {
                 let window: BareJid = jid.clone().into();
                 self.add_subjects(
@@ -164,6 +299,17 @@ where
                         .collect(),
                 );
             }
+            UIEvent::Core(Event::ChatState {
+                from, type_, state, ..
+            }) => {
+                self.set_composing(from, type_, state == &ChatState::Composing);
+            }
+            UIEvent::Core(Event::Message(_, Message::Xmpp(message)))
+                if message.direction == Direction::Incoming =>
+            {
+                // Sending a message implies the participant stopped composing
+                self.set_composing(&message.from_full, &message.type_, false);
+            }
             _ => {}
         }
     }
@@ -176,22 +322,145 @@ where
     }
 }
 
+const DEFAULT_STATUS_FORMAT: &str = " {account} {latency} {windows}";
+/// Window listing the rooms found by /rooms
+const ROOMS_WINDOW: &str = "rooms";
+
+/// Component of the status bar
+#[derive(Debug, Clone, PartialEq)]
+enum StatusComponent {
+    Text(String),
+    /// Connected account
+    Account,
+    /// Latency of the connection of the account
+    Latency,
+    /// Current window
+    Window,
+    /// Windows with unread messages
+    Windows,
+    /// Number of windows with unread messages
+    Unread,
+    /// Current time, with the messages time format
+    Time,
+}
+
+/// Split a format like `{account} | {windows}` into text and named components
+fn parse_format<C, F>(
+    kind: &str,
+    format: &str,
+    text: fn(String) -> C,
+    component: F,
+) -> Result<Vec<C>, String>
+where
+    F: Fn(&str) -> Option<C>,
+{
+    let mut components = Vec::new();
+    let mut remaining = format;
+
+    while let Some(start) = remaining.find('{') {
+        if start > 0 {
+            components.push(text(remaining[..start].to_string()));
+        }
+        let end = match remaining[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("Unclosed {{ in {} format {}", kind, format)),
+        };
+        let name = &remaining[start + 1..end];
+        match component(name) {
+            Some(component) => components.push(component),
+            None => return Err(format!("Unknown {} component {{{}}}", kind, name)),
+        }
+        remaining = &remaining[end + 1..];
+    }
+
+    if !remaining.is_empty() {
+        components.push(text(remaining.to_string()));
+    }
+
+    Ok(components)
+}
+
+/// Parse a status bar format like `{account} | {windows}`
+fn parse_status_format(format: &str) -> Result<Vec<StatusComponent>, String> {
+    parse_format("status", format, StatusComponent::Text, |name| match name {
+        "account" => Some(StatusComponent::Account),
+        "latency" => Some(StatusComponent::Latency),
+        "window" => Some(StatusComponent::Window),
+        "windows" => Some(StatusComponent::Windows),
+        "unread" => Some(StatusComponent::Unread),
+        "time" => Some(StatusComponent::Time),
+        _ => None,
+    })
+}
+
+thread_local! {
+    static STATUS_FORMAT: RefCell<Vec<StatusComponent>> =
+        RefCell::new(parse_status_format(DEFAULT_STATUS_FORMAT).unwrap());
+}
+
+/// Change the components displayed by the status bar
+fn set_status_format(format: &str) -> Result<(), String> {
+    let components = parse_status_format(format)?;
+    STATUS_FORMAT.with(|status_format| *status_format.borrow_mut() = components);
+    Ok(())
+}
+
+/// Component of the input prompt
+#[derive(Debug, Clone, PartialEq)]
+enum PromptComponent {
+    Text(String),
+    /// Label of the window
+    Window,
+    /// Account of the conversation
+    Account,
+    /// Our nick in the conversation
+    Nick,
+}
+
+/// Parse an input prompt format like `[{window}] {nick}> `
+fn parse_prompt_format(format: &str) -> Result<Vec<PromptComponent>, String> {
+    parse_format("prompt", format, PromptComponent::Text, |name| match name {
+        "window" => Some(PromptComponent::Window),
+        "account" => Some(PromptComponent::Account),
+        "nick" => Some(PromptComponent::Nick),
+        _ => None,
+    })
+}
+
 struct WinBar {
     connection: Option<String>,
+    latency: Option<Latency>,
+    /// Whether the connected account appears offline to its contacts
+    invisible: bool,
     windows: Vec<String>,
     current_window: Option<String>,
     highlighted: Vec<String>,
+    /// Unread windows with messages addressed to us
+    mentioned: HashSet<String>,
+    /// Channel windows that have been left
+    detached: HashSet<String>,
     dirty: bool,
+    /// Line and columns span of each rendered window name
+    line: u16,
+    regions: Vec<(u16, u16, String)>,
+    scheduler: Scheduler,
 }
 
 impl WinBar {
-    pub fn new() -> Self {
+    pub fn new(scheduler: Scheduler) -> Self {
         Self {
             connection: None,
+            latency: None,
+            invisible: false,
             windows: Vec::new(),
             current_window: None,
             highlighted: Vec::new(),
+            mentioned: HashSet::new(),
+            detached: HashSet::new(),
             dirty: true,
+            line: 0,
+            regions: Vec::new(),
+            scheduler,
         }
     }
 
@@ -203,6 +472,8 @@ impl WinBar {
     pub fn del_window(&mut self, window: &str) {
         self.windows.retain(|win| win != window);
         self.highlighted.retain(|win| win != window);
+        self.mentioned.remove(window);
+        self.detached.remove(window);
         self.dirty = true;
     }
 
@@ -217,14 +488,100 @@ impl WinBar {
                 i += 1;
             }
         }
+        self.mentioned.remove(window);
         self.dirty = true;
     }
 
-    pub fn highlight_window(&mut self, window: &str) {
+    pub fn highlight_window(&mut self, window: &str, mention: bool) {
         if self.highlighted.iter().find(|w| w == &window).is_none() {
             self.highlighted.push(window.to_string());
             self.dirty = true;
         }
+        if mention && self.mentioned.insert(window.to_string()) {
+            self.dirty = true;
+        }
+    }
+}
+
+impl WinBar {
+    /// Render windows with unread messages, return the new written length
+    fn render_windows<W: Write>(
+        &mut self,
+        dimension: &Dimension,
+        screen: &mut Screen<W>,
+        mut written: usize,
+    ) -> usize {
+        let theme = theme::current();
+        let mut first = true;
+        let mut remaining = self.highlighted.len();
+
+        for window in &self.highlighted {
+            // Keep space for at least ", +X]"
+            let remaining_len = if remaining > 1 {
+                format!("{}", remaining).len() + 4
+            } else {
+                0
+            };
+
+            let number = match self.windows.iter().position(|win| win == window) {
+                Some(index) => format!("{}:", index + 1),
+                None => String::new(),
+            };
+            let label = terminus::clean(&window_label(window));
+            let label_len = terminus::term_string_visible_len(&label);
+
+            if number.len() + label_len + written + remaining_len > dimension.w.unwrap() as usize {
+                if !first {
+                    vprint!(screen, ", +{}", remaining);
+                    written += format!("{}", remaining).len() + 3;
+                }
+                break;
+            }
+
+            if first {
+                vprint!(screen, "[");
+                written += 2; // Also count the closing bracket
+                first = false;
+            } else {
+                vprint!(screen, ", ");
+                written += 2;
+            }
+            // Only windows with messages addressed to us stand out
+            match self.mentioned.contains(window) {
+                true => vprint!(
+                    screen,
+                    "{}{}{}{}{}{}",
+                    number,
+                    termion::style::Bold,
+                    color::Fg(theme.highlight),
+                    label,
+                    color::Fg(theme.win_bar_fg),
+                    termion::style::NoBold
+                ),
+                false if self.detached.contains(window) => vprint!(
+                    screen,
+                    "{}{}{}{}",
+                    number,
+                    termion::style::Faint,
+                    label,
+                    termion::style::NoFaint
+                ),
+                false => vprint!(screen, "{}{}", number, label),
+            }
+            written += number.len();
+            // written already accounts for the closing bracket
+            let start = dimension.x + written as u16 - 1;
+            self.regions
+                .push((start, start + label_len as u16, window.clone()));
+            written += label_len;
+            remaining -= 1;
+        }
+
+        if !first {
+            vprint!(screen, "]");
+        }
+
+        written
     }
 }
 
@@ -235,7 +592,10 @@ where
     fn render(&mut self, dimension: &Dimension, screen: &mut Screen<W>) {
         save_cursor!(screen);
 
+        let theme = theme::current();
         let mut written = 0;
+        self.line = dimension.y;
+        self.regions.clear();
 
         vprint!(
             screen,
@@ -245,8 +605,8 @@ where
         vprint!(
             screen,
             "{}{}",
-            color::Bg(color::Blue),
-            color::Fg(color::White)
+            color::Bg(theme.win_bar_bg),
+            color::Fg(theme.win_bar_fg)
         );
 
         for _ in 0..dimension.w.unwrap() {
@@ -258,50 +618,54 @@ where
             "{}",
             termion::cursor::Goto(dimension.x, dimension.y)
         );
-        if let Some(connection) = &self.connection {
-            vprint!(screen, " {}", connection);
-            written += 1 + connection.len();
-        }
-
-        let mut first = true;
-        let mut remaining = self.highlighted.len();
-
-        for window in &self.highlighted {
-            // Keep space for at least ", +X]"
-            let remaining_len = if remaining > 1 {
-                format!("{}", remaining).len() + 4
-            } else {
-                0
-            };
 
-            if window.len() + written + remaining_len > dimension.w.unwrap() as usize {
-                if !first {
-                    vprint!(screen, ", +{}", remaining);
+        let width = dimension.w.unwrap() as usize;
+        let components = STATUS_FORMAT.with(|format| format.borrow().clone());
+        for component in components {
+            let text = match component {
+                StatusComponent::Text(text) => text,
+                StatusComponent::Account => match &self.connection {
+                    Some(connection) if self.invisible => format!(
+                        "{}{} (invisible){}",
+                        termion::style::Faint,
+                        connection,
+                        termion::style::NoFaint
+                    ),
+                    Some(connection) => connection.clone(),
+                    None => String::new(),
+                },
+                StatusComponent::Latency => match self.latency {
+                    Some(latency @ Latency::Stale(_)) => format!(
+                        "{}⚠ {}{}",
+                        color::Fg(theme.error),
+                        latency,
+                        color::Fg(theme.win_bar_fg)
+                    ),
+                    Some(latency) => latency.to_string(),
+                    None => String::new(),
+                },
+                StatusComponent::Window => match &self.current_window {
+                    Some(window) if self.detached.contains(window) => format!(
+                        "{}{}{}",
+                        termion::style::Faint,
+                        window_label(window),
+                        termion::style::NoFaint
+                    ),
+                    Some(window) => window_label(window),
+                    None => String::new(),
+                },
+                StatusComponent::Unread => format!("{}", self.highlighted.len()),
+                StatusComponent::Time => format_time(&LocalTz::now().into()),
+                StatusComponent::Windows => {
+                    written = self.render_windows(dimension, screen, written);
+                    continue;
                 }
-                break;
-            }
-
-            if first {
-                vprint!(screen, " [");
-                written += 3; // Also count the closing bracket
-                first = false;
-            } else {
-                vprint!(screen, ", ");
-                written += 2;
-            }
-            vprint!(
-                screen,
-                "{}{}{}",
-                termion::style::Bold,
-                window,
-                termion::style::NoBold
-            );
-            written += window.len();
-            remaining -= 1;
-        }
+            };
 
-        if !first {
-            vprint!(screen, "]");
+            let text =
+                terminus::term_string_visible_truncate(&text, width.saturating_sub(written), None);
+            vprint!(screen, "{}", text);
+            written += terminus::term_string_visible_len(&text);
         }
 
         vprint!(
@@ -312,7 +676,6 @@ where
         );
 
         restore_cursor!(screen);
-        flush!(screen);
         self.dirty = false;
     }
 
@@ -328,26 +691,69 @@ where
             UIEvent::AddWindow(name, _) => {
                 self.add_window(terminus::clean(name));
             }
+            UIEvent::WindowsOrder(windows) => {
+                self.windows = windows.iter().map(|win| terminus::clean(win)).collect();
+                self.dirty = true;
+            }
             UIEvent::Core(Event::Close(window)) => {
                 self.del_window(&window);
             }
+            UIEvent::Core(Event::Leave(channel, _)) => {
+                self.detached
+                    .insert(terminus::clean(&channel.jid.to_string()));
+                self.dirty = true;
+            }
+            UIEvent::Core(Event::Joined { channel, .. }) => {
+                let window = BareJid::from(channel.clone()).to_string();
+                self.dirty |= self.detached.remove(&terminus::clean(&window));
+            }
             UIEvent::Core(Event::Connected(account, _)) => {
                 self.connection = Some(terminus::clean(&account.to_string()));
+                self.latency = None;
+                self.invisible = false;
                 self.dirty = true;
             }
-            UIEvent::Core(Event::Message(_, Message::Xmpp(message))) => {
-                let mut highlighted = None;
-                let messaged_window = terminus::clean(&message.from.to_string());
-                for window in &self.windows {
-                    if &messaged_window == window && Some(window) != self.current_window.as_ref() {
-                        highlighted = Some(window.clone());
-                    }
+            UIEvent::Core(Event::Latency(account, latency)) => {
+                if self.connection == Some(terminus::clean(&account.to_string())) {
+                    self.latency = Some(*latency);
+                    self.dirty = true;
                 }
-                if highlighted.is_some() {
-                    self.highlight_window(&highlighted.unwrap());
+            }
+            UIEvent::Core(Event::Invisible(account, invisible)) => {
+                if self.connection == Some(terminus::clean(&account.to_string())) {
+                    self.invisible = *invisible;
+                    self.dirty = true;
+                }
+            }
+            UIEvent::Core(Event::ReadState(state)) => {
+                let window = terminus::clean(&state.conversation.to_string());
+                if Some(&window) != self.current_window.as_ref() {
+                    self.highlighted.retain(|win| *win != window);
+                    self.mentioned.remove(&window);
+                    if state.unread > 0 {
+                        self.highlight_window(&window, state.highlighted);
+                    }
                     self.dirty = true;
                 }
             }
+            UIEvent::Core(Event::Tick) | UIEvent::Core(Event::WindowAlias(..)) => self.dirty = true,
+            UIEvent::Core(Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y))) => {
+                if *y == self.line {
+                    let clicked = self
+                        .regions
+                        .iter()
+                        .find(|(start, end, _)| *x >= *start && *x < *end);
+                    if let Some((_, _, window)) = clicked {
+                        self.scheduler.schedule(Event::Win(window.clone()));
+                    }
+                }
+            }
+            UIEvent::Activity(window, mention) => {
+                let window = terminus::clean(window);
+                if Some(&window) != self.current_window.as_ref() {
+                    self.highlight_window(&window, *mention);
+                }
+            }
             _ => {}
         }
     }
@@ -360,168 +766,746 @@ where
     }
 }
 
-impl fmt::Display for Message {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Message::Log(message) => {
-                let timestamp = Local.from_utc_datetime(&message.timestamp.naive_local());
-                for line in message.body.lines() {
-                    write!(
-                        f,
-                        "{}{} - {}\n",
-                        color::Fg(color::White),
-                        timestamp.format("%T"),
-                        line
-                    )?;
-                }
+/// Completion candidates, shown above the input while cycling through them
+struct CompletionBar {
+    candidates: Vec<String>,
+    current: usize,
+    dirty: bool,
+    /// The bar has been shown or hidden since the last layout
+    layout_dirty: bool,
+}
 
-                Ok(())
+impl CompletionBar {
+    fn new() -> Self {
+        Self {
+            candidates: Vec::new(),
+            current: 0,
+            dirty: true,
+            layout_dirty: false,
+        }
+    }
+
+    fn set_candidates(&mut self, candidates: Vec<String>, current: usize) {
+        self.layout_dirty |= self.candidates.is_empty() != candidates.is_empty();
+        self.candidates = candidates;
+        self.current = current;
+        self.dirty = true;
+    }
+
+    /// Index of the first candidate to display so that the current one fits in `width`
+    fn first_visible(&self, width: usize) -> usize {
+        let len = |candidate: &String| terminus::term_string_visible_len(candidate.trim_end()) + 1;
+        let mut first = 0;
+        while first < self.current
+            && self.candidates[first..=self.current]
+                .iter()
+                .map(len)
+                .sum::<usize>()
+                > width
+        {
+            first += 1;
+        }
+        first
+    }
+}
+
+impl<W> View<UIEvent, W> for CompletionBar
+where
+    W: Write,
+{
+    fn layout(&mut self, dimension: &mut Dimension, top: u16, left: u16) {
+        dimension.x = left;
+        dimension.y = top;
+        self.layout_dirty = false;
+    }
+
+    fn render(&mut self, dimension: &Dimension, screen: &mut Screen<W>) {
+        self.dirty = false;
+        if dimension.h == Some(0) {
+            return;
+        }
+
+        save_cursor!(screen);
+        goto!(screen, dimension.x, dimension.y);
+
+        let width = dimension.w.unwrap() as usize;
+        let mut written = 0;
+        for (i, candidate) in self
+            .candidates
+            .iter()
+            .enumerate()
+            .skip(self.first_visible(width))
+        {
+            let available = width.saturating_sub(written + 1);
+            if available == 0 {
+                break;
             }
-            Message::Xmpp(message) => {
-                let author = terminus::clean(
-                    &match &message.type_ {
-                        XmppMessageType::Channel => match &message.from_full {
-                            Jid::Full(from) => from.resource.clone(),
-                            Jid::Bare(from) => from.to_string(),
-                        },
-                        XmppMessageType::Chat => message.from.to_string(),
-                    }
-                    .to_string(),
-                );
 
-                let timestamp =
-                    Local.from_utc_datetime(&message.get_original_timestamp().naive_local());
-                let body = message.get_last_body();
-                let me = body.starts_with("/me");
-                let padding_len = match me {
-                    true => format!("{} - {}: ", timestamp.format("%T"), author).len(),
-                    false => format!("{} - * {}", timestamp.format("%T"), author).len(),
-                };
-                let padding = " ".repeat(padding_len);
+            let mut candidate = terminus::clean(candidate.trim_end());
+            if terminus::term_string_visible_len(&candidate) > available {
+                candidate = terminus::term_string_visible_truncate(&candidate, available, None);
+            }
 
-                let (r, g, b) = id_to_rgb(&author);
+            vprint!(screen, " ");
+            match i == self.current {
+                true => vprint!(
+                    screen,
+                    "{}{}{}",
+                    termion::style::Invert,
+                    candidate,
+                    termion::style::NoInvert
+                ),
+                false => vprint!(screen, "{}", candidate),
+            }
+            written += terminus::term_string_visible_len(&candidate) + 1;
+        }
 
-                let mut attributes = "".to_string();
-                if message.has_multiple_version() {
-                    attributes.push_str("✎ ");
-                }
+        vprint!(screen, "{}", " ".repeat(width.saturating_sub(written)));
+        restore_cursor!(screen);
+    }
 
-                match me {
-                    true => write!(
-                        f,
-                        "{}{} - {}* {}{}{}",
-                        color::Fg(color::White),
-                        timestamp.format("%T"),
-                        attributes,
-                        color::Fg(color::Rgb(r, g, b)),
-                        author,
-                        color::Fg(color::White)
-                    ),
-                    false => write!(
-                        f,
-                        "{}{} - {}{}{}:{} ",
-                        color::Fg(color::White),
-                        timestamp.format("%T"),
-                        attributes,
-                        color::Fg(color::Rgb(r, g, b)),
-                        author,
-                        color::Fg(color::White)
-                    ),
-                }?;
+    fn is_layout_dirty(&self) -> bool {
+        self.layout_dirty
+    }
 
-                let mut iter = match me {
-                    true => body.strip_prefix("/me").unwrap().lines(),
-                    false => body.lines(),
-                };
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
 
-                if let Some(line) = iter.next() {
-                    write!(f, "{}", terminus::clean(line))?;
-                }
-                while let Some(line) = iter.next() {
-                    write!(f, "\n{}{}", padding, terminus::clean(line))?;
+    fn event(&mut self, event: &mut UIEvent) {
+        match event {
+            UIEvent::Core(Event::CompletionCandidates(candidates, current)) => {
+                self.set_candidates(candidates.clone(), *current)
+            }
+            UIEvent::Core(Event::ResetCompletion) | UIEvent::Core(Event::ChangeWindow(_)) => {
+                if !self.candidates.is_empty() {
+                    self.set_candidates(Vec::new(), 0);
                 }
-
-                Ok(())
             }
+            _ => {}
         }
     }
-}
 
-impl fmt::Display for contact::Group {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}{}{}",
-            color::Fg(color::Yellow),
-            terminus::clean(&self.0),
-            color::Fg(color::White)
-        )
+    fn get_layouts(&self) -> Layouts {
+        Layouts {
+            width: Layout::match_parent(),
+            height: match self.candidates.is_empty() {
+                true => Layout::absolute(0),
+                false => Layout::absolute(1),
+            },
+        }
     }
 }
 
-#[derive(Clone, Debug, Ord, PartialOrd)]
-pub enum RosterItem {
-    Contact(contact::Contact),
-    Bookmark(contact::Bookmark),
-    Window(String),
+const DEFAULT_TIME_FORMAT: &str = "%T";
+
+thread_local! {
+    static TIME_FORMAT: RefCell<String> = RefCell::new(DEFAULT_TIME_FORMAT.to_string());
 }
 
-impl Hash for RosterItem {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        match self {
-            Self::Contact(contact) => contact.jid.hash(state),
-            Self::Bookmark(bookmark) => bookmark.jid.hash(state),
-            Self::Window(window) => window.hash(state),
-        };
+fn check_time_format(format: &str) -> Result<(), String> {
+    match StrftimeItems::new(format).any(|item| item == Item::Error) {
+        true => Err(format!("Invalid time format {}", format)),
+        false => Ok(()),
     }
 }
 
-impl PartialEq for RosterItem {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Contact(a), Self::Contact(b)) => a.eq(b),
-            (Self::Bookmark(a), Self::Bookmark(b)) => a.eq(b),
-            (Self::Window(a), Self::Window(b)) => a.eq(b),
-            _ => false,
-        }
-    }
+/// Change the strftime format used for message timestamps
+fn set_time_format(format: &str) -> Result<(), String> {
+    check_time_format(format)?;
+    TIME_FORMAT.with(|time_format| *time_format.borrow_mut() = format.to_string());
+    Ok(())
 }
 
-impl Eq for RosterItem {}
+fn format_time(timestamp: &DateTime<FixedOffset>) -> String {
+    let timestamp = Local.from_utc_datetime(&timestamp.naive_local());
+    TIME_FORMAT.with(|format| timestamp.format(&format.borrow()).to_string())
+}
+
+/// Separator line inserted between two messages sent on different days
+fn date_separator(previous: &Message, message: &Message) -> Option<String> {
+    let previous = Local.from_utc_datetime(&previous.timestamp().naive_local());
+    let current = Local.from_utc_datetime(&message.timestamp().naive_local());
+
+    match previous.naive_local().date() == current.naive_local().date() {
+        true => None,
+        false => Some(format!(
+            "{}--- {} ---{}",
+            color::Fg(theme::current().timestamp),
+            current.format("%a %-d %b"),
+            color::Fg(theme::current().text)
+        )),
+    }
+}
+
+/// URLs found in messages, numbered per window in order of arrival
+#[derive(Default)]
+struct Urls {
+    windows: HashMap<String, Vec<String>>,
+    /// URLs of each message and their numbers, by window, sender and message id
+    messages: HashMap<(String, String, String), (Vec<String>, Vec<usize>)>,
+}
+
+/// Key of the URLs of a message, ids are only unique for a sender in a window
+fn urls_key(message: &VersionedXmppMessage) -> (String, String, String) {
+    let window = match message.direction {
+        Direction::Incoming => message.from.to_string(),
+        Direction::Outgoing => message.to.to_string(),
+    };
+    (window, message.from_full.to_string(), message.id.clone())
+}
+
+thread_local! {
+    static URLS: RefCell<Urls> = RefCell::new(Urls::default());
+}
+
+fn register_urls(message: &VersionedXmppMessage) {
+    URLS.with(|urls| {
+        let mut urls = urls.borrow_mut();
+        let key = urls_key(message);
+
+        // Lines are cleaned one by one like when the message is displayed
+        let found = message
+            .get_last_body()
+            .lines()
+            .flat_map(|line| {
+                let line = terminus::clean(line);
+                url::find_urls(&line)
+                    .into_iter()
+                    .map(|range| line[range].to_string())
+                    .collect::<Vec<String>>()
+            })
+            .collect::<Vec<String>>();
+        // A correction with other URLs gets new numbers
+        if let Some((registered, _)) = urls.messages.get(&key) {
+            if registered == &found {
+                return;
+            }
+        }
+        let window_urls = urls.windows.entry(key.0.clone()).or_insert(Vec::new());
+        let mut numbers = Vec::new();
+        for url in &found {
+            window_urls.push(url.clone());
+            numbers.push(window_urls.len());
+        }
+        urls.messages.insert(key, (found, numbers));
+    });
+}
+
+/// Get the URL numbered `number` in the given window, or the last one
+fn get_url(window: &str, number: Option<usize>) -> Option<String> {
+    URLS.with(|urls| {
+        let urls = urls.borrow();
+        let window_urls = urls.windows.get(window)?;
+        match number {
+            Some(number) if number > 0 => window_urls.get(number - 1).cloned(),
+            Some(_) => None,
+            None => window_urls.last().cloned(),
+        }
+    })
+}
+
+/// Size above which files shared encrypted aren't downloaded by /open
+const MAX_ENCRYPTED_FILE_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Download and decrypt a file shared with an aesgcm:// URL, into a new file only we can read
+async fn decrypt_to_file(url: &str) -> Result<PathBuf, String> {
+    let data = preview::download(url, MAX_ENCRYPTED_FILE_SIZE).await?;
+    let name = url
+        .split(|c| c == '?' || c == '#')
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("file");
+    // Others can write to the temporary directory, the name can't be guessed there and the file is
+    // always a new one, never a file or a link planted beforehand
+    let dir = match dirs::runtime_dir() {
+        Some(dir) => {
+            let dir = dir.join("aparte");
+            fs::create_dir_all(&dir)
+                .and_then(|_| fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)))
+                .map_err(|err| format!("Cannot create {}: {}", dir.display(), err))?;
+            dir
+        }
+        None => env::temp_dir(),
+    };
+    let path = dir.join(format!("aparte-{}-{}", Uuid::new_v4(), name));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(&data))
+        .map_err(|err| format!("Cannot write {}: {}", path.display(), err))?;
+    Ok(path)
+}
+
+/// Open a URL, or the decrypted copy of the file for aesgcm:// URLs
+fn open_url(aparte: &mut Aparte, url: String) {
+    if EncryptedUrl::is_encrypted(&url) {
+        aparte.spawn(async move {
+            let info = match decrypt_to_file(&url).await {
+                Ok(path) => match open_url_with_xdg(path.as_os_str()) {
+                    Ok(()) => format!("Opened {} decrypted in {}", url, path.display()),
+                    Err(err) => format!("Cannot open {}: {}", path.display(), err),
+                },
+                Err(err) => err,
+            };
+            Event::Message(None, Message::log(info))
+        });
+    } else if let Err(err) = open_url_with_xdg(url.as_ref()) {
+        aparte.log(format!("Cannot open {}: {}", url, err));
+    }
+}
+
+fn open_url_with_xdg(target: &OsStr) -> Result<(), IoError> {
+    ProcessCommand::new("xdg-open")
+        .arg(target)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
+
+/// Underline URLs of a line and append their number
+fn decorate_urls(line: &str, numbers: &mut impl Iterator<Item = usize>) -> String {
+    let mut decorated = String::new();
+    let mut last = 0;
+    for range in url::find_urls(line) {
+        decorated.push_str(&line[last..range.start]);
+        decorated.push_str(&format!(
+            "{}{}{}",
+            termion::style::Underline,
+            &line[range.clone()],
+            termion::style::NoUnderline
+        ));
+        if let Some(number) = numbers.next() {
+            decorated.push_str(&format!("[{}]", number));
+        }
+        last = range.end;
+    }
+    decorated.push_str(&line[last..]);
+
+    decorated
+}
+
+thread_local! {
+    /// Image previews, by message id
+    static PREVIEWS: RefCell<HashMap<String, Rc<Graphic>>> = RefCell::new(HashMap::new());
+}
+
+thread_local! {
+    /// Messages written while disconnected, not sent yet
+    static PENDING: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    /// Error of sent messages that bounced
+    static ERRORS: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+fn message_preview(message: &Message) -> Option<Rc<Graphic>> {
+    match message {
+        Message::Xmpp(message) => {
+            PREVIEWS.with(|previews| previews.borrow().get(&message.id).cloned())
+        }
+        _ => None,
+    }
+}
+
+/// Size of the terminal, or the usual 80x24 when it cannot be queried
+fn terminal_size() -> (u16, u16) {
+    termion::terminal_size().unwrap_or_else(|err| {
+        warn!("{}", Error::Terminal(err));
+        (80, 24)
+    })
+}
+
+/// Show a notification through the freedesktop notification service
+fn desktop_notification(summary: &str, body: &str) -> Result<(), String> {
+    notify_rust::Notification::new()
+        .appname("aparte")
+        .summary(summary)
+        .body(body)
+        .show()
+        .map(|_| ())
+        .map_err(|err| format!("Cannot show desktop notification: {}", err))
+}
+
+/// Escape sequence asking the terminal to set its clipboard, working through SSH
+fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64::encode(text))
+}
+
+/// Whether the terminal is remote, local clipboard tools would miss it
+fn over_ssh() -> bool {
+    env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some()
+}
+
+/// Copy with the first clipboard tool found, if any
+fn copy_with_tool(text: &str) -> Option<Result<(), String>> {
+    let tools: [(&str, &[&str]); 3] = [
+        ("wl-copy", &[]),
+        ("xclip", &["-selection", "clipboard"]),
+        ("xsel", &["--clipboard", "--input"]),
+    ];
+
+    for (tool, args) in tools.iter() {
+        let child = ProcessCommand::new(tool)
+            .args(args.iter())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Ok(mut child) = child {
+            if let Some(stdin) = child.stdin.as_mut() {
+                if let Err(err) = stdin.write_all(text.as_bytes()) {
+                    return Some(Err(format!("Cannot copy to clipboard: {}", err)));
+                }
+            }
+            // Close stdin so that the tool knows the whole text has been written
+            drop(child.stdin.take());
+            return match child.wait() {
+                Ok(status) if status.success() => Some(Ok(())),
+                _ => Some(Err(format!("Cannot copy to clipboard with {}", tool))),
+            };
+        }
+    }
+
+    None
+}
+
+/// Timestamp, attributes and author preceding the body of a message
+fn xmpp_message_author(message: &VersionedXmppMessage) -> String {
+    terminus::clean(
+        &match &message.type_ {
+            XmppMessageType::Channel => match &message.from_full {
+                Jid::Full(from) => from.resource.clone(),
+                Jid::Bare(from) => from.to_string(),
+            },
+            XmppMessageType::Chat => match &message.device {
+                Some(device) => format!("{} ({})", message.from, device),
+                None => message.from.to_string(),
+            },
+        }
+        .to_string(),
+    )
+}
+
+/// Number of characters of replied messages shown above replies
+const REPLY_PREVIEW_LENGTH: usize = 50;
+
+/// First line of the quote of a reply, shortened, after the replied occupant nick in channels
+fn reply_preview(message: &VersionedXmppMessage, reply: &Reply) -> String {
+    let line = reply.quote.lines().next().unwrap_or("");
+    let mut preview: String = line.graphemes(true).take(REPLY_PREVIEW_LENGTH).collect();
+    if preview.len() < line.len() || reply.quote.lines().count() > 1 {
+        preview.push('…');
+    }
+    match (&message.type_, &reply.to) {
+        (XmppMessageType::Channel, Some(Jid::Full(to))) => format!("{}: {}", to.resource, preview),
+        _ => preview,
+    }
+}
+
+fn xmpp_message_prefix(message: &VersionedXmppMessage, theme: &Theme) -> String {
+    let author = xmpp_message_author(message);
+
+    let timestamp = format_time(message.get_original_timestamp());
+    let nick_color = theme.nick(&author);
+
+    let mut attributes = "".to_string();
+    if PENDING.with(|pending| pending.borrow().contains(&message.id)) {
+        attributes.push_str("⧗ ");
+    }
+    if message.has_multiple_version() {
+        attributes.push_str("✎ ");
+    }
+
+    match message.get_last_body().starts_with("/me") {
+        true => format!(
+            "{}{}{} - {}* {}{}{}",
+            color::Fg(theme.timestamp),
+            timestamp,
+            color::Fg(theme.text),
+            attributes,
+            color::Fg(nick_color),
+            author,
+            color::Fg(theme.text)
+        ),
+        false => format!(
+            "{}{}{} - {}{}{}:{} ",
+            color::Fg(theme.timestamp),
+            timestamp,
+            color::Fg(theme.text),
+            attributes,
+            color::Fg(nick_color),
+            author,
+            color::Fg(theme.text)
+        ),
+    }
+}
+
+/// Messages of an author sent within this number of seconds are grouped under one prefix
+const GROUP_INTERVAL: i64 = 5 * 60;
+
+/// True if `message` follows `previous` closely enough to be displayed without its prefix
+fn is_continuation(previous: &Message, message: &Message) -> bool {
+    match (previous, message) {
+        (Message::Xmpp(previous), Message::Xmpp(message)) => {
+            let elapsed = message
+                .get_original_timestamp()
+                .signed_duration_since(*previous.get_original_timestamp());
+            previous.type_ == message.type_
+                && !previous.get_last_body().starts_with("/me")
+                && !message.get_last_body().starts_with("/me")
+                && xmpp_message_author(previous) == xmpp_message_author(message)
+                && elapsed >= chrono::Duration::zero()
+                && elapsed < chrono::Duration::seconds(GROUP_INTERVAL)
+        }
+        _ => false,
+    }
+}
+
+/// Display a message, replacing its prefix with blanks when it continues the previous one
+fn format_message(message: &Message, previous: Option<&Message>) -> String {
+    let formatted = format!("{}", message);
+    match (message, previous) {
+        (Message::Xmpp(xmpp_message), Some(previous)) if is_continuation(previous, message) => {
+            let prefix = xmpp_message_prefix(xmpp_message, &theme::current());
+            match formatted.strip_prefix(&prefix) {
+                Some(body) => format!(
+                    "{}{}",
+                    " ".repeat(terminus::term_string_visible_len(&prefix)),
+                    body
+                ),
+                None => formatted,
+            }
+        }
+        _ => formatted,
+    }
+}
+
+/// Visible width of the prefix of a message, used as hanging indent for its following lines
+fn message_indent(message: &Message) -> usize {
+    match message {
+        Message::Log(message) => {
+            terminus::term_string_visible_len(&format!("{} - ", format_time(&message.timestamp)))
+        }
+        Message::Xmpp(message) => {
+            terminus::term_string_visible_len(&xmpp_message_prefix(message, &theme::current()))
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::Log(message) => {
+                let theme = theme::current();
+                let timestamp = format_time(&message.timestamp);
+                for line in message.body.lines() {
+                    write!(
+                        f,
+                        "{}{} - {}{}\n",
+                        color::Fg(theme.timestamp),
+                        timestamp,
+                        color::Fg(theme.text),
+                        line
+                    )?;
+                }
+
+                Ok(())
+            }
+            Message::Xmpp(message) => {
+                let theme = theme::current();
+                let prefix = xmpp_message_prefix(message, &theme);
+                let padding = " ".repeat(terminus::term_string_visible_len(&prefix));
+                write!(f, "{}", prefix)?;
+
+                if let Some(reply) = &message.reply {
+                    write!(
+                        f,
+                        "{}↪ {}{}\n{}",
+                        color::Fg(theme.timestamp),
+                        terminus::clean(&reply_preview(message, reply)),
+                        color::Fg(theme.text),
+                        padding
+                    )?;
+                }
+
+                let body = message.get_last_body();
+                let mut iter = match body.starts_with("/me") {
+                    true => body.strip_prefix("/me").unwrap().lines(),
+                    false => body.lines(),
+                };
+
+                let mut numbers = URLS
+                    .with(|urls| {
+                        urls.borrow()
+                            .messages
+                            .get(&urls_key(message))
+                            .map(|(_, numbers)| numbers.clone())
+                    })
+                    .unwrap_or(Vec::new())
+                    .into_iter();
+
+                if let Some(line) = iter.next() {
+                    write!(f, "{}", decorate_urls(&terminus::clean(line), &mut numbers))?;
+                }
+                while let Some(line) = iter.next() {
+                    write!(
+                        f,
+                        "\n{}{}",
+                        padding,
+                        decorate_urls(&terminus::clean(line), &mut numbers)
+                    )?;
+                }
+
+                if let Some(error) = ERRORS.with(|errors| errors.borrow().get(&message.id).cloned())
+                {
+                    write!(
+                        f,
+                        "\n{}{}✗ {}{}",
+                        padding,
+                        color::Fg(theme.error),
+                        terminus::clean(&error),
+                        color::Fg(theme.text)
+                    )?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for contact::Group {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let theme = theme::current();
+        write!(
+            f,
+            "{}{}{}",
+            color::Fg(theme.group),
+            terminus::clean(&self.0),
+            color::Fg(theme.text)
+        )
+    }
+}
+
+#[derive(Clone, Debug, Ord, PartialOrd)]
+pub enum RosterItem {
+    Contact(contact::Contact),
+    Bookmark(contact::Bookmark),
+    Window(String),
+}
+
+impl Hash for RosterItem {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Self::Contact(contact) => contact.jid.hash(state),
+            Self::Bookmark(bookmark) => bookmark.jid.hash(state),
+            Self::Window(window) => window.hash(state),
+        };
+    }
+}
+
+impl PartialEq for RosterItem {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Contact(a), Self::Contact(b)) => a.eq(b),
+            (Self::Bookmark(a), Self::Bookmark(b)) => a.eq(b),
+            (Self::Window(a), Self::Window(b)) => a.eq(b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for RosterItem {}
+
+impl RosterItem {
+    /// Contacts are sorted by presence then name, other items by their natural order
+    fn compare(a: &Self, b: &Self) -> cmp::Ordering {
+        match (a, b) {
+            (Self::Contact(a), Self::Contact(b)) => {
+                let name = |contact: &contact::Contact| match &contact.name {
+                    Some(name) => name.to_lowercase(),
+                    None => contact.jid.to_string().to_lowercase(),
+                };
+                a.presence
+                    .rank()
+                    .cmp(&b.presence.rank())
+                    .then_with(|| name(a).cmp(&name(b)))
+                    .then_with(|| a.cmp(b))
+            }
+            (a, b) => a.cmp(b),
+        }
+    }
+
+    /// Whether the displayed name or jid contains `text`, ignoring case
+    fn matches(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        let (jid, name) = match self {
+            Self::Contact(contact) => (contact.jid.to_string(), contact.name.clone()),
+            Self::Bookmark(bookmark) => (bookmark.jid.to_string(), bookmark.name.clone()),
+            Self::Window(window) => (window.clone(), None),
+        };
+
+        jid.to_lowercase().contains(&text)
+            || name.map_or(false, |name| name.to_lowercase().contains(&text))
+    }
+}
 
 impl fmt::Display for RosterItem {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let theme = theme::current();
         match &self {
             Self::Contact(contact) => {
+                // Presence is given by the bullet so that the name keeps its consistent color
                 match contact.presence {
                     contact::Presence::Available | contact::Presence::Chat => {
-                        write!(f, "{}", color::Fg(color::Green))?
+                        write!(f, "{}● ", color::Fg(theme.available))?
                     }
                     contact::Presence::Away
                     | contact::Presence::Dnd
                     | contact::Presence::Xa
-                    | contact::Presence::Unavailable => write!(f, "{}", color::Fg(color::White))?,
+                    | contact::Presence::Unavailable => {
+                        write!(f, "{}○ ", color::Fg(theme.unavailable))?
+                    }
                 };
 
+                let jid = contact.jid.to_string();
                 let disp = match &contact.name {
-                    Some(name) => format!(
-                        "{} ({})",
-                        terminus::clean(name),
-                        terminus::clean(&contact.jid.to_string()),
-                    ),
-                    None => terminus::clean(&contact.jid.to_string()),
+                    Some(name) => format!("{} ({})", terminus::clean(name), terminus::clean(&jid),),
+                    None => terminus::clean(&jid),
                 };
+                let key = contact.avatar.as_ref().unwrap_or(&jid);
+                write!(
+                    f,
+                    "{} ",
+                    initial_block(contact.name.as_deref().unwrap_or(&jid), key, &theme)
+                )?;
 
-                write!(f, "{}{}", disp, color::Fg(color::White))
+                write!(
+                    f,
+                    "{}{}{}",
+                    color::Fg(theme.nick(&jid)),
+                    disp,
+                    color::Fg(theme.text)
+                )
             }
 
             Self::Bookmark(bookmark) => {
+                let jid = bookmark.jid.to_string();
                 let disp = match &bookmark.name {
                     Some(name) => terminus::clean(name),
-                    None => terminus::clean(&bookmark.jid.to_string()),
+                    None => terminus::clean(&jid),
                 };
+                write!(
+                    f,
+                    "{} ",
+                    initial_block(bookmark.name.as_deref().unwrap_or(&jid), &jid, &theme)
+                )?;
 
-                write!(f, "{}{}", disp, color::Fg(color::White))
+                write!(f, "{}{}", disp, color::Fg(theme.text))
             }
             Self::Window(window) => {
                 let disp = terminus::clean(window);
@@ -532,47 +1516,149 @@ impl fmt::Display for RosterItem {
     }
 }
 
+/// Initial of a name, skipping leading punctuation, uppercased
+fn initial(name: &str) -> String {
+    name.graphemes(true)
+        .find(|grapheme| grapheme.chars().all(char::is_alphanumeric))
+        .map_or("?".to_string(), |grapheme| grapheme.to_uppercase())
+}
+
+/// Block with the initial of a name in the color of `key`, standing for its avatar
+fn initial_block(name: &str, key: &str, theme: &Theme) -> String {
+    format!(
+        "{}{}{}{}{}",
+        color::Fg(theme.nick(key)),
+        termion::style::Invert,
+        terminus::clean(&initial(name)),
+        termion::style::NoInvert,
+        color::Fg(theme.text)
+    )
+}
+
 impl fmt::Display for conversation::Occupant {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let (r, g, b) = id_to_rgb(&self.nick);
+        let theme = theme::current();
         let nick = self.nick.clone();
+        let key = match &self.jid {
+            Some(jid) => jid.to_string(),
+            None => nick.clone(),
+        };
+        let block = initial_block(&nick, &key, &theme);
 
-        write!(
-            f,
-            "{}{}{}",
-            color::Fg(color::Rgb(r, g, b)),
-            terminus::clean(&nick),
-            color::Fg(color::White)
-        )
+        let prefix = match (self.role, self.affiliation) {
+            (conversation::Role::Moderator, _)
+            | (_, conversation::Affiliation::Owner)
+            | (_, conversation::Affiliation::Admin) => "@",
+            (_, conversation::Affiliation::Member) => "+",
+            _ => " ",
+        };
+
+        match self.presence {
+            contact::Presence::Away | contact::Presence::Xa => write!(
+                f,
+                "{}{} {}{}{}",
+                prefix,
+                block,
+                termion::style::Faint,
+                terminus::clean(&nick),
+                termion::style::NoFaint
+            )?,
+            contact::Presence::Dnd => write!(
+                f,
+                "{}{} {}{}",
+                prefix,
+                block,
+                color::Fg(theme.dnd),
+                terminus::clean(&nick)
+            )?,
+            _ => write!(
+                f,
+                "{}{} {}{}",
+                prefix,
+                block,
+                color::Fg(theme.nick(&nick)),
+                terminus::clean(&nick)
+            )?,
+        };
+
+        if let Some(status) = &self.status {
+            write!(
+                f,
+                " {}{}{}",
+                termion::style::Faint,
+                terminus::clean(status),
+                termion::style::NoFaint
+            )?;
+        }
+
+        write!(f, "{}", color::Fg(theme.text))
+    }
+}
+
+impl fmt::Display for Room {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let theme = theme::current();
+        match &self.name {
+            Some(name) => write!(
+                f,
+                "{}{}{} {}{}{}",
+                color::Fg(theme.nick(&self.jid.to_string())),
+                terminus::clean(name),
+                color::Fg(theme.text),
+                termion::style::Faint,
+                self.jid,
+                termion::style::NoFaint
+            )?,
+            None => write!(f, "{}", self.jid)?,
+        }
+
+        match self.occupants {
+            Some(1) => write!(f, " · 1 occupant")?,
+            Some(occupants) => write!(f, " · {} occupants", occupants)?,
+            None => {}
+        }
+
+        if let Some(description) = &self.description {
+            write!(
+                f,
+                " · {}{}{}",
+                termion::style::Faint,
+                terminus::clean(description),
+                termion::style::NoFaint
+            )?;
+        }
+
+        Ok(())
     }
 }
 
 impl fmt::Display for conversation::Role {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let theme = theme::current();
         match self {
             conversation::Role::Moderator => write!(
                 f,
                 "{}Moderators{}",
-                color::Fg(color::Yellow),
-                color::Fg(color::Yellow)
+                color::Fg(theme.group),
+                color::Fg(theme.text)
             ),
             conversation::Role::Participant => write!(
                 f,
                 "{}Participants{}",
-                color::Fg(color::Yellow),
-                color::Fg(color::Yellow)
+                color::Fg(theme.group),
+                color::Fg(theme.text)
             ),
             conversation::Role::Visitor => write!(
                 f,
                 "{}Visitors{}",
-                color::Fg(color::Yellow),
-                color::Fg(color::Yellow)
+                color::Fg(theme.group),
+                color::Fg(theme.text)
             ),
             conversation::Role::None => write!(
                 f,
                 "{}Others{}",
-                color::Fg(color::Yellow),
-                color::Fg(color::Yellow)
+                color::Fg(theme.group),
+                color::Fg(theme.text)
             ),
         }
     }
@@ -589,81 +1675,276 @@ impl Scheduler {
     }
 }
 
+/// Terminal state to go back to on exit or panic
 struct PanicHandler {
-    panic: Arc<Mutex<Option<String>>>,
-    backtrace: Arc<Mutex<Option<Backtrace>>>,
+    /// Terminal attributes before raw mode
+    termios: Option<libc::termios>,
+}
+
+/// Leave the alternate screen and raw mode, as before aparté started
+fn restore_terminal(termios: &Option<libc::termios>) {
+    let mut stdout = std::io::stdout();
+    let _ = write!(
+        stdout,
+        "{}{}{}{}",
+        PASTE_DISABLE,
+        MOUSE_DISABLE,
+        termion::screen::ToMainScreen,
+        termion::cursor::Show
+    );
+    let _ = stdout.flush();
+    if let Some(termios) = termios {
+        unsafe { libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, termios) };
+    }
 }
 
 impl PanicHandler {
-    pub fn new() -> Self {
-        let panic = Arc::new(Mutex::new(None));
-        let backtrace = Arc::new(Mutex::new(None));
+    /// Must be created before entering raw mode
+    pub fn new(data_dir: PathBuf) -> Self {
+        let termios = unsafe {
+            let mut termios = std::mem::zeroed();
+            match libc::tcgetattr(libc::STDOUT_FILENO, &mut termios) {
+                0 => Some(termios),
+                _ => None,
+            }
+        };
 
-        let panic_for_hook = panic.clone();
-        let backtrace_for_hook = backtrace.clone();
         panic::set_hook(Box::new(move |info| {
-            let panic = format!("{}", info);
-            panic_for_hook
-                .lock()
-                .expect("cannot lock panic")
-                .replace(panic);
-
-            let backtrace = Backtrace::new_unresolved();
-            backtrace_for_hook
-                .lock()
-                .expect("cannot lock backtrace")
-                .replace(backtrace);
+            restore_terminal(&termios);
+
+            let backtrace = Backtrace::new();
+            error!("Oops Aparté {}", info);
+            error!("{:?}", backtrace);
+            eprintln!("Oops Aparté {}", info);
+            eprintln!("{:?}", backtrace);
+            eprintln!("This isn’t normal behavior. Please report issue.");
+            eprintln!("Logs are in {}", data_dir.display());
+
+            // The terminal is given back, the interface can't go on even if another task panicked
+            std::process::exit(101);
         }));
 
-        Self { panic, backtrace }
+        Self { termios }
     }
 }
 
 impl Drop for PanicHandler {
     fn drop(&mut self) {
-        if let Some(panic) = self.panic.lock().expect("cannot lock panic").as_ref() {
-            println!("Oops Aparté {}", panic);
-            error!("Oops Aparté {}", panic);
-            println!("This isn’t normal behavior. Please report issue.");
-            error!("This isn’t normal behavior. Please report issue.");
-            if let Some(backtrace) = self
-                .backtrace
-                .lock()
-                .expect("cannot lock backtrace")
-                .as_mut()
-            {
-                println!("Aparté is gathering more info in logfile…");
-                backtrace.resolve();
-                error!("{:?}", backtrace);
-                println!("All done.");
-                let data_dir = dirs::data_dir().unwrap();
-                let aparte_data = data_dir.join("aparte").join("aparte.log");
-                println!("Please check {}", aparte_data.to_str().unwrap());
+        restore_terminal(&self.termios);
+    }
+}
+
+/// How the UI is drawn in the terminal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayMode {
+    /// Windows, bars and side panes drawn over the whole alternate screen
+    Fullscreen,
+    /// Messages printed one after the other without colors, the input staying on the last line,
+    /// for screen readers and braille displays
+    Accessible,
+    /// Messages printed one after the other in the main screen, kept in the terminal scrollback
+    Plain,
+    /// Nothing drawn nor read from the terminal, aparté being driven by the control socket, DBus
+    /// and scripts
+    Headless,
+}
+
+/// Number of messages kept for each window other than the current one in line modes
+const LINE_PENDING_COUNT: usize = 200;
+
+/// Output of the line modes: messages of the current window are appended to the terminal, those
+/// of other windows are printed when switching to them
+struct LineOutput {
+    colors: bool,
+    prompt: String,
+    width: usize,
+    /// Input line as last printed and the column of its cursor, None once overwritten
+    input: Option<(String, usize)>,
+    /// Messages received by other windows since they were the current one
+    pending: HashMap<String, VecDeque<Message>>,
+}
+
+impl LineOutput {
+    fn new(colors: bool) -> Self {
+        Self {
+            colors,
+            prompt: String::new(),
+            width: 0,
+            input: None,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Print text in place of the input, which is printed again afterwards
+    fn print<W: Write>(&mut self, screen: &mut W, text: &str) {
+        let text = match self.colors {
+            true => text.to_string(),
+            false => terminus::clean(text),
+        };
+        vprint!(screen, "\r{}", termion::clear::CurrentLine);
+        for line in text.trim_end_matches('\n').split('\n') {
+            vprint!(screen, "{}{}\r\n", line, termion::style::Reset);
+        }
+        self.input = None;
+    }
+
+    /// Keep a message of another window until switching to it
+    fn keep(&mut self, window: &str, message: &Message) {
+        let pending = self
+            .pending
+            .entry(window.to_string())
+            .or_insert_with(VecDeque::new);
+        pending.push_back(message.clone());
+        if pending.len() > LINE_PENDING_COUNT {
+            pending.pop_front();
+        }
+    }
+
+    /// Announce the new current window and print the messages it received meanwhile
+    fn show_window<W: Write>(&mut self, screen: &mut W, window: &str) {
+        self.print(screen, &format!("--- {} ---", window_label(window)));
+        for message in self.pending.remove(window).unwrap_or_default() {
+            self.print(screen, &message.to_string());
+        }
+    }
+
+    /// Print the input again if it changed, its start being hidden when it doesn't fit
+    fn show_input<W: Write>(&mut self, screen: &mut W, buf: &str, cursor: usize, password: bool) {
+        let buf = match password {
+            true => String::new(),
+            false => buf.replace('\n', "↵"),
+        };
+        let mut graphemes = self
+            .prompt
+            .graphemes(true)
+            .chain(buf.graphemes(true))
+            .collect::<Vec<&str>>();
+        let mut column = self.prompt.graphemes(true).count() + if password { 0 } else { cursor };
+        // The last column is kept for the cursor, the line must never wrap
+        if self.width > 1 && column > self.width - 2 {
+            let hidden = column - (self.width - 2);
+            graphemes.drain(..hidden);
+            column -= hidden;
+        }
+        graphemes.truncate(self.width.saturating_sub(1));
+        let line = graphemes.concat();
+
+        if self.input.as_ref() != Some(&(line.clone(), column)) {
+            vprint!(screen, "\r{}{}\r", termion::clear::CurrentLine, line);
+            if column > 0 {
+                vprint!(screen, "{}", termion::cursor::Right(column as u16));
             }
+            self.input = Some((line, column));
         }
     }
 }
 
 pub struct UIMod {
     screen: Screen<Stdout>,
+    /// Output of the line modes, views are only drawn in fullscreen mode
+    lines: Option<LineOutput>,
+    /// Nothing is drawn, logs are written to the log file
+    headless: bool,
     windows: Vec<String>,
     current_window: Option<String>,
     unread_windows: LinkedHashSet<String>,
+    /// Number of unread incoming messages of each window
+    unread_counts: HashMap<String, usize>,
+    /// Id and date of the newest message of each window
+    newest: HashMap<String, (String, DateTime<FixedOffset>)>,
+    /// Newest message of each window when it was last the current one
+    last_read: HashMap<String, String>,
     conversations: HashMap<String, Conversation>,
     root: LinearLayout<UIEvent, Stdout>,
     dimension: Option<Dimension>,
     password_command: Option<Command>,
+    /// Ctrl-X has been pressed, waiting for the second key of the chord
+    ctrl_x: bool,
+    /// The last key yanked text, Alt-Y then cycles the kill ring instead of copying the input
+    yanked: bool,
+    /// Keys move the copy mode cursor of the current window instead of editing input
+    copy_mode: bool,
+    /// Message the next one sent from a window replies to
+    replies: HashMap<String, Reply>,
+    /// Last message we sent in each window
+    last_sent: HashMap<String, VersionedXmppMessage>,
+    /// Windows whose next sent message corrects the last one sent
+    corrections: HashSet<String>,
+    /// Account whose rooms are listed in the room directory
+    room_directory: Option<Account>,
+    notify_config: NotifyConfig,
+    highlights: Highlights,
+    scrollback_config: ScrollbackConfig,
+    /// Width of side lists, their layout one when not set
+    side_width: Option<SideWidth>,
+    /// Input prompt of each window type
+    prompts: HashMap<String, Vec<PromptComponent>>,
+    do_not_disturb: bool,
+    /// Muted windows and when they are unmuted, None until /unmute
+    muted: HashMap<String, Option<DateTime<FixedOffset>>>,
+    /// Unread windows with messages addressed to us
+    highlighted_windows: HashSet<String>,
+    /// Channel windows left with /leave, kept until closed or joined again
+    detached: HashSet<String>,
+    /// Last title set on the terminal
+    terminal_title: Option<String>,
     outgoing_event_queue: Rc<RefCell<Vec<Event>>>,
     #[allow(dead_code)]
     panic_handler: PanicHandler, // Defining panic_handler last guarantee that it will be dropped last (after terminal restoration)
 }
 
 impl UIMod {
-    pub fn new() -> Self {
-        let stdout = std::io::stdout().into_raw_mode().unwrap();
-        let screen = AlternateScreen::from(stdout);
+    /// Conversations of the open windows, in the windows order, and the current window
+    pub fn open_conversations(&self) -> (Vec<Conversation>, Option<String>) {
+        let conversations = self
+            .windows
+            .iter()
+            .filter(|window| !self.detached.contains(*window))
+            .filter_map(|window| self.conversations.get(window).cloned())
+            .collect();
+        (conversations, self.current_window.clone())
+    }
+
+    /// What has been read in the conversation of each window
+    pub fn read_states(&self) -> Vec<ReadState> {
+        self.windows
+            .iter()
+            .filter_map(|window| {
+                let (account, conversation) = match self.conversations.get(window)? {
+                    Conversation::Chat(chat) => (chat.account.clone(), chat.contact.clone()),
+                    Conversation::Channel(channel) => {
+                        (channel.account.clone(), channel.jid.clone())
+                    }
+                };
+                Some(ReadState {
+                    account,
+                    conversation,
+                    last_read: self.last_read.get(window).cloned(),
+                    unread: self.unread_counts.get(window).copied().unwrap_or(0),
+                    highlighted: self.highlighted_windows.contains(window),
+                })
+            })
+            .collect()
+    }
+
+    pub fn new(display: DisplayMode, data_dir: &Path) -> Result<Self, Error> {
+        let panic_handler = PanicHandler::new(data_dir.to_path_buf());
 
-        let panic_handler = PanicHandler::new();
+        let terminal = || -> Result<_, Error> {
+            let stdout = std::io::stdout().into_raw_mode().map_err(Error::Terminal)?;
+            Ok(MouseTerminal::from(AlternateScreen::from(stdout)))
+        };
+        let (mut screen, lines) = match display {
+            DisplayMode::Fullscreen => (Screen::new(terminal()?), None),
+            DisplayMode::Accessible => (Screen::direct(terminal()?), Some(LineOutput::new(false))),
+            DisplayMode::Plain => (Screen::direct(terminal()?), Some(LineOutput::new(true))),
+            DisplayMode::Headless => (Screen::headless(), None),
+        };
+        // Line modes print in the main screen, below what was already there
+        if lines.is_some() {
+            vprint!(screen, "{}{}", termion::screen::ToMainScreen, MOUSE_DISABLE);
+            flush!(screen);
+        }
 
         let mut layout = LinearLayout::<UIEvent, Stdout>::new(Orientation::Vertical).with_event(
             |layout, event| {
@@ -673,12 +1954,32 @@ impl UIMod {
             },
         );
 
+        let outgoing_event_queue = Rc::new(RefCell::new(Vec::new()));
+        let scheduler = Scheduler {
+            queue: outgoing_event_queue.clone(),
+        };
+
         let title_bar = TitleBar::new();
-        let frame =
-            FrameLayout::<UIEvent, Stdout, String>::new().with_event(|frame, event| match event {
+        let frame = FrameLayout::<UIEvent, Stdout, String>::new().with_event(
+            move |frame, event| match event {
                 UIEvent::Core(Event::ChangeWindow(name)) => {
                     frame.set_current(name.to_string());
                 }
+                UIEvent::Core(Event::Split(orientation)) => {
+                    frame.split(orientation.clone());
+                }
+                UIEvent::Core(Event::Unsplit) => {
+                    if let Some(window) = frame.unsplit() {
+                        scheduler.schedule(Event::Win(window));
+                    }
+                }
+                UIEvent::Core(Event::Key(Key::Alt('o'))) => {
+                    if let Some(window) = frame.focus_next() {
+                        scheduler.schedule(Event::Win(window));
+                    }
+                }
+                UIEvent::Core(Event::Key(Key::Alt('+'))) => frame.resize(0.05),
+                UIEvent::Core(Event::Key(Key::Alt('-'))) => frame.resize(-0.05),
                 UIEvent::AddWindow(name, view) => {
                     let view = view.take().unwrap();
                     frame.insert_boxed(name.to_string(), view);
@@ -699,7 +2000,15 @@ impl UIMod {
                     }
                 }
                 UIEvent::Core(Event::Key(Key::PageUp))
-                | UIEvent::Core(Event::Key(Key::PageDown)) => {
+                | UIEvent::Core(Event::Key(Key::PageDown))
+                | UIEvent::Core(Event::Key(Key::Ctrl('r')))
+                | UIEvent::Core(Event::Key(Key::Ctrl('s')))
+                | UIEvent::Core(Event::Search(_))
+                | UIEvent::Search(..)
+                | UIEvent::Core(Event::ToggleRoster)
+                | UIEvent::Core(Event::Mouse(_))
+                | UIEvent::CopyMode(_)
+                | UIEvent::SidePaneWidth(_) => {
                     if let Some(current) = frame.get_current_mut() {
                         current.event(event);
                     }
@@ -709,8 +2018,11 @@ impl UIMod {
                         child.event(event);
                     }
                 }
-            });
-        let win_bar = WinBar::new();
+            },
+        );
+        let win_bar = WinBar::new(Scheduler {
+            queue: outgoing_event_queue.clone(),
+        });
         let input = Input::new().with_event(|input, event| match event {
             UIEvent::Core(Event::Key(Key::Char(c))) => input.key(*c),
             UIEvent::Core(Event::Key(Key::Backspace)) => input.backspace(),
@@ -729,6 +2041,17 @@ impl UIMod {
             UIEvent::Core(Event::Key(Key::Ctrl('w'))) => input.backward_delete_word(),
             UIEvent::Core(Event::Key(Key::Ctrl('u'))) => input.delete_from_cursor_to_start(),
             UIEvent::Core(Event::Key(Key::Ctrl('k'))) => input.delete_from_cursor_to_end(),
+            UIEvent::Core(Event::Key(Key::Ctrl('t'))) => input.transpose(),
+            UIEvent::Core(Event::Key(Key::Ctrl('y'))) => input.yank(),
+            UIEvent::Core(Event::Key(Key::Alt('y'))) => {
+                input.yank_pop();
+            }
+            UIEvent::Core(Event::Key(Key::Alt('b'))) => input.backward_word(),
+            UIEvent::Core(Event::Key(Key::Alt('f'))) => input.forward_word(),
+            UIEvent::Core(Event::Key(Key::Alt('d'))) => input.delete_word(),
+            UIEvent::Core(Event::Key(Key::Alt('\r')))
+            | UIEvent::Core(Event::Key(Key::Alt('\n'))) => input.key('\n'),
+            UIEvent::Core(Event::Paste(text)) => input.paste(text),
             UIEvent::Validate(result) => {
                 let mut result = result.borrow_mut();
                 result.replace(input.validate());
@@ -743,30 +2066,62 @@ impl UIMod {
                 input.dirty = true;
             }
             UIEvent::Core(Event::ReadPassword(_)) => input.password(),
+            UIEvent::Prompt(prompt) => input.set_prompt(prompt),
             _ => {}
         });
 
         layout.push(title_bar);
         layout.push(frame);
         layout.push(win_bar);
+        layout.push(CompletionBar::new());
         layout.push(input);
 
-        Self {
+        Ok(Self {
             screen,
+            lines,
+            headless: display == DisplayMode::Headless,
             root: layout,
             dimension: None,
             windows: Vec::new(),
             unread_windows: LinkedHashSet::new(),
+            unread_counts: HashMap::new(),
+            newest: HashMap::new(),
+            last_read: HashMap::new(),
             current_window: None,
             conversations: HashMap::new(),
             password_command: None,
-            outgoing_event_queue: Rc::new(RefCell::new(Vec::new())),
+            ctrl_x: false,
+            yanked: false,
+            copy_mode: false,
+            replies: HashMap::new(),
+            last_sent: HashMap::new(),
+            corrections: HashSet::new(),
+            room_directory: None,
+            notify_config: NotifyConfig::default(),
+            highlights: Highlights::default(),
+            scrollback_config: ScrollbackConfig::default(),
+            side_width: None,
+            prompts: HashMap::new(),
+            do_not_disturb: false,
+            muted: HashMap::new(),
+            highlighted_windows: HashSet::new(),
+            detached: HashSet::new(),
+            terminal_title: None,
+            outgoing_event_queue,
             panic_handler,
-        }
+        })
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.headless
     }
 
-    pub fn event_stream(&self) -> EventStream {
-        EventStream::new()
+    /// Events read from the terminal, None when headless
+    pub fn event_stream(&self) -> Option<EventStream> {
+        match self.headless {
+            true => None,
+            false => Some(EventStream::new()),
+        }
     }
 
     fn get_scheduler(&self) -> Scheduler {
@@ -780,8 +2135,14 @@ impl UIMod {
         match &conversation {
             Conversation::Chat(chat) => {
                 let chat_for_event = chat.clone();
-                let chatwin = BufferedWin::<UIEvent, Stdout, Message>::new().with_event(
-                    move |view, event| {
+                let scrollback = self.scrollback_config.get(&chat.contact.to_string());
+                let chatwin = BufferedWin::<UIEvent, Stdout, Message>::new()
+                    .with_indent(message_indent)
+                    .with_separator(date_separator)
+                    .with_format(format_message)
+                    .with_graphic(message_preview)
+                    .with_scrollback(scrollback.messages, scrollback.bytes)
+                    .with_event(move |view, event| {
                         match event {
                             UIEvent::Core(Event::Message(_, Message::Xmpp(message))) => {
                                 match message.direction {
@@ -800,7 +2161,8 @@ impl UIMod {
                                 }
                             }
                             UIEvent::Core(Event::Key(Key::PageUp)) => {
-                                if view.page_up() {
+                                // Older messages would be dropped as soon as loaded
+                                if view.page_up() && !view.is_trimmed() {
                                     let from = view.first().map(|message| message.timestamp());
                                     scheduler.schedule(Event::LoadChatHistory {
                                         account: chat_for_event.account.clone(),
@@ -812,10 +2174,33 @@ impl UIMod {
                             UIEvent::Core(Event::Key(Key::PageDown)) => {
                                 view.page_down();
                             }
+                            UIEvent::Core(Event::Preview { .. })
+                            | UIEvent::Core(Event::Pending { .. })
+                            | UIEvent::Core(Event::MessageError { .. }) => view.dirty = true,
+                            UIEvent::Search(term, found) => {
+                                *found.borrow_mut() = Some(view.search(term));
+                            }
+                            UIEvent::Core(Event::Search(None)) => view.clear_search(),
+                            UIEvent::CopyMode(action) => copy_mode(view, action),
+                            UIEvent::Core(Event::Key(Key::Ctrl('r'))) => {
+                                view.search_next();
+                            }
+                            UIEvent::Core(Event::Key(Key::Ctrl('s'))) => {
+                                view.search_previous();
+                            }
+                            UIEvent::Core(Event::ClearBuffer(window)) => {
+                                if *window == chat_for_event.contact.to_string() {
+                                    view.clear();
+                                }
+                            }
+                            UIEvent::Core(Event::Info(window, message)) => {
+                                if *window == chat_for_event.contact.to_string() {
+                                    view.insert(message.clone());
+                                }
+                            }
                             _ => {}
                         }
-                    },
-                );
+                    });
 
                 self.add_window(chat.contact.to_string(), Box::new(chatwin));
                 self.conversations
@@ -830,8 +2215,14 @@ impl UIMod {
                     });
 
                 let channel_for_event = channel.clone();
-                let chanwin = BufferedWin::<UIEvent, Stdout, Message>::new().with_event(
-                    move |view, event| {
+                let scrollback = self.scrollback_config.get(&channel.jid.to_string());
+                let chanwin = BufferedWin::<UIEvent, Stdout, Message>::new()
+                    .with_indent(message_indent)
+                    .with_separator(date_separator)
+                    .with_format(format_message)
+                    .with_graphic(message_preview)
+                    .with_scrollback(scrollback.messages, scrollback.bytes)
+                    .with_event(move |view, event| {
                         match event {
                             UIEvent::Core(Event::Message(_, Message::Xmpp(message))) => {
                                 match message.direction {
@@ -850,7 +2241,8 @@ impl UIMod {
                                 }
                             }
                             UIEvent::Core(Event::Key(Key::PageUp)) => {
-                                if view.page_up() {
+                                // Older messages would be dropped as soon as loaded
+                                if view.page_up() && !view.is_trimmed() {
                                     let from = view.first().map(|message| message.timestamp());
                                     scheduler.schedule(Event::LoadChannelHistory {
                                         account: channel_for_event.account.clone(),
@@ -862,17 +2254,42 @@ impl UIMod {
                             UIEvent::Core(Event::Key(Key::PageDown)) => {
                                 view.page_down();
                             }
+                            UIEvent::Core(Event::Preview { .. })
+                            | UIEvent::Core(Event::Pending { .. })
+                            | UIEvent::Core(Event::MessageError { .. }) => view.dirty = true,
+                            UIEvent::Search(term, found) => {
+                                *found.borrow_mut() = Some(view.search(term));
+                            }
+                            UIEvent::Core(Event::Search(None)) => view.clear_search(),
+                            UIEvent::CopyMode(action) => copy_mode(view, action),
+                            UIEvent::Core(Event::Key(Key::Ctrl('r'))) => {
+                                view.search_next();
+                            }
+                            UIEvent::Core(Event::Key(Key::Ctrl('s'))) => {
+                                view.search_previous();
+                            }
+                            UIEvent::Core(Event::ClearBuffer(window)) => {
+                                if *window == channel_for_event.jid.to_string() {
+                                    view.clear();
+                                }
+                            }
+                            UIEvent::Core(Event::Info(window, message)) => {
+                                if *window == channel_for_event.jid.to_string() {
+                                    view.insert(message.clone());
+                                }
+                            }
                             _ => {}
                         }
-                    },
-                );
+                    });
                 layout.push(chanwin);
 
                 let roster_jid = channel.jid.clone();
+                let roster_account = channel.account.clone();
+                let roster_scheduler = self.get_scheduler();
                 let roster =
                     ListView::<UIEvent, Stdout, conversation::Role, conversation::Occupant>::new()
                         .with_layouts(Layouts {
-                            width: Layout::wrap_content(),
+                            width: Layout::wrap_content().with_relative_max(0.3),
                             height: Layout::match_parent(),
                         })
                         .with_none_group()
@@ -885,29 +2302,692 @@ impl UIMod {
                                 ..
                             }) => {
                                 if roster_jid == *conversation {
-                                    view.insert(occupant.clone(), Some(occupant.role));
+                                    match occupant.presence {
+                                        contact::Presence::Unavailable => {
+                                            // The role of a leaving occupant is none
+                                            for role in &[
+                                                conversation::Role::Moderator,
+                                                conversation::Role::Participant,
+                                                conversation::Role::Visitor,
+                                                conversation::Role::None,
+                                            ] {
+                                                let _ = view.remove(occupant.clone(), Some(*role));
+                                            }
+                                        }
+                                        _ => view.insert(occupant.clone(), Some(occupant.role)),
+                                    }
+                                }
+                            }
+                            UIEvent::Core(Event::ToggleRoster) => view.toggle_visibility(),
+                            UIEvent::SidePaneWidth(result) => {
+                                *result.borrow_mut() = view.width();
+                            }
+                            UIEvent::ResizeSidePane(width) => view.set_width(*width),
+                            UIEvent::Core(Event::Mouse(MouseEvent::Press(
+                                MouseButton::Left,
+                                x,
+                                y,
+                            ))) => {
+                                let jid = view
+                                    .item_at(*x, *y)
+                                    .and_then(|occupant| occupant.jid.clone());
+                                if let Some(jid) = jid {
+                                    roster_scheduler.schedule(Event::Chat {
+                                        account: roster_account.clone(),
+                                        contact: jid,
+                                    });
                                 }
                             }
                             _ => {}
                         });
                 layout.push(roster);
 
-                self.add_window(channel.get_name(), Box::new(layout));
-                self.conversations
-                    .insert(channel.get_name(), conversation.clone());
-            }
+                self.add_window(channel.get_name(), Box::new(layout));
+                self.conversations
+                    .insert(channel.get_name(), conversation.clone());
+            }
+        }
+    }
+
+    fn add_window(&mut self, name: String, window: Box<dyn View<UIEvent, Stdout>>) {
+        self.windows.push(name.clone());
+        self.root.event(&mut UIEvent::AddWindow(name, Some(window)));
+        if let Some(side_width) = self.side_width {
+            let (width, _) = terminal_size();
+            self.root
+                .event(&mut UIEvent::ResizeSidePane(side_width.columns(width)));
+        }
+    }
+
+    /// Temporary window listing messages, like search results, until it is closed
+    fn add_results(&mut self, name: &str) {
+        let window = name.to_string();
+        let results = BufferedWin::<UIEvent, Stdout, Message>::new()
+            .with_indent(message_indent)
+            .with_separator(date_separator)
+            .with_event(move |view, event| match event {
+                UIEvent::Core(Event::Results(name, messages)) if *name == window => {
+                    view.clear();
+                    for message in messages {
+                        view.insert(message.clone());
+                    }
+                }
+                UIEvent::Core(Event::Info(name, message)) if *name == window => {
+                    view.insert(message.clone());
+                }
+                UIEvent::Core(Event::Key(Key::PageUp)) => {
+                    view.page_up();
+                }
+                UIEvent::Core(Event::Key(Key::PageDown)) => {
+                    view.page_down();
+                }
+                UIEvent::Search(term, found) => {
+                    *found.borrow_mut() = Some(view.search(term));
+                }
+                UIEvent::Core(Event::Search(None)) => view.clear_search(),
+                UIEvent::CopyMode(action) => copy_mode(view, action),
+                UIEvent::Core(Event::Key(Key::Ctrl('r'))) => {
+                    view.search_next();
+                }
+                UIEvent::Core(Event::Key(Key::Ctrl('s'))) => {
+                    view.search_previous();
+                }
+                _ => {}
+            });
+        self.add_window(name.to_string(), Box::new(results));
+    }
+
+    /// Window listing the rooms of a conference service, see /rooms
+    fn add_room_directory(&mut self) {
+        let directory = ListView::<UIEvent, Stdout, contact::Group, Room>::new()
+            .with_none_group()
+            .with_sort_item_by(|a, b| {
+                b.occupants
+                    .cmp(&a.occupants)
+                    .then_with(|| a.jid.to_string().cmp(&b.jid.to_string()))
+            })
+            .with_event(|view, event| match event {
+                UIEvent::Core(Event::RoomDirectory { .. }) => view.clear(),
+                UIEvent::Core(Event::DirectoryRoom(_, room)) => view.insert(room.clone(), None),
+                UIEvent::MoveSelection(delta) => view.move_selection(*delta),
+                UIEvent::SelectedRoom(result) => {
+                    *result.borrow_mut() = view.selection().cloned();
+                }
+                _ => {}
+            });
+        self.add_window(ROOMS_WINDOW.to_string(), Box::new(directory));
+    }
+
+    /// Join the room selected in the room directory
+    fn join_selected_room(&mut self, aparte: &mut Aparte) {
+        let result = Rc::new(RefCell::new(None));
+        self.root
+            .event(&mut UIEvent::SelectedRoom(Rc::clone(&result)));
+        let room = result.borrow_mut().take();
+        match room {
+            Some(room) => aparte.schedule(Event::RawCommand(
+                self.room_directory.clone(),
+                "console".to_string(),
+                format!("/join {}", room.jid),
+            )),
+            None => aparte.log("No room selected, select one with Up and Down".to_string()),
+        }
+    }
+
+    /// Grow or shrink side lists by the given number of columns
+    fn resize_side_pane(&mut self, delta: i32) {
+        let (width, _) = terminal_size();
+        let current = match self.side_width {
+            Some(side_width) => Some(side_width.columns(width)),
+            None => {
+                let result = Rc::new(RefCell::new(None));
+                self.root
+                    .event(&mut UIEvent::SidePaneWidth(Rc::clone(&result)));
+                let current = result.borrow_mut().take();
+                current
+            }
+        };
+
+        // The current window has no visible side list
+        let current = match current {
+            Some(current) => current,
+            None => return,
+        };
+
+        let columns = cmp::max(
+            1,
+            cmp::min(i32::from(width) - 1, i32::from(current) + delta),
+        );
+        self.side_width = Some(SideWidth::Absolute(columns as u16));
+        self.redraw();
+    }
+
+    /// Render what changed during a batch of events, the terminal is written to once
+    pub fn render(&mut self) {
+        if self.lines.is_some() {
+            self.show_input();
+        } else if self.root.is_layout_dirty() {
+            self.redraw();
+        } else if self.root.is_dirty() && !self.headless {
+            let dimension: &Dimension = self.dimension.as_ref().unwrap();
+            self.root.render(dimension, &mut self.screen);
+        }
+        flush!(self.screen);
+    }
+
+    /// Draw the whole screen from scratch
+    fn redraw(&mut self) {
+        if self.headless {
+            return;
+        }
+        let (width, height) = terminal_size();
+        self.screen.resize(width, height);
+        if let Some(side_width) = self.side_width {
+            self.root
+                .event(&mut UIEvent::ResizeSidePane(side_width.columns(width)));
+        }
+        let mut dimension = Dimension::new();
+        self.root.measure(&mut dimension, Some(width), Some(height));
+        self.root.layout(&mut dimension, 1, 1);
+        match &mut self.lines {
+            Some(lines) => {
+                lines.width = width as usize;
+                lines.input = None;
+            }
+            None => self.root.render(&dimension, &mut self.screen),
+        }
+        self.dimension = Some(dimension);
+    }
+
+    /// Print the input line again in line modes
+    fn show_input(&mut self) {
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let (buf, cursor, password) = result.borrow_mut().take().unwrap();
+        if let Some(lines) = &mut self.lines {
+            lines.show_input(&mut self.screen, &buf, cursor.get(), password);
+        }
+    }
+
+    /// Print a message in line modes if its window is the current one, keep it otherwise. Logs
+    /// are always printed.
+    fn print_line(&mut self, window: &str, message: &Message) {
+        let current = self.current_window.as_deref() == Some(window);
+        if let Some(lines) = &mut self.lines {
+            match current || window == "console" {
+                true => lines.print(&mut self.screen, &message.to_string()),
+                false => lines.keep(window, message),
+            }
+        }
+    }
+
+    /// Clear the terminal and draw everything again, like after a resize
+    fn repaint(&mut self) {
+        vprint!(self.screen, "{}", termion::clear::All);
+        if self.lines.is_some() {
+            vprint!(self.screen, "{}", termion::cursor::Goto(1, 1));
+        }
+        self.redraw();
+    }
+
+    /// Hand the terminal over to another program
+    fn suspend(&mut self) {
+        INPUT_SUSPENDED.store(true, Ordering::SeqCst);
+        vprint!(
+            self.screen,
+            "{}{}{}{}",
+            PASTE_DISABLE,
+            MOUSE_DISABLE,
+            termion::screen::ToMainScreen,
+            termion::cursor::Show
+        );
+        flush!(self.screen);
+        let _ = self.screen.suspend_raw_mode();
+    }
+
+    fn resume(&mut self) {
+        let _ = self.screen.activate_raw_mode();
+        match self.lines {
+            Some(_) => vprint!(self.screen, "{}", PASTE_ENABLE),
+            None => vprint!(
+                self.screen,
+                "{}{}{}{}",
+                termion::screen::ToAlternateScreen,
+                MOUSE_ENABLE,
+                PASTE_ENABLE,
+                termion::clear::All
+            ),
+        }
+        flush!(self.screen);
+        INPUT_SUSPENDED.store(false, Ordering::SeqCst);
+        self.redraw();
+    }
+
+    /// Copy with OSC 52 over SSH or when no clipboard tool is installed
+    fn copy_to_clipboard(&mut self, text: &str) -> Result<(), String> {
+        if !over_ssh() {
+            if let Some(result) = copy_with_tool(text) {
+                return result;
+            }
+        }
+
+        vprint!(self.screen, "{}", osc52(text));
+        Ok(())
+    }
+
+    fn copy_input(&mut self, aparte: &mut Aparte) {
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let (raw_buf, _, password) = result.borrow_mut().take().unwrap();
+        if password || raw_buf.is_empty() {
+            aparte.log("Nothing to copy".to_string());
+            return;
+        }
+
+        match self.copy_to_clipboard(&raw_buf) {
+            Ok(()) => aparte.log(format!("Copied {} chars", raw_buf.chars().count())),
+            Err(err) => aparte.log(err),
+        }
+    }
+
+    /// Edit the input in $VISUAL or $EDITOR, and send the result once saved
+    fn compose_in_editor(&mut self, aparte: &mut Aparte) {
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let (raw_buf, _, password) = result.borrow_mut().take().unwrap();
+        if password {
+            return;
+        }
+
+        let path = env::temp_dir().join(format!("aparte-{}.txt", Uuid::new_v4()));
+        if let Err(err) = fs::write(&path, &raw_buf) {
+            aparte.log(format!("Cannot create {}: {}", path.display(), err));
+            return;
+        }
+
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or("vi".to_string());
+        let mut args = editor.split_whitespace();
+        let program = args.next().unwrap_or("vi");
+
+        self.suspend();
+        let status = ProcessCommand::new(program).args(args).arg(&path).status();
+        self.resume();
+
+        let content = match status {
+            Ok(status) if status.success() => fs::read_to_string(&path)
+                .map_err(|err| format!("Cannot read {}: {}", path.display(), err)),
+            Ok(status) => Err(format!("{} exited with {}", editor, status)),
+            Err(err) => Err(format!("Cannot run {}: {}", editor, err)),
+        };
+        let _ = fs::remove_file(&path);
+
+        match content {
+            // An empty file aborts the message, like git does
+            Ok(content) if content.trim().is_empty() => {}
+            Ok(content) => {
+                // Editors usually end files with a newline
+                let content = content.trim_end_matches('\n').to_string();
+                let cursor = Cursor::new(content.graphemes(true).count());
+                self.root
+                    .event(&mut UIEvent::Core(Event::Completed(content, cursor)));
+                aparte.schedule(Event::Key(Key::Char('\n')));
+            }
+            Err(err) => aparte.log(err),
+        }
+    }
+
+    fn copy_mode_key(&mut self, aparte: &mut Aparte, key: &Key) {
+        let yank = |item| {
+            let result = Rc::new(RefCell::new(None));
+            let action = match item {
+                true => CopyModeAction::YankItem(Rc::clone(&result)),
+                false => CopyModeAction::Yank(Rc::clone(&result)),
+            };
+            (result, action)
+        };
+
+        let (result, action) = match key {
+            Key::Up | Key::Char('k') => (None, CopyModeAction::Move(1)),
+            Key::Down | Key::Char('j') => (None, CopyModeAction::Move(-1)),
+            Key::PageUp => (None, CopyModeAction::PageMove(1)),
+            Key::PageDown => (None, CopyModeAction::PageMove(-1)),
+            Key::Char('v') | Key::Char(' ') => (None, CopyModeAction::Mark),
+            Key::Char('y') | Key::Char('\n') => {
+                let (result, action) = yank(false);
+                (Some(result), action)
+            }
+            Key::Char('Y') => {
+                let (result, action) = yank(true);
+                (Some(result), action)
+            }
+            Key::Esc | Key::Char('q') | Key::Ctrl('c') => (None, CopyModeAction::Stop),
+            Key::Char('r') => return self.reply_to_copy_item(aparte),
+            _ => return,
+        };
+
+        let stop = matches!(action, CopyModeAction::Stop);
+        self.root.event(&mut UIEvent::CopyMode(action));
+
+        if let Some(result) = result {
+            match result.borrow_mut().take() {
+                Some(text) => match self.copy_to_clipboard(&text) {
+                    Ok(()) => aparte.log(format!("Copied {} chars", text.chars().count())),
+                    Err(err) => aparte.log(err),
+                },
+                None => aparte.log("Nothing to copy".to_string()),
+            }
+            self.root
+                .event(&mut UIEvent::CopyMode(CopyModeAction::Stop));
+            self.copy_mode = false;
+        } else if stop {
+            self.copy_mode = false;
+        }
+    }
+
+    /// Load the last message sent in the current window into the empty input, to correct it
+    /// with the next message sent. Return whether it has been loaded.
+    fn correct_last_sent(&mut self) -> bool {
+        let window = match &self.current_window {
+            Some(window) if !self.corrections.contains(window) => window.clone(),
+            _ => return false,
+        };
+        let body = match self.last_sent.get(&window) {
+            Some(message) => message.get_last_body().to_string(),
+            None => return false,
+        };
+
+        let result = Rc::new(RefCell::new(None));
+        self.root.event(&mut UIEvent::GetInput(Rc::clone(&result)));
+        let (raw_buf, _, password) = result.borrow_mut().take().unwrap();
+        if password || !raw_buf.is_empty() {
+            return false;
+        }
+
+        let cursor = Cursor::new(body.graphemes(true).count());
+        self.root
+            .event(&mut UIEvent::Core(Event::Completed(body, cursor)));
+        self.corrections.insert(window);
+        self.update_prompt();
+        true
+    }
+
+    /// Reply with the next message sent from the current window to the one under the copy mode
+    /// cursor
+    fn reply_to_copy_item(&mut self, aparte: &mut Aparte) {
+        let result = Rc::new(RefCell::new(None));
+        self.root
+            .event(&mut UIEvent::CopyMode(CopyModeAction::Item(Rc::clone(
+                &result,
+            ))));
+        let item = result.borrow_mut().take();
+        match (item, self.current_window.clone()) {
+            (Some(Message::Xmpp(message)), Some(window))
+                if self.conversations.contains_key(&window) =>
+            {
+                let info = format!(
+                    "Replying to {}, Esc to cancel",
+                    xmpp_message_author(&message)
+                );
+                aparte.schedule(Event::Info(window.clone(), Message::log(info)));
+                self.replies.insert(window, Reply::new(&message));
+                self.update_prompt();
+            }
+            _ => aparte.log("Nothing to reply to".to_string()),
+        }
+        self.root
+            .event(&mut UIEvent::CopyMode(CopyModeAction::Stop));
+        self.copy_mode = false;
+    }
+
+    /// Messages addressed to us: chat messages and channel messages containing our nick or
+    /// matching the highlight rules of the channel
+    fn is_highlight(&self, window: &str, message: &VersionedXmppMessage) -> bool {
+        match self.conversations.get(window) {
+            Some(Conversation::Channel(channel)) => {
+                let body = message.get_last_body();
+                body.contains(&channel.nick) || self.highlights.matches(window, body)
+            }
+            _ => true,
+        }
+    }
+
+    fn is_muted(&self, window: &str) -> bool {
+        match self.muted.get(window) {
+            Some(Some(until)) => LocalTz::now() < *until,
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Unmute windows whose mute duration has passed
+    fn unmute_expired(&mut self, aparte: &mut Aparte) {
+        let now = LocalTz::now();
+        let expired: Vec<String> = self
+            .muted
+            .iter()
+            .filter(|(_, until)| until.map_or(false, |until| until <= now))
+            .map(|(window, _)| window.clone())
+            .collect();
+        for window in expired {
+            self.muted.remove(&window);
+            aparte.log(format!("{} unmuted", window));
+        }
+    }
+
+    /// Mark the window of an incoming message and notify it according to its level
+    fn notify(&mut self, aparte: &mut Aparte, window: &str, message: &VersionedXmppMessage) {
+        if self.is_muted(window) {
+            return;
+        }
+        let level = self.notify_config.get(window);
+        let highlight = self.is_highlight(window, message);
+
+        if level != NotifyLevel::None
+            && self.windows.iter().any(|win| win == window)
+            && Some(window) != self.current_window.as_deref()
+        {
+            let unread = self.unread_windows.insert(window.to_string());
+            *self.unread_counts.entry(window.to_string()).or_insert(0) += 1;
+            let highlighted = highlight && self.highlighted_windows.insert(window.to_string());
+            if let Some(lines) = &mut self.lines {
+                if highlighted {
+                    let announce = format!("Message addressed to you in {}", window_label(window));
+                    lines.print(&mut self.screen, &announce);
+                } else if unread {
+                    let announce = format!("New messages in {}", window_label(window));
+                    lines.print(&mut self.screen, &announce);
+                }
+            }
+            self.root
+                .event(&mut UIEvent::Activity(window.to_string(), highlight));
+            self.update_terminal_title();
+        }
+
+        let ring = match level {
+            NotifyLevel::All => true,
+            NotifyLevel::Highlight => highlight,
+            NotifyLevel::Activity | NotifyLevel::None => false,
+        };
+        if !ring || self.do_not_disturb {
+            return;
+        }
+        aparte.schedule(Event::Notification(String::from("")));
+
+        if self.notify_config.desktop && highlight && Some(window) != self.current_window.as_deref()
+        {
+            let summary = match (&message.type_, &message.from_full) {
+                (XmppMessageType::Channel, Jid::Full(from)) => {
+                    format!("{} in {}", from.resource, window_label(window))
+                }
+                _ => window_label(window),
+            };
+            let body = match self.notify_config.preview {
+                true => message.get_last_body().to_string(),
+                false => String::from("New message"),
+            };
+            if let Err(err) = desktop_notification(&summary, &body) {
+                warn!("{}", err);
+            }
+        }
+    }
+
+    pub fn change_window(&mut self, window: &str) {
+        if self.current_window.as_deref() != Some(window) {
+            if let Some(lines) = &mut self.lines {
+                lines.show_window(&mut self.screen, window);
+            }
+        }
+        self.root
+            .event(&mut UIEvent::Core(Event::ChangeWindow(window.to_string())));
+        self.current_window = Some(window.to_string());
+        self.unread_windows.remove(window);
+        self.unread_counts.remove(window);
+        self.highlighted_windows.remove(window);
+        if let Some((id, _)) = self.newest.get(window) {
+            self.last_read.insert(window.to_string(), id.clone());
+        }
+        self.update_terminal_title();
+        self.update_prompt();
+    }
+
+    /// Send text to the conversation of a window, as if typed in its input
+    fn send_text(&mut self, aparte: &mut Aparte, window: &str, text: &str) {
+        let conversation = match self.conversations.get(window) {
+            Some(conversation) => conversation,
+            None => return,
+        };
+        let reply = self.replies.remove(window);
+        if self.corrections.remove(window) {
+            if let Some(last) = self.last_sent.get(window) {
+                let mut bodies = HashMap::new();
+                bodies.insert("".to_string(), text.to_string());
+                let mut message = last.clone();
+                message.history.push(XmppMessageVersion {
+                    id: Uuid::new_v4().to_string(),
+                    timestamp: LocalTz::now().into(),
+                    bodies,
+                });
+                let account = match conversation {
+                    Conversation::Chat(chat) => &chat.account,
+                    Conversation::Channel(channel) => &channel.account,
+                };
+                aparte.schedule(Event::SendMessage(account.clone(), Message::Xmpp(message)));
+                self.update_prompt();
+                return;
+            }
+        }
+
+        match conversation {
+            Conversation::Chat(chat) => {
+                let account = &chat.account;
+                let us = account.clone().into();
+                let from: Jid = us;
+                let to: Jid = chat.contact.clone().into();
+                let id = Uuid::new_v4();
+                let timestamp = LocalTz::now().into();
+                let mut bodies = HashMap::new();
+                bodies.insert("".to_string(), text.to_string());
+                let mut message =
+                    Message::outgoing_chat(id.to_string(), timestamp, &from, &to, &bodies);
+                if let Message::Xmpp(message) = &mut message {
+                    message.reply = reply;
+                }
+                aparte.schedule(Event::SendMessage(account.clone(), message));
+            }
+            Conversation::Channel(channel) if self.detached.contains(window) => {
+                aparte.log(format!("Not in {}, use /join to come back", channel.jid));
+            }
+            Conversation::Channel(channel) => {
+                let account = &channel.account;
+                let mut us = account.clone();
+                us.resource = channel.nick.clone();
+                let from: Jid = us.into();
+                let to: Jid = channel.jid.clone().into();
+                let id = Uuid::new_v4();
+                let timestamp = LocalTz::now().into();
+                let mut bodies = HashMap::new();
+                bodies.insert("".to_string(), text.to_string());
+                let mut message =
+                    Message::outgoing_channel(id.to_string(), timestamp, &from, &to, &bodies);
+                if let Message::Xmpp(message) = &mut message {
+                    message.reply = reply;
+                }
+                aparte.schedule(Event::SendMessage(account.clone(), message));
+            }
+        }
+        self.update_prompt();
+    }
+
+    /// Show the prompt of the current window type in front of the input
+    fn update_prompt(&mut self) {
+        let window = match &self.current_window {
+            Some(window) => window,
+            None => return,
+        };
+
+        let (kind, account, nick) = match self.conversations.get(window) {
+            Some(Conversation::Chat(chat)) => {
+                ("chat", Some(&chat.account), chat.account.node.clone())
+            }
+            Some(Conversation::Channel(channel)) => (
+                "channel",
+                Some(&channel.account),
+                Some(channel.nick.clone()),
+            ),
+            None => ("console", None, None),
+        };
+
+        let mut prompt = match self.prompts.get(kind) {
+            Some(components) => components
+                .iter()
+                .map(|component| match component {
+                    PromptComponent::Text(text) => text.clone(),
+                    PromptComponent::Window => window_label(window),
+                    PromptComponent::Account => account
+                        .map(|account| account.to_string())
+                        .unwrap_or_default(),
+                    PromptComponent::Nick => nick.clone().unwrap_or_default(),
+                })
+                .collect::<String>(),
+            // Line modes need a prompt to tell where the input is
+            None if self.lines.is_some() => format!("{}> ", window_label(window)),
+            None => String::new(),
+        };
+        if self.replies.contains_key(window) {
+            prompt.insert_str(0, "↪ ");
+        }
+        if self.corrections.contains(window) {
+            prompt.insert_str(0, "✎ ");
+        }
+
+        if let Some(lines) = &mut self.lines {
+            lines.prompt = terminus::clean(&prompt);
         }
+        self.root
+            .event(&mut UIEvent::Prompt(terminus::clean(&prompt)));
     }
 
-    fn add_window(&mut self, name: String, window: Box<dyn View<UIEvent, Stdout>>) {
-        self.windows.push(name.clone());
-        self.root.event(&mut UIEvent::AddWindow(name, Some(window)));
-    }
+    /// Show the current window and the number of unread windows in the terminal title
+    fn update_terminal_title(&mut self) {
+        let mut title = match &self.current_window {
+            Some(window) => format!("aparte - {}", window_label(window)),
+            None => String::from("aparte"),
+        };
+        if !self.unread_windows.is_empty() {
+            let mark = match self.highlighted_windows.is_empty() {
+                true => "",
+                false => "!",
+            };
+            title.push_str(&format!(" (+{}{})", self.unread_windows.len(), mark));
+        }
+        let title = terminus::clean(&title).replace(|c: char| c.is_control(), "");
 
-    pub fn change_window(&mut self, window: &str) {
-        self.root
-            .event(&mut UIEvent::Core(Event::ChangeWindow(window.to_string())));
-        self.current_window = Some(window.to_string());
+        if self.terminal_title.as_ref() != Some(&title) {
+            vprint!(self.screen, "\x1b]2;{}\x07", title);
+            self.terminal_title = Some(title);
+        }
     }
 
     #[allow(unused)] // XXX Should be used when alt+arrow is fixed see https://gitlab.redox-os.org/redox-os/termion/-/issues/183
@@ -934,6 +3014,43 @@ impl UIMod {
         }
     }
 
+    /// Window numbered `number`, starting at 1
+    fn get_window(&self, number: usize) -> Option<String> {
+        match number {
+            0 => None,
+            number => self.windows.get(number - 1).cloned(),
+        }
+    }
+
+    fn move_window(&mut self, position: usize) -> Result<(), String> {
+        let current = match &self.current_window {
+            Some(current) => current.clone(),
+            None => return Err("No current window".to_string()),
+        };
+        if position == 0 || position > self.windows.len() {
+            return Err(format!("Invalid window number {}", position));
+        }
+
+        self.windows.retain(|win| win != &current);
+        self.windows.insert(position - 1, current);
+        self.root
+            .event(&mut UIEvent::WindowsOrder(self.windows.clone()));
+        Ok(())
+    }
+
+    fn swap_windows(&mut self, first: usize, second: usize) -> Result<(), String> {
+        for number in &[first, second] {
+            if *number == 0 || *number > self.windows.len() {
+                return Err(format!("Invalid window number {}", number));
+            }
+        }
+
+        self.windows.swap(first - 1, second - 1);
+        self.root
+            .event(&mut UIEvent::WindowsOrder(self.windows.clone()));
+        Ok(())
+    }
+
     pub fn get_windows(&self) -> Vec<String> {
         self.windows.clone()
     }
@@ -944,15 +3061,91 @@ impl UIMod {
 }
 
 impl ModTrait for UIMod {
-    fn init(&mut self, _aparte: &mut Aparte) -> Result<(), ()> {
-        vprint!(&mut self.screen, "{}", termion::clear::All);
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        let (theme, errors) = Theme::from_config(&aparte.config.theme);
+        for error in errors {
+            aparte.log(error);
+        }
+        theme::set(theme);
 
-        let (width, height) = termion::terminal_size().unwrap();
-        let mut dimension = Dimension::new();
-        self.root.measure(&mut dimension, Some(width), Some(height));
-        self.root.layout(&mut dimension, 1, 1);
-        self.root.render(&dimension, &mut self.screen);
-        self.dimension = Some(dimension);
+        if let Some(time_format) = &aparte.config.time_format {
+            if let Err(error) = set_time_format(time_format) {
+                aparte.log(error);
+            }
+        }
+
+        self.notify_config = aparte.config.notify.clone();
+        match aparte.config.highlight.compile() {
+            Ok(highlights) => self.highlights = highlights,
+            Err(err) => aparte.log(err),
+        }
+        self.scrollback_config = aparte.config.scrollback.clone();
+
+        let prompts = aparte.config.prompt.clone();
+        for (kind, format) in vec![
+            ("console", prompts.console),
+            ("chat", prompts.chat),
+            ("channel", prompts.channel),
+        ] {
+            if let Some(format) = format {
+                match parse_prompt_format(&format) {
+                    Ok(components) => {
+                        self.prompts.insert(kind.to_string(), components);
+                    }
+                    Err(error) => aparte.log(error),
+                }
+            }
+        }
+
+        if let Some(width) = &aparte.config.roster.width {
+            match width.parse() {
+                Ok(width) => self.side_width = Some(width),
+                Err(error) => aparte.log(error),
+            }
+        }
+
+        if let Some(status_format) = &aparte.config.status_format {
+            if let Err(error) = set_status_format(status_format) {
+                aparte.log(error);
+            }
+        }
+
+        let time_format = TIME_FORMAT.with(|format| format.borrow().clone());
+        aparte.settings.register_validated(
+            "time_format",
+            toml::Value::String(time_format),
+            "strftime format of message timestamps",
+            |value| check_time_format(value.as_str().unwrap_or("")),
+        );
+        let status_format = aparte
+            .config
+            .status_format
+            .clone()
+            .unwrap_or(DEFAULT_STATUS_FORMAT.to_string());
+        aparte.settings.register_validated(
+            "status_format",
+            toml::Value::String(status_format),
+            "Components of the status bar",
+            |value| parse_status_format(value.as_str().unwrap_or("")).map(|_| ()),
+        );
+        aparte.settings.register(
+            "notify.desktop",
+            toml::Value::Boolean(self.notify_config.desktop),
+            "Show desktop notifications for messages addressed to you",
+        );
+        aparte.settings.register(
+            "notify.preview",
+            toml::Value::Boolean(self.notify_config.preview),
+            "Include the message in desktop notifications",
+        );
+
+        // Save the terminal title to restore it on exit
+        vprint!(&mut self.screen, "\x1b[22;0t");
+        vprint!(&mut self.screen, "{}", PASTE_ENABLE);
+        if self.lines.is_none() {
+            vprint!(&mut self.screen, "{}", termion::clear::All);
+        }
+        self.redraw();
 
         let mut console = LinearLayout::<UIEvent, Stdout>::new(Orientation::Horizontal).with_event(
             |layout, event| {
@@ -961,42 +3154,84 @@ impl ModTrait for UIMod {
                 }
             },
         );
+        let scrollback = self.scrollback_config.get("console");
         console.push(
-            BufferedWin::<UIEvent, Stdout, Message>::new().with_event(|view, event| match event {
-                UIEvent::Core(Event::Message(_, Message::Log(message))) => {
-                    view.insert(Message::Log(message.clone()));
-                }
-                UIEvent::Core(Event::Key(Key::PageUp)) => {
-                    view.page_up();
-                }
-                UIEvent::Core(Event::Key(Key::PageDown)) => {
-                    view.page_down();
-                }
-                _ => {}
-            }),
+            BufferedWin::<UIEvent, Stdout, Message>::new()
+                .with_indent(message_indent)
+                .with_separator(date_separator)
+                .with_scrollback(scrollback.messages, scrollback.bytes)
+                .with_event(|view, event| match event {
+                    UIEvent::Core(Event::Message(_, Message::Log(message))) => {
+                        view.insert(Message::Log(message.clone()));
+                    }
+                    UIEvent::Core(Event::Key(Key::PageUp)) => {
+                        view.page_up();
+                    }
+                    UIEvent::Core(Event::Key(Key::PageDown)) => {
+                        view.page_down();
+                    }
+                    UIEvent::Search(term, found) => {
+                        *found.borrow_mut() = Some(view.search(term));
+                    }
+                    UIEvent::Core(Event::Search(None)) => view.clear_search(),
+                    UIEvent::CopyMode(action) => copy_mode(view, action),
+                    UIEvent::Core(Event::Key(Key::Ctrl('r'))) => {
+                        view.search_next();
+                    }
+                    UIEvent::Core(Event::Key(Key::Ctrl('s'))) => {
+                        view.search_previous();
+                    }
+                    UIEvent::Core(Event::ClearBuffer(window)) if *window == "console" => {
+                        view.clear()
+                    }
+                    UIEvent::Core(Event::Info(window, message)) if *window == "console" => {
+                        view.insert(message.clone())
+                    }
+                    _ => {}
+                }),
         );
+        let scheduler = self.get_scheduler();
+        let offline_contacts = aparte.config.roster.offline;
         let roster = ListView::<UIEvent, Stdout, contact::Group, RosterItem>::new()
             .with_layouts(Layouts {
                 width: Layout::wrap_content().with_relative_max(0.3),
                 height: Layout::match_parent(),
             })
             .with_none_group()
-            .with_sort_item()
-            .with_event(|view, event| match event {
+            .with_sort_item_by(RosterItem::compare)
+            .with_event(move |view, event| match event {
                 UIEvent::Core(Event::Connected(_, _)) => {
                     view.add_group(contact::Group(String::from("Windows")));
                     view.add_group(contact::Group(String::from("Contacts")));
                     view.add_group(contact::Group(String::from("Bookmarks")));
+                    if offline_contacts == OfflineContacts::Group {
+                        view.add_group(contact::Group(String::from("Offline")));
+                    }
                 }
                 UIEvent::Core(Event::Contact(_, contact))
                 | UIEvent::Core(Event::ContactUpdate(_, contact)) => {
-                    if contact.groups.len() > 0 {
-                        for group in &contact.groups {
-                            view.insert(RosterItem::Contact(contact.clone()), Some(group.clone()));
+                    let item = RosterItem::Contact(contact.clone());
+                    let offline_group = contact::Group(String::from("Offline"));
+                    let groups = match contact.groups.len() {
+                        0 => vec![contact::Group(String::from("Contacts"))],
+                        _ => contact.groups.clone(),
+                    };
+
+                    // The contact may move between its groups and the offline one
+                    for group in groups.iter().chain(std::iter::once(&offline_group)) {
+                        let _ = view.remove(item.clone(), Some(group.clone()));
+                    }
+
+                    match (&contact.presence, offline_contacts) {
+                        (contact::Presence::Unavailable, OfflineContacts::Hide) => {}
+                        (contact::Presence::Unavailable, OfflineContacts::Group) => {
+                            view.insert(item, Some(offline_group));
+                        }
+                        _ => {
+                            for group in groups {
+                                view.insert(item.clone(), Some(group));
+                            }
                         }
-                    } else {
-                        let group = contact::Group(String::from("Contacts"));
-                        view.insert(RosterItem::Contact(contact.clone()), Some(group));
                     }
                 }
                 UIEvent::Core(Event::Bookmark(bookmark)) => {
@@ -1023,6 +3258,38 @@ impl ModTrait for UIMod {
                     let group = contact::Group(String::from("Windows"));
                     let _ = view.remove(RosterItem::Window(window.clone()), Some(group));
                 }
+                UIEvent::Core(Event::ToggleRoster) => view.toggle_visibility(),
+                UIEvent::SidePaneWidth(result) => {
+                    *result.borrow_mut() = view.width();
+                }
+                UIEvent::ResizeSidePane(width) => view.set_width(*width),
+                UIEvent::Core(Event::RosterFilter(Some(text))) => {
+                    let text = text.clone();
+                    view.set_filter(move |item: &RosterItem| item.matches(&text));
+                }
+                UIEvent::Core(Event::RosterFilter(None)) => view.clear_filter(),
+                UIEvent::Core(Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y))) => {
+                    match view.item_at(*x, *y) {
+                        Some(RosterItem::Contact(contact)) => {
+                            scheduler.schedule(Event::RawCommand(
+                                None,
+                                "console".to_string(),
+                                format!("/msg {}", contact.jid),
+                            ));
+                        }
+                        Some(RosterItem::Bookmark(bookmark)) => {
+                            scheduler.schedule(Event::RawCommand(
+                                None,
+                                "console".to_string(),
+                                format!("/join {}", bookmark.jid),
+                            ));
+                        }
+                        Some(RosterItem::Window(window)) => {
+                            scheduler.schedule(Event::Win(window.clone()));
+                        }
+                        None => {}
+                    }
+                }
                 _ => {}
             });
         console.push(roster);
@@ -1033,8 +3300,15 @@ impl ModTrait for UIMod {
         Ok(())
     }
 
+    /// Display events once other mods had a chance to consume them
+    fn priority(&self) -> i32 {
+        -10
+    }
+
     fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
         match event {
+            Event::ReadPassword(_) if self.headless => aparte
+                .log("Cannot ask for a password when headless, set a password_command".to_string()),
             Event::ReadPassword(command) => {
                 self.password_command = Some(command.clone());
                 self.root
@@ -1053,31 +3327,45 @@ impl ModTrait for UIMod {
                             Direction::Incoming => message.from.to_string(),
                             Direction::Outgoing => message.to.to_string(),
                         };
+                        // Left channels don't get new messages
+                        if message.type_ == XmppMessageType::Channel
+                            && self.detached.contains(&window_name)
+                        {
+                            return;
+                        }
+                        register_urls(message);
 
                         if !self.conversations.contains_key(&window_name) {
+                            let account = match account {
+                                Some(account) => account.clone(),
+                                None => {
+                                    warn!("Ignore message {} without account", message.id);
+                                    return;
+                                }
+                            };
                             let conversation = match message.type_ {
                                 XmppMessageType::Chat => match message.direction {
                                     Direction::Incoming => Conversation::Chat(Chat {
-                                        account: account.clone().unwrap(),
+                                        account,
                                         contact: message.from.clone(),
                                     }),
                                     Direction::Outgoing => Conversation::Chat(Chat {
-                                        account: account.clone().unwrap(),
+                                        account,
                                         contact: message.to.clone(),
                                     }),
                                 },
                                 XmppMessageType::Channel => match message.direction {
                                     Direction::Incoming => Conversation::Channel(Channel {
-                                        account: account.clone().unwrap(),
+                                        nick: account.resource.clone(),
+                                        account,
                                         jid: message.from.clone(),
-                                        nick: account.as_ref().unwrap().resource.clone(),
                                         name: None,
                                         occupants: HashMap::new(),
                                     }),
                                     Direction::Outgoing => Conversation::Channel(Channel {
-                                        account: account.clone().unwrap(),
+                                        nick: account.resource.clone(),
+                                        account,
                                         jid: message.to.clone(),
-                                        nick: account.as_ref().unwrap().resource.clone(),
                                         name: None,
                                         occupants: HashMap::new(),
                                     }),
@@ -1088,18 +3376,34 @@ impl ModTrait for UIMod {
                         }
 
                         if message.direction == Direction::Incoming {
-                            let mut unread = None;
-                            for window in &self.windows {
-                                if &message.from.to_string() == window
-                                    && Some(window) != self.current_window.as_ref()
-                                {
-                                    unread = Some(window.clone());
-                                }
-                            }
-                            if unread.is_some() {
-                                self.unread_windows.insert(unread.unwrap());
+                            self.notify(aparte, &window_name, message);
+                        }
+
+                        let timestamp = message.get_original_timestamp();
+                        if self
+                            .newest
+                            .get(&window_name)
+                            .map_or(true, |(_, newest)| newest <= timestamp)
+                        {
+                            self.newest
+                                .insert(window_name.clone(), (message.id.clone(), *timestamp));
+                            if Some(&window_name) == self.current_window.as_ref() {
+                                self.last_read
+                                    .insert(window_name.clone(), message.id.clone());
                             }
-                            aparte.schedule(Event::Notification(String::from("")));
+                        }
+
+                        // Older messages are loaded when scrolling up, corrections keep their id
+                        let last = self.last_sent.get(&window_name);
+                        if message.direction == Direction::Outgoing
+                            && message.device.is_none()
+                            && last.map_or(true, |last| {
+                                last.id == message.id
+                                    || last.get_original_timestamp()
+                                        <= message.get_original_timestamp()
+                            })
+                        {
+                            self.last_sent.insert(window_name.clone(), message.clone());
                         }
                     }
                     Message::Log(_message) => {}
@@ -1109,6 +3413,48 @@ impl ModTrait for UIMod {
                     account.clone(),
                     message.clone(),
                 )));
+
+                if let (true, Message::Log(message)) = (self.headless, message) {
+                    info!("{}", terminus::clean(&message.body));
+                }
+                if self.lines.is_some() {
+                    let window = match message {
+                        Message::Xmpp(message) => match message.direction {
+                            Direction::Incoming => message.from.to_string(),
+                            Direction::Outgoing => message.to.to_string(),
+                        },
+                        Message::Log(_) => "console".to_string(),
+                    };
+                    self.print_line(&window, message);
+                }
+            }
+            Event::Info(window, message) => {
+                self.print_line(window, message);
+                self.root.event(&mut UIEvent::Core(event.clone()));
+            }
+            Event::ReadState(state) => {
+                // History has been loaded in the window, replace its unread state by the stored one
+                let window = state.conversation.to_string();
+                if !self.windows.contains(&window) || Some(&window) == self.current_window.as_ref()
+                {
+                    return;
+                }
+                if let Some(last_read) = &state.last_read {
+                    self.last_read.insert(window.clone(), last_read.clone());
+                }
+                self.unread_windows.remove(&window);
+                self.unread_counts.remove(&window);
+                self.highlighted_windows.remove(&window);
+                if state.unread > 0 {
+                    self.unread_windows.insert(window.clone());
+                    self.unread_counts.insert(window.clone(), state.unread);
+                    if state.highlighted {
+                        self.highlighted_windows.insert(window.clone());
+                    }
+                }
+                self.update_terminal_title();
+                self.root
+                    .event(&mut UIEvent::Core(Event::ReadState(state.clone())));
             }
             Event::Chat { account, contact } => {
                 // Should we store account association?
@@ -1143,29 +3489,170 @@ impl ModTrait for UIMod {
                         }),
                     );
                 }
+                if self.detached.remove(&win_name) {
+                    self.root.event(&mut UIEvent::Core(event.clone()));
+                }
                 if *user_request {
                     self.change_window(&win_name);
                 }
             }
+            Event::Leave(channel, _) => {
+                let window = channel.jid.to_string();
+                if self.windows.contains(&window) {
+                    self.detached.insert(window.clone());
+                    self.root.event(&mut UIEvent::Core(event.clone()));
+                    let left = format!("You left {}, use /join to come back", channel.jid);
+                    aparte.schedule(Event::Info(window, Message::log(left)));
+                }
+            }
             Event::Win(window) => {
+                let number = window.parse::<usize>().ok();
+                let aliased = self
+                    .windows
+                    .iter()
+                    .find(|win| &window_label(win) == window)
+                    .cloned();
                 if self.windows.contains(window) {
                     self.change_window(&window);
+                } else if let Some(window) = number.and_then(|number| self.get_window(number)) {
+                    self.change_window(&window);
+                } else if let Some(window) = aliased {
+                    self.change_window(&window);
                 } else {
                     aparte.log(format!("Unknown window {}", window));
                 }
             }
-            Event::WindowChange => {
-                let (width, height) = termion::terminal_size().unwrap();
-                let mut dimension = Dimension::new();
-                self.root.measure(&mut dimension, Some(width), Some(height));
-                self.root.layout(&mut dimension, 1, 1);
-                self.root.render(&dimension, &mut self.screen);
-                self.dimension = Some(dimension);
+            Event::Results(window, messages) => {
+                if !self.windows.contains(window) {
+                    self.add_results(window);
+                }
+                self.root.event(&mut UIEvent::Core(event.clone()));
+                for message in messages {
+                    self.print_line(window, message);
+                }
+                self.change_window(window);
+            }
+            Event::RoomDirectory { account, .. } => {
+                if !self.windows.iter().any(|window| window == ROOMS_WINDOW) {
+                    self.add_room_directory();
+                }
+                self.room_directory = Some(account.clone());
+                self.root.event(&mut UIEvent::Core(event.clone()));
+                self.change_window(ROOMS_WINDOW);
+            }
+            Event::DirectoryRoom(account, _) => {
+                // Rooms still coming for a directory replaced by another account's one
+                if self.room_directory.as_ref() == Some(account) {
+                    self.root.event(&mut UIEvent::Core(event.clone()));
+                }
+            }
+            Event::WindowAlias(window, alias) => {
+                set_window_alias(window, alias.as_deref());
+                self.update_terminal_title();
+                self.update_prompt();
+                self.root.event(&mut UIEvent::Core(event.clone()));
+            }
+            Event::Bookmark(bookmark) => {
+                // Bookmark names are the aliases of channel windows
+                let window = bookmark.jid.to_string();
+                match &bookmark.name {
+                    Some(name) if name != &window => set_window_alias(&window, Some(name)),
+                    _ => set_window_alias(&window, None),
+                }
+                self.root.event(&mut UIEvent::Core(event.clone()));
+            }
+            Event::DeletedBookmark(jid) => {
+                set_window_alias(&jid.to_string(), None);
+                self.root.event(&mut UIEvent::Core(event.clone()));
+            }
+            Event::Setting(key, value) => {
+                let result = match (key.as_str(), value) {
+                    ("time_format", toml::Value::String(format)) => set_time_format(format),
+                    ("status_format", toml::Value::String(format)) => set_status_format(format),
+                    ("notify.desktop", toml::Value::Boolean(desktop)) => {
+                        self.notify_config.desktop = *desktop;
+                        Ok(())
+                    }
+                    ("notify.preview", toml::Value::Boolean(preview)) => {
+                        self.notify_config.preview = *preview;
+                        Ok(())
+                    }
+                    _ => Ok(()),
+                };
+                match result {
+                    Ok(()) => self.redraw(),
+                    Err(error) => aparte.log(error),
+                }
+            }
+            Event::SendText(window, text) => match self.conversations.contains_key(window) {
+                true => self.send_text(aparte, window, text),
+                false => aparte.log(format!("No conversation in window {}", window)),
+            },
+            Event::NotifyLevel(window, level) => {
+                self.notify_config.windows.insert(window.clone(), *level);
+                aparte.log(format!("Notification level of {} set to {}", window, level));
+            }
+            Event::DoNotDisturb(state) => {
+                self.do_not_disturb = state.unwrap_or(!self.do_not_disturb);
+                match self.do_not_disturb {
+                    true => aparte.log("Do not disturb enabled".to_string()),
+                    false => aparte.log("Do not disturb disabled".to_string()),
+                }
             }
+            Event::Mute(window, until) => {
+                self.muted.insert(window.clone(), *until);
+                match until {
+                    Some(until) => aparte.log(format!(
+                        "{} muted until {}",
+                        window,
+                        until.with_timezone(&Local).format("%T")
+                    )),
+                    None => aparte.log(format!("{} muted", window)),
+                }
+            }
+            Event::Unmute(window) => match self.muted.remove(window) {
+                Some(_) => aparte.log(format!("{} unmuted", window)),
+                None => aparte.log(format!("{} isn't muted", window)),
+            },
+            Event::Tick => {
+                self.unmute_expired(aparte);
+                self.root.event(&mut UIEvent::Core(event.clone()));
+            }
+            Event::MoveWindow(position) => {
+                if let Err(err) = self.move_window(*position) {
+                    aparte.log(err);
+                }
+            }
+            Event::SwapWindows(first, second) => {
+                if let Err(err) = self.swap_windows(*first, *second) {
+                    aparte.log(err);
+                }
+            }
+            Event::WindowChange => self.redraw(),
             Event::Close(window) => {
-                if window != "console" {
+                if window == "console" {
+                    aparte.log("The console can't be closed".to_string());
+                } else if !self.windows.contains(window) {
+                    aparte.log(format!("Unknown window {}", window));
+                } else {
+                    // Closing a channel window means leaving it, unless already left
+                    if let Some(Conversation::Channel(channel)) = self.conversations.remove(window)
+                    {
+                        if !self.detached.remove(window) {
+                            aparte.schedule(Event::Leave(channel, None));
+                        }
+                    }
                     self.windows.retain(|win| win != window);
                     self.unread_windows.remove(window);
+                    self.unread_counts.remove(window);
+                    self.newest.remove(window);
+                    self.last_read.remove(window);
+                    self.muted.remove(window);
+                    if let Some(lines) = &mut self.lines {
+                        lines.pending.remove(window);
+                    }
+                    self.highlighted_windows.remove(window);
+                    self.update_terminal_title();
                     if Some(window) == self.current_window.as_ref() {
                         let current = self.windows.iter().next().cloned();
                         if let Some(current) = current {
@@ -1176,9 +3663,14 @@ impl ModTrait for UIMod {
                         .event(&mut UIEvent::Core(Event::Close(window.clone())))
                 }
             }
+            Event::Key(key) if self.copy_mode => self.copy_mode_key(aparte, key),
             Event::Key(key) => {
+                let ctrl_x = std::mem::replace(&mut self.ctrl_x, false);
+                let yanked = std::mem::replace(&mut self.yanked, false);
                 match key {
-                    Key::Char('\t') => {
+                    Key::Ctrl('x') => self.ctrl_x = true,
+                    Key::Ctrl('e') if ctrl_x => self.compose_in_editor(aparte),
+                    Key::Char('\t') | Key::BackTab => {
                         let result = Rc::new(RefCell::new(None));
 
                         let (raw_buf, cursor, password) = {
@@ -1189,7 +3681,7 @@ impl ModTrait for UIMod {
                         };
 
                         if password {
-                            aparte.schedule(Event::Key(Key::Char('\t')));
+                            aparte.schedule(Event::Key(key.clone()));
                         } else {
                             let window = self.current_window.clone().unwrap();
                             let account = match self.conversations.get(&window) {
@@ -1204,6 +3696,7 @@ impl ModTrait for UIMod {
                                 context: window,
                                 raw_buf,
                                 cursor,
+                                backward: *key == Key::BackTab,
                             });
                         }
                     }
@@ -1231,55 +3724,10 @@ impl ModTrait for UIMod {
                             aparte.schedule(Event::RawCommand(account, window, raw_buf.clone()));
                         } else if raw_buf.len() > 0 {
                             if let Some(current_window) = self.current_window.clone() {
-                                if let Some(conversation) = self.conversations.get(&current_window)
-                                {
-                                    match conversation {
-                                        Conversation::Chat(chat) => {
-                                            let account = &chat.account;
-                                            let us = account.clone().into();
-                                            let from: Jid = us;
-                                            let to: Jid = chat.contact.clone().into();
-                                            let id = Uuid::new_v4();
-                                            let timestamp = LocalTz::now().into();
-                                            let mut bodies = HashMap::new();
-                                            bodies.insert("".to_string(), raw_buf.clone());
-                                            let message = Message::outgoing_chat(
-                                                id.to_string(),
-                                                timestamp,
-                                                &from,
-                                                &to,
-                                                &bodies,
-                                            );
-                                            aparte.schedule(Event::SendMessage(
-                                                account.clone(),
-                                                message,
-                                            ));
-                                        }
-                                        Conversation::Channel(channel) => {
-                                            let account = &channel.account;
-                                            let mut us = account.clone();
-                                            us.resource = channel.nick.clone();
-                                            let from: Jid = us.into();
-                                            let to: Jid = channel.jid.clone().into();
-                                            let id = Uuid::new_v4();
-                                            let timestamp = LocalTz::now().into();
-                                            let mut bodies = HashMap::new();
-                                            bodies.insert("".to_string(), raw_buf.clone());
-                                            let message = Message::outgoing_channel(
-                                                id.to_string(),
-                                                timestamp,
-                                                &from,
-                                                &to,
-                                                &bodies,
-                                            );
-                                            aparte.schedule(Event::SendMessage(
-                                                account.clone(),
-                                                message,
-                                            ));
-                                        }
-                                    }
-                                }
+                                self.send_text(aparte, &current_window, &raw_buf);
                             }
+                        } else if self.current_window.as_deref() == Some(ROOMS_WINDOW) {
+                            self.join_selected_room(aparte);
                         }
                     }
                     Key::Alt('a') => {
@@ -1287,39 +3735,133 @@ impl ModTrait for UIMod {
                             self.change_window(&window);
                         }
                     }
+                    Key::Alt('r') => aparte.schedule(Event::ToggleRoster),
+                    Key::Ctrl('l') => self.repaint(),
+                    Key::Alt('<') => self.resize_side_pane(2),
+                    Key::Alt('>') => self.resize_side_pane(-2),
+                    Key::Alt(c @ '1'..='9') => {
+                        let number = c.to_digit(10).unwrap() as usize;
+                        if let Some(window) = self.get_window(number) {
+                            self.change_window(&window);
+                        }
+                    }
+                    Key::Alt('c') if self.lines.is_none() => {
+                        self.copy_mode = true;
+                        self.root
+                            .event(&mut UIEvent::CopyMode(CopyModeAction::Start));
+                    }
+                    Key::Alt('y') if !yanked => self.copy_input(aparte),
+                    Key::Ctrl('y') | Key::Alt('y') => {
+                        self.yanked = true;
+                        self.root.event(&mut UIEvent::Core(Event::Key(key.clone())));
+                    }
+                    Key::Up | Key::Down if self.current_window.as_deref() == Some(ROOMS_WINDOW) => {
+                        let delta = match key {
+                            Key::Up => -1,
+                            _ => 1,
+                        };
+                        self.root.event(&mut UIEvent::MoveSelection(delta));
+                    }
+                    Key::Up => {
+                        aparte.schedule(Event::ResetCompletion);
+                        if !self.correct_last_sent() {
+                            self.root.event(&mut UIEvent::Core(Event::Key(key.clone())));
+                        }
+                    }
+                    Key::Esc
+                        if self.current_window.as_ref().map_or(false, |window| {
+                            self.replies.contains_key(window) || self.corrections.contains(window)
+                        }) =>
+                    {
+                        if let Some(window) = self.current_window.clone() {
+                            if self.corrections.remove(&window) {
+                                self.root.event(&mut UIEvent::Core(Event::Completed(
+                                    String::new(),
+                                    Cursor::new(0),
+                                )));
+                                let info = Message::log("Correction cancelled".to_string());
+                                aparte.schedule(Event::Info(window.clone(), info));
+                            }
+                            if self.replies.remove(&window).is_some() {
+                                let info = Message::log("Reply cancelled".to_string());
+                                aparte.schedule(Event::Info(window, info));
+                            }
+                        }
+                        self.update_prompt();
+                    }
                     _ => {
                         aparte.schedule(Event::ResetCompletion);
                         self.root.event(&mut UIEvent::Core(Event::Key(key.clone())));
                     }
                 }
             }
+            Event::Paste(_) if self.copy_mode => {}
+            Event::Paste(text) => {
+                aparte.schedule(Event::ResetCompletion);
+                self.root
+                    .event(&mut UIEvent::Core(Event::Paste(text.clone())));
+            }
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _)) => {
+                aparte.schedule(Event::Key(Key::PageUp));
+            }
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, _, _)) => {
+                aparte.schedule(Event::Key(Key::PageDown));
+            }
             Event::Completed(raw_buf, cursor) => {
                 self.root.event(&mut UIEvent::Core(Event::Completed(
                     raw_buf.clone(),
                     cursor.clone(),
                 )));
             }
-            Event::Notification(_) => {
-                vprint!(self.screen, "\x07");
-                flush!(self.screen);
+            Event::OpenUrl(number) | Event::CopyUrl(number) => {
+                let url = self
+                    .current_window
+                    .as_ref()
+                    .and_then(|window| get_url(window, *number));
+                match (url, event) {
+                    (None, _) => aparte.log("No such URL in this window".to_string()),
+                    (Some(url), Event::OpenUrl(_)) => open_url(aparte, url),
+                    (Some(url), _) => match self.copy_to_clipboard(&url) {
+                        Ok(()) => aparte.log(format!("Copied {}", url)),
+                        Err(err) => aparte.log(err),
+                    },
+                }
+            }
+            Event::Preview { id, graphic } => {
+                PREVIEWS.with(|previews| {
+                    previews
+                        .borrow_mut()
+                        .insert(id.clone(), Rc::new(graphic.clone()))
+                });
+                self.root.event(&mut UIEvent::Core(event.clone()));
+            }
+            Event::Notification(_) => vprint!(self.screen, "\x07"),
+            Event::Pending { id, pending } => {
+                PENDING.with(|pendings| match pending {
+                    true => pendings.borrow_mut().insert(id.clone()),
+                    false => pendings.borrow_mut().remove(id),
+                });
+                self.root.event(&mut UIEvent::Core(event.clone()));
+            }
+            Event::MessageError { id, error, .. } => {
+                ERRORS.with(|errors| match error {
+                    Some(error) => errors.borrow_mut().insert(id.clone(), error.clone()),
+                    None => errors.borrow_mut().remove(id),
+                });
+                self.root.event(&mut UIEvent::Core(event.clone()));
+            }
+            Event::Search(Some(term)) => {
+                let found = Rc::new(RefCell::new(None));
+                self.root
+                    .event(&mut UIEvent::Search(term.clone(), Rc::clone(&found)));
+                if *found.borrow() == Some(false) {
+                    aparte.log(format!("No match for {}", term));
+                }
             }
             // Forward all unknown events
             event => self.root.event(&mut UIEvent::Core(event.clone())),
         }
 
-        // Update rendering
-        if self.root.is_layout_dirty() {
-            let (width, height) = termion::terminal_size().unwrap();
-            let mut dimension = Dimension::new();
-            self.root.measure(&mut dimension, Some(width), Some(height));
-            self.root.layout(&mut dimension, 1, 1);
-            self.root.render(&dimension, &mut self.screen);
-            self.dimension = Some(dimension);
-        } else if self.root.is_dirty() {
-            let dimension: &Dimension = self.dimension.as_ref().unwrap();
-            self.root.render(dimension, &mut self.screen);
-        }
-
         // Handle queued outgoing event
         for event in self.outgoing_event_queue.borrow_mut().drain(..) {
             aparte.schedule(event);
@@ -1327,15 +3869,47 @@ impl ModTrait for UIMod {
     }
 }
 
+impl Drop for UIMod {
+    fn drop(&mut self) {
+        // Restore the title saved at startup, terminals without a title stack get an empty one
+        vprint!(self.screen, "\x1b]2;\x07\x1b[23;0t");
+        vprint!(self.screen, "{}", PASTE_DISABLE);
+        if self.lines.is_some() {
+            vprint!(self.screen, "\r{}", termion::clear::CurrentLine);
+        }
+        flush!(self.screen);
+    }
+}
+
 impl fmt::Display for UIMod {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Aparté UI")
     }
 }
 
+/// Mouse reporting modes enabled by termion's MouseTerminal
+const MOUSE_ENABLE: &str = "\x1b[?1000h\x1b[?1002h\x1b[?1015h\x1b[?1006h";
+const MOUSE_DISABLE: &str = "\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l";
+
+/// Bracketed paste mode, pasted text is surrounded by PASTE_START and PASTE_END
+const PASTE_ENABLE: &str = "\x1b[?2004h";
+const PASTE_DISABLE: &str = "\x1b[?2004l";
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Stop reading the tty while another program, like an editor, uses it
+static INPUT_SUSPENDED: AtomicBool = AtomicBool::new(false);
+
+enum TtyEvent {
+    Termion(TermionEvent),
+    Paste(String),
+}
+
 struct TermionEventStream {
     channel: mpsc::Receiver<Result<u8, IoError>>,
     waker: Arc<AtomicWaker>,
+    /// Bytes received since the start of a bracketed paste
+    paste: Option<Vec<u8>>,
 }
 
 impl TermionEventStream {
@@ -1348,6 +3922,32 @@ impl TermionEventStream {
             let mut input = get_tty().expect("cannot get tty for stdin reading");
             let mut buf = [0u8; 256];
             loop {
+                if INPUT_SUSPENDED.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(50));
+                    continue;
+                }
+
+                // Never block in read, the tty must be released as soon as input is suspended
+                let mut pollfd = libc::pollfd {
+                    fd: input.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                match unsafe { libc::poll(&mut pollfd, 1, 100) } {
+                    0 => continue,
+                    n if n < 0 => {
+                        let err = IoError::last_os_error();
+                        match err.kind() {
+                            IoErrorKind::Interrupted => continue,
+                            _ => {
+                                error!("Cannot poll input pipe: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
                 match input.read(&mut buf[..]) {
                     Ok(n) => {
                         for byte in buf[..n].iter() {
@@ -1372,6 +3972,7 @@ impl TermionEventStream {
         Self {
             channel: recv,
             waker,
+            paste: None,
         }
     }
 }
@@ -1397,25 +3998,62 @@ impl<'a, T> Iterator for IterWrapper<'a, T> {
 }
 
 impl Stream for TermionEventStream {
-    type Item = TermionEvent;
+    type Item = TtyEvent;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let byte = match self.channel.try_recv() {
-            Ok(Ok(byte)) => byte,
-            Ok(Err(_)) => return Poll::Ready(None),
-            Err(mpsc::TryRecvError::Empty) => {
-                self.waker.register(cx.waker());
-                return Poll::Pending;
+        loop {
+            let byte = match self.channel.try_recv() {
+                Ok(Ok(byte)) => byte,
+                Ok(Err(_)) => return Poll::Ready(None),
+                Err(mpsc::TryRecvError::Empty) => {
+                    self.waker.register(cx.waker());
+                    return Poll::Pending;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => return Poll::Ready(None),
+            };
+
+            if let Some(paste) = self.paste.as_mut() {
+                paste.push(byte);
+                if paste.ends_with(PASTE_END) {
+                    let mut paste = self.paste.take().unwrap();
+                    paste.truncate(paste.len() - PASTE_END.len());
+                    let text = String::from_utf8_lossy(&paste).into_owned();
+                    return Poll::Ready(Some(TtyEvent::Paste(text)));
+                }
+                continue;
+            }
+
+            // termion doesn't know the paste start sequence, other sequences are given back to it
+            let mut lookahead = Vec::new();
+            if byte == PASTE_START[0] {
+                while lookahead.len() + 1 < PASTE_START.len() {
+                    match self.channel.try_recv() {
+                        Ok(Ok(next)) => {
+                            lookahead.push(next);
+                            if next != PASTE_START[lookahead.len()] {
+                                break;
+                            }
+                        }
+                        _ => break,
+                    }
+                }
+
+                if lookahead == PASTE_START[1..] {
+                    self.paste = Some(Vec::new());
+                    continue;
+                }
             }
-            Err(mpsc::TryRecvError::Disconnected) => return Poll::Ready(None),
-        };
 
-        let mut iter = IterWrapper::new(&mut self.channel);
-        if let Ok(event) = termion_parse_event(byte, &mut iter) {
-            Poll::Ready(Some(event))
-        } else {
-            self.waker.register(cx.waker());
-            Poll::Pending
+            let mut iter = lookahead
+                .into_iter()
+                .map(Ok)
+                .chain(IterWrapper::new(&mut self.channel));
+            return if let Ok(event) = termion_parse_event(byte, &mut iter) {
+                Poll::Ready(Some(TtyEvent::Termion(event)))
+            } else {
+                self.waker.register(cx.waker());
+                Poll::Pending
+            };
         }
     }
 }
@@ -1437,7 +4075,7 @@ impl Stream for EventStream {
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         match Pin::new(&mut self.inner).poll_next(cx) {
-            Poll::Ready(Some(TermionEvent::Key(key))) => {
+            Poll::Ready(Some(TtyEvent::Termion(TermionEvent::Key(key)))) => {
                 match key {
                     //Key::Alt('\x1b') => {
                     //    match Pin::new(&mut self.inner).poll_next(cx) {
@@ -1467,20 +4105,22 @@ impl Stream for EventStream {
                     Key::Alt(c) => Poll::Ready(Some(Event::Key(Key::Alt(c)))),
                     Key::PageUp => Poll::Ready(Some(Event::Key(Key::PageUp))),
                     Key::PageDown => Poll::Ready(Some(Event::Key(Key::PageDown))),
+                    Key::Esc => Poll::Ready(Some(Event::Key(Key::Esc))),
+                    Key::BackTab => Poll::Ready(Some(Event::Key(Key::BackTab))),
                     _ => {
                         self.inner.waker.register(cx.waker());
                         Poll::Pending
                     }
                 }
             }
-            Poll::Ready(Some(TermionEvent::Mouse(_))) => {
-                self.inner.waker.register(cx.waker());
-                Poll::Pending
+            Poll::Ready(Some(TtyEvent::Termion(TermionEvent::Mouse(mouse)))) => {
+                Poll::Ready(Some(Event::Mouse(mouse)))
             }
-            Poll::Ready(Some(TermionEvent::Unsupported(_))) => {
+            Poll::Ready(Some(TtyEvent::Termion(TermionEvent::Unsupported(_)))) => {
                 self.inner.waker.register(cx.waker());
                 Poll::Pending
             }
+            Poll::Ready(Some(TtyEvent::Paste(text))) => Poll::Ready(Some(Event::Paste(text))),
             Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => {
                 self.inner.waker.register(cx.waker());
@@ -1489,3 +4129,119 @@ impl Stream for EventStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_initial() {
+        assert_eq!(initial("juliet@capulet.lit"), "J");
+        assert_eq!(initial("_éloïse"), "É");
+        assert_eq!(initial("!!"), "?");
+    }
+
+    #[test]
+    fn test_line_output() {
+        // Given
+        let mut lines = LineOutput::new(false);
+        lines.prompt = "> ".to_string();
+        lines.width = 10;
+        let mut output = Vec::new();
+
+        // When
+        lines.print(&mut output, "\x1b[31mred\nline\n");
+        lines.show_input(&mut output, "hello world", 11, false);
+
+        // Then
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\r\x1b[2Kred\x1b[m\r\nline\x1b[m\r\n\r\x1b[2Klo world\r\x1b[8C"
+        );
+        let mut output = Vec::new();
+        lines.show_input(&mut output, "hello world", 11, false);
+        assert!(output.is_empty());
+        lines.show_input(&mut output, "secret", 6, true);
+        assert_eq!(String::from_utf8(output).unwrap(), "\r\x1b[2K> \r\x1b[2C");
+    }
+
+    #[test]
+    fn test_osc52() {
+        assert_eq!(osc52("aparté"), "\x1b]52;c;YXBhcnTDqQ==\x07");
+    }
+
+    #[test]
+    fn test_parse_status_format() {
+        assert_eq!(
+            parse_status_format("{account} | {windows} {time}"),
+            Ok(vec![
+                StatusComponent::Account,
+                StatusComponent::Text(" | ".to_string()),
+                StatusComponent::Windows,
+                StatusComponent::Text(" ".to_string()),
+                StatusComponent::Time,
+            ])
+        );
+        assert_eq!(
+            parse_status_format("{latency}"),
+            Ok(vec![StatusComponent::Latency])
+        );
+        assert!(parse_status_format("{lag}").is_err());
+        assert!(parse_status_format("{account").is_err());
+    }
+
+    #[test]
+    fn test_parse_prompt_format() {
+        assert_eq!(
+            parse_prompt_format("[{window}] {nick}> "),
+            Ok(vec![
+                PromptComponent::Text("[".to_string()),
+                PromptComponent::Window,
+                PromptComponent::Text("] ".to_string()),
+                PromptComponent::Nick,
+                PromptComponent::Text("> ".to_string()),
+            ])
+        );
+        assert!(parse_prompt_format("{windows}").is_err());
+    }
+
+    #[test]
+    fn test_register_urls() {
+        // Given
+        let room = Jid::from_str("verona@chat.shakespeare.lit/romeo").unwrap();
+        let juliet = Jid::from_str("verona@chat.shakespeare.lit/juliet").unwrap();
+        let tybalt = Jid::from_str("verona@chat.shakespeare.lit/tybalt").unwrap();
+        let message = |from: &Jid, body: &str| {
+            let mut bodies = HashMap::new();
+            bodies.insert(String::new(), body.to_string());
+            match Message::incoming_channel("1", LocalTz::now().into(), from, &room, &bodies) {
+                Message::Xmpp(message) => message,
+                _ => unreachable!(),
+            }
+        };
+        let numbers = |message: &VersionedXmppMessage| {
+            URLS.with(|urls| urls.borrow().messages.get(&urls_key(message)).cloned())
+                .map(|(_, numbers)| numbers)
+        };
+        let first = message(&juliet, "https://capulet.lit");
+        let same_id = message(&tybalt, "https://montague.lit");
+        let corrected = message(&juliet, "https://capulet.lit/balcony");
+
+        // When
+        register_urls(&first);
+        register_urls(&same_id);
+        register_urls(&first);
+        let first_numbers = numbers(&first);
+        register_urls(&corrected);
+
+        // Then
+        assert_eq!(first_numbers, Some(vec![1]));
+        assert_eq!(numbers(&same_id), Some(vec![2]));
+        assert_eq!(numbers(&corrected), Some(vec![3]));
+        assert_eq!(
+            get_url("verona@chat.shakespeare.lit", Some(3)),
+            Some("https://capulet.lit/balcony".to_string())
+        );
+    }
+}
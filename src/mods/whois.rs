@@ -0,0 +1,536 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use chrono::{DateTime, FixedOffset};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use xmpp_parsers::disco::{DiscoInfoQuery, DiscoInfoResult};
+use xmpp_parsers::iq::{Iq, IqType};
+use xmpp_parsers::{BareJid, Element, Jid};
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::contact::{self, Contact};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::Message;
+use crate::mods::contact::ContactMod;
+use crate::mods::conversation::ConversationMod;
+
+const NS_VCARD: &str = "vcard-temp";
+const NS_LAST: &str = "jabber:iq:last";
+const NS_TIME: &str = "urn:xmpp:time";
+const NS_VERSION: &str = "jabber:iq:version";
+
+/// Time after which a whois is shown with the answers received so far
+const WHOIS_TIMEOUT: Duration = Duration::from_secs(30);
+
+command_def!(
+    whois,
+    r#"/whois <jid|nick>
+
+    jid|nick      Contact, or occupant of the current channel
+
+Description:
+    Show what can be learnt about a contact: its identity, vCard, last
+    activity, local time and client version.
+
+Example:
+    /whois juliet@capulet.lit
+    /whois Romeo
+"#,
+    {
+        target: String = {
+            completion: (|aparte, command| {
+                let mut targets: Vec<String> = aparte
+                    .get_mod::<ContactMod>()
+                    .contacts
+                    .iter()
+                    .map(|(_, contact)| contact.jid.to_string())
+                    .collect();
+                if let Ok(channel) = ConversationMod::current_channel(aparte, &command) {
+                    targets.extend(channel.occupants.keys().cloned());
+                }
+                targets
+            })
+        }
+    },
+    |aparte, command| {
+        let account = command
+            .account
+            .clone()
+            .or_else(|| aparte.current_account())
+            .ok_or(format!("No connection found"))?;
+        let occupant = match ConversationMod::current_channel(aparte, &command) {
+            Ok(channel) if channel.occupants.contains_key(&target) => {
+                Some(Jid::Full(channel.jid.with_resource(target.clone())))
+            }
+            _ => None,
+        };
+        let jid = match occupant {
+            Some(jid) => jid,
+            None => Jid::from_str(&target)
+                .map_err(|err| format!("Invalid JID {}: {}", target, err))?,
+        };
+        WhoisMod::whois(aparte, &account, jid, command.context.clone());
+        Ok(())
+    }
+);
+
+/// Queries sent for a whois, in the order their answers are displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Query {
+    Disco,
+    VCard,
+    Last,
+    Time,
+    Version,
+}
+
+impl Query {
+    const ALL: [Query; 5] = [
+        Query::Disco,
+        Query::VCard,
+        Query::Last,
+        Query::Time,
+        Query::Version,
+    ];
+
+    fn payload(&self) -> Element {
+        match self {
+            Query::Disco => DiscoInfoQuery { node: None }.into(),
+            Query::VCard => Element::builder("vCard", NS_VCARD).build(),
+            Query::Last => Element::builder("query", NS_LAST).build(),
+            Query::Time => Element::builder("time", NS_TIME).build(),
+            Query::Version => Element::builder("query", NS_VERSION).build(),
+        }
+    }
+
+    /// Readable lines of an answer
+    fn parse(&self, jid: &Jid, el: Element) -> Vec<(&'static str, String)> {
+        match self {
+            Query::Disco => match DiscoInfoResult::try_from(el) {
+                Ok(disco) => {
+                    let mut lines: Vec<(&'static str, String)> = disco
+                        .identities
+                        .iter()
+                        .map(|identity| {
+                            let value = match &identity.name {
+                                Some(name) => {
+                                    format!("{} ({}/{})", name, identity.category, identity.type_)
+                                }
+                                None => format!("{}/{}", identity.category, identity.type_),
+                            };
+                            ("Identity", value)
+                        })
+                        .collect();
+                    lines.push(("Features", disco.features.len().to_string()));
+                    lines
+                }
+                Err(_) => Vec::new(),
+            },
+            Query::VCard => [
+                ("Name", vec!["FN"]),
+                ("Nickname", vec!["NICKNAME"]),
+                ("Email", vec!["EMAIL", "USERID"]),
+                ("Birthday", vec!["BDAY"]),
+                ("Organization", vec!["ORG", "ORGNAME"]),
+                ("Website", vec!["URL"]),
+            ]
+            .iter()
+            .filter_map(|(label, path)| {
+                let mut child = &el;
+                for name in path {
+                    child = child.get_child(name, NS_VCARD)?;
+                }
+                let text = child.text().trim().to_string();
+                match text.is_empty() {
+                    true => None,
+                    false => Some((*label, text)),
+                }
+            })
+            .collect(),
+            Query::Last => {
+                let seconds = el.attr("seconds").and_then(|s| s.parse::<u64>().ok());
+                let status = el.text().trim().to_string();
+                match (seconds, jid) {
+                    (None, _) => Vec::new(),
+                    // A full JID answers with its idle time, a bare JID with the time since its
+                    // last disconnection, 0 when it is online
+                    (Some(seconds), Jid::Full(_)) => vec![("Idle", format_duration(seconds))],
+                    (Some(0), Jid::Bare(_)) => vec![("Last seen", "online".to_string())],
+                    (Some(seconds), Jid::Bare(_)) => {
+                        let mut value = format!("{} ago", format_duration(seconds));
+                        if !status.is_empty() {
+                            value.push_str(&format!(" ({})", status));
+                        }
+                        vec![("Last seen", value)]
+                    }
+                }
+            }
+            Query::Time => {
+                let utc = el.get_child("utc", NS_TIME).map(|utc| utc.text());
+                let tzo = el.get_child("tzo", NS_TIME).map(|tzo| tzo.text());
+                let utc = utc.and_then(|utc| DateTime::parse_from_rfc3339(utc.trim()).ok());
+                let tzo = tzo.and_then(|tzo| parse_tzo(tzo.trim()));
+                match (utc, tzo) {
+                    (Some(utc), Some(tzo)) => vec![(
+                        "Local time",
+                        utc.with_timezone(&tzo)
+                            .format("%Y-%m-%d %H:%M (%:z)")
+                            .to_string(),
+                    )],
+                    _ => Vec::new(),
+                }
+            }
+            Query::Version => {
+                let field = |name: &str| {
+                    el.get_child(name, NS_VERSION)
+                        .map(|child| child.text().trim().to_string())
+                        .filter(|text| !text.is_empty())
+                };
+                match (field("name"), field("version"), field("os")) {
+                    (None, _, _) => Vec::new(),
+                    (Some(name), version, os) => {
+                        let mut value = name;
+                        if let Some(version) = version {
+                            value.push_str(&format!(" {}", version));
+                        }
+                        if let Some(os) = os {
+                            value.push_str(&format!(" on {}", os));
+                        }
+                        vec![("Client", value)]
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Timezone offset as written in XEP-0082, like "Z" or "-06:00"
+fn parse_tzo(tzo: &str) -> Option<FixedOffset> {
+    if tzo == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let sign = match tzo.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let mut parts = tzo[1..].splitn(2, ':');
+    let hours = parts
+        .next()?
+        .parse::<i32>()
+        .ok()
+        .filter(|hours| *hours < 24)?;
+    let minutes = parts
+        .next()?
+        .parse::<i32>()
+        .ok()
+        .filter(|minutes| *minutes < 60)?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Two largest units of a duration, like "3d 4h" or "12m 5s"
+fn format_duration(seconds: u64) -> String {
+    let units = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+    let parts: Vec<String> = units
+        .iter()
+        .scan(seconds, |left, (unit, size)| {
+            let count = *left / size;
+            *left %= size;
+            Some((count, unit))
+        })
+        .skip_while(|(count, _)| *count == 0)
+        .take(2)
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, unit)| format!("{}{}", count, unit))
+        .collect();
+    match parts.is_empty() {
+        true => "0s".to_string(),
+        false => parts.join(" "),
+    }
+}
+
+/// Known resources of a contact, only the asked one for a full JID
+fn resources(contact: &Contact, jid: &Jid) -> Vec<(&'static str, String)> {
+    let mut names: Vec<&String> = contact
+        .resources
+        .keys()
+        .filter(|name| match jid {
+            Jid::Full(jid) => **name == jid.resource,
+            Jid::Bare(_) => true,
+        })
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| {
+            let resource = &contact.resources[name];
+            let presence = match resource.presence {
+                contact::Presence::Available => "available",
+                contact::Presence::Chat => "chatty",
+                contact::Presence::Away => "away",
+                contact::Presence::Xa => "extended away",
+                contact::Presence::Dnd => "do not disturb",
+                contact::Presence::Unavailable => "unavailable",
+            };
+            let mut details = vec![presence.to_string()];
+            details.push(format!("priority {}", resource.priority));
+            if let Some(status) = &resource.status {
+                details.push(format!("\"{}\"", status));
+            }
+            if let Some(client) = &resource.client {
+                details.push(client.clone());
+            }
+            ("Resource", format!("{} ({})", name, details.join(", ")))
+        })
+        .collect()
+}
+
+/// A whois waiting for the answers to its queries
+struct Whois {
+    jid: Jid,
+    window: String,
+    pending: usize,
+    sent: Instant,
+    /// Resources known from presences, before any answer
+    resources: Vec<(&'static str, String)>,
+    answers: BTreeMap<Query, Vec<(&'static str, String)>>,
+}
+
+impl Whois {
+    fn summary(&self) -> String {
+        let lines: Vec<String> = self
+            .resources
+            .iter()
+            .chain(self.answers.values().flatten())
+            .map(|(label, value)| format!("  {}: {}", label, value))
+            .collect();
+        let mut summary = match lines.is_empty() {
+            true => format!("Nothing is known about {}", self.jid),
+            false => format!("Whois {}\n{}", self.jid, lines.join("\n")),
+        };
+        if self.pending > 0 {
+            summary.push_str(&format!("\n  ({} queries unanswered)", self.pending));
+        }
+        summary
+    }
+}
+
+/// Summary of what contacts and occupants tell about themselves
+pub struct WhoisMod {
+    /// Whois and query of each sent iq
+    iqs: HashMap<String, (String, Query)>,
+    whois: HashMap<String, Whois>,
+}
+
+impl WhoisMod {
+    pub fn new() -> Self {
+        Self {
+            iqs: HashMap::new(),
+            whois: HashMap::new(),
+        }
+    }
+
+    fn whois(aparte: &mut Aparte, account: &Account, jid: Jid, window: String) {
+        let id = Uuid::new_v4().to_hyphenated().to_string();
+        let mut iqs = Vec::new();
+        for query in Query::ALL.iter() {
+            let iq_id = Uuid::new_v4().to_hyphenated().to_string();
+            let iq = Iq {
+                from: None,
+                to: Some(jid.clone()),
+                id: iq_id.clone(),
+                payload: IqType::Get(query.payload()),
+            };
+            iqs.push(iq);
+            aparte
+                .get_mod_mut::<WhoisMod>()
+                .iqs
+                .insert(iq_id, (id.clone(), *query));
+        }
+        let bare: BareJid = jid.clone().into();
+        let resources = aparte
+            .get_mod::<ContactMod>()
+            .contacts
+            .values()
+            .find(|contact| contact.jid == bare)
+            .map_or(Vec::new(), |contact| resources(contact, &jid));
+        aparte.get_mod_mut::<WhoisMod>().whois.insert(
+            id,
+            Whois {
+                jid,
+                window,
+                pending: iqs.len(),
+                sent: Instant::now(),
+                resources,
+                answers: BTreeMap::new(),
+            },
+        );
+        for iq in iqs {
+            aparte.send(account, iq.into());
+        }
+    }
+
+    fn handle_iq(&mut self, aparte: &mut Aparte, iq: &Iq) {
+        let (id, query) = match self.iqs.remove(&iq.id) {
+            Some(query) => query,
+            None => return,
+        };
+        let whois = match self.whois.get_mut(&id) {
+            Some(whois) => whois,
+            None => return,
+        };
+        // Errors only mean the contact doesn't tell
+        if let IqType::Result(Some(el)) = iq.payload.clone() {
+            let lines = query.parse(&whois.jid, el);
+            whois.answers.insert(query, lines);
+        }
+        whois.pending -= 1;
+        if whois.pending == 0 {
+            if let Some(whois) = self.whois.remove(&id) {
+                aparte.schedule(Event::Info(
+                    whois.window.clone(),
+                    Message::log(whois.summary()),
+                ));
+            }
+        }
+    }
+
+    /// Show the whois waiting for too long with what has been answered, later answers are ignored
+    fn tick(&mut self, aparte: &mut Aparte) {
+        let expired: Vec<String> = self
+            .whois
+            .iter()
+            .filter(|(_, whois)| whois.sent.elapsed() >= WHOIS_TIMEOUT)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            self.iqs.retain(|_, (whois, _)| *whois != id);
+            if let Some(whois) = self.whois.remove(&id) {
+                aparte.schedule(Event::Info(
+                    whois.window.clone(),
+                    Message::log(whois.summary()),
+                ));
+            }
+        }
+    }
+}
+
+impl ModTrait for WhoisMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(whois::new());
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Iq(_, iq) => self.handle_iq(aparte, iq),
+            Event::Tick => self.tick(aparte),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for WhoisMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Whois")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        // Given
+        let jid = Jid::from_str("juliet@capulet.lit/balcony").unwrap();
+        let vcard: Element = "<vCard xmlns='vcard-temp'><FN>Juliet Capulet</FN><EMAIL><USERID>juliet@capulet.lit</USERID></EMAIL></vCard>".parse().unwrap();
+        let last: Element = "<query xmlns='jabber:iq:last' seconds='903'/>"
+            .parse()
+            .unwrap();
+        let time: Element =
+            "<time xmlns='urn:xmpp:time'><tzo>-06:00</tzo><utc>2006-12-19T17:58:35Z</utc></time>"
+                .parse()
+                .unwrap();
+        let version: Element =
+            "<query xmlns='jabber:iq:version'><name>Aparté</name><version>0.2</version></query>"
+                .parse()
+                .unwrap();
+        let mut contact = Contact {
+            jid: jid.clone().into(),
+            name: None,
+            subscription: xmpp_parsers::roster::Subscription::Both,
+            presence: contact::Presence::Unavailable,
+            groups: Vec::new(),
+            resources: HashMap::new(),
+            avatar: None,
+        };
+        contact.update_resource(
+            "balcony",
+            contact::Resource {
+                presence: contact::Presence::Away,
+                priority: 5,
+                status: Some("Wherefore art thou".to_string()),
+                client: Some("https://aparte.dev".to_string()),
+            },
+        );
+        let mut whois = Whois {
+            jid: jid.clone(),
+            window: "console".to_string(),
+            pending: 0,
+            sent: Instant::now(),
+            resources: resources(&contact, &jid),
+            answers: BTreeMap::new(),
+        };
+
+        // When
+        for (query, el) in vec![
+            (Query::Version, version),
+            (Query::Time, time),
+            (Query::VCard, vcard),
+            (Query::Last, last),
+        ] {
+            whois.answers.insert(query, query.parse(&jid, el));
+        }
+
+        // Then
+        assert_eq!(
+            whois.summary(),
+            "Whois juliet@capulet.lit/balcony
+  Resource: balcony (away, priority 5, \"Wherefore art thou\", https://aparte.dev)
+  Name: Juliet Capulet
+  Email: juliet@capulet.lit
+  Idle: 15m 3s
+  Local time: 2006-12-19 11:58 (-06:00)
+  Client: Aparté 0.2"
+        );
+    }
+
+    #[test]
+    fn test_parse_tzo() {
+        assert_eq!(parse_tzo("Z"), FixedOffset::east_opt(0));
+        assert_eq!(parse_tzo("-06:00"), FixedOffset::west_opt(6 * 3600));
+        assert_eq!(
+            parse_tzo("+05:30"),
+            FixedOffset::east_opt(5 * 3600 + 30 * 60)
+        );
+        assert_eq!(parse_tzo("+24:00"), None);
+        assert_eq!(parse_tzo("+00:60"), None);
+        assert_eq!(parse_tzo("+999999:00"), None);
+        assert_eq!(parse_tzo("06:00"), None);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(3600), "1h");
+        assert_eq!(format_duration(90061), "1d 1h");
+    }
+}
@@ -0,0 +1,350 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use uuid::Uuid;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::message::Message as XmppParsersMessage;
+use xmpp_parsers::presence::Presence;
+use xmpp_parsers::Element;
+
+use crate::account::Account;
+use crate::command::{Command, CommandParser};
+use crate::core::{Aparte, Event, ModTrait};
+use crate::message::Message;
+
+const XML_WINDOW: &str = "xml";
+const NS_JABBER_CLIENT: &str = "jabber:client";
+
+command_def!(
+    xml,
+    r#"/xml [filter=<filter>] [<stanza>]
+
+    filter    Stanzas shown in the console, all of them when empty
+    stanza    Stanza to send, quoted with double quotes
+
+Description:
+    Open the XML console, which shows the stanzas sent and received while it
+    is open. With a stanza, send it from the current account once checked to
+    be a valid message, presence or iq, and mark its answer in the console.
+    Stanzas are in the jabber:client namespace, and iqs without id get one.
+
+    A filter is made of space separated terms, prefixed with - to exclude the
+    matching stanzas instead of showing them:
+        iq, message, presence    stanzas of this kind
+        xmlns=<namespace>        stanzas with an element in a namespace
+                                 starting with this one
+        jid=<text>               stanzas from or to an address containing
+                                 this text
+    Stanzas matching none of the terms without - are hidden.
+
+Examples:
+    /xml
+    /xml "<iq type='get' to='capulet.lit'><ping xmlns='urn:xmpp:ping'/></iq>"
+    /xml filter="-presence -xmlns=urn:xmpp:mam"
+    /xml filter="iq jid=capulet.lit"
+    /xml filter="#,
+    {
+        filter: Named<String>,
+        stanza: Option<String>
+    },
+    |aparte, command| {
+        if let Some(filter) = filter {
+            let filter = StanzaFilter::parse(&filter)?;
+            let info = match filter.is_empty() {
+                true => format!("Showing all stanzas"),
+                false => format!("Filtering stanzas with {}", filter),
+            };
+            XmlConsoleMod::open(aparte);
+            aparte.get_mod_mut::<XmlConsoleMod>().filter = filter;
+            aparte.schedule(Event::Info(XML_WINDOW.to_string(), Message::log(info)));
+        }
+
+        let stanza = match stanza {
+            Some(stanza) => Some(parse_stanza(&stanza)?),
+            None => None,
+        };
+        let account = match stanza {
+            Some(_) => Some(
+                command
+                    .account
+                    .clone()
+                    .or_else(|| aparte.current_account())
+                    .ok_or(format!("No connection found"))?,
+            ),
+            None => None,
+        };
+
+        XmlConsoleMod::open(aparte);
+        if let (Some(account), Some(stanza)) = (account, stanza) {
+            if let Some(id) = stanza.attr("id") {
+                aparte
+                    .get_mod_mut::<XmlConsoleMod>()
+                    .sent
+                    .insert(id.to_string());
+            }
+            aparte.send(&account, stanza);
+        }
+        Ok(())
+    }
+);
+
+/// Parse a hand-written stanza, and check it is a valid one
+fn parse_stanza(text: &str) -> Result<Element, String> {
+    // Wrapping gives the stanza the namespace of the stream
+    let wrapper: Element = format!("<stream xmlns='{}'>{}</stream>", NS_JABBER_CLIENT, text)
+        .parse()
+        .map_err(|err| format!("Invalid XML: {}", err))?;
+    if wrapper.texts().any(|text| !text.trim().is_empty()) {
+        return Err(format!("Only one stanza can be sent"));
+    }
+    let mut stanzas = wrapper.children();
+    let mut stanza = match (stanzas.next(), stanzas.next()) {
+        (Some(stanza), None) => stanza.clone(),
+        _ => return Err(format!("Only one stanza can be sent")),
+    };
+
+    match stanza.name() {
+        "iq" => {
+            if stanza.attr("id").is_none() {
+                stanza.set_attr("id", Uuid::new_v4().to_hyphenated().to_string());
+            }
+            Iq::try_from(stanza.clone()).map_err(|err| format!("Invalid iq: {}", err))?;
+        }
+        "message" => {
+            XmppParsersMessage::try_from(stanza.clone())
+                .map_err(|err| format!("Invalid message: {}", err))?;
+        }
+        "presence" => {
+            Presence::try_from(stanza.clone())
+                .map_err(|err| format!("Invalid presence: {}", err))?;
+        }
+        name => return Err(format!("<{}> isn't a message, presence or iq", name)),
+    }
+
+    Ok(stanza)
+}
+
+/// Term of a stanza filter
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    /// Stanza name: iq, message or presence
+    Kind(String),
+    /// Namespace prefix of any element of the stanza
+    Namespace(String),
+    /// Text contained in the from or to address
+    Jid(String),
+}
+
+impl Term {
+    fn matches(&self, stanza: &Element) -> bool {
+        match self {
+            Term::Kind(kind) => stanza.name() == kind.as_str(),
+            Term::Namespace(ns) => has_namespace(stanza, ns),
+            Term::Jid(text) => ["from", "to"].iter().any(|attr| {
+                stanza
+                    .attr(attr)
+                    .map_or(false, |jid| jid.contains(text.as_str()))
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Term::Kind(kind) => write!(f, "{}", kind),
+            Term::Namespace(ns) => write!(f, "xmlns={}", ns),
+            Term::Jid(text) => write!(f, "jid={}", text),
+        }
+    }
+}
+
+fn has_namespace(element: &Element, ns: &str) -> bool {
+    element.ns().starts_with(ns) || element.children().any(|child| has_namespace(child, ns))
+}
+
+/// Stanzas shown in the console
+#[derive(Debug, Clone, Default, PartialEq)]
+struct StanzaFilter {
+    /// Shown stanzas match one of these, when there are some
+    included: Vec<Term>,
+    /// Shown stanzas match none of these
+    excluded: Vec<Term>,
+}
+
+impl StanzaFilter {
+    fn parse(filter: &str) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for word in filter.split_whitespace() {
+            let (exclude, term) = match word.strip_prefix('-') {
+                Some(term) => (true, term),
+                None => (false, word),
+            };
+            let term = if let Some(ns) = term.strip_prefix("xmlns=") {
+                Term::Namespace(ns.to_string())
+            } else if let Some(text) = term.strip_prefix("jid=") {
+                Term::Jid(text.to_string())
+            } else if ["iq", "message", "presence"].contains(&term) {
+                Term::Kind(term.to_string())
+            } else {
+                return Err(format!("Invalid filter term {}", word));
+            };
+            match exclude {
+                true => parsed.excluded.push(term),
+                false => parsed.included.push(term),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.included.is_empty() && self.excluded.is_empty()
+    }
+
+    fn matches(&self, stanza: &Element) -> bool {
+        (self.included.is_empty() || self.included.iter().any(|term| term.matches(stanza)))
+            && !self.excluded.iter().any(|term| term.matches(stanza))
+    }
+}
+
+impl fmt::Display for StanzaFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let included = self.included.iter().map(|term| term.to_string());
+        let excluded = self.excluded.iter().map(|term| format!("-{}", term));
+        let terms: Vec<String> = included.chain(excluded).collect();
+        write!(f, "{}", terms.join(" "))
+    }
+}
+
+/// Show the stanzas exchanged with servers, and send hand-written ones
+pub struct XmlConsoleMod {
+    open: bool,
+    filter: StanzaFilter,
+    /// Id of the stanzas sent with /xml, to mark their answers
+    sent: HashSet<String>,
+}
+
+impl XmlConsoleMod {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            filter: StanzaFilter::default(),
+            sent: HashSet::new(),
+        }
+    }
+
+    /// Show the console, starting to record stanzas if it isn't already open
+    fn open(aparte: &mut Aparte) {
+        let open = {
+            let mut console = aparte.get_mod_mut::<XmlConsoleMod>();
+            std::mem::replace(&mut console.open, true)
+        };
+        match open {
+            true => aparte.schedule(Event::Win(XML_WINDOW.to_string())),
+            false => {
+                aparte.echo_stanzas(true);
+                aparte.schedule(Event::Results(XML_WINDOW.to_string(), Vec::new()));
+            }
+        }
+    }
+
+    fn show(&mut self, aparte: &mut Aparte, account: &Account, stanza: &Element, incoming: bool) {
+        let answer = incoming && stanza.attr("id").map_or(false, |id| self.sent.remove(id));
+        // Answers to /xml are shown whatever the filter
+        if !answer && !self.filter.matches(stanza) {
+            return;
+        }
+        let direction = match (incoming, answer) {
+            (true, true) => "⇐ answer",
+            (true, false) => "←",
+            (false, _) => "→",
+        };
+        let text = format!("{} {} {}", direction, account, String::from(stanza));
+        aparte.schedule(Event::Info(XML_WINDOW.to_string(), Message::log(text)));
+    }
+}
+
+impl ModTrait for XmlConsoleMod {
+    fn init(&mut self, aparte: &mut Aparte) -> Result<(), ()> {
+        aparte.add_command(xml::new());
+
+        Ok(())
+    }
+
+    fn on_event(&mut self, aparte: &mut Aparte, event: &Event) {
+        match event {
+            Event::Stanza(account, stanza) if self.open => self.show(aparte, account, stanza, true),
+            Event::SentStanza(account, stanza) if self.open => {
+                self.show(aparte, account, stanza, false)
+            }
+            Event::Close(window) if window == XML_WINDOW => {
+                self.open = false;
+                self.sent.clear();
+                aparte.echo_stanzas(false);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for XmlConsoleMod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "XML console")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stanza() {
+        // Given
+        let ping = "<iq type='get' to='capulet.lit'><ping xmlns='urn:xmpp:ping'/></iq>";
+
+        // When
+        let stanza = parse_stanza(ping).unwrap();
+
+        // Then
+        assert!(stanza.is("iq", NS_JABBER_CLIENT));
+        assert!(stanza.attr("id").is_some());
+        assert!(stanza.has_child("ping", "urn:xmpp:ping"));
+        assert!(parse_stanza("<iq type='result' id='1'/>").is_ok());
+        assert!(parse_stanza("<iq type='get'/>").is_err());
+        assert!(parse_stanza("<query xmlns='jabber:iq:roster'/>").is_err());
+        assert!(parse_stanza("<presence/><presence/>").is_err());
+        assert!(parse_stanza("<presence>").is_err());
+    }
+
+    #[test]
+    fn test_stanza_filter() {
+        // Given
+        let filter = StanzaFilter::parse("iq message -xmlns=urn:xmpp:mam -jid=verona@").unwrap();
+        let ping: Element = "<iq xmlns='jabber:client' type='get' id='1' to='capulet.lit'><ping xmlns='urn:xmpp:ping'/></iq>".parse().unwrap();
+        let archived: Element = "<message xmlns='jabber:client' to='romeo@montague.lit/orchard'><result xmlns='urn:xmpp:mam:2' id='1'/></message>".parse().unwrap();
+        let channel: Element = "<message xmlns='jabber:client' from='verona@chat.shakespeare.lit/Juliet'><body>Hi</body></message>".parse().unwrap();
+        let presence: Element =
+            "<presence xmlns='jabber:client' from='juliet@capulet.lit/balcony'/>"
+                .parse()
+                .unwrap();
+
+        // When
+        let shown: Vec<bool> = [&ping, &archived, &channel, &presence]
+            .iter()
+            .map(|stanza| filter.matches(stanza))
+            .collect();
+
+        // Then
+        assert_eq!(shown, vec![true, false, false, false]);
+        assert_eq!(
+            filter.to_string(),
+            "iq message -xmlns=urn:xmpp:mam -jid=verona@"
+        );
+        assert!(StanzaFilter::parse("").unwrap().matches(&presence));
+        assert!(StanzaFilter::parse("-presence").unwrap().matches(&ping));
+        assert!(StanzaFilter::parse("roster").is_err());
+    }
+}
@@ -0,0 +1,88 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::time::{Duration, Instant};
+
+/// Token bucket allowing `burst` sends at once, then `rate` sends per second
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    /// A rate of 0 disables the limit
+    pub fn new(rate: f64, burst: u32, now: Instant) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+    }
+
+    /// Time to wait before the next send is allowed
+    pub fn delay(&mut self, now: Instant) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::from_secs(0);
+        }
+
+        self.refill(now);
+        match self.tokens >= 1.0 {
+            true => Duration::from_secs(0),
+            false => Duration::from_secs_f64((1.0 - self.tokens) / self.rate),
+        }
+    }
+
+    /// Account for a send
+    pub fn take(&mut self, now: Instant) {
+        if self.rate > 0.0 {
+            self.refill(now);
+            self.tokens -= 1.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_rate() {
+        // Given
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2.0, 3, start);
+
+        // When
+        for _ in 0..3 {
+            assert_eq!(limiter.delay(start), Duration::from_secs(0));
+            limiter.take(start);
+        }
+
+        // Then
+        assert_eq!(limiter.delay(start), Duration::from_millis(500));
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.delay(later), Duration::from_secs(0));
+        limiter.take(later);
+        assert_eq!(limiter.delay(later), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(0.0, 1, start);
+        for _ in 0..100 {
+            limiter.take(start);
+        }
+        assert_eq!(limiter.delay(start), Duration::from_secs(0));
+    }
+}
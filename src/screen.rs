@@ -0,0 +1,542 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::io::{self, Write};
+use termion::input::MouseTerminal;
+use termion::raw::RawTerminal;
+use termion::screen::AlternateScreen;
+use unicode_segmentation::UnicodeSegmentation;
+
+type Terminal<W> = MouseTerminal<AlternateScreen<RawTerminal<W>>>;
+
+/// Graphic rendition of a cell
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Style {
+    /// Enabled attributes, indexed by their SGR code (1 is bold, 3 is italic…)
+    attributes: [bool; 10],
+    foreground: Option<String>,
+    background: Option<String>,
+}
+
+impl Style {
+    /// Apply the parameters of a Select Graphic Rendition sequence
+    fn apply(&mut self, params: &str) {
+        let params = params.split(';').collect::<Vec<&str>>();
+        let mut i = 0;
+        while i < params.len() {
+            let code = params[i].parse::<u8>().unwrap_or(0);
+            match code {
+                0 => *self = Style::default(),
+                1..=9 => self.attributes[code as usize] = true,
+                // termion's NoBold
+                21 => self.attributes[1] = false,
+                22 => {
+                    self.attributes[1] = false;
+                    self.attributes[2] = false;
+                }
+                23..=29 => self.attributes[(code - 20) as usize] = false,
+                30..=37 | 90..=97 => self.foreground = Some(code.to_string()),
+                39 => self.foreground = None,
+                40..=47 | 100..=107 => self.background = Some(code.to_string()),
+                49 => self.background = None,
+                38 | 48 => {
+                    // Extended colors are either 5;<index> or 2;<r>;<g>;<b>
+                    let len = match params.get(i + 1) {
+                        Some(&"5") => 2,
+                        Some(&"2") => 4,
+                        _ => 0,
+                    };
+                    let end = std::cmp::min(i + 1 + len, params.len());
+                    let color = params[i..end].join(";");
+                    match code {
+                        38 => self.foreground = Some(color),
+                        _ => self.background = Some(color),
+                    }
+                    i = end;
+                    continue;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    fn sequence(&self) -> String {
+        let mut codes = vec!["0".to_string()];
+        for (code, enabled) in self.attributes.iter().enumerate() {
+            if *enabled {
+                codes.push(code.to_string());
+            }
+        }
+        codes.extend(self.foreground.iter().cloned());
+        codes.extend(self.background.iter().cloned());
+        format!("\x1b[{}m", codes.join(";"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cell {
+    grapheme: String,
+    style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            grapheme: " ".to_string(),
+            style: Style::default(),
+        }
+    }
+}
+
+/// Content of the terminal as written by views, compared with what is displayed on flush
+struct Grid {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    /// Cells displayed by the terminal, None when unknown
+    drawn: Vec<Option<Cell>>,
+    /// Rows written or invalidated since the last render, the others are left as drawn
+    dirty: Vec<bool>,
+    /// Position of the cursor, starting at 1 like termion's Goto
+    cursor: (u16, u16),
+    saved_cursor: (u16, u16),
+    cursor_visible: bool,
+    style: Style,
+    /// Incomplete escape sequence or UTF-8 char
+    pending: Vec<u8>,
+    /// Sequences written before the cells: modes, title, bell…
+    controls: Vec<u8>,
+    /// Images written after the cells, at the position they were drawn at
+    graphics: Vec<((u16, u16), Vec<u8>)>,
+}
+
+impl Grid {
+    fn new() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            cells: Vec::new(),
+            drawn: Vec::new(),
+            dirty: Vec::new(),
+            cursor: (1, 1),
+            saved_cursor: (1, 1),
+            cursor_visible: true,
+            style: Style::default(),
+            pending: Vec::new(),
+            controls: Vec::new(),
+            graphics: Vec::new(),
+        }
+    }
+
+    fn resize(&mut self, width: u16, height: u16) {
+        if (width, height) != (self.width, self.height) {
+            let size = width as usize * height as usize;
+            self.width = width;
+            self.height = height;
+            self.cells = vec![Cell::default(); size];
+            self.drawn = vec![None; size];
+            self.dirty = vec![true; height as usize];
+        }
+    }
+
+    /// Mark a row as changed, starting at 1 like termion's Goto
+    fn mark(&mut self, y: u16) {
+        if let Some(dirty) = self.dirty.get_mut((y as usize).wrapping_sub(1)) {
+            *dirty = true;
+        }
+    }
+
+    fn index(&self, x: u16, y: u16) -> Option<usize> {
+        match x >= 1 && y >= 1 && x <= self.width && y <= self.height {
+            true => Some((y as usize - 1) * self.width as usize + x as usize - 1),
+            false => None,
+        }
+    }
+
+    fn invalidate(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        for y in y..y + height {
+            self.mark(y);
+            for x in x..x + width {
+                if let Some(index) = self.index(x, y) {
+                    self.drawn[index] = None;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) {
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend_from_slice(buf);
+
+        let mut start = 0;
+        while start < input.len() {
+            match self.parse(&input[start..]) {
+                Some(len) => start += len,
+                None => break,
+            }
+        }
+        self.pending = input[start..].to_vec();
+    }
+
+    /// Handle the first sequence or text run of input, return its length or None if incomplete
+    fn parse(&mut self, input: &[u8]) -> Option<usize> {
+        match input[0] {
+            b'\x1b' => match input.get(1)? {
+                b'[' => {
+                    let end = 2 + input[2..]
+                        .iter()
+                        .position(|byte| (0x40..=0x7e).contains(byte))?;
+                    let params = String::from_utf8_lossy(&input[2..end]).to_string();
+                    self.csi(&params, input[end], &input[..=end]);
+                    Some(end + 1)
+                }
+                b']' | b'_' | b'P' => {
+                    // String terminated by ST, OSC can also be terminated by BEL
+                    let end = (2..input.len()).find(|i| {
+                        input[*i] == b'\x07' || (input[*i] == b'\\' && input[*i - 1] == b'\x1b')
+                    })?;
+                    let sequence = input[..=end].to_vec();
+                    if input[1] == b']' && !sequence.starts_with(b"\x1b]1337;") {
+                        self.controls.extend(sequence);
+                    } else {
+                        // Chunks of an image must be sent without anything in between
+                        match self.graphics.last_mut() {
+                            Some((position, graphic)) if *position == self.cursor => {
+                                graphic.extend(sequence)
+                            }
+                            _ => self.graphics.push((self.cursor, sequence)),
+                        }
+                    }
+                    Some(end + 1)
+                }
+                b'7' => {
+                    self.saved_cursor = self.cursor;
+                    Some(2)
+                }
+                b'8' => {
+                    self.cursor = self.saved_cursor;
+                    Some(2)
+                }
+                _ => {
+                    self.controls.extend(&input[..2]);
+                    Some(2)
+                }
+            },
+            b'\x07' => {
+                self.controls.push(b'\x07');
+                Some(1)
+            }
+            b'\r' => {
+                self.cursor.0 = 1;
+                Some(1)
+            }
+            b'\n' => {
+                self.cursor = (1, self.cursor.1 + 1);
+                Some(1)
+            }
+            byte if byte < 0x20 || byte == 0x7f => Some(1),
+            _ => {
+                let end = input
+                    .iter()
+                    .position(|byte| *byte < 0x20 || *byte == 0x7f)
+                    .unwrap_or(input.len());
+                let (text, len) = match std::str::from_utf8(&input[..end]) {
+                    Ok(text) => (text.to_string(), end),
+                    Err(err) => match (err.valid_up_to(), err.error_len()) {
+                        // Wait for the end of a char split between two writes
+                        (0, None) => return None,
+                        (valid, None) => {
+                            (String::from_utf8_lossy(&input[..valid]).to_string(), valid)
+                        }
+                        (valid, Some(invalid)) => (
+                            String::from_utf8_lossy(&input[..valid + invalid]).to_string(),
+                            valid + invalid,
+                        ),
+                    },
+                };
+                self.mark(self.cursor.1);
+                for grapheme in text.graphemes(true) {
+                    if let Some(index) = self.index(self.cursor.0, self.cursor.1) {
+                        self.cells[index] = Cell {
+                            grapheme: grapheme.to_string(),
+                            style: self.style.clone(),
+                        };
+                    }
+                    self.cursor.0 = self.cursor.0.saturating_add(1);
+                }
+                Some(len)
+            }
+        }
+    }
+
+    /// Handle a Control Sequence Introducer
+    fn csi(&mut self, params: &str, action: u8, sequence: &[u8]) {
+        match (params, action) {
+            (_, b'H') | (_, b'f') => {
+                let mut params = params.split(';').map(|param| param.parse().unwrap_or(1));
+                let y = params.next().unwrap_or(1);
+                let x = params.next().unwrap_or(1);
+                self.cursor = (x, y);
+            }
+            (_, b'm') => self.style.apply(params),
+            ("", b's') => self.saved_cursor = self.cursor,
+            ("", b'u') => self.cursor = self.saved_cursor,
+            ("?25", b'l') => self.cursor_visible = false,
+            ("?25", b'h') => self.cursor_visible = true,
+            ("2", b'J') => {
+                // The terminal is cleared along with the grid
+                self.controls.extend(sequence);
+                for cell in self.cells.iter_mut() {
+                    *cell = Cell::default();
+                }
+                for cell in self.drawn.iter_mut() {
+                    *cell = Some(Cell::default());
+                }
+            }
+            ("", b'K') | ("0", b'K') => {
+                self.mark(self.cursor.1);
+                for x in self.cursor.0..=self.width {
+                    if let Some(index) = self.index(x, self.cursor.1) {
+                        self.cells[index] = Cell {
+                            grapheme: " ".to_string(),
+                            style: self.style.clone(),
+                        };
+                    }
+                }
+            }
+            _ => self.controls.extend(sequence),
+        }
+    }
+
+    /// Sequences updating the terminal to display the grid
+    fn render(&mut self) -> Vec<u8> {
+        let mut output = std::mem::take(&mut self.controls);
+        let mut style = None;
+        let width = self.width as usize;
+
+        // Rows are written from their start, chars wider than a cell then shift the row like
+        // they do when written by views
+        for y in 0..self.height as usize {
+            if !std::mem::replace(&mut self.dirty[y], false) {
+                continue;
+            }
+            let row = y * width..(y + 1) * width;
+            if row
+                .clone()
+                .all(|index| self.drawn[index].as_ref() == Some(&self.cells[index]))
+            {
+                continue;
+            }
+
+            output.extend(format!("{}", termion::cursor::Goto(1, y as u16 + 1)).bytes());
+            for index in row {
+                let cell = &self.cells[index];
+                if style.as_ref() != Some(&cell.style) {
+                    output.extend(cell.style.sequence().bytes());
+                    style = Some(cell.style.clone());
+                }
+                output.extend(cell.grapheme.bytes());
+                self.drawn[index] = Some(cell.clone());
+            }
+        }
+
+        for ((x, y), graphic) in self.graphics.drain(..) {
+            output.extend(format!("{}", termion::cursor::Goto(x, y)).bytes());
+            output.extend(graphic);
+        }
+
+        if style.is_some() {
+            output.extend(format!("{}", termion::style::Reset).bytes());
+        }
+        output.extend(format!("{}", termion::cursor::Goto(self.cursor.0, self.cursor.1)).bytes());
+        match self.cursor_visible {
+            true => output.extend(format!("{}", termion::cursor::Show).bytes()),
+            false => output.extend(format!("{}", termion::cursor::Hide).bytes()),
+        }
+
+        output
+    }
+}
+
+/// Terminal written through an in-memory grid, only the rows that changed are written on flush
+pub struct Screen<W: Write> {
+    /// Everything written is dropped without terminal
+    terminal: Option<Terminal<W>>,
+    grid: Grid,
+    /// Write straight to the terminal, for output appended line by line
+    direct: bool,
+}
+
+impl<W: Write> Screen<W> {
+    pub fn new(terminal: Terminal<W>) -> Self {
+        Self {
+            terminal: Some(terminal),
+            grid: Grid::new(),
+            direct: false,
+        }
+    }
+
+    /// Screen writing to the terminal without going through the grid
+    pub fn direct(terminal: Terminal<W>) -> Self {
+        Self {
+            direct: true,
+            ..Self::new(terminal)
+        }
+    }
+
+    /// Screen without terminal, when running headless
+    pub fn headless() -> Self {
+        Self {
+            terminal: None,
+            grid: Grid::new(),
+            direct: true,
+        }
+    }
+
+    /// Leave raw mode, to hand the terminal over to another program
+    pub fn suspend_raw_mode(&self) -> io::Result<()> {
+        match &self.terminal {
+            Some(terminal) => terminal.suspend_raw_mode(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn activate_raw_mode(&self) -> io::Result<()> {
+        match &self.terminal {
+            Some(terminal) => terminal.activate_raw_mode(),
+            None => Ok(()),
+        }
+    }
+
+    /// Resize the grid, everything is written again on next flush
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.grid.resize(width, height);
+    }
+
+    /// Write the given area again on next flush, even if unchanged
+    pub fn invalidate(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        self.grid.invalidate(x, y, width, height);
+    }
+}
+
+impl<W: Write> Write for Screen<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.terminal {
+            None => Ok(buf.len()),
+            Some(terminal) if self.direct => terminal.write(buf),
+            Some(_) => {
+                self.grid.write(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let terminal = match &mut self.terminal {
+            Some(terminal) => terminal,
+            None => return Ok(()),
+        };
+        if !self.direct {
+            let output = self.grid.render();
+            terminal.write_all(&output)?;
+        }
+        terminal.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(grid: &mut Grid) -> String {
+        String::from_utf8(grid.render()).unwrap()
+    }
+
+    #[test]
+    fn test_grid_only_changed_rows() {
+        // Given
+        let mut grid = Grid::new();
+        grid.resize(4, 2);
+        grid.write(b"\x1b[1;1Hab\x1b[2;1Hcd");
+        render(&mut grid);
+
+        // When
+        grid.write(b"\x1b[1;1Hab\x1b[2;1H\x1b[1mcx\x1b[21m");
+        let output = render(&mut grid);
+
+        // Then
+        assert_eq!(
+            output,
+            "\x1b[2;1H\x1b[0;1mcx\x1b[0m  \x1b[m\x1b[2;3H\x1b[?25h"
+        );
+        assert_eq!(render(&mut grid), "\x1b[2;3H\x1b[?25h");
+    }
+
+    #[test]
+    fn test_grid_dirty_rows() {
+        // Given
+        let mut grid = Grid::new();
+        grid.resize(4, 3);
+        render(&mut grid);
+
+        // When
+        grid.write(b"\x1b[2;1Hab\x1b[3;1H");
+        let written = grid.dirty.clone();
+        grid.invalidate(1, 1, 4, 1);
+        let invalidated = grid.dirty.clone();
+        render(&mut grid);
+
+        // Then
+        assert_eq!(written, vec![false, true, false]);
+        assert_eq!(invalidated, vec![true, true, false]);
+        assert_eq!(grid.dirty, vec![false, false, false]);
+    }
+
+    #[test]
+    fn test_grid_controls_and_graphics() {
+        // Given
+        let mut grid = Grid::new();
+        grid.resize(4, 2);
+        render(&mut grid);
+
+        // When
+        grid.write(b"\x1b[s\x1b]2;title\x07\x1b[2;2H\x1b_Ga=T;AAAA\x1b\\\x1b[u");
+        let output = render(&mut grid);
+
+        // Then
+        assert!(output.starts_with("\x1b]2;title\x07"));
+        assert!(output.contains("\x1b[2;2H\x1b_Ga=T;AAAA\x1b\\"));
+        assert!(output.ends_with("\x1b[1;1H\x1b[?25h"));
+    }
+
+    #[test]
+    fn test_grid_split_char() {
+        // Given
+        let mut grid = Grid::new();
+        grid.resize(4, 1);
+
+        // When
+        grid.write(&"é".as_bytes()[..1]);
+        grid.write(&"é".as_bytes()[1..]);
+
+        // Then
+        assert_eq!(grid.cells[0].grapheme, "é");
+        assert_eq!(grid.cursor, (2, 1));
+    }
+
+    #[test]
+    fn test_style_extended_colors() {
+        // Given
+        let mut style = Style::default();
+
+        // When
+        style.apply("38;2;1;2;3;48;5;4;3");
+
+        // Then
+        assert_eq!(style.foreground, Some("38;2;1;2;3".to_string()));
+        assert_eq!(style.background, Some("48;5;4".to_string()));
+        assert!(style.attributes[3]);
+        assert_eq!(style.sequence(), "\x1b[0;3;38;2;1;2;3;48;5;4m");
+    }
+}
@@ -0,0 +1,182 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::collections::BTreeMap;
+use toml::Value;
+
+/// Option that can be changed at runtime with /set
+#[derive(Debug, Clone)]
+pub struct Setting {
+    pub help: &'static str,
+    /// Current value, its type is the one of the setting
+    pub value: Value,
+    /// Check a new value before it is applied
+    validate: fn(&Value) -> Result<(), String>,
+}
+
+/// Registry of the settings of all mods, indexed by their dotted config key
+#[derive(Debug, Default)]
+pub struct Settings {
+    settings: BTreeMap<String, Setting>,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a setting with its current value
+    pub fn register(&mut self, key: &str, value: Value, help: &'static str) {
+        self.register_validated(key, value, help, |_| Ok(()));
+    }
+
+    /// Register a setting whose new values must pass `validate`
+    pub fn register_validated(
+        &mut self,
+        key: &str,
+        value: Value,
+        help: &'static str,
+        validate: fn(&Value) -> Result<(), String>,
+    ) {
+        self.settings.insert(
+            key.to_string(),
+            Setting {
+                help,
+                value,
+                validate,
+            },
+        );
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Setting> {
+        self.settings.get(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Setting)> {
+        self.settings.iter()
+    }
+
+    /// Parse a raw value given the type of the setting
+    pub fn parse(&self, key: &str, raw: &str) -> Result<Value, String> {
+        let setting = self
+            .settings
+            .get(key)
+            .ok_or(format!("Unknown setting {}", key))?;
+        let invalid = |type_: &str| format!("Invalid value for {}, expected {}", key, type_);
+
+        match setting.value {
+            Value::Boolean(_) => match raw {
+                "true" | "on" | "yes" => Ok(Value::Boolean(true)),
+                "false" | "off" | "no" => Ok(Value::Boolean(false)),
+                _ => Err(invalid("a boolean")),
+            },
+            Value::Integer(_) => raw
+                .parse()
+                .map(Value::Integer)
+                .map_err(|_| invalid("an integer")),
+            Value::Float(_) => raw
+                .parse()
+                .map(Value::Float)
+                .map_err(|_| invalid("a number")),
+            _ => Ok(Value::String(raw.to_string())),
+        }
+    }
+
+    /// Change the value of a setting, it must keep its type
+    pub fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match self.settings.get_mut(key) {
+            Some(setting) if setting.value.same_type(&value) => {
+                (setting.validate)(&value)?;
+                setting.value = value;
+                Ok(())
+            }
+            Some(_) => Err(format!("Invalid type for {}", key)),
+            None => Err(format!("Unknown setting {}", key)),
+        }
+    }
+}
+
+/// Write the value of a setting in a config file, keeping its formatting and comments
+pub fn persist(config: &str, key: &str, value: &Value) -> Result<String, String> {
+    let mut document = config
+        .parse::<toml_edit::Document>()
+        .map_err(|err| format!("Malformed config file: {}", err))?;
+
+    let value = match value {
+        Value::Boolean(value) => toml_edit::value(*value),
+        Value::Integer(value) => toml_edit::value(*value),
+        Value::Float(value) => toml_edit::value(*value),
+        Value::String(value) => toml_edit::value(value.as_str()),
+        _ => return Err(format!("Cannot save {}", key)),
+    };
+
+    let mut parts = key.split('.');
+    let mut item = &mut document[parts.next().unwrap()];
+    for part in parts {
+        item = &mut item[part];
+    }
+    *item = value;
+
+    Ok(document.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_parse_and_set() {
+        // Given
+        let mut settings = Settings::new();
+        settings.register("preview.rows", Value::Integer(8), "rows");
+        settings.register("time_format", Value::String("%T".to_string()), "format");
+
+        // When
+        let rows = settings.parse("preview.rows", "4").unwrap();
+        settings.set("preview.rows", rows).unwrap();
+
+        // Then
+        assert_eq!(
+            settings.get("preview.rows").unwrap().value,
+            Value::Integer(4)
+        );
+        assert!(settings.parse("preview.rows", "four").is_err());
+        assert!(settings.parse("unknown", "4").is_err());
+        assert!(settings.set("time_format", Value::Boolean(true)).is_err());
+    }
+
+    #[test]
+    fn test_settings_validation() {
+        // Given
+        let mut settings = Settings::new();
+        settings.register_validated(
+            "preview.rows",
+            Value::Integer(8),
+            "rows",
+            |value| match value.as_integer() {
+                Some(rows) if rows > 0 => Ok(()),
+                _ => Err("Rows must be positive".to_string()),
+            },
+        );
+
+        // Then
+        assert!(settings.set("preview.rows", Value::Integer(0)).is_err());
+        assert_eq!(
+            settings.get("preview.rows").unwrap().value,
+            Value::Integer(8)
+        );
+    }
+
+    #[test]
+    fn test_persist() {
+        let config = "# Colors\ntime_format = \"%T\"\n\n[preview]\nenabled = true\n";
+
+        let config = persist(config, "preview.rows", &Value::Integer(4)).unwrap();
+        let config = persist(&config, "time_format", &Value::String("%R".to_string())).unwrap();
+
+        assert!(config.starts_with("# Colors\n"));
+        assert!(config.contains("time_format = \"%R\""));
+        assert!(config.contains("rows = 4"));
+        assert!(config.contains("enabled = true"));
+    }
+}
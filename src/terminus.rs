@@ -9,12 +9,11 @@ use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::hash::Hash;
 use std::io::Write;
+use std::mem;
 use std::rc::Rc;
-use termion::raw::RawTerminal;
-use termion::screen::AlternateScreen;
 use unicode_segmentation::UnicodeSegmentation;
 
-pub type Screen<W> = AlternateScreen<RawTerminal<W>>;
+pub use crate::screen::Screen;
 
 pub fn term_string_visible_len(string: &str) -> usize {
     // Count each grapheme on a given struct but ignore invisible chars sequences like '\x1b[…'
@@ -53,6 +52,43 @@ pub fn term_string_visible_len(string: &str) -> usize {
 }
 
 /// Remove all terminal specific chars sequences
+/// Surround occurrences of `term` in the text displayed by `buf` with `start` and `end`, escape
+/// sequences being skipped like `clean` does
+fn highlight_term(buf: &str, term: &str, start: &str, end: &str) -> String {
+    // Displayed text, and the position in buf of each of its bytes
+    let mut text = String::new();
+    let mut positions = Vec::new();
+    let mut chars = buf.char_indices().peekable();
+    while let Some((position, c)) = chars.next() {
+        if c == '\x1b' {
+            if let Some((_, '[')) = chars.peek() {
+                chars.next();
+                while let Some((_, c)) = chars.next() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        text.push(c);
+        positions.extend(position..position + c.len_utf8());
+    }
+
+    let mut output = String::new();
+    let mut copied = 0;
+    for (index, _) in text.match_indices(term) {
+        let (from, to) = (positions[index], positions[index + term.len() - 1] + 1);
+        output.push_str(&buf[copied..from]);
+        output.push_str(start);
+        output.push_str(&buf[from..to]);
+        output.push_str(end);
+        copied = to;
+    }
+    output.push_str(&buf[copied..]);
+    output
+}
+
 pub fn clean(string: &str) -> String {
     let mut output = String::new();
     let mut iter = string.chars();
@@ -394,7 +430,7 @@ macro_rules! flush {
     };
 }
 
-#[cfg(not(feature = "no-cursor-save"))]
+/// The cursor is only saved by the screen grid, it is never sent to the terminal
 #[macro_export]
 macro_rules! save_cursor {
     ($screen:expr) => {
@@ -402,15 +438,6 @@ macro_rules! save_cursor {
     };
 }
 
-#[cfg(feature = "no-cursor-save")]
-#[macro_export]
-macro_rules! save_cursor {
-    ($screen:expr) => {
-        let (cursor_x, cursor_y) = $screen.cursor_pos().unwrap();
-    };
-}
-
-#[cfg(not(feature = "no-cursor-save"))]
 #[macro_export]
 macro_rules! restore_cursor {
     ($screen:expr) => {
@@ -418,15 +445,187 @@ macro_rules! restore_cursor {
     };
 }
 
-#[cfg(feature = "no-cursor-save")]
-#[macro_export]
-macro_rules! restore_cursor {
-    ($screen:expr) => {
-        goto!($screen, cursor_x, cursor_y);
-    };
+impl<E, W> dyn View<E, W> where W: Write {}
+
+/// Tree of panes splitting a FrameLayout area, each leaf displays at most one child
+enum Pane<K> {
+    Leaf(Option<K>),
+    Split {
+        /// How children are laid out, same meaning as for LinearLayout
+        orientation: Orientation,
+        /// Share of the space given to the first child
+        ratio: f32,
+        first: Box<Pane<K>>,
+        second: Box<Pane<K>>,
+    },
 }
 
-impl<E, W> dyn View<E, W> where W: Write {}
+impl<K> Pane<K>
+where
+    K: Clone,
+{
+    fn count(&self) -> usize {
+        match self {
+            Pane::Leaf(_) => 1,
+            Pane::Split { first, second, .. } => first.count() + second.count(),
+        }
+    }
+
+    fn leaves<'a>(&'a self, leaves: &mut Vec<&'a Option<K>>) {
+        match self {
+            Pane::Leaf(key) => leaves.push(key),
+            Pane::Split { first, second, .. } => {
+                first.leaves(leaves);
+                second.leaves(leaves);
+            }
+        }
+    }
+
+    fn leaves_mut<'a>(&'a mut self, leaves: &mut Vec<&'a mut Option<K>>) {
+        match self {
+            Pane::Leaf(key) => leaves.push(key),
+            Pane::Split { first, second, .. } => {
+                first.leaves_mut(leaves);
+                second.leaves_mut(leaves);
+            }
+        }
+    }
+
+    fn nth_leaf_mut<'a>(&'a mut self, n: &mut usize) -> Option<&'a mut Pane<K>> {
+        match self {
+            Pane::Leaf(_) => {
+                if *n == 0 {
+                    Some(self)
+                } else {
+                    *n -= 1;
+                    None
+                }
+            }
+            Pane::Split { first, second, .. } => match first.nth_leaf_mut(n) {
+                Some(leaf) => Some(leaf),
+                None => second.nth_leaf_mut(n),
+            },
+        }
+    }
+
+    /// Remove the nth leaf, its sibling taking the space of their parent
+    fn remove_leaf(&mut self, n: usize) {
+        let sibling = match self {
+            Pane::Leaf(_) => return,
+            Pane::Split { first, second, .. } => {
+                let first_count = first.count();
+                if n < first_count {
+                    match **first {
+                        Pane::Leaf(_) => mem::replace(&mut **second, Pane::Leaf(None)),
+                        _ => return first.remove_leaf(n),
+                    }
+                } else {
+                    match **second {
+                        Pane::Leaf(_) => mem::replace(&mut **first, Pane::Leaf(None)),
+                        _ => return second.remove_leaf(n - first_count),
+                    }
+                }
+            }
+        };
+        *self = sibling;
+    }
+
+    /// Grow the nth leaf by delta inside its closest split, return false if there is none
+    fn resize(&mut self, n: usize, delta: f32) -> bool {
+        match self {
+            Pane::Leaf(_) => false,
+            Pane::Split {
+                ratio,
+                first,
+                second,
+                ..
+            } => {
+                let first_count = first.count();
+                if n < first_count {
+                    if !first.resize(n, delta) {
+                        *ratio = (*ratio + delta).max(0.1).min(0.9);
+                    }
+                } else if !second.resize(n - first_count, delta) {
+                    *ratio = (*ratio - delta).max(0.1).min(0.9);
+                }
+                true
+            }
+        }
+    }
+
+    /// Compute dimension of each leaf and of separators between panes
+    fn arrange(
+        &self,
+        area: Dimension,
+        leaves: &mut Vec<(Option<K>, Dimension)>,
+        separators: &mut Vec<Dimension>,
+    ) {
+        match self {
+            Pane::Leaf(key) => leaves.push((key.clone(), area)),
+            Pane::Split {
+                orientation,
+                ratio,
+                first,
+                second,
+            } => {
+                let (w, h) = (area.w.unwrap_or(0), area.h.unwrap_or(0));
+                let (first_area, separator, second_area) = match orientation {
+                    Orientation::Horizontal => {
+                        let available = w.saturating_sub(1);
+                        let first_w = (available as f32 * ratio) as u16;
+                        (
+                            Dimension {
+                                x: area.x,
+                                y: area.y,
+                                w: Some(first_w),
+                                h: Some(h),
+                            },
+                            Dimension {
+                                x: area.x + first_w,
+                                y: area.y,
+                                w: Some(1),
+                                h: Some(h),
+                            },
+                            Dimension {
+                                x: area.x + first_w + 1,
+                                y: area.y,
+                                w: Some(available - first_w),
+                                h: Some(h),
+                            },
+                        )
+                    }
+                    Orientation::Vertical => {
+                        let available = h.saturating_sub(1);
+                        let first_h = (available as f32 * ratio) as u16;
+                        (
+                            Dimension {
+                                x: area.x,
+                                y: area.y,
+                                w: Some(w),
+                                h: Some(first_h),
+                            },
+                            Dimension {
+                                x: area.x,
+                                y: area.y + first_h,
+                                w: Some(w),
+                                h: Some(1),
+                            },
+                            Dimension {
+                                x: area.x,
+                                y: area.y + first_h + 1,
+                                w: Some(w),
+                                h: Some(available - first_h),
+                            },
+                        )
+                    }
+                };
+                first.arrange(first_area, leaves, separators);
+                separators.push(separator);
+                second.arrange(second_area, leaves, separators);
+            }
+        }
+    }
+}
 
 pub struct FrameLayout<E, W, K>
 where
@@ -434,7 +633,12 @@ where
     W: Write,
 {
     children: HashMap<K, (Dimension, Box<dyn View<E, W>>)>,
-    current: Option<K>,
+    panes: Pane<K>,
+    /// Index of the focused leaf of panes
+    focus: usize,
+    separators: Vec<Dimension>,
+    /// Areas of the panes without window, blanked as they may still show a previous one
+    empty: Vec<Dimension>,
     event_handler: Option<Rc<RefCell<Box<dyn FnMut(&mut Self, &mut E)>>>>,
     dirty: bool,
     layouts: Layouts,
@@ -448,7 +652,10 @@ where
     pub fn new() -> Self {
         Self {
             children: HashMap::new(),
-            current: None,
+            panes: Pane::Leaf(None),
+            focus: 0,
+            separators: Vec::new(),
+            empty: Vec::new(),
             event_handler: None,
             dirty: true,
             layouts: Layouts {
@@ -472,14 +679,29 @@ where
         self
     }
 
+    fn visible(&self) -> Vec<K> {
+        let mut leaves = Vec::new();
+        self.panes.leaves(&mut leaves);
+        leaves.into_iter().filter_map(|key| key.clone()).collect()
+    }
+
+    /// Display the given child in the focused pane, swapping panes if it's already visible
     pub fn set_current(&mut self, key: K) {
-        self.current = Some(key);
+        let mut leaves = Vec::new();
+        self.panes.leaves_mut(&mut leaves);
+        let focused = leaves[self.focus].clone();
+        for (i, leaf) in leaves.iter_mut().enumerate() {
+            if i != self.focus && leaf.as_ref() == Some(&key) {
+                **leaf = focused.clone();
+            }
+        }
+        *leaves[self.focus] = Some(key);
         self.dirty = true;
     }
 
     pub fn get_current_mut<'a>(&'a mut self) -> Option<&'a mut Box<dyn View<E, W>>> {
-        if let Some(current) = &self.current {
-            if let Some((_, view)) = self.children.get_mut(current) {
+        if let Some(current) = self.get_current_key().cloned() {
+            if let Some((_, view)) = self.children.get_mut(&current) {
                 Some(view)
             } else {
                 unreachable!();
@@ -491,7 +713,7 @@ where
 
     #[allow(unused)]
     pub fn get_current<'a>(&'a self) -> Option<&'a Box<dyn View<E, W>>> {
-        if let Some(current) = &self.current {
+        if let Some(current) = self.get_current_key() {
             if let Some((_, view)) = self.children.get(current) {
                 Some(view)
             } else {
@@ -504,7 +726,9 @@ where
 
     #[allow(unused)]
     pub fn get_current_key<'a>(&'a self) -> Option<&'a K> {
-        self.current.as_ref()
+        let mut leaves = Vec::new();
+        self.panes.leaves(&mut leaves);
+        leaves[self.focus].as_ref()
     }
 
     #[allow(unused)]
@@ -523,8 +747,59 @@ where
 
     pub fn remove(&mut self, key: &K) {
         self.children.remove(key);
-        if Some(key) == self.current.as_ref() {
-            self.current = self.children.keys().next().cloned();
+
+        let visible = self.visible();
+        let replacement = self
+            .children
+            .keys()
+            .find(|child| !visible.contains(*child))
+            .cloned();
+
+        let mut leaves = Vec::new();
+        self.panes.leaves_mut(&mut leaves);
+        for leaf in leaves {
+            if leaf.as_ref() == Some(key) {
+                *leaf = replacement.clone();
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Split the focused pane in two, the new pane is empty
+    pub fn split(&mut self, orientation: Orientation) {
+        let mut n = self.focus;
+        if let Some(leaf) = self.panes.nth_leaf_mut(&mut n) {
+            let first = mem::replace(leaf, Pane::Leaf(None));
+            *leaf = Pane::Split {
+                orientation,
+                ratio: 0.5,
+                first: Box::new(first),
+                second: Box::new(Pane::Leaf(None)),
+            };
+        }
+        self.dirty = true;
+    }
+
+    /// Close the focused pane, return the child displayed in the newly focused pane
+    pub fn unsplit(&mut self) -> Option<K> {
+        if self.panes.count() > 1 {
+            self.panes.remove_leaf(self.focus);
+            self.focus = cmp::min(self.focus, self.panes.count() - 1);
+            self.dirty = true;
+        }
+        self.get_current_key().cloned()
+    }
+
+    /// Move focus to the next pane, return the child it displays
+    pub fn focus_next(&mut self) -> Option<K> {
+        self.focus = (self.focus + 1) % self.panes.count();
+        self.get_current_key().cloned()
+    }
+
+    /// Grow (or shrink with a negative delta) the focused pane by the given share of its split
+    pub fn resize(&mut self, delta: f32) {
+        if self.panes.resize(self.focus, delta) {
+            self.dirty = true;
         }
     }
 
@@ -553,20 +828,33 @@ where
         width_spec: Option<u16>,
         height_spec: Option<u16>,
     ) {
+        // Children are measured in layout, once their pane is known
         dimension.w = width_spec;
         dimension.h = height_spec;
-
-        for (_, (child_dimension, child_view)) in self.children.iter_mut() {
-            child_view.measure(child_dimension, dimension.w, dimension.h);
-        }
     }
 
     fn layout(&mut self, dimension: &mut Dimension, top: u16, left: u16) {
         dimension.x = left;
         dimension.y = top;
 
-        for (_, (child_dimension, child_view)) in self.children.iter_mut() {
-            child_view.layout(child_dimension, top, left);
+        // Hidden children only update their content, they are laid out once displayed
+        let mut leaves = Vec::new();
+        self.separators.clear();
+        self.empty.clear();
+        self.panes
+            .arrange(dimension.clone(), &mut leaves, &mut self.separators);
+        for (key, area) in leaves {
+            let child = match key {
+                Some(key) => self.children.get_mut(&key),
+                None => None,
+            };
+            match child {
+                Some((child_dimension, child_view)) => {
+                    child_view.measure(child_dimension, area.w, area.h);
+                    child_view.layout(child_dimension, area.y, area.x);
+                }
+                None => self.empty.push(area),
+            }
         }
 
         // Set dirty to ensure all children are rendered on next render
@@ -574,8 +862,30 @@ where
     }
 
     fn render(&mut self, _dimension: &Dimension, screen: &mut Screen<W>) {
-        if let Some(current) = &self.current {
-            let (child_dimension, child_view) = self.children.get_mut(current).unwrap();
+        if self.dirty {
+            save_cursor!(screen);
+            for separator in &self.separators {
+                let (w, h) = (separator.w.unwrap(), separator.h.unwrap());
+                for y in separator.y..separator.y + h {
+                    goto!(screen, separator.x, y);
+                    match w {
+                        1 => vprint!(screen, "│"),
+                        _ => vprint!(screen, "{}", "─".repeat(w.into())),
+                    }
+                }
+            }
+            for area in &self.empty {
+                let (w, h) = (area.w.unwrap_or(0), area.h.unwrap_or(0));
+                for y in area.y..area.y + h {
+                    goto!(screen, area.x, y);
+                    vprint!(screen, "{}", " ".repeat(w.into()));
+                }
+            }
+            restore_cursor!(screen);
+        }
+
+        for key in self.visible() {
+            let (child_dimension, child_view) = self.children.get_mut(&key).unwrap();
             if self.dirty || child_view.is_dirty() {
                 child_view.render(child_dimension, screen);
             }
@@ -586,28 +896,20 @@ where
     fn is_layout_dirty(&self) -> bool {
         match self.dirty {
             true => true,
-            _ => {
-                if let Some(current) = &self.current {
-                    let (_, child_view) = self.children.get(current).unwrap();
-                    child_view.is_layout_dirty()
-                } else {
-                    false
-                }
-            }
+            _ => self
+                .visible()
+                .iter()
+                .any(|key| self.children.get(key).unwrap().1.is_layout_dirty()),
         }
     }
 
     fn is_dirty(&self) -> bool {
         match self.dirty {
             true => true,
-            _ => {
-                if let Some(current) = &self.current {
-                    let (_, child_view) = self.children.get(current).unwrap();
-                    child_view.is_dirty()
-                } else {
-                    false
-                }
-            }
+            _ => self
+                .visible()
+                .iter()
+                .any(|key| self.children.get(key).unwrap().1.is_dirty()),
         }
     }
 
@@ -942,8 +1244,17 @@ pub struct Input<E> {
     pub event_handler: Option<Rc<RefCell<Box<dyn FnMut(&mut Self, &mut E)>>>>,
     pub dirty: bool,
     width: usize,
+    /// Text displayed in front of the buffer
+    prompt: String,
+    /// Text removed by kill commands, most recent last
+    kill_ring: Vec<String>,
+    /// Start of the last yanked text and its index in the kill ring
+    yanked: Option<(Cursor, usize)>,
 }
 
+/// Number of kills kept in the kill ring
+const KILL_RING_SIZE: usize = 16;
+
 impl<E> Input<E> {
     pub fn new() -> Self {
         Self {
@@ -957,6 +1268,16 @@ impl<E> Input<E> {
             event_handler: None,
             dirty: true,
             width: 0,
+            prompt: String::new(),
+            kill_ring: Vec::new(),
+            yanked: None,
+        }
+    }
+
+    pub fn set_prompt(&mut self, prompt: &str) {
+        if self.prompt != prompt {
+            self.prompt = prompt.to_string();
+            self.dirty = true;
         }
     }
 
@@ -978,6 +1299,16 @@ impl<E> Input<E> {
         }
     }
 
+    /// Insert pasted text, keeping its newlines instead of validating the input
+    pub fn paste(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        for c in text.trim_end_matches('\n').chars() {
+            if c == '\n' || c == '\t' || !c.is_control() {
+                self.key(c);
+            }
+        }
+    }
+
     pub fn backspace(&mut self) {
         if self.cursor > Cursor::new(0) {
             self.cursor -= 1;
@@ -1039,18 +1370,92 @@ impl<E> Input<E> {
             word_start -= 1;
         }
 
-        self.buf.replace_range(
-            word_start.index(&self.buf)..self.cursor.index(&self.buf),
-            "",
-        );
+        let killed = self
+            .buf
+            .drain(word_start.index(&self.buf)..self.cursor.index(&self.buf))
+            .collect();
+        self.kill(killed);
         self.cursor = word_start;
         if !self.password {
             self.dirty = true;
         }
     }
 
+    /// Cursor position at the start of the current or previous word
+    fn previous_word(&self) -> Cursor {
+        let graphemes = self.buf.graphemes(true).collect::<Vec<&str>>();
+        let mut index = self.cursor.get();
+        while index > 0 && !is_word(graphemes[index - 1]) {
+            index -= 1;
+        }
+        while index > 0 && is_word(graphemes[index - 1]) {
+            index -= 1;
+        }
+        Cursor::new(index)
+    }
+
+    /// Cursor position at the end of the current or next word
+    fn next_word(&self) -> Cursor {
+        let graphemes = self.buf.graphemes(true).collect::<Vec<&str>>();
+        let mut index = self.cursor.get();
+        while index < graphemes.len() && !is_word(graphemes[index]) {
+            index += 1;
+        }
+        while index < graphemes.len() && is_word(graphemes[index]) {
+            index += 1;
+        }
+        Cursor::new(index)
+    }
+
+    pub fn backward_word(&mut self) {
+        self.cursor = self.previous_word();
+        if !self.password {
+            self.dirty = true;
+        }
+    }
+
+    pub fn forward_word(&mut self) {
+        self.cursor = self.next_word();
+        if !self.password {
+            self.dirty = true;
+        }
+    }
+
+    pub fn delete_word(&mut self) {
+        let word_end = self.next_word();
+        self.buf
+            .replace_range(self.cursor.index(&self.buf)..word_end.index(&self.buf), "");
+        if !self.password {
+            self.dirty = true;
+        }
+    }
+
+    /// Swap the characters around the cursor, or the last two ones at the end of the line
+    pub fn transpose(&mut self) {
+        let mut graphemes = self
+            .buf
+            .graphemes(true)
+            .map(String::from)
+            .collect::<Vec<String>>();
+        let mut index = self.cursor.get();
+        if index == 0 || graphemes.len() < 2 {
+            return;
+        }
+        if index == graphemes.len() {
+            index -= 1;
+        }
+
+        graphemes.swap(index - 1, index);
+        self.buf = graphemes.concat();
+        self.cursor = Cursor::new(index + 1);
+        if !self.password {
+            self.dirty = true;
+        }
+    }
+
     pub fn delete_from_cursor_to_start(&mut self) {
-        self.buf.replace_range(0..self.cursor.index(&self.buf), "");
+        let killed = self.buf.drain(..self.cursor.index(&self.buf)).collect();
+        self.kill(killed);
         self.cursor = Cursor::new(0);
         self.view = Cursor::new(0);
         if !self.password {
@@ -1059,7 +1464,60 @@ impl<E> Input<E> {
     }
 
     pub fn delete_from_cursor_to_end(&mut self) {
-        self.buf.replace_range(self.cursor.index(&self.buf).., "");
+        let killed = self.buf.drain(self.cursor.index(&self.buf)..).collect();
+        self.kill(killed);
+        if !self.password {
+            self.dirty = true;
+        }
+    }
+
+    /// Keep removed text in the kill ring, passwords are never kept
+    fn kill(&mut self, text: String) {
+        if self.password || text.is_empty() {
+            return;
+        }
+        self.kill_ring.push(text);
+        if self.kill_ring.len() > KILL_RING_SIZE {
+            self.kill_ring.remove(0);
+        }
+    }
+
+    /// Insert the most recent kill at the cursor
+    pub fn yank(&mut self) {
+        if let Some(index) = self.kill_ring.len().checked_sub(1) {
+            let start = self.cursor.clone();
+            self.insert_kill(index);
+            self.yanked = Some((start, index));
+        }
+    }
+
+    /// Replace the text just yanked by the previous kill, return false when the text before the
+    /// cursor isn't the last yanked one
+    pub fn yank_pop(&mut self) -> bool {
+        let (start, index) = match self.yanked.take() {
+            Some(yanked) => yanked,
+            None => return false,
+        };
+        let range = start.index(&self.buf)..self.cursor.index(&self.buf);
+        if self.kill_ring.get(index).map(String::as_str) != self.buf.get(range.clone()) {
+            return false;
+        }
+
+        self.buf.replace_range(range, "");
+        self.cursor = start.clone();
+        let index = match index {
+            0 => self.kill_ring.len() - 1,
+            index => index - 1,
+        };
+        self.insert_kill(index);
+        self.yanked = Some((start, index));
+        true
+    }
+
+    fn insert_kill(&mut self, index: usize) {
+        let text = &self.kill_ring[index];
+        self.buf.insert_str(self.cursor.index(&self.buf), text);
+        self.cursor += text.graphemes(true).count();
         if !self.password {
             self.dirty = true;
         }
@@ -1173,6 +1631,10 @@ impl<E> Input<E> {
     }
 }
 
+fn is_word(grapheme: &str) -> bool {
+    grapheme.chars().all(char::is_alphanumeric)
+}
+
 impl<E, W> View<E, W> for Input<E>
 where
     W: Write,
@@ -1183,23 +1645,31 @@ where
             true => {
                 goto!(screen, dimension.x, dimension.y);
                 vprint!(screen, "password: ");
-                flush!(screen);
 
                 self.dirty = false;
             }
             false => {
+                // The prompt never takes more than half of the line
+                let mut prompt = self.prompt.clone();
+                if term_string_visible_len(&prompt) > self.width / 2 {
+                    prompt = term_string_visible_truncate(&prompt, self.width / 2, None);
+                }
+                let prompt_len = term_string_visible_len(&prompt);
+                let x = dimension.x + prompt_len as u16;
+                self.width -= prompt_len;
+
                 // Max displayable size is view width less 1 for cursor
-                let max_size = (dimension.w.unwrap() - 1) as usize;
+                let max_size = self.width - 1;
 
                 // cursor must always be inside the view
                 if self.cursor < self.view {
                     if self.cursor < max_size {
                         self.view = Cursor::new(0);
                     } else {
-                        self.view = &self.cursor - (dimension.w.unwrap() as usize - 1);
+                        self.view = &self.cursor - (self.width - 1);
                     }
-                } else if self.cursor > &self.view + (dimension.w.unwrap() as usize - 1) {
-                    self.view = &self.cursor - (dimension.w.unwrap() as usize - 1);
+                } else if self.cursor > &self.view + (self.width - 1) {
+                    self.view = &self.cursor - (self.width - 1);
                 }
                 assert!(self.cursor >= self.view);
                 assert!(self.cursor <= &self.view + (max_size + 1));
@@ -1210,15 +1680,15 @@ where
                 let cursor = &self.cursor - &self.view;
 
                 goto!(screen, dimension.x, dimension.y);
+                vprint!(screen, "{}", prompt);
                 for _ in 0..max_size {
                     vprint!(screen, " ");
                 }
 
-                goto!(screen, dimension.x, dimension.y);
-                vprint!(screen, "{}", buf);
-                goto!(screen, dimension.x + cursor.get() as u16, dimension.y);
-
-                flush!(screen);
+                goto!(screen, x, dimension.y);
+                // Newlines of multi-line messages are shown as a single visible char
+                vprint!(screen, "{}", buf.replace('\n', "↵"));
+                goto!(screen, x + cursor.get() as u16, dimension.y);
 
                 self.dirty = false;
             }
@@ -1263,6 +1733,16 @@ where
     fn page_down(&mut self) -> bool;
 }
 
+/// Inline graphic drawn over blank lines reserved after an item of a BufferedWin
+#[derive(Debug, Clone)]
+pub struct Graphic {
+    pub rows: u16,
+    /// Escape sequence drawing the graphic at the cursor position
+    pub draw: String,
+    /// Escape sequence removing the drawn graphic, empty if writing over it is enough
+    pub clear: String,
+}
+
 pub struct BufferedWin<E, W, I>
 where
     I: fmt::Display + Hash + Eq + Ord,
@@ -1275,6 +1755,40 @@ where
     width: usize,
     height: usize,
     layouts: Layouts,
+    /// Hanging indent of the wrapped lines of an item
+    indent: Option<Box<dyn Fn(&I) -> usize>>,
+    /// Line to insert between two consecutive items
+    separator: Option<Box<dyn Fn(&I, &I) -> Option<String>>>,
+    /// Text of an item given the previous one, its Display implementation by default
+    format: Option<Box<dyn Fn(&I, Option<&I>) -> String>>,
+    /// Graphic displayed below an item
+    graphic: Option<Box<dyn Fn(&I) -> Option<Rc<Graphic>>>>,
+    /// Graphics drawn by the last render
+    drawn: Vec<Rc<Graphic>>,
+    search: Option<String>,
+    /// Rendered line of the current search match, counted from the bottom like `view`
+    search_match: Option<usize>,
+    /// Copy mode cursor line, counted from the bottom like `view`
+    copy_cursor: Option<usize>,
+    /// Other end of the copy mode selection
+    copy_mark: Option<usize>,
+    /// Maximum number of items kept in history
+    max_items: Option<usize>,
+    /// Maximum size of the displayed items kept in history, in bytes
+    max_bytes: Option<usize>,
+    /// Size of the displayed items, only counted when max_bytes is set
+    bytes: usize,
+    /// Items have been dropped from history to respect the limits
+    trimmed: bool,
+}
+
+/// Rendered lines of a BufferedWin
+struct RenderedItems {
+    lines: Vec<String>,
+    /// Graphics with the index of the first line reserved for them
+    graphics: Vec<(usize, Rc<Graphic>)>,
+    /// Index of the first line of each item
+    starts: Vec<usize>,
 }
 
 impl<E, W, I> BufferedWin<E, W, I>
@@ -1294,6 +1808,19 @@ where
                 width: Layout::match_parent(),
                 height: Layout::match_parent(),
             },
+            indent: None,
+            separator: None,
+            format: None,
+            graphic: None,
+            drawn: Vec::new(),
+            search: None,
+            search_match: None,
+            copy_cursor: None,
+            copy_mark: None,
+            max_items: None,
+            max_bytes: None,
+            bytes: 0,
+            trimmed: false,
         }
     }
 
@@ -1311,16 +1838,141 @@ where
         self
     }
 
+    /// Align wrapped lines of an item on the column returned by `indent`
+    pub fn with_indent<F>(mut self, indent: F) -> Self
+    where
+        F: Fn(&I) -> usize + 'static,
+    {
+        self.indent = Some(Box::new(indent));
+        self
+    }
+
+    /// Insert the line returned by `separator` between two consecutive items
+    pub fn with_separator<F>(mut self, separator: F) -> Self
+    where
+        F: Fn(&I, &I) -> Option<String> + 'static,
+    {
+        self.separator = Some(Box::new(separator));
+        self
+    }
+
+    /// Display items with the text returned by `format`, which also gets the previous item
+    pub fn with_format<F>(mut self, format: F) -> Self
+    where
+        F: Fn(&I, Option<&I>) -> String + 'static,
+    {
+        self.format = Some(Box::new(format));
+        self
+    }
+
+    /// Reserve lines below items to draw the graphic returned by `graphic`
+    pub fn with_graphic<F>(mut self, graphic: F) -> Self
+    where
+        F: Fn(&I) -> Option<Rc<Graphic>> + 'static,
+    {
+        self.graphic = Some(Box::new(graphic));
+        self
+    }
+
+    /// Drop the oldest items when history exceeds `max_items` items or `max_bytes` bytes
+    pub fn with_scrollback(mut self, max_items: Option<usize>, max_bytes: Option<usize>) -> Self {
+        self.max_items = max_items;
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    fn item_size(&self, item: &I) -> usize {
+        match self.max_bytes {
+            Some(_) => format!("{}", item).len(),
+            None => 0,
+        }
+    }
+
+    /// Evict the oldest items beyond the scrollback limits, the last item is always kept
+    fn trim(&mut self) {
+        let mut count = self.history.len();
+        let mut bytes = self.bytes;
+        let mut evicted = 0;
+        for item in &self.history {
+            let over = self.max_items.map_or(false, |max| count > max)
+                || self.max_bytes.map_or(false, |max| bytes > max);
+            if !over || count == 1 {
+                break;
+            }
+            count -= 1;
+            bytes = bytes.saturating_sub(self.item_size(item));
+            evicted += 1;
+        }
+
+        if evicted > 0 {
+            let history = std::mem::take(&mut self.history);
+            self.history = history.into_iter().skip(evicted).collect();
+            self.bytes = bytes;
+            self.trimmed = true;
+            self.dirty = true;
+        }
+    }
+
+    /// True if older items have been dropped to respect the scrollback limits
+    pub fn is_trimmed(&self) -> bool {
+        self.trimmed
+    }
+
+    /// Empty the history
+    pub fn clear(&mut self) {
+        self.history.clear();
+        self.bytes = 0;
+        self.trimmed = false;
+        self.view = 0;
+        self.search_match = None;
+        self.copy_cursor = None;
+        self.copy_mark = None;
+        self.dirty = true;
+    }
+
     fn get_rendered_items(&self) -> Vec<String> {
+        self.render_items().lines
+    }
+
+    fn render_items(&self) -> RenderedItems {
         let max_len = self.width;
         let mut buffers: Vec<String> = Vec::new();
+        let mut graphics = Vec::new();
+        let mut starts = Vec::new();
+
+        if self.trimmed {
+            buffers.push(format!(
+                "{}-- older messages trimmed --{}",
+                termion::style::Italic,
+                termion::style::NoItalic
+            ));
+        }
 
+        let mut previous = None;
         for buf in &self.history {
-            let formatted = format!("{}", buf);
+            starts.push(buffers.len());
+            let indent = match &self.indent {
+                Some(indent) => std::cmp::min(indent(buf), max_len / 2),
+                None => 0,
+            };
+            let separator = match (&self.separator, previous) {
+                (Some(separator), Some(previous)) => separator(previous, buf),
+                _ => None,
+            };
+            let item = match &self.format {
+                Some(format) => format(buf, previous),
+                None => format!("{}", buf),
+            };
+            previous = Some(buf);
+            let formatted = match separator {
+                Some(separator) => format!("{}\n{}", separator, item),
+                None => item,
+            };
             for line in formatted.lines() {
                 let mut words = line.split_word_bounds();
 
                 let mut line_len = 0;
+                let mut line_start = 0;
                 let mut chunk = String::new();
                 while let Some(word) = words.next() {
                     let visible_word;
@@ -1392,47 +2044,271 @@ where
 
                     let grapheme_count = visible_word.graphemes(true).count();
 
-                    if line_len + grapheme_count > max_len {
-                        // Wrap line
+                    if line_len + grapheme_count > max_len && line_len > line_start {
+                        // Wrap line before this word
                         buffers.push(chunk);
-                        chunk = String::new();
-                        line_len = 0;
+                        chunk = " ".repeat(indent);
+                        line_len = indent;
+                        line_start = indent;
                     }
 
-                    chunk.push_str(visible_word);
-                    line_len += grapheme_count;
+                    if line_len == line_start && line_start > 0 && visible_word.trim().is_empty() {
+                        // Don't start a wrapped line with whitespace
+                        continue;
+                    }
+
+                    if line_len + grapheme_count > max_len {
+                        // Word doesn't fit on a line by itself, break it
+                        for grapheme in visible_word.graphemes(true) {
+                            if line_len >= max_len && line_len > line_start {
+                                buffers.push(chunk);
+                                chunk = " ".repeat(indent);
+                                line_len = indent;
+                                line_start = indent;
+                            }
+                            chunk.push_str(grapheme);
+                            line_len += 1;
+                        }
+                    } else {
+                        chunk.push_str(visible_word);
+                        line_len += grapheme_count;
+                    }
                 }
 
                 buffers.push(chunk);
             }
+
+            if let Some(graphic) = self.graphic.as_ref().and_then(|graphic| graphic(buf)) {
+                graphics.push((buffers.len(), graphic.clone()));
+                for _ in 0..graphic.rows {
+                    buffers.push(String::new());
+                }
+            }
         }
 
-        buffers
+        RenderedItems {
+            lines: buffers,
+            graphics,
+            starts,
+        }
     }
 
     #[allow(dead_code)]
     pub fn first<'a>(&'a self) -> Option<&'a I> {
         self.history.iter().nth(0)
     }
-}
 
-impl<E, W, I> Window<E, W, I> for BufferedWin<E, W, I>
-where
-    W: Write,
-    I: fmt::Display + Hash + Eq + Ord,
-{
-    fn insert(&mut self, item: I) {
-        // We don't care about rendered buffer, we avoid computation here at cost of false positive
-        // (set dirty while in fact it shouldn't)
-        let len = self.history.len();
-        let position = len
-            - self
+    /// Search backward for `term` starting from the bottom of the view, return true if found
+    pub fn search(&mut self, term: &str) -> bool {
+        self.search = Some(term.to_string());
+        self.search_match = None;
+        self.dirty = true;
+        self.find_match(self.view, true)
+    }
+
+    /// Jump to the previous (older) match, return true if found
+    pub fn search_next(&mut self) -> bool {
+        match self.search_match {
+            Some(current) => self.find_match(current + 1, true),
+            None => self.find_match(self.view, true),
+        }
+    }
+
+    /// Jump to the next (newer) match, return true if found
+    pub fn search_previous(&mut self) -> bool {
+        match self.search_match {
+            Some(current) if current > 0 => self.find_match(current - 1, false),
+            _ => false,
+        }
+    }
+
+    pub fn clear_search(&mut self) {
+        self.search = None;
+        self.search_match = None;
+        self.dirty = true;
+    }
+
+    fn find_match(&mut self, from: usize, backward: bool) -> bool {
+        let term = match &self.search {
+            Some(term) if !term.is_empty() => term,
+            _ => return false,
+        };
+
+        let buffers = self.get_rendered_items();
+        let count = buffers.len();
+        if count == 0 {
+            return false;
+        }
+
+        let is_match = |index: &usize| clean(&buffers[count - 1 - index]).contains(term.as_str());
+        let found = match backward {
+            true => (from..count).find(is_match),
+            false => (0..=cmp::min(from, count.saturating_sub(1)))
+                .rev()
+                .find(is_match),
+        };
+
+        match found {
+            Some(index) => {
+                self.search_match = Some(index);
+                // Keep the match roughly in the middle of the window
+                let max = count.saturating_sub(self.height);
+                self.view = cmp::min(index.saturating_sub(self.height / 2), max);
+                self.dirty = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Enter copy mode with the cursor on the last visible line
+    pub fn start_copy(&mut self) {
+        self.copy_cursor = Some(self.view);
+        self.copy_mark = None;
+        self.dirty = true;
+    }
+
+    pub fn stop_copy(&mut self) {
+        self.copy_cursor = None;
+        self.copy_mark = None;
+        self.dirty = true;
+    }
+
+    /// Move the copy mode cursor `count` lines up (positive) or down (negative)
+    pub fn move_copy_cursor(&mut self, count: isize) {
+        let cursor = match self.copy_cursor {
+            Some(cursor) => cursor,
+            None => return,
+        };
+        let lines = self.get_rendered_items().len();
+        if lines == 0 {
+            return;
+        }
+
+        let cursor = match count < 0 {
+            true => cursor.saturating_sub((-count) as usize),
+            false => cmp::min(cursor + count as usize, lines - 1),
+        };
+        self.copy_cursor = Some(cursor);
+
+        // Keep the cursor visible
+        if cursor < self.view {
+            self.view = cursor;
+        } else if self.height > 0 && cursor >= self.view + self.height {
+            self.view = cursor + 1 - self.height;
+        }
+        self.dirty = true;
+    }
+
+    /// Move the copy mode cursor `count` pages up (positive) or down (negative)
+    pub fn page_copy_cursor(&mut self, count: isize) {
+        self.move_copy_cursor(count * self.height as isize);
+    }
+
+    /// Start or cancel the selection of a region at the copy mode cursor
+    pub fn toggle_copy_mark(&mut self) {
+        self.copy_mark = match self.copy_mark {
+            Some(_) => None,
+            None => self.copy_cursor,
+        };
+        self.dirty = true;
+    }
+
+    /// Lines counted from the bottom between the copy mode cursor and mark
+    fn copy_selection(&self) -> Option<(usize, usize)> {
+        let cursor = self.copy_cursor?;
+        let mark = self.copy_mark.unwrap_or(cursor);
+        Some((cmp::min(cursor, mark), cmp::max(cursor, mark)))
+    }
+
+    /// Text of the selected lines, without formatting
+    pub fn get_copy_selection(&self) -> Option<String> {
+        let (bottom, top) = self.copy_selection()?;
+        let lines = self.get_rendered_items();
+        let count = lines.len();
+        if top >= count {
+            return None;
+        }
+
+        let selected = lines[count - 1 - top..count - bottom]
+            .iter()
+            .map(|line| clean(line).trim_end().to_string())
+            .collect::<Vec<String>>();
+        Some(selected.join("\n"))
+    }
+
+    /// Text of the whole item under the copy mode cursor, without formatting
+    pub fn get_copy_item(&self) -> Option<String> {
+        self.get_copy_cursor_item()
+            .map(|item| clean(&format!("{}", item)))
+    }
+
+    /// Item under the copy mode cursor
+    pub fn get_copy_cursor_item(&self) -> Option<&I> {
+        let cursor = self.copy_cursor?;
+        let rendered = self.render_items();
+        let count = rendered.lines.len();
+        if cursor >= count {
+            return None;
+        }
+
+        let line = count - 1 - cursor;
+        let index = rendered.starts.iter().rposition(|start| *start <= line)?;
+        self.history.iter().nth(index)
+    }
+
+    fn highlight(&self, buf: &str, index: usize) -> String {
+        if let Some((bottom, top)) = self.copy_selection() {
+            if index >= bottom && index <= top {
+                return format!(
+                    "{}{}{}",
+                    termion::style::Invert,
+                    clean(buf),
+                    termion::style::NoInvert
+                );
+            }
+        }
+
+        match &self.search {
+            Some(term) if !term.is_empty() && clean(buf).contains(term.as_str()) => {
+                let (start, end) = match self.search_match == Some(index) {
+                    true => (
+                        format!("{}", termion::style::Invert),
+                        format!("{}", termion::style::NoInvert),
+                    ),
+                    false => (
+                        format!("{}", termion::style::Underline),
+                        format!("{}", termion::style::NoUnderline),
+                    ),
+                };
+                highlight_term(buf, term, &start, &end)
+            }
+            _ => buf.to_string(),
+        }
+    }
+}
+
+impl<E, W, I> Window<E, W, I> for BufferedWin<E, W, I>
+where
+    W: Write,
+    I: fmt::Display + Hash + Eq + Ord,
+{
+    fn insert(&mut self, item: I) {
+        // We don't care about rendered buffer, we avoid computation here at cost of false positive
+        // (set dirty while in fact it shouldn't)
+        let len = self.history.len();
+        let position = len
+            - self
                 .history
                 .iter()
                 .position(|iter| iter > &item)
                 .unwrap_or(self.history.len());
-        self.history.replace(item);
+        self.bytes += self.item_size(&item);
+        if let Some(replaced) = self.history.replace(item) {
+            self.bytes = self.bytes.saturating_sub(self.item_size(&replaced));
+        }
         self.dirty |= position >= self.view && position <= self.view + self.height;
+        self.trim();
     }
 
     fn page_up(&mut self) -> bool {
@@ -1482,15 +2358,34 @@ where
 
         self.next_line = 0;
 
-        let buffers = self.get_rendered_items();
+        // Graphics can be left behind over unchanged lines, write everything again
+        if !self.drawn.is_empty() {
+            screen.invalidate(
+                dimension.x,
+                dimension.y,
+                dimension.w.unwrap(),
+                dimension.h.unwrap(),
+            );
+        }
+        for graphic in self.drawn.drain(..) {
+            vprint!(screen, "{}", graphic.clear);
+        }
+
+        let RenderedItems {
+            lines: buffers,
+            graphics,
+            ..
+        } = self.render_items();
         let count = buffers.len();
-        let mut iter = buffers.iter();
+        let mut iter = buffers.iter().enumerate();
 
+        let mut first = 0;
         if count > dimension.h.unwrap() as usize {
             for _ in 0..count - dimension.h.unwrap() as usize - self.view {
                 if iter.next().is_none() {
                     break;
                 }
+                first += 1;
             }
         }
 
@@ -1501,14 +2396,23 @@ where
             }
 
             goto!(screen, dimension.x, y);
-            if let Some(buf) = iter.next() {
-                vprint!(screen, "{}", buf);
+            if let Some((index, buf)) = iter.next() {
+                vprint!(screen, "{}", self.highlight(buf, count - 1 - index));
                 self.next_line += 1;
             }
         }
 
+        // Only draw graphics fully visible, they would overflow the window otherwise
+        let last = first + dimension.h.unwrap() as usize;
+        for (line, graphic) in graphics {
+            if line >= first && line + graphic.rows as usize <= last {
+                goto!(screen, dimension.x, dimension.y + (line - first) as u16);
+                vprint!(screen, "{}", graphic.draw);
+                self.drawn.push(graphic);
+            }
+        }
+
         restore_cursor!(screen);
-        flush!(screen);
 
         self.dirty = false;
     }
@@ -1543,6 +2447,16 @@ where
     event_handler: Option<Rc<RefCell<Box<dyn FnMut(&mut Self, &mut E)>>>>,
     dirty: bool,
     layouts: Layouts,
+    /// Horizontal span and line of each rendered item, used to resolve mouse clicks
+    columns: (u16, u16),
+    rendered: Vec<(u16, V)>,
+    hidden: bool,
+    /// Only items matching this predicate are displayed
+    filter: Option<Box<dyn Fn(&V) -> bool>>,
+    /// Highlighted item, kept in view
+    selected: Option<V>,
+    /// Number of lines scrolled out of the top of the list
+    offset: usize,
 }
 
 impl<E, W, G, V> ListView<E, W, G, V>
@@ -1562,6 +2476,12 @@ where
                 width: Layout::match_parent(),
                 height: Layout::match_parent(),
             },
+            columns: (0, 0),
+            rendered: Vec::new(),
+            hidden: false,
+            filter: None,
+            selected: None,
+            offset: 0,
         }
     }
 
@@ -1578,6 +2498,19 @@ where
         self
     }
 
+    /// Get the item rendered at the given screen position
+    pub fn item_at<'a>(&'a self, x: u16, y: u16) -> Option<&'a V> {
+        let (start, end) = self.columns;
+        if x < start || x >= end {
+            return None;
+        }
+
+        self.rendered
+            .iter()
+            .find(|(line, _)| *line == y)
+            .map(|(_, item)| item)
+    }
+
     pub fn with_none_group(mut self) -> Self {
         if let Entry::Vacant(vacant) = self.items.entry(None) {
             vacant.insert(HashSet::new());
@@ -1654,6 +2587,47 @@ where
         self.dirty = true;
     }
 
+    /// Hide the list, it then takes no space, or show it back
+    pub fn toggle_visibility(&mut self) {
+        self.hidden = !self.hidden;
+        self.dirty = true;
+    }
+
+    /// Width of the list as last rendered, none when it is hidden
+    pub fn width(&self) -> Option<u16> {
+        match self.hidden {
+            true => None,
+            false => Some(self.columns.1 - self.columns.0),
+        }
+    }
+
+    /// Fix the width of the list instead of its layout one
+    pub fn set_width(&mut self, width: u16) {
+        self.layouts.width = Layout::absolute(width);
+        self.dirty = true;
+    }
+
+    /// Only display items for which `filter` returns true
+    pub fn set_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(&V) -> bool + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+        self.dirty = true;
+    }
+
+    pub fn clear_filter(&mut self) {
+        self.filter = None;
+        self.dirty = true;
+    }
+
+    fn is_shown(&self, item: &V) -> bool {
+        match &self.filter {
+            Some(filter) => filter(item),
+            None => true,
+        }
+    }
+
     pub fn remove(&mut self, item: V, group: Option<G>) -> Result<(), ()> {
         match self.items.entry(group) {
             Entry::Vacant(_) => Err(()),
@@ -1663,13 +2637,80 @@ where
             }
         }
     }
+
+    /// Remove every item, keeping the groups
+    pub fn clear(&mut self) {
+        for (_, items) in self.items.iter_mut() {
+            items.clear();
+        }
+        self.selected = None;
+        self.offset = 0;
+        self.dirty = true;
+    }
+
+    pub fn selection(&self) -> Option<&V> {
+        self.selected.as_ref()
+    }
+}
+
+impl<E, W, G, V> ListView<E, W, G, V>
+where
+    G: fmt::Display + Hash + Eq,
+    V: fmt::Display + Hash + Eq + Clone,
+{
+    /// Displayed groups with their displayed items, in display order
+    fn shown_items(&mut self) -> Vec<(Option<String>, Vec<V>)> {
+        let filter = &self.filter;
+        let sort_item = &mut self.sort_item;
+        self.items
+            .iter()
+            .map(|(group, items)| {
+                let mut items = items
+                    .iter()
+                    .filter(|item| filter.as_ref().map_or(true, |filter| filter(item)))
+                    .collect::<Vec<&V>>();
+                if let Some(sort) = sort_item {
+                    items.sort_by(|a, b| sort(*a, *b));
+                }
+                (
+                    group.as_ref().map(|group| format!("{}", group)),
+                    items.into_iter().cloned().collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// Select the item `delta` lines below the selected one, or the first one if none is
+    pub fn move_selection(&mut self, delta: isize) {
+        let items: Vec<V> = self
+            .shown_items()
+            .into_iter()
+            .flat_map(|(_, items)| items)
+            .collect();
+        if items.is_empty() {
+            return;
+        }
+        let selected = self
+            .selected
+            .as_ref()
+            .and_then(|selected| items.iter().position(|item| item == selected));
+        let index = match selected {
+            Some(index) => cmp::min(
+                cmp::max(index as isize + delta, 0) as usize,
+                items.len() - 1,
+            ),
+            None => 0,
+        };
+        self.selected = Some(items[index].clone());
+        self.dirty = true;
+    }
 }
 
 impl<E, W, G, V> View<E, W> for ListView<E, W, G, V>
 where
     W: Write,
     G: fmt::Display + Hash + Eq,
-    V: fmt::Display + Hash + Eq,
+    V: fmt::Display + Hash + Eq + Clone,
 {
     fn measure(
         &mut self,
@@ -1677,6 +2718,12 @@ where
         width_spec: Option<u16>,
         height_spec: Option<u16>,
     ) {
+        if self.hidden {
+            dimension.w = Some(0);
+            dimension.h = Some(0);
+            return;
+        }
+
         let layouts = self.get_layouts();
         dimension.w = match layouts.width.behavior {
             LayoutBehavior::MatchParent => width_spec,
@@ -1693,7 +2740,7 @@ where
                         None => "",
                     };
 
-                    for item in items {
+                    for item in items.iter().filter(|item| self.is_shown(item)) {
                         width = cmp::max(
                             width,
                             term_string_visible_len(&format!("{}{}", indent, item)) as u16,
@@ -1721,7 +2768,7 @@ where
                         height += 1;
                     }
 
-                    height += items.len() as u16;
+                    height += items.iter().filter(|item| self.is_shown(item)).count() as u16;
                 }
 
                 match height_spec {
@@ -1739,8 +2786,15 @@ where
     fn render(&mut self, dimension: &Dimension, screen: &mut Screen<W>) {
         save_cursor!(screen);
 
-        let mut y = dimension.y;
         let width: usize = dimension.w.unwrap().into();
+        self.columns = (dimension.x, dimension.x + dimension.w.unwrap());
+        self.rendered.clear();
+
+        if self.hidden || width == 0 {
+            restore_cursor!(screen);
+            self.dirty = false;
+            return;
+        }
 
         for y in dimension.y..dimension.y + dimension.h.unwrap() {
             goto!(screen, dimension.x, y);
@@ -1751,49 +2805,58 @@ where
             goto!(screen, dimension.x, y);
         }
 
-        for (group, items) in &self.items {
-            if y > dimension.y + dimension.h.unwrap() {
-                break;
+        let mut lines: Vec<(String, Option<V>)> = Vec::new();
+        for (group, items) in self.shown_items() {
+            let indent = match group {
+                Some(_) => "  ",
+                None => "",
+            };
+            if let Some(group) = group {
+                lines.push((group, None));
+            }
+            for item in items {
+                lines.push((format!("{}{}", indent, item), Some(item)));
             }
+        }
+
+        // Scroll to keep the selected item in view
+        let height: usize = dimension.h.unwrap().into();
+        let selected = self.selected.as_ref().and_then(|selected| {
+            lines
+                .iter()
+                .position(|(_, item)| item.as_ref() == Some(selected))
+        });
+        match selected {
+            Some(index) if index < self.offset => self.offset = index,
+            Some(index) if index >= self.offset + height => self.offset = index + 1 - height,
+            _ => {}
+        }
+        self.offset = cmp::min(self.offset, lines.len().saturating_sub(height));
 
+        for (line, (mut disp, item)) in lines.into_iter().skip(self.offset).take(height).enumerate()
+        {
+            let y = dimension.y + line as u16;
             goto!(screen, dimension.x, y);
 
-            if group.is_some() {
-                let mut disp = format!("{}", group.as_ref().unwrap());
-                if term_string_visible_len(&disp) > width {
-                    disp = term_string_visible_truncate(&disp, width, Some("…"));
-                }
-                vprint!(screen, "{}", disp);
-                y += 1;
+            if term_string_visible_len(&disp) > width {
+                disp = term_string_visible_truncate(&disp, width, Some("…"));
             }
-
-            let mut items = items.iter().collect::<Vec<&V>>();
-            if let Some(sort) = &mut self.sort_item {
-                items.sort_by(|a, b| sort(*a, *b));
+            match item.is_some() && item == self.selected {
+                true => vprint!(
+                    screen,
+                    "{}{}{}",
+                    termion::style::Invert,
+                    disp,
+                    termion::style::NoInvert
+                ),
+                false => vprint!(screen, "{}", disp),
             }
-
-            for item in items {
-                if y > dimension.y + dimension.h.unwrap() {
-                    break;
-                }
-
-                goto!(screen, dimension.x, y);
-
-                let mut disp = match group {
-                    Some(_) => format!("  {}", item),
-                    None => format!("{}", item),
-                };
-                if term_string_visible_len(&disp) > width {
-                    disp = term_string_visible_truncate(&disp, width, Some("…"));
-                }
-                vprint!(screen, "{}", disp);
-
-                y += 1;
+            if let Some(item) = item {
+                self.rendered.push((y, item));
             }
         }
 
         restore_cursor!(screen);
-        flush!(screen);
 
         self.dirty = false;
     }
@@ -1885,6 +2948,445 @@ mod tests {
         assert_eq!(input.buf, "ab".to_string());
     }
 
+    #[test]
+    fn test_input_word_movement() {
+        // Given
+        let mut input = Input::<()>::new();
+        for c in "hello, big world".chars() {
+            input.key(c);
+        }
+
+        // When
+        input.backward_word();
+        input.backward_word();
+
+        // Then
+        assert_eq!(input.cursor, Cursor::new(7));
+
+        // When
+        input.forward_word();
+
+        // Then
+        assert_eq!(input.cursor, Cursor::new(10));
+
+        // When
+        input.home();
+        input.delete_word();
+
+        // Then
+        assert_eq!(input.buf, ", big world".to_string());
+    }
+
+    #[test]
+    fn test_input_transpose() {
+        // Given
+        let mut input = Input::<()>::new();
+        input.key('a');
+        input.key('b');
+        input.key('c');
+
+        // When
+        input.transpose();
+
+        // Then
+        assert_eq!(input.buf, "acb".to_string());
+        assert_eq!(input.cursor, Cursor::new(3));
+
+        // When
+        input.home();
+        input.right();
+        input.transpose();
+
+        // Then
+        assert_eq!(input.buf, "cab".to_string());
+        assert_eq!(input.cursor, Cursor::new(2));
+    }
+
+    #[test]
+    fn test_input_kill_ring() {
+        // Given
+        let mut input = Input::<()>::new();
+        for c in "hello big world".chars() {
+            input.key(c);
+        }
+        input.backward_delete_word();
+        input.backward_word();
+        input.delete_from_cursor_to_end();
+        input.delete_from_cursor_to_start();
+
+        // When
+        input.yank();
+
+        // Then
+        assert_eq!(input.buf, "hello ".to_string());
+        assert_eq!(input.cursor, Cursor::new(6));
+
+        // When
+        assert!(input.yank_pop());
+
+        // Then
+        assert_eq!(input.buf, "big ".to_string());
+
+        // When
+        assert!(input.yank_pop());
+        assert!(input.yank_pop());
+
+        // Then
+        assert_eq!(input.buf, "hello ".to_string());
+
+        // When
+        input.key('!');
+
+        // Then
+        assert!(!input.yank_pop());
+        assert_eq!(input.buf, "hello !".to_string());
+    }
+
+    #[test]
+    fn test_input_paste() {
+        // Given
+        let mut input = Input::<()>::new();
+        input.key('>');
+
+        // When
+        input.paste("first\r\nsecond\x07\r");
+
+        // Then
+        assert_eq!(input.buf, ">first\nsecond".to_string());
+    }
+
+    #[test]
+    fn test_input_multiline() {
+        // Given
+        let mut input = Input::<()>::new();
+
+        // When
+        input.key('a');
+        input.key('\n');
+        input.key('b');
+        input.backspace();
+        input.backspace();
+        input.key('\n');
+        input.key('c');
+
+        // Then
+        assert_eq!(input.validate(), ("a\nc".to_string(), false));
+    }
+
+    #[test]
+    fn test_buffered_win_search() {
+        // Given
+        let mut win = BufferedWin::<(), MockWriter, String>::new();
+        win.width = 80;
+        win.height = 2;
+        win.insert("a foo".to_string());
+        win.insert("b bar".to_string());
+        win.insert("c foo".to_string());
+        win.insert("d baz".to_string());
+
+        // When
+        let found = win.search("foo");
+
+        // Then
+        assert!(found);
+        assert_eq!(win.search_match, Some(1));
+        assert!(win.search_next());
+        assert_eq!(win.search_match, Some(3));
+        assert_eq!(win.view, 2);
+        assert!(!win.search_next());
+        assert!(win.search_previous());
+        assert_eq!(win.search_match, Some(1));
+        assert!(!win.search("qux"));
+    }
+
+    #[test]
+    fn test_buffered_win_scrollback() {
+        // Given
+        let mut win =
+            BufferedWin::<(), MockWriter, String>::new().with_scrollback(Some(3), Some(10));
+        win.width = 80;
+        win.height = 5;
+
+        // When
+        win.insert("a".to_string());
+        win.insert("b".to_string());
+        win.insert("c".to_string());
+        win.insert("d".to_string());
+
+        // Then
+        assert!(win.is_trimmed());
+        assert_eq!(
+            win.history.iter().cloned().collect::<Vec<String>>(),
+            vec!["b", "c", "d"]
+        );
+        assert_eq!(win.get_rendered_items().len(), 4);
+
+        // When
+        win.insert("e too long".to_string());
+
+        // Then
+        assert_eq!(
+            win.history.iter().cloned().collect::<Vec<String>>(),
+            vec!["e too long"]
+        );
+
+        // When
+        win.clear();
+
+        // Then
+        assert!(!win.is_trimmed());
+        assert!(win.get_rendered_items().is_empty());
+    }
+
+    #[test]
+    fn test_buffered_win_format_with_previous() {
+        // Given
+        let mut win =
+            BufferedWin::<(), MockWriter, String>::new().with_format(
+                |item, previous| match previous {
+                    Some(previous) if previous[..1] == item[..1] => format!("  {}", &item[1..]),
+                    _ => item.to_string(),
+                },
+            );
+        win.width = 80;
+        win.height = 3;
+
+        // When
+        win.insert("a: foo".to_string());
+        win.insert("a: haz".to_string());
+        win.insert("b: bar".to_string());
+
+        // Then
+        assert_eq!(
+            win.get_rendered_items(),
+            vec!["a: foo", "  : haz", "b: bar"]
+        );
+    }
+
+    #[test]
+    fn test_buffered_win_wrap_with_indent() {
+        // Given
+        let mut win = BufferedWin::<(), MockWriter, String>::new().with_indent(|_| 4);
+        win.width = 12;
+        win.height = 3;
+
+        // When
+        win.insert("foo: hello big world".to_string());
+
+        // Then
+        assert_eq!(
+            win.get_rendered_items(),
+            vec!["foo: hello ", "    big ", "    world"]
+        );
+    }
+
+    #[test]
+    fn test_buffered_win_graphic() {
+        // Given
+        let graphic = Rc::new(Graphic {
+            rows: 2,
+            draw: String::new(),
+            clear: String::new(),
+        });
+        let mut win = BufferedWin::<(), MockWriter, String>::new().with_graphic(move |item| {
+            match item.as_str() {
+                "a" => Some(graphic.clone()),
+                _ => None,
+            }
+        });
+        win.width = 80;
+        win.height = 5;
+
+        // When
+        win.insert("a".to_string());
+        win.insert("b".to_string());
+        let rendered = win.render_items();
+
+        // Then
+        assert_eq!(rendered.lines, vec!["a", "", "", "b"]);
+        assert_eq!(rendered.graphics.len(), 1);
+        assert_eq!(rendered.graphics[0].0, 1);
+    }
+
+    #[test]
+    fn test_buffered_win_copy_mode() {
+        // Given
+        let mut win = BufferedWin::<(), MockWriter, String>::new();
+        win.width = 80;
+        win.height = 5;
+        win.insert("a".to_string());
+        win.insert("b\nc".to_string());
+        win.insert("d".to_string());
+
+        // When
+        win.start_copy();
+        win.move_copy_cursor(1);
+        win.toggle_copy_mark();
+        win.move_copy_cursor(2);
+
+        // Then
+        assert_eq!(win.get_copy_selection(), Some("a\nb\nc".to_string()));
+        assert_eq!(win.get_copy_item(), Some("a".to_string()));
+        win.move_copy_cursor(-1);
+        assert_eq!(win.get_copy_item(), Some("b\nc".to_string()));
+    }
+
+    #[test]
+    fn test_buffered_win_separator() {
+        // Given
+        let mut win = BufferedWin::<(), MockWriter, String>::new().with_separator(|a, b| {
+            match a.chars().next() == b.chars().next() {
+                true => None,
+                false => Some("---".to_string()),
+            }
+        });
+        win.width = 80;
+        win.height = 5;
+
+        // When
+        win.insert("a1".to_string());
+        win.insert("a2".to_string());
+        win.insert("b1".to_string());
+
+        // Then
+        assert_eq!(win.get_rendered_items(), vec!["a1", "a2", "---", "b1"]);
+    }
+
+    #[test]
+    fn test_list_view_filter_and_toggle() {
+        // Given
+        let mut list = ListView::<(), MockWriter, String, String>::new()
+            .with_layouts(Layouts {
+                width: Layout::wrap_content(),
+                height: Layout::wrap_content(),
+            })
+            .with_none_group();
+        list.insert("romeo".to_string(), None);
+        list.insert("juliet".to_string(), None);
+        let mut dimension = Dimension::new();
+
+        // When
+        list.set_filter(|item| item.starts_with('r'));
+        View::<(), MockWriter>::measure(&mut list, &mut dimension, None, None);
+
+        // Then
+        assert_eq!(dimension.w, Some(5));
+        assert_eq!(dimension.h, Some(1));
+
+        list.toggle_visibility();
+        View::<(), MockWriter>::measure(&mut list, &mut dimension, None, None);
+        assert_eq!(dimension.w, Some(0));
+        assert_eq!(dimension.h, Some(0));
+    }
+
+    #[test]
+    fn test_list_view_set_width() {
+        // Given
+        let mut list = ListView::<(), MockWriter, String, String>::new()
+            .with_layouts(Layouts {
+                width: Layout::wrap_content(),
+                height: Layout::match_parent(),
+            })
+            .with_none_group();
+        list.insert("romeo".to_string(), None);
+        let mut dimension = Dimension::new();
+
+        // When
+        list.set_width(12);
+        View::<(), MockWriter>::measure(&mut list, &mut dimension, Some(80), Some(24));
+
+        // Then
+        assert_eq!(dimension.w, Some(12));
+
+        View::<(), MockWriter>::measure(&mut list, &mut dimension, Some(8), Some(24));
+        assert_eq!(dimension.w, Some(8));
+    }
+
+    #[test]
+    fn test_frame_layout_split() {
+        // Given
+        let mut frame = FrameLayout::<(), MockWriter, String>::new();
+        frame.insert(
+            "a".to_string(),
+            BufferedWin::<(), MockWriter, String>::new(),
+        );
+        frame.insert(
+            "b".to_string(),
+            BufferedWin::<(), MockWriter, String>::new(),
+        );
+        frame.set_current("a".to_string());
+
+        // When
+        frame.split(Orientation::Horizontal);
+        let focused = frame.focus_next();
+        frame.set_current("b".to_string());
+        let mut dimension = Dimension::new();
+        frame.measure(&mut dimension, Some(81), Some(10));
+        frame.layout(&mut dimension, 1, 1);
+
+        // Then
+        assert_eq!(focused, None);
+        assert_eq!(frame.visible(), vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(frame.children.get("b").unwrap().0.x, 42);
+        assert_eq!(frame.children.get("b").unwrap().0.w, Some(40));
+        assert_eq!(frame.focus_next(), Some("a".to_string()));
+        assert_eq!(frame.unsplit(), Some("b".to_string()));
+        assert_eq!(frame.visible(), vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn test_frame_layout_empty_pane() {
+        // Given
+        let mut frame = FrameLayout::<(), MockWriter, String>::new();
+        frame.insert(
+            "a".to_string(),
+            BufferedWin::<(), MockWriter, String>::new(),
+        );
+        frame.set_current("a".to_string());
+
+        // When
+        frame.split(Orientation::Vertical);
+        let mut dimension = Dimension::new();
+        frame.measure(&mut dimension, Some(80), Some(11));
+        frame.layout(&mut dimension, 1, 1);
+
+        // Then
+        assert_eq!(frame.empty.len(), 1);
+        assert_eq!(frame.empty[0].y, 7);
+        assert_eq!(frame.empty[0].h, Some(5));
+    }
+
+    #[test]
+    fn test_frame_layout_hidden_children() {
+        // Given
+        let mut frame = FrameLayout::<(), MockWriter, String>::new();
+        frame.insert(
+            "a".to_string(),
+            BufferedWin::<(), MockWriter, String>::new(),
+        );
+        frame.insert(
+            "b".to_string(),
+            BufferedWin::<(), MockWriter, String>::new(),
+        );
+        frame.set_current("a".to_string());
+
+        // When
+        let mut dimension = Dimension::new();
+        frame.measure(&mut dimension, Some(80), Some(10));
+        frame.layout(&mut dimension, 1, 1);
+
+        // Then
+        assert_eq!(frame.children.get("a").unwrap().0.w, Some(80));
+        assert_eq!(frame.children.get("b").unwrap().0.w, None);
+
+        // When
+        frame.set_current("b".to_string());
+        frame.layout(&mut dimension, 1, 1);
+
+        // Then
+        assert_eq!(frame.children.get("b").unwrap().0.w, Some(80));
+    }
+
     #[test]
     fn test_term_string_clean() {
         // Given
@@ -1897,6 +3399,20 @@ mod tests {
         assert_eq!(cleaned, "test Blink");
     }
 
+    #[test]
+    fn test_highlight_term() {
+        // Given
+        let input = "\x1b[38;5;3mromeo\x1b[0m: 3 m[";
+
+        // When
+        let digit = highlight_term(input, "3", "<", ">");
+        let spanning = highlight_term(input, "o: 3", "<", ">");
+
+        // Then
+        assert_eq!(digit, "\x1b[38;5;3mromeo\x1b[0m: <3> m[");
+        assert_eq!(spanning, "\x1b[38;5;3mrome<o\x1b[0m: 3> m[");
+    }
+
     #[test]
     fn test_term_string_visible_truncate() {
         // Given
@@ -0,0 +1,424 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use termion::color;
+
+use crate::color::id_to_rgb;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Reset,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    LightBlack,
+    LightRed,
+    LightGreen,
+    LightYellow,
+    LightBlue,
+    LightMagenta,
+    LightCyan,
+    LightWhite,
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Parse a color name (`red`, `light-blue`, `reset`…) or a `#rrggbb` hex value
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let color = match value.to_lowercase().replace('_', "-").as_str() {
+            "reset" | "default" => Color::Reset,
+            "black" => Color::Black,
+            "red" => Color::Red,
+            "green" => Color::Green,
+            "yellow" => Color::Yellow,
+            "blue" => Color::Blue,
+            "magenta" => Color::Magenta,
+            "cyan" => Color::Cyan,
+            "white" => Color::White,
+            "light-black" | "gray" | "grey" => Color::LightBlack,
+            "light-red" => Color::LightRed,
+            "light-green" => Color::LightGreen,
+            "light-yellow" => Color::LightYellow,
+            "light-blue" => Color::LightBlue,
+            "light-magenta" => Color::LightMagenta,
+            "light-cyan" => Color::LightCyan,
+            "light-white" => Color::LightWhite,
+            hex if hex.len() == 7 && hex.is_ascii() && hex.starts_with('#') => {
+                let component = |i: usize| {
+                    u8::from_str_radix(&hex[i..i + 2], 16)
+                        .map_err(|_| format!("Invalid color {}", value))
+                };
+                Color::Rgb(component(1)?, component(3)?, component(5)?)
+            }
+            _ => return Err(format!("Invalid color {}", value)),
+        };
+
+        Ok(color)
+    }
+
+    fn termion(&self, depth: ColorDepth) -> Box<dyn color::Color> {
+        match *self {
+            Color::Reset => Box::new(color::Reset),
+            Color::Black => Box::new(color::Black),
+            Color::Red => Box::new(color::Red),
+            Color::Green => Box::new(color::Green),
+            Color::Yellow => Box::new(color::Yellow),
+            Color::Blue => Box::new(color::Blue),
+            Color::Magenta => Box::new(color::Magenta),
+            Color::Cyan => Box::new(color::Cyan),
+            Color::White => Box::new(color::White),
+            Color::LightBlack => Box::new(color::LightBlack),
+            Color::LightRed => Box::new(color::LightRed),
+            Color::LightGreen => Box::new(color::LightGreen),
+            Color::LightYellow => Box::new(color::LightYellow),
+            Color::LightBlue => Box::new(color::LightBlue),
+            Color::LightMagenta => Box::new(color::LightMagenta),
+            Color::LightCyan => Box::new(color::LightCyan),
+            Color::LightWhite => Box::new(color::LightWhite),
+            Color::Rgb(r, g, b) => match depth {
+                ColorDepth::TrueColor => Box::new(color::Rgb(r, g, b)),
+                ColorDepth::Ansi256 => Box::new(color::AnsiValue(to_ansi256(r, g, b))),
+                ColorDepth::Ansi16 => to_ansi16(r, g, b).termion(depth),
+            },
+        }
+    }
+}
+
+impl color::Color for Color {
+    fn write_fg(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.termion(current().depth).write_fg(f)
+    }
+
+    fn write_bg(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.termion(current().depth).write_bg(f)
+    }
+}
+
+/// Colors supported by the terminal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorDepth {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Guess terminal capabilities from $COLORTERM and $TERM
+    pub fn detect() -> Self {
+        Self::from_env(env::var("COLORTERM").ok(), env::var("TERM").ok())
+    }
+
+    fn from_env(colorterm: Option<String>, term: Option<String>) -> Self {
+        match (colorterm.as_deref(), term.as_deref()) {
+            (Some("truecolor"), _) | (Some("24bit"), _) => ColorDepth::TrueColor,
+            (_, Some(term)) if term.ends_with("-direct") => ColorDepth::TrueColor,
+            (_, Some(term)) if term.contains("256color") => ColorDepth::Ansi256,
+            _ => ColorDepth::Ansi16,
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "auto" => Ok(Self::detect()),
+            "16" => Ok(ColorDepth::Ansi16),
+            "256" => Ok(ColorDepth::Ansi256),
+            "truecolor" | "24bit" => Ok(ColorDepth::TrueColor),
+            _ => Err(format!("Invalid color depth {}", value)),
+        }
+    }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (i32::from(a) - i32::from(b)).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+/// Nearest color of the xterm 256 colors palette (color cube or grayscale ramp)
+fn to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |c: u8| match c {
+        0..=47 => 0,
+        48..=114 => 1,
+        c => (usize::from(c) - 35) / 40,
+    };
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (LEVELS[ri], LEVELS[gi], LEVELS[bi]);
+
+    let average = (u16::from(r) + u16::from(g) + u16::from(b)) / 3;
+    let gray_index = cmp::min(average.saturating_sub(3) / 10, 23) as u8;
+    let gray = 8 + 10 * gray_index;
+
+    if distance((r, g, b), (gray, gray, gray)) < distance((r, g, b), cube) {
+        232 + gray_index
+    } else {
+        (16 + 36 * ri + 6 * gi + bi) as u8
+    }
+}
+
+/// Nearest of the 16 basic colors, using xterm default palette
+fn to_ansi16(r: u8, g: u8, b: u8) -> Color {
+    const PALETTE: [(Color, (u8, u8, u8)); 16] = [
+        (Color::Black, (0, 0, 0)),
+        (Color::Red, (205, 0, 0)),
+        (Color::Green, (0, 205, 0)),
+        (Color::Yellow, (205, 205, 0)),
+        (Color::Blue, (0, 0, 238)),
+        (Color::Magenta, (205, 0, 205)),
+        (Color::Cyan, (0, 205, 205)),
+        (Color::White, (229, 229, 229)),
+        (Color::LightBlack, (127, 127, 127)),
+        (Color::LightRed, (255, 0, 0)),
+        (Color::LightGreen, (0, 255, 0)),
+        (Color::LightYellow, (255, 255, 0)),
+        (Color::LightBlue, (92, 92, 255)),
+        (Color::LightMagenta, (255, 0, 255)),
+        (Color::LightCyan, (0, 255, 255)),
+        (Color::LightWhite, (255, 255, 255)),
+    ];
+
+    PALETTE
+        .iter()
+        .min_by_key(|(_, rgb)| distance((r, g, b), *rgb))
+        .map(|(color, _)| *color)
+        .unwrap()
+}
+
+/// Theme section of the config file
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ThemeConfig {
+    /// Name of the built-in theme used as base
+    pub base: Option<String>,
+    /// Colors supported by the terminal, detected when unset
+    pub color_depth: Option<String>,
+    /// Overridden colors, indexed by theme entry
+    #[serde(flatten)]
+    pub colors: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub title_bar_fg: Color,
+    pub title_bar_bg: Color,
+    pub win_bar_fg: Color,
+    pub win_bar_bg: Color,
+    pub text: Color,
+    pub timestamp: Color,
+    /// Windows with unread messages in the win bar
+    pub highlight: Color,
+    /// Fixed color for nicks, None to use a consistent color per nick
+    pub nick: Option<Color>,
+    /// Roster groups and occupant roles
+    pub group: Color,
+    pub available: Color,
+    pub unavailable: Color,
+    /// Occupants that don't want to be disturbed
+    pub dnd: Color,
+    /// Errors of messages that couldn't be delivered
+    pub error: Color,
+    /// RGB colors are downgraded to what the terminal supports
+    pub depth: ColorDepth,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            title_bar_fg: Color::White,
+            title_bar_bg: Color::Blue,
+            win_bar_fg: Color::White,
+            win_bar_bg: Color::Blue,
+            text: Color::White,
+            timestamp: Color::White,
+            highlight: Color::White,
+            nick: None,
+            group: Color::Yellow,
+            available: Color::Green,
+            unavailable: Color::White,
+            dnd: Color::Red,
+            error: Color::Red,
+            depth: ColorDepth::detect(),
+        }
+    }
+}
+
+impl Theme {
+    /// Get a built-in theme by name
+    pub fn builtin(name: &str) -> Result<Self, String> {
+        match name {
+            "default" => Ok(Self::default()),
+            "light" => Ok(Self {
+                text: Color::Black,
+                timestamp: Color::LightBlack,
+                group: Color::Magenta,
+                available: Color::Green,
+                unavailable: Color::LightBlack,
+                ..Self::default()
+            }),
+            "mono" => Ok(Self {
+                title_bar_fg: Color::Black,
+                title_bar_bg: Color::White,
+                win_bar_fg: Color::Black,
+                win_bar_bg: Color::White,
+                text: Color::Reset,
+                timestamp: Color::Reset,
+                highlight: Color::Black,
+                nick: Some(Color::Reset),
+                group: Color::Reset,
+                available: Color::Reset,
+                unavailable: Color::Reset,
+                dnd: Color::Reset,
+                error: Color::Reset,
+                ..Self::default()
+            }),
+            _ => Err(format!("Unknown theme {}", name)),
+        }
+    }
+
+    /// Build the theme described by the config, unknown entries are reported as errors but
+    /// don't prevent the rest of the theme to be applied
+    pub fn from_config(config: &ThemeConfig) -> (Self, Vec<String>) {
+        let mut errors = Vec::new();
+        let mut theme = match &config.base {
+            Some(base) => Self::builtin(base).unwrap_or_else(|err| {
+                errors.push(err);
+                Self::default()
+            }),
+            None => Self::default(),
+        };
+
+        if let Some(depth) = &config.color_depth {
+            match ColorDepth::parse(depth) {
+                Ok(depth) => theme.depth = depth,
+                Err(err) => errors.push(err),
+            }
+        }
+
+        for (entry, value) in &config.colors {
+            if let Err(err) = theme.set(entry, value) {
+                errors.push(err);
+            }
+        }
+
+        (theme, errors)
+    }
+
+    pub fn set(&mut self, entry: &str, value: &str) -> Result<(), String> {
+        if entry == "nick" {
+            self.nick = match value {
+                "auto" => None,
+                value => Some(Color::parse(value)?),
+            };
+            return Ok(());
+        }
+
+        let color = Color::parse(value)?;
+        match entry {
+            "title_bar_fg" => self.title_bar_fg = color,
+            "title_bar_bg" => self.title_bar_bg = color,
+            "win_bar_fg" => self.win_bar_fg = color,
+            "win_bar_bg" => self.win_bar_bg = color,
+            "text" => self.text = color,
+            "timestamp" => self.timestamp = color,
+            "highlight" => self.highlight = color,
+            "group" => self.group = color,
+            "available" => self.available = color,
+            "unavailable" => self.unavailable = color,
+            "dnd" => self.dnd = color,
+            "error" => self.error = color,
+            _ => return Err(format!("Unknown theme entry {}", entry)),
+        }
+
+        Ok(())
+    }
+
+    /// Color of the given nick
+    pub fn nick(&self, nick: &str) -> Color {
+        match self.nick {
+            Some(color) => color,
+            None => {
+                let (r, g, b) = id_to_rgb(nick);
+                Color::Rgb(r, g, b)
+            }
+        }
+    }
+}
+
+thread_local! {
+    static THEME: RefCell<Theme> = RefCell::new(Theme::default());
+}
+
+/// Get the theme in use
+pub fn current() -> Theme {
+    THEME.with(|theme| *theme.borrow())
+}
+
+/// Change the theme in use
+pub fn set(theme: Theme) {
+    THEME.with(|current| current.replace(theme));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(Color::parse("light-blue"), Ok(Color::LightBlue));
+        assert_eq!(Color::parse("#ff8000"), Ok(Color::Rgb(255, 128, 0)));
+        assert!(Color::parse("#ff80").is_err());
+        assert!(Color::parse("chartreuse").is_err());
+    }
+
+    #[test]
+    fn test_theme_from_config() {
+        let mut colors = HashMap::new();
+        colors.insert("timestamp".to_string(), "grey".to_string());
+        colors.insert("unknown".to_string(), "red".to_string());
+        let config = ThemeConfig {
+            base: Some("light".to_string()),
+            color_depth: Some("256".to_string()),
+            colors,
+        };
+
+        let (theme, errors) = Theme::from_config(&config);
+
+        assert_eq!(theme.text, Color::Black);
+        assert_eq!(theme.timestamp, Color::LightBlack);
+        assert_eq!(theme.depth, ColorDepth::Ansi256);
+        assert_eq!(errors, vec!["Unknown theme entry unknown".to_string()]);
+    }
+
+    #[test]
+    fn test_color_depth_from_env() {
+        let env = |value: &str| Some(value.to_string());
+        assert_eq!(
+            ColorDepth::from_env(env("truecolor"), env("xterm")),
+            ColorDepth::TrueColor
+        );
+        assert_eq!(
+            ColorDepth::from_env(None, env("xterm-256color")),
+            ColorDepth::Ansi256
+        );
+        assert_eq!(ColorDepth::from_env(None, env("vt100")), ColorDepth::Ansi16);
+        assert_eq!(ColorDepth::from_env(None, None), ColorDepth::Ansi16);
+    }
+
+    #[test]
+    fn test_color_downgrade() {
+        assert_eq!(to_ansi256(255, 0, 0), 196);
+        assert_eq!(to_ansi256(0, 0, 0), 16);
+        assert_eq!(to_ansi256(128, 128, 128), 244);
+        assert_eq!(to_ansi16(250, 10, 10), Color::LightRed);
+        assert_eq!(to_ansi16(10, 10, 200), Color::Blue);
+    }
+}
@@ -0,0 +1,100 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+use std::ops::Range;
+
+const SCHEMES: [&str; 5] = ["https://", "http://", "aesgcm://", "ftp://", "xmpp:"];
+
+/// Find URLs in the given text, returned as byte ranges
+///
+/// A URL starts with a known scheme and ends at the next whitespace. Trailing punctuation and
+/// unbalanced closing brackets are considered part of the surrounding text.
+pub fn find_urls(text: &str) -> Vec<Range<usize>> {
+    let mut urls = Vec::new();
+    let mut start = 0;
+
+    for word in text.split_whitespace() {
+        // split_whitespace yields subslices, find their offset from the remaining text
+        let offset = start + text[start..].find(word).unwrap();
+        start = offset + word.len();
+
+        let begin = match SCHEMES.iter().filter_map(|scheme| word.find(scheme)).min() {
+            Some(begin) => begin,
+            None => continue,
+        };
+        let candidate = &word[begin..];
+
+        let mut end = candidate.len();
+        while let Some(c) = candidate[..end].chars().last() {
+            let trim = match c {
+                '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' | '>' => true,
+                ')' => {
+                    candidate[..end].matches('(').count() < candidate[..end].matches(')').count()
+                }
+                ']' => {
+                    candidate[..end].matches('[').count() < candidate[..end].matches(']').count()
+                }
+                _ => false,
+            };
+            if !trim {
+                break;
+            }
+            end -= c.len_utf8();
+        }
+
+        // Skip lone schemes
+        let url = &candidate[..end];
+        if !SCHEMES
+            .iter()
+            .any(|scheme| url.starts_with(scheme) && url.len() > scheme.len())
+        {
+            continue;
+        }
+
+        urls.push(offset + begin..offset + begin + end);
+    }
+
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(text: &str) -> Vec<&str> {
+        find_urls(text)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect()
+    }
+
+    #[test]
+    fn test_find_urls() {
+        assert_eq!(
+            urls("see https://example.org/a?b=c and http://foo.bar"),
+            vec!["https://example.org/a?b=c", "http://foo.bar"]
+        );
+        assert_eq!(
+            urls("aesgcm://example.org/cat.jpg#0a1b"),
+            vec!["aesgcm://example.org/cat.jpg#0a1b"]
+        );
+    }
+
+    #[test]
+    fn test_find_urls_trailing_punctuation() {
+        assert_eq!(
+            urls("(https://en.wikipedia.org/wiki/Rust_(language)), really?"),
+            vec!["https://en.wikipedia.org/wiki/Rust_(language)"]
+        );
+        assert_eq!(
+            urls("go to <https://example.org>."),
+            vec!["https://example.org"]
+        );
+    }
+
+    #[test]
+    fn test_find_urls_none() {
+        assert!(urls("nothing to see: https:// here").is_empty());
+        assert!(urls("xmpp: protocol").is_empty());
+    }
+}